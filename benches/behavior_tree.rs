@@ -0,0 +1,25 @@
+use real_time_sync::behavior_tree::bench::{BenchConfig, run_benchmarks};
+
+//	cargo bench -- [size] [trees] [iterations]
+fn main(){
+	let args:Vec<usize> = std::env::args().skip(1).filter_map(|arg| arg.parse().ok()).collect();
+	let default_config = BenchConfig::default();
+	let config = BenchConfig{
+		size:args.first().cloned().unwrap_or(default_config.size),
+		trees:args.get(1).cloned().unwrap_or(default_config.trees),
+		iterations:args.get(2).cloned().unwrap_or(default_config.iterations),
+	};
+
+	println!("behavior tree benchmarks: size {}, trees {}, iterations {}", config.size, config.trees, config.iterations);
+	match run_benchmarks(&config){
+		Ok(results) => {
+			for result in results.iter(){
+				println!("{}", result);
+			}
+		},
+		Err(err) => {
+			eprintln!("benchmark failed: {}", err);
+			std::process::exit(1);
+		},
+	}
+}
@@ -6,3 +6,22 @@ pub mod composite;
 pub mod action;
 pub mod decorator;
 pub mod conditional;
+pub mod snapshot;
+pub mod replay;
+pub mod trace;
+pub mod event_handle;
+pub mod wire;
+pub mod mirror;
+pub mod batch;
+pub mod sync_message;
+pub mod router;
+pub mod bandwidth;
+pub mod manager;
+pub mod profile;
+pub mod sharded;
+pub mod bench;
+pub mod registry;
+pub mod compiled;
+#[cfg(any(feature = "yaml", feature = "toml", feature = "ron"))]
+pub mod authoring;
+pub mod builder;
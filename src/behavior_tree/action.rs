@@ -1,3 +1,4 @@
 pub mod idle;
 pub mod play_ani_for_sync;
-pub mod role_follow_joystick;
\ No newline at end of file
+pub mod role_follow_joystick;
+pub mod perform_interruption;
\ No newline at end of file
@@ -0,0 +1,60 @@
+use std::collections::HashMap;
+
+use super::super::interface::{IAction, ITaskProxy, IBehaviorTree};
+use super::super::consts::TaskStatus;
+use super::super::registry::TaskIds;
+
+//  打断配置里引用的Interrupt任务，被打断的Interrupt以interruptSuccess对应的状态结束
+pub struct PerformInterruption{
+    task_ids:TaskIds,
+    config_task_ids:Vec<i32>,
+    interrupt_task_ids:Vec<i32>,
+    interrupt_success:bool,
+}
+
+impl PerformInterruption{
+    pub fn new(variables:HashMap<String, serde_json::Value>, task_ids:TaskIds) -> Self{
+        let interrupt_success = match variables.get("Boolean,interruptSuccess"){
+            Some(value) => value.as_bool().unwrap_or(false),
+            None => false,
+        };
+
+        let config_task_ids = match variables.get("BehaviorDesigner.Runtime.Tasks.Interrupt[],interruptTasks"){
+            Some(value) => value.as_array().map(|ids| ids.iter().filter_map(|id| id.as_i64()).map(|id| id as i32).collect()).unwrap_or_default(),
+            None => Vec::new(),
+        };
+
+        Self{
+            task_ids,
+            config_task_ids,
+            interrupt_task_ids:Vec::new(),
+            interrupt_success,
+        }
+    }
+}
+
+impl IAction for PerformInterruption{
+    //  解析的时候引用的任务可能还没有生成，等全部任务生成以后再检查
+    fn initialize_variables(&mut self, task_proxy:&mut dyn ITaskProxy)->Result<(), Box<dyn std::error::Error>> {
+        let task_ids = self.task_ids.lock().map_err(|_| "task ids lock poisoned")?;
+        for id in self.config_task_ids.iter(){
+            if !task_ids.contains(id){
+                return Err(format!("PerformInterruption references unknown task {}", id).into());
+            }
+        }
+        Ok(())
+    }
+
+    //  运行时的任务ID在树初始化的时候重新分配，所以在on_awake里面再取，不在树上的任务忽略
+    fn on_awake(&mut self, task_proxy:&mut dyn ITaskProxy, behavior_tree:&dyn IBehaviorTree) {
+        self.interrupt_task_ids = self.config_task_ids.iter().filter_map(|id| behavior_tree.task_id_by_config_id(*id)).collect();
+    }
+
+    fn on_update(&mut self, task_proxy:&mut dyn ITaskProxy, behavior_tree:&dyn IBehaviorTree)->TaskStatus {
+        let status = if self.interrupt_success { TaskStatus::Success } else { TaskStatus::Failure };
+        for task_id in self.interrupt_task_ids.iter(){
+            behavior_tree.request_interrupt(*task_id, status.clone(), false);
+        }
+        TaskStatus::Success
+    }
+}
@@ -1,19 +1,81 @@
+use std::collections::HashMap;
+use serde::{Serialize, Deserialize};
+
 use super::super::interface::{IAction, ITaskProxy, IBehaviorTree};
-use super::super::consts::TaskStatus;
+use super::super::consts::{TaskStatus, SyncUpdatePolicy};
+use super::super::sync_message::{SyncMessageType, send_sync_message};
+use super::super::wire::{WireReader, WireWriter};
 
-pub struct PlayAniForSync{
+//  同步给客户端播放的动画
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct PlayAniSyncData{
+    pub animation:String,
+    #[serde(rename = "loop")]
+    pub is_loop:bool,
+}
+
+impl SyncMessageType for PlayAniSyncData{
+    const MESSAGE_TYPE:u32 = 1;
+
+    fn write(&self, writer:&mut WireWriter)->Result<(), Box<dyn std::error::Error>>{
+        writer.write_str(&self.animation);
+        writer.write_bool(self.is_loop);
+        Ok(())
+    }
 
+    fn read(reader:&mut WireReader)->Result<Self, Box<dyn std::error::Error>>{
+        let animation = reader.read_string()?;
+        let is_loop = reader.read_bool()?;
+        Ok(Self{animation, is_loop})
+    }
+}
+
+pub struct PlayAniForSync{
+    data:PlayAniSyncData,
 }
 
 impl PlayAniForSync{
-    pub fn new() -> Self{
-        Self{}
+    pub fn new(variables:HashMap<String, serde_json::Value>) -> Self{
+        let animation = match variables.get("String,AnimationName"){
+            Some(value) => value.as_str().unwrap_or_default().to_string(),
+            None => String::new(),
+        };
+
+        let is_loop = match variables.get("Boolean,isLoop"){
+            Some(value) => value.as_bool().unwrap_or(false),
+            None => false,
+        };
+
+        Self{
+            data:PlayAniSyncData{animation, is_loop},
+        }
     }
 }
 
 
 impl IAction for PlayAniForSync{
+    fn on_start(&mut self, task_proxy:&mut dyn ITaskProxy, behavior_tree:&dyn IBehaviorTree){
+        if let Err(error) = send_sync_message(task_proxy, &self.data){
+            behavior_tree.report_task_error(task_proxy, error.as_ref());
+        }
+    }
+
     fn on_update(&mut self, task_proxy:&mut dyn ITaskProxy, behavior_tree:&dyn IBehaviorTree)->TaskStatus {
         TaskStatus::Running
     }
-}
\ No newline at end of file
+
+    fn is_sync_to_client(&self)->bool{
+        true
+    }
+
+    //  动画只在开始的时候发，update不需要同步
+    fn sync_update_policy(&self)->SyncUpdatePolicy{
+        SyncUpdatePolicy::StartEndOnly
+    }
+
+    fn rebuild_sync_datas(&self, task_proxy:&dyn ITaskProxy, behavior_tree:&dyn IBehaviorTree){
+        if let Err(error) = send_sync_message(task_proxy, &self.data){
+            behavior_tree.report_task_error(task_proxy, error.as_ref());
+        }
+    }
+}
@@ -0,0 +1,339 @@
+use std::{rc::Rc, cell::RefCell, sync::{Arc, RwLock}};
+
+use super::consts::AbortType;
+use super::interface::{IParser, ITaskProxy, TaskAddData};
+use super::registry::{TaskConfig, TaskRegistry, TreeConfig};
+
+//	手写行为树用的格式，YAML、TOML、RON三种写法的结构一样：
+//	顶层是 root + 可选的 detached，也可以直接写根任务
+//	任务的保留字段：type(完整类型名、注册的简称或者snake_case的类型名) name id instant disabled abort children
+//	其它字段都是变量，字段名没有类型前缀的时候按值补上，比如 AnimationName: run 等于 "String,AnimationName": "run"
+//	id可以不写，按前序自动分配，跳过手写了的id
+//	RON里type是关键字，要写成r#type
+//	每种格式在对应的feature(yaml、toml、ron)打开的时候才有
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum AuthoringFormat{
+	#[cfg(feature = "yaml")]
+	Yaml,
+	#[cfg(feature = "toml")]
+	Toml,
+	#[cfg(feature = "ron")]
+	Ron,
+}
+
+impl AuthoringFormat{
+	fn load(&self, text:&str)->Result<serde_json::Value, Box<dyn std::error::Error>>{
+		let value = match self{
+			#[cfg(feature = "yaml")]
+			AuthoringFormat::Yaml => serde_yaml_ng::from_str(text)?,
+			#[cfg(feature = "toml")]
+			AuthoringFormat::Toml => toml::from_str(text)?,
+			#[cfg(feature = "ron")]
+			AuthoringFormat::Ron => ron::from_str(text)?,
+		};
+		Ok(value)
+	}
+}
+
+const RESERVED_KEYS:[&str; 7] = ["type", "name", "id", "instant", "disabled", "abort", "children"];
+
+fn parse_abort_type(value:&serde_json::Value)->Result<AbortType, Box<dyn std::error::Error>>{
+	match value.as_str(){
+		Some("none"|"None") => Ok(AbortType::None),
+		Some("self"|"Self") => Ok(AbortType::Self_),
+		Some("lower_priority"|"LowerPriority") => Ok(AbortType::LowerPriority),
+		Some("both"|"Both") => Ok(AbortType::Both),
+		_ => Err(format!("unknown abort type: {}", value).into()),
+	}
+}
+
+//	没有类型前缀的变量按值推断Behavior Designer的类型名
+fn variable_key(key:&str, value:&serde_json::Value)->Result<String, Box<dyn std::error::Error>>{
+	if key.contains(','){
+		return Ok(key.to_string());
+	}
+	let type_name = match value{
+		serde_json::Value::Bool(_) => "Boolean",
+		serde_json::Value::String(_) => "String",
+		serde_json::Value::Number(number) if number.is_f64() => "Single",
+		serde_json::Value::Number(_) => "Int32",
+		_ => return Err(format!("variable {} needs a type prefix, e.g. \"Type,{}\"", key, key).into()),
+	};
+	Ok(format!("{},{}", type_name, key))
+}
+
+fn parse_task_config(registry:&TaskRegistry, task:&serde_json::Value)->Result<TaskConfig, Box<dyn std::error::Error>>{
+	let task_object = task.as_object().ok_or("task config is not a table")?;
+	let type_name = task_object.get("type").and_then(|type_name| type_name.as_str()).ok_or("task type is missing")?;
+	let corresponding_type = registry.resolve_type(type_name).ok_or_else(|| format!("unknown task type: {}", type_name))?;
+	let name = task_object.get("name").and_then(|name| name.as_str()).unwrap_or("");
+	let id = match task_object.get("id"){
+		Some(id) => id.as_i64().filter(|id| *id > 0 && *id <= i32::MAX as i64).ok_or_else(|| format!("task id must be a positive integer: {}", id))? as i32,
+		//	0表示没有配置，最后统一分配
+		None => 0,
+	};
+	let mut task_config = TaskConfig::new(&corresponding_type, name, id);
+
+	if let Some(instant) = task_object.get("instant"){
+		task_config.instant = Some(instant.as_bool().ok_or("task instant is not a bool")?);
+	}
+	if let Some(disabled) = task_object.get("disabled"){
+		task_config.disabled = Some(disabled.as_bool().ok_or("task disabled is not a bool")?);
+	}
+	if let Some(abort) = task_object.get("abort"){
+		task_config.abort_type = Some(parse_abort_type(abort)?);
+	}
+
+	for (key, value) in task_object.iter(){
+		if !RESERVED_KEYS.contains(&key.as_str()){
+			task_config.variables.insert(variable_key(key, value)?, value.clone());
+		}
+	}
+
+	if let Some(children) = task_object.get("children"){
+		for child in children.as_array().ok_or("task children is not a list")?.iter(){
+			task_config.children.push(parse_task_config(registry, child)?);
+		}
+	}
+
+	Ok(task_config)
+}
+
+//	手写格式转成跟格式无关的配置，类型简称需要注册表来解析
+pub fn parse_tree_config(format:AuthoringFormat, registry:&TaskRegistry, config:&[u8])->Result<TreeConfig, Box<dyn std::error::Error>>{
+	let document = format.load(std::str::from_utf8(config)?)?;
+	let (root_task, detached_tasks) = match document.get("root"){
+		Some(root_task) => {
+			let detached_tasks = match document.get("detached"){
+				Some(detached_tasks) => detached_tasks.as_array().ok_or("detached is not a list")?.clone(),
+				None => Vec::new(),
+			};
+			(root_task.clone(), detached_tasks)
+		},
+		None => (document, Vec::new()),
+	};
+
+	let root_task = parse_task_config(registry, &root_task)?;
+	let mut detached_task_configs = Vec::new();
+	for detached_task in detached_tasks.iter(){
+		detached_task_configs.push(parse_task_config(registry, detached_task)?);
+	}
+
+	let mut tree_config = TreeConfig{root_task, detached_tasks:detached_task_configs};
+	tree_config.assign_task_ids()?;
+	Ok(tree_config)
+}
+
+//	跟JsonParser共用任务注册表，注册到表里的任务两种格式都能用
+pub struct AuthoringParser{
+	format:AuthoringFormat,
+	registry:Arc<RwLock<TaskRegistry>>,
+}
+
+impl AuthoringParser{
+	pub fn new(format:AuthoringFormat)->Self{
+		Self::from_registry(format, TaskRegistry::new())
+	}
+
+	pub fn from_registry(format:AuthoringFormat, registry:Arc<RwLock<TaskRegistry>>)->Self{
+		Self{format, registry}
+	}
+
+	pub fn with_registry(format:AuthoringFormat, registry:Arc<RwLock<TaskRegistry>>)->Rc<RefCell<Box<dyn IParser>>>{
+		Rc::new(RefCell::new(Box::new(Self::from_registry(format, registry))))
+	}
+
+	pub fn registry(&self)->Arc<RwLock<TaskRegistry>>{
+		self.registry.clone()
+	}
+}
+
+impl IParser for AuthoringParser{
+	fn deserialize(&self, config:&Vec<u8>, task_add_data:&mut TaskAddData)->Result<Box<dyn ITaskProxy>, Box<dyn std::error::Error>>{
+		let registry = self.registry.read().map_err(|_| "task registry lock poisoned")?;
+		let tree_config = parse_tree_config(self.format, &registry, config)?;
+		registry.build(tree_config, task_add_data)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use serde_json::json;
+	use super::super::json_parser::JsonParser;
+
+	#[cfg(feature = "yaml")]
+	const YAML_TREE:&str = r#"
+root:
+  type: selector
+  abort: lower_priority
+  children:
+    - type: sequence
+      abort: both
+      children:
+        - type: need_follow_joystick
+        - type: role_follow_joystick
+    - type: play_ani_for_sync
+      name: Run
+      instant: true
+      AnimationName: run
+      isLoop: true
+    - type: perform_interruption
+      interruptSuccess: false
+      "BehaviorDesigner.Runtime.Tasks.Interrupt[],interruptTasks": [10]
+detached:
+  - type: interrupt
+    id: 10
+    children:
+      - type: idle
+"#;
+
+	#[cfg(feature = "toml")]
+	const TOML_TREE:&str = r#"
+[root]
+type = "selector"
+abort = "lower_priority"
+
+[[root.children]]
+type = "sequence"
+abort = "both"
+children = [{ type = "need_follow_joystick" }, { type = "role_follow_joystick" }]
+
+[[root.children]]
+type = "play_ani_for_sync"
+name = "Run"
+instant = true
+AnimationName = "run"
+isLoop = true
+
+[[root.children]]
+type = "perform_interruption"
+interruptSuccess = false
+"BehaviorDesigner.Runtime.Tasks.Interrupt[],interruptTasks" = [10]
+
+[[detached]]
+type = "interrupt"
+id = 10
+children = [{ type = "idle" }]
+"#;
+
+	#[cfg(feature = "ron")]
+	const RON_TREE:&str = r##"(
+	root: (
+		r#type: "selector",
+		abort: "lower_priority",
+		children: [
+			(r#type: "sequence", abort: "both", children: [(r#type: "need_follow_joystick"), (r#type: "role_follow_joystick")]),
+			(r#type: "play_ani_for_sync", name: "Run", instant: true, AnimationName: "run", isLoop: true),
+			{"type": "perform_interruption", "interruptSuccess": false, "BehaviorDesigner.Runtime.Tasks.Interrupt[],interruptTasks": [10]},
+		],
+	),
+	detached: [(r#type: "interrupt", id: 10, children: [(r#type: "idle")])],
+)"##;
+
+	//	同一棵树用Behavior Designer的json写出来
+	fn expected_json()->Vec<u8>{
+		json!({
+			"RootTask": {
+				"Type": "BehaviorDesigner.Runtime.Tasks.Selector",
+				"Name": "",
+				"ID": 1,
+				"BehaviorDesigner.Runtime.Tasks.AbortType,abortType": "LowerPriority",
+				"Children": [
+					{
+						"Type": "BehaviorDesigner.Runtime.Tasks.Sequence",
+						"ID": 2,
+						"BehaviorDesigner.Runtime.Tasks.AbortType,abortType": "Both",
+						"Children": [
+							{"Type": "BehaviorDesigner.Runtime.Tasks.Role.MainRole.NeedFollowJoystick", "ID": 3},
+							{"Type": "BehaviorDesigner.Runtime.Tasks.RoleFollowJoystick", "ID": 4}
+						]
+					},
+					{
+						"Type": "BehaviorDesigner.Runtime.Tasks.PlayAniForSync",
+						"Name": "Run",
+						"ID": 5,
+						"Instant": true,
+						"String,AnimationName": "run",
+						"Boolean,isLoop": true
+					},
+					{
+						"Type": "BehaviorDesigner.Runtime.Tasks.PerformInterruption",
+						"ID": 6,
+						"Boolean,interruptSuccess": false,
+						"BehaviorDesigner.Runtime.Tasks.Interrupt[],interruptTasks": [10]
+					}
+				]
+			},
+			"DetachedTasksConfigs": [{
+				"Type": "BehaviorDesigner.Runtime.Tasks.Interrupt",
+				"ID": 10,
+				"Children": [{"Type": "BehaviorDesigner.Runtime.Tasks.Idle", "ID": 7}]
+			}]
+		}).to_string().into_bytes()
+	}
+
+	#[test]
+	fn test_authoring_formats_match_json() {
+		let registry = TaskRegistry::new();
+		let expected = JsonParser::parse_tree_config(&expected_json()).unwrap();
+		let mut formats = Vec::new();
+		#[cfg(feature = "yaml")]
+		formats.push((AuthoringFormat::Yaml, YAML_TREE));
+		#[cfg(feature = "toml")]
+		formats.push((AuthoringFormat::Toml, TOML_TREE));
+		#[cfg(feature = "ron")]
+		formats.push((AuthoringFormat::Ron, RON_TREE));
+		for (format, text) in formats{
+			let tree_config = parse_tree_config(format, &registry.read().unwrap(), text.as_bytes()).unwrap();
+			assert_eq!(tree_config, expected, "{:?}", format);
+
+			let parser = AuthoringParser::with_registry(format, registry.clone());
+			let root_task = parser.borrow().deserialize(&text.as_bytes().to_vec(), &mut TaskAddData::new()).unwrap();
+			assert_eq!(root_task.children().len(), 3);
+		}
+	}
+
+	#[cfg(feature = "yaml")]
+	#[test]
+	fn test_authoring_parser_shares_registry() {
+		use super::super::interface::IAction;
+		use super::super::action::idle::Idle;
+
+		let registry = TaskRegistry::new();
+		let json_parser = JsonParser::with_registry(registry.clone());
+		let yaml_parser = AuthoringParser::with_registry(AuthoringFormat::Yaml, registry.clone());
+		let config = b"type: guard\nchildren:\n  - type: idle\n".to_vec();
+		assert!(yaml_parser.borrow().deserialize(&config, &mut TaskAddData::new()).is_err());
+
+		//	注册到共享的表里以后，两种格式都能用，简称也能用
+		registry.write().unwrap().register_action_fn("Game.Guard", |_variables, _task_ids| -> Box<dyn IAction> {Box::new(Idle::new())});
+		registry.write().unwrap().register_alias("guard", "Game.Guard");
+		let json_config = json!({"RootTask": {"Type": "Game.Guard", "ID": 1}}).to_string().into_bytes();
+		assert!(json_parser.borrow().deserialize(&json_config, &mut TaskAddData::new()).is_ok());
+		let root_task = AuthoringParser::with_registry(AuthoringFormat::Yaml, registry.clone()).borrow().deserialize(&b"type: guard".to_vec(), &mut TaskAddData::new()).unwrap();
+		assert_eq!(root_task.corresponding_type(), "Game.Guard");
+		assert_eq!(root_task.id(), 1);
+	}
+
+	#[cfg(feature = "yaml")]
+	#[test]
+	fn test_authoring_errors() {
+		let registry = TaskRegistry::new();
+		let registry = registry.read().unwrap();
+		let parse = |text:&str| parse_tree_config(AuthoringFormat::Yaml, &registry, text.as_bytes());
+
+		assert!(parse("type: unknown_task").is_err());
+		assert!(parse("type: sequence\nabort: sometimes").is_err());
+		assert!(parse("type: sequence\nchildren:\n  - {type: idle, id: 2}\n  - {type: idle, id: 2}").is_err());
+		assert!(parse("type: play_ani_for_sync\nframes: [1, 2]").is_err());
+		assert!(parse("name: missing type").is_err());
+		assert!(parse("type: [").is_err());
+
+		//	自动分配的id跳过手写的id
+		let tree_config = parse("type: sequence\nchildren:\n  - {type: idle, id: 2}\n  - type: idle\nSpeed: 1.5").unwrap();
+		assert_eq!(tree_config.root_task.id, 1);
+		assert_eq!(tree_config.root_task.children[1].id, 3);
+		assert_eq!(tree_config.root_task.variables["Single,Speed"], json!(1.5));
+	}
+}
@@ -0,0 +1,208 @@
+use std::{rc::Rc, cell::RefCell, collections::{HashMap, VecDeque}};
+
+use super::interface::{IBehaviorTree, ITaskProxy};
+use super::wire::{SyncMessageKind, SyncPacket};
+
+#[derive(Clone, Copy, Default, PartialEq, Debug)]
+pub struct BandwidthUsage{
+	pub bytes:u64,
+	pub messages:u64,
+}
+
+impl BandwidthUsage{
+	fn add(&mut self, bytes:usize){
+		self.bytes += bytes as u64;
+		self.messages += 1;
+	}
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct BandwidthRecord{
+	pub timestamp:u64,
+	pub tree_id:u64,
+	pub kind:SyncMessageKind,
+	//	执行栈的消息和全量状态没有
+	pub corresponding_type:Option<String>,
+	pub bytes:usize,
+}
+
+//	一段时间内流量最大的树、action类型和消息类型，按字节数从大到小
+#[derive(Clone, PartialEq, Debug)]
+pub struct BandwidthReport{
+	pub window_in_milli:u64,
+	pub total:BandwidthUsage,
+	pub trees:Vec<(u64, BandwidthUsage)>,
+	pub corresponding_types:Vec<(String, BandwidthUsage)>,
+	pub kinds:Vec<(SyncMessageKind, BandwidthUsage)>,
+}
+
+impl std::fmt::Display for BandwidthReport{
+	fn fmt(&self, f:&mut std::fmt::Formatter<'_>)->std::fmt::Result{
+		writeln!(f, "sync bandwidth in last {}ms: {} bytes, {} messages", self.window_in_milli, self.total.bytes, self.total.messages)?;
+		for (tree_id, usage) in self.trees.iter(){
+			writeln!(f, "  tree {}: {} bytes, {} messages", tree_id, usage.bytes, usage.messages)?;
+		}
+		for (corresponding_type, usage) in self.corresponding_types.iter(){
+			writeln!(f, "  type {}: {} bytes, {} messages", corresponding_type, usage.bytes, usage.messages)?;
+		}
+		for (kind, usage) in self.kinds.iter(){
+			writeln!(f, "  kind {:?}: {} bytes, {} messages", kind, usage.bytes, usage.messages)?;
+		}
+		Ok(())
+	}
+}
+
+fn top<K:Clone>(usages:HashMap<K, BandwidthUsage>, count:usize, order:impl Fn(&K, &K)->std::cmp::Ordering)->Vec<(K, BandwidthUsage)>{
+	let mut usages:Vec<(K, BandwidthUsage)> = usages.into_iter().collect();
+	usages.sort_by(|(a_key, a), (b_key, b)| b.bytes.cmp(&a.bytes).then_with(|| order(a_key, b_key)));
+	usages.truncate(count);
+	usages
+}
+
+//	同步流量统计，只保留retention_in_milli以内的明细，总量一直累计
+pub struct BandwidthMeter{
+	retention_in_milli:u64,
+	records:VecDeque<BandwidthRecord>,
+	total:BandwidthUsage,
+}
+
+impl BandwidthMeter{
+	pub fn new(retention_in_milli:u64)->Rc<RefCell<Self>>{
+		Rc::new(RefCell::new(Self{
+			retention_in_milli,
+			records:VecDeque::new(),
+			total:BandwidthUsage::default(),
+		}))
+	}
+
+	pub fn total(&self)->BandwidthUsage{
+		self.total
+	}
+
+	pub fn record(&mut self, timestamp:u64, tree_id:u64, kind:SyncMessageKind, corresponding_type:Option<String>, bytes:usize){
+		self.total.add(bytes);
+		self.records.push_back(BandwidthRecord{timestamp, tree_id, kind, corresponding_type, bytes});
+
+		let expire_time = timestamp.saturating_sub(self.retention_in_milli);
+		while self.records.front().is_some_and(|record| record.timestamp < expire_time){
+			self.records.pop_front();
+		}
+	}
+
+	//	时间用树的时钟，action和并发任务的消息按task的corresponding_type统计
+	pub fn record_packet(&mut self, behavior_tree:&dyn IBehaviorTree, packet:&SyncPacket, task:Option<&dyn ITaskProxy>, bytes:usize){
+		let timestamp = behavior_tree.timestamp_in_mill();
+		self.record(timestamp, packet.message.tree_id(), packet.message.kind(), task.map(|task| task.corresponding_type().to_string()), bytes);
+	}
+
+	//	没有SyncPacket的时候按树和消息类型记
+	pub fn record_message(&mut self, behavior_tree:&dyn IBehaviorTree, kind:SyncMessageKind, task:Option<&dyn ITaskProxy>, bytes:usize){
+		let timestamp = behavior_tree.timestamp_in_mill();
+		self.record(timestamp, behavior_tree.id(), kind, task.map(|task| task.corresponding_type().to_string()), bytes);
+	}
+
+	//	统计[now - window_in_milli, now]之间的记录，每类只取前count个
+	pub fn report(&self, now:u64, window_in_milli:u64, count:usize)->BandwidthReport{
+		let start_time = now.saturating_sub(window_in_milli);
+		let mut total = BandwidthUsage::default();
+		let mut trees = HashMap::new();
+		let mut corresponding_types = HashMap::new();
+		let mut kinds = HashMap::new();
+		for record in self.records.iter().filter(|record| record.timestamp >= start_time && record.timestamp <= now){
+			total.add(record.bytes);
+			trees.entry(record.tree_id).or_insert_with(BandwidthUsage::default).add(record.bytes);
+			kinds.entry(record.kind).or_insert_with(BandwidthUsage::default).add(record.bytes);
+			if let Some(corresponding_type) = &record.corresponding_type{
+				corresponding_types.entry(corresponding_type.clone()).or_insert_with(BandwidthUsage::default).add(record.bytes);
+			}
+		}
+
+		BandwidthReport{
+			window_in_milli,
+			total,
+			trees:top(trees, count, |a, b| a.cmp(b)),
+			corresponding_types:top(corresponding_types, count, |a, b| a.cmp(b)),
+			kinds:top(kinds, count, |a, b| a.cmp(b)),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::cell::Cell;
+	use super::super::interface::IClock;
+	use super::super::json_parser::JsonParser;
+	use super::super::runtime::BehaviorTree;
+	use super::super::wire::{FullStateCollector, SyncSequences, WireEncodeRuntimeEventHandle};
+	use super::super::router::{SyncObserver, SyncRouter, SyncRouterRuntimeEventHandle};
+
+	struct ManualClock{
+		now:Rc<Cell<u64>>,
+	}
+
+	impl IClock for ManualClock{
+		fn timestamp_in_mill(&self)->u64{
+			self.now.get()
+		}
+	}
+
+	#[test]
+	fn test_bandwidth_report() {
+		let meter = BandwidthMeter::new(1000);
+		let mut meter = meter.borrow_mut();
+		meter.record(0, 1, SyncMessageKind::NewStack, None, 500);
+		meter.record(100, 1, SyncMessageKind::ActionStart, Some("Ani".to_string()), 30);
+		meter.record(200, 2, SyncMessageKind::ActionUpdate, Some("Move".to_string()), 20);
+		meter.record(300, 2, SyncMessageKind::ActionUpdate, Some("Move".to_string()), 20);
+		meter.record(400, 3, SyncMessageKind::ActionUpdate, Some("Move".to_string()), 5);
+
+		let report = meter.report(400, 300, 2);
+		assert_eq!(report.total, BandwidthUsage{bytes:75, messages:4});
+		assert_eq!(report.trees, vec![(2, BandwidthUsage{bytes:40, messages:2}), (1, BandwidthUsage{bytes:30, messages:1})]);
+		assert_eq!(report.corresponding_types, vec![("Move".to_string(), BandwidthUsage{bytes:45, messages:3}), ("Ani".to_string(), BandwidthUsage{bytes:30, messages:1})]);
+		assert_eq!(report.kinds[0], (SyncMessageKind::ActionUpdate, BandwidthUsage{bytes:45, messages:3}));
+		assert!(report.to_string().contains("tree 2: 40 bytes"));
+
+		//	超过保留时间的明细会删掉，总量还在
+		meter.record(2000, 1, SyncMessageKind::RemoveStack, None, 1);
+		assert_eq!(meter.report(2000, 5000, 10).total.messages, 1);
+		assert_eq!(meter.total(), BandwidthUsage{bytes:576, messages:6});
+	}
+
+	#[test]
+	fn test_bandwidth_meter_in_sync_path() {
+		let file_bytes = std::fs::read("src/behavior_tree/test_behaviortree.json").unwrap();
+		let parser = JsonParser::new();
+		let now = Rc::new(Cell::new(1000));
+		let clock:Rc<RefCell<Box<dyn IClock>>> = Rc::new(RefCell::new(Box::new(ManualClock{now:now.clone()})));
+		let meter = BandwidthMeter::new(60_000);
+		let messages = Rc::new(RefCell::new(Vec::new()));
+		let sequences = SyncSequences::new();
+		let behavior_tree = BehaviorTree::new(1, &file_bytes, 1, &Rc::downgrade(&clock),
+			Box::new(WireEncodeRuntimeEventHandle::new(messages.clone(), sequences.clone()).with_meter(meter.clone())), Rc::downgrade(&parser));
+
+		let router = SyncRouter::new();
+		router.borrow_mut().set_meter(meter.clone());
+		let routed_tree = BehaviorTree::new(2, &file_bytes, 1, &Rc::downgrade(&clock), Box::new(SyncRouterRuntimeEventHandle::new(router.clone())), Rc::downgrade(&parser));
+		router.borrow_mut().subscribe(SyncObserver{observer_id:1, team:0}, routed_tree.borrow().as_ref());
+		router.borrow_mut().subscribe(SyncObserver{observer_id:2, team:0}, routed_tree.borrow().as_ref());
+
+		behavior_tree.borrow_mut().enable().unwrap();
+		routed_tree.borrow_mut().enable().unwrap();
+		now.set(1100);
+		behavior_tree.borrow_mut().update();
+		routed_tree.borrow_mut().update();
+
+		let full_state_bytes = FullStateCollector::encode_with_meter(behavior_tree.borrow().as_ref(), &sequences.borrow(), &meter);
+
+		let sent_bytes:usize = messages.borrow().iter().map(|bytes| bytes.len()).sum::<usize>() + full_state_bytes.len();
+		let routed_bytes:usize = router.borrow_mut().take_all_messages().into_iter().flat_map(|(_, messages)| messages).map(|bytes| bytes.len()).sum();
+		let report = meter.borrow().report(1100, 1000, 10);
+		assert_eq!(report.total.bytes, (sent_bytes + routed_bytes) as u64);
+		assert_eq!(report.trees.iter().find(|(tree_id, _)| *tree_id == 1).unwrap().1.bytes, sent_bytes as u64);
+		assert!(report.kinds.iter().any(|(kind, _)| *kind == SyncMessageKind::FullState));
+		assert!(report.kinds.iter().any(|(kind, _)| *kind == SyncMessageKind::NewStack));
+		assert!(report.corresponding_types.iter().any(|(corresponding_type, _)| corresponding_type == "BehaviorDesigner.Runtime.Tasks.PlayAniForSync"));
+	}
+}
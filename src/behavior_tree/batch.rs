@@ -0,0 +1,309 @@
+use std::{rc::Rc, cell::RefCell};
+
+use super::wire::{WireReader, WireWriter};
+
+//	批次格式：版本(1字节) 标记(1字节) 消息个数(varint) 原始长度(varint) 内容
+//	内容是 (长度 + 消息)...，标记里有压缩位的时候内容是压缩过的
+pub const BATCH_VERSION:u8 = 1;
+
+const FLAG_COMPRESSED:u8 = 1;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Compression{
+	None,
+	//	简单的LZ77，压缩后没有变小就不压缩
+	Lz,
+}
+
+//	一次flush的结果，size是实际发送的字节数
+#[derive(Clone, PartialEq, Debug)]
+pub struct SyncBatch{
+	pub bytes:Vec<u8>,
+	pub message_count:usize,
+	pub raw_size:usize,
+	pub size:usize,
+	pub compressed:bool,
+}
+
+#[derive(Clone, Copy, Default, PartialEq, Debug)]
+pub struct BatchStats{
+	pub batches:u64,
+	pub messages:u64,
+	pub raw_bytes:u64,
+	pub bytes:u64,
+}
+
+//	把一次update(或者一帧里很多树)产生的同步消息合成一个包
+//	messages跟WireEncodeRuntimeEventHandle共用，每帧调用一次flush
+pub struct SyncBatcher{
+	messages:Rc<RefCell<Vec<Vec<u8>>>>,
+	compression:Compression,
+	stats:BatchStats,
+}
+
+impl SyncBatcher{
+	pub fn new(messages:Rc<RefCell<Vec<Vec<u8>>>>, compression:Compression)->Self{
+		Self{
+			messages,
+			compression,
+			stats:BatchStats::default(),
+		}
+	}
+
+	pub fn stats(&self)->BatchStats{
+		self.stats
+	}
+
+	//	没有消息的时候返回None
+	pub fn flush(&mut self)->Option<SyncBatch>{
+		let messages = std::mem::take(&mut *self.messages.borrow_mut());
+		if messages.is_empty(){
+			return None;
+		}
+
+		let batch = encode_batch(&messages, self.compression);
+		self.stats.batches += 1;
+		self.stats.messages += batch.message_count as u64;
+		self.stats.raw_bytes += batch.raw_size as u64;
+		self.stats.bytes += batch.size as u64;
+		Some(batch)
+	}
+}
+
+pub fn encode_batch(messages:&[Vec<u8>], compression:Compression)->SyncBatch{
+	let mut content = WireWriter::new();
+	for message in messages.iter(){
+		content.write_bytes(message);
+	}
+	let content = content.into_bytes();
+
+	let compressed_content = match compression{
+		Compression::None => None,
+		Compression::Lz => Some(lz_compress(&content)).filter(|compressed| compressed.len() < content.len()),
+	};
+
+	let mut writer = WireWriter::new();
+	writer.write_u8(BATCH_VERSION);
+	writer.write_u8(if compressed_content.is_some() { FLAG_COMPRESSED } else { 0 });
+	writer.write_varint(messages.len() as u64);
+	writer.write_varint(content.len() as u64);
+	let mut bytes = writer.into_bytes();
+	let compressed = compressed_content.is_some();
+	bytes.extend_from_slice(compressed_content.as_ref().unwrap_or(&content));
+
+	SyncBatch{
+		size:bytes.len(),
+		bytes,
+		message_count:messages.len(),
+		raw_size:content.len(),
+		compressed,
+	}
+}
+
+//	拆成一个个SyncPacket的字节
+pub fn decode_batch(bytes:&[u8])->Result<Vec<Vec<u8>>, Box<dyn std::error::Error>>{
+	let mut reader = WireReader::new(bytes);
+	let version = reader.read_u8()?;
+	if version != BATCH_VERSION{
+		return Err(format!("unsupported batch version: {}", version).into());
+	}
+	let flags = reader.read_u8()?;
+	let count = reader.read_varint()? as usize;
+	let raw_size = reader.read_varint()? as usize;
+	let header_size = bytes.len() - reader.remaining();
+	let content = if flags & FLAG_COMPRESSED != 0{
+		lz_decompress(&bytes[header_size..], raw_size)?
+	}else{
+		bytes[header_size..].to_vec()
+	};
+	if content.len() != raw_size{
+		return Err("batch size mismatch".into());
+	}
+
+	let mut reader = WireReader::new(&content);
+	let mut messages = Vec::new();
+	for _ in 0..count{
+		messages.push(reader.read_bytes()?.to_vec());
+	}
+	if !reader.is_empty(){
+		return Err("trailing bytes after batch".into());
+	}
+	Ok(messages)
+}
+
+const LZ_MIN_MATCH:usize = 3;
+const LZ_MAX_MATCH:usize = LZ_MIN_MATCH + 255;
+const LZ_WINDOW:usize = 65535;
+const LZ_HASH_SIZE:usize = 4096;
+
+fn lz_hash(bytes:&[u8])->usize{
+	((bytes[0] as usize) << 8 ^ (bytes[1] as usize) << 4 ^ bytes[2] as usize) % LZ_HASH_SIZE
+}
+
+//	每8个记号前面一个控制字节，位为1是匹配(偏移2字节 + 长度1字节)，为0是原样的1个字节
+fn lz_compress(input:&[u8])->Vec<u8>{
+	let mut output = Vec::new();
+	let mut heads = vec![usize::MAX; LZ_HASH_SIZE];
+	let mut position = 0;
+	let mut control_index = 0;
+	let mut control_bit = 8;
+
+	while position < input.len(){
+		if control_bit == 8{
+			control_index = output.len();
+			output.push(0);
+			control_bit = 0;
+		}
+
+		let mut match_len = 0;
+		let mut match_offset = 0;
+		if position + LZ_MIN_MATCH <= input.len(){
+			let hash = lz_hash(&input[position..]);
+			let candidate = heads[hash];
+			heads[hash] = position;
+			if candidate != usize::MAX && position - candidate <= LZ_WINDOW{
+				let max_len = LZ_MAX_MATCH.min(input.len() - position);
+				while match_len < max_len && input[candidate + match_len] == input[position + match_len]{
+					match_len += 1;
+				}
+				match_offset = position - candidate;
+			}
+		}
+
+		if match_len >= LZ_MIN_MATCH{
+			output[control_index] |= 1 << control_bit;
+			output.extend_from_slice(&(match_offset as u16).to_le_bytes());
+			output.push((match_len - LZ_MIN_MATCH) as u8);
+			for skipped in position + 1..position + match_len{
+				if skipped + LZ_MIN_MATCH <= input.len(){
+					heads[lz_hash(&input[skipped..])] = skipped;
+				}
+			}
+			position += match_len;
+		}else{
+			output.push(input[position]);
+			position += 1;
+		}
+		control_bit += 1;
+	}
+	output
+}
+
+fn lz_decompress(input:&[u8], raw_size:usize)->Result<Vec<u8>, Box<dyn std::error::Error>>{
+	let mut output = Vec::new();
+	let mut position = 0;
+	while position < input.len(){
+		let control = input[position];
+		position += 1;
+		for bit in 0..8{
+			if position >= input.len() || output.len() >= raw_size{
+				break;
+			}
+			if control & (1 << bit) != 0{
+				if position + 3 > input.len(){
+					return Err("corrupted batch".into());
+				}
+				let offset = u16::from_le_bytes([input[position], input[position + 1]]) as usize;
+				let len = input[position + 2] as usize + LZ_MIN_MATCH;
+				position += 3;
+				if offset == 0 || offset > output.len(){
+					return Err("corrupted batch".into());
+				}
+				let start = output.len() - offset;
+				for index in 0..len{
+					output.push(output[start + index]);
+				}
+			}else{
+				output.push(input[position]);
+				position += 1;
+			}
+		}
+	}
+	Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use super::super::interface::IClock;
+	use super::super::json_parser::JsonParser;
+	use super::super::mirror::SyncMirror;
+	use super::super::runtime::BehaviorTree;
+	use super::super::wire::{SyncSequences, WireEncodeRuntimeEventHandle};
+
+	struct DummyClock;
+
+	impl IClock for DummyClock{
+		fn timestamp_in_mill(&self)->u64{
+			0
+		}
+	}
+
+	#[test]
+	fn test_lz_compress() {
+		let inputs:Vec<Vec<u8>> = vec![
+			Vec::new(),
+			vec![1],
+			b"abcabcabcabcabcabcabcabcabcabc".to_vec(),
+			(0..2000u32).map(|i| (i % 7) as u8).collect(),
+			(0..300u32).map(|i| (i * 31 % 251) as u8).collect(),
+		];
+		for input in inputs.iter(){
+			let compressed = lz_compress(input);
+			assert_eq!(&lz_decompress(&compressed, input.len()).unwrap(), input);
+		}
+		assert!(lz_compress(&inputs[3]).len() < inputs[3].len() / 10);
+	}
+
+	#[test]
+	fn test_sync_batcher() {
+		let file_bytes = std::fs::read("src/behavior_tree/test_behaviortree.json").unwrap();
+		let parser = JsonParser::new();
+		let clock:Rc<RefCell<Box<dyn IClock>>> = Rc::new(RefCell::new(Box::new(DummyClock)));
+		let messages = Rc::new(RefCell::new(Vec::new()));
+		let sequences = SyncSequences::new();
+		let mut behavior_trees = Vec::new();
+		for id in 0..8{
+			let behavior_tree = BehaviorTree::new(id, &file_bytes, id, &Rc::downgrade(&clock), Box::new(WireEncodeRuntimeEventHandle::new(messages.clone(), sequences.clone())), Rc::downgrade(&parser));
+			behavior_tree.borrow_mut().enable().unwrap();
+			behavior_trees.push(behavior_tree);
+		}
+
+		let mut batcher = SyncBatcher::new(messages.clone(), Compression::Lz);
+		let mut mirror = SyncMirror::new();
+		let mut expected_mirror = SyncMirror::new();
+
+		//	一帧里所有树的消息合成一个包
+		for behavior_tree in behavior_trees.iter(){
+			behavior_tree.borrow_mut().update();
+		}
+		let frame_messages = messages.borrow().clone();
+		for bytes in frame_messages.iter(){
+			expected_mirror.apply_bytes(bytes).unwrap();
+		}
+		let plain = encode_batch(&frame_messages, Compression::None);
+		let batch = batcher.flush().unwrap();
+		assert!(batcher.flush().is_none());
+
+		assert_eq!(batch.message_count, frame_messages.len());
+		assert_eq!(batch.raw_size, plain.raw_size);
+		assert!(!plain.compressed);
+		assert!(batch.compressed);
+		assert!(batch.size < plain.size);
+		assert_eq!(batch.size, batch.bytes.len());
+
+		assert_eq!(decode_batch(&plain.bytes).unwrap(), frame_messages);
+		for bytes in decode_batch(&batch.bytes).unwrap(){
+			mirror.apply_bytes(&bytes).unwrap();
+		}
+		for id in 0..8{
+			assert!(mirror.tree(id).is_some());
+			assert!(mirror.tree(id) == expected_mirror.tree(id));
+		}
+
+		let stats = batcher.stats();
+		assert_eq!(stats.batches, 1);
+		assert_eq!(stats.messages, frame_messages.len() as u64);
+		assert_eq!(stats.bytes, batch.size as u64);
+	}
+}
@@ -0,0 +1,294 @@
+use std::{rc::Rc, cell::RefCell, time::{Duration, Instant}};
+use serde_json::json;
+
+use super::interface::{IBehaviorTree, IClock, IParser, TaskAddData};
+use super::json_parser::JsonParser;
+use super::registry::TaskRegistry;
+use super::compiled::{compile_json, CompiledParser};
+use super::runtime::BehaviorTree;
+use super::wire::{FullStateCollector, SyncSequences, WireEncodeRuntimeEventHandle};
+
+//	生成的测试树的形状，参数是规模
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum BenchTreeShape{
+	//	每层一个条件加一个子Sequence，最底层是一直Running的同步动画
+	DeepSequence(usize),
+	//	一个Parallel下面挂很多一直Running的action，每个子任务一个执行栈
+	WideParallel(usize),
+	//	Parallel下面很多abortType为Both的Sequence，每帧都要重新评估条件
+	ConditionalAborts(usize),
+}
+
+impl BenchTreeShape{
+	pub fn name(&self)->String{
+		match self{
+			BenchTreeShape::DeepSequence(depth) => format!("deep_sequence({})", depth),
+			BenchTreeShape::WideParallel(width) => format!("wide_parallel({})", width),
+			BenchTreeShape::ConditionalAborts(count) => format!("conditional_aborts({})", count),
+		}
+	}
+}
+
+struct TaskIdGenerator{
+	next_id:i64,
+}
+
+impl TaskIdGenerator{
+	fn next(&mut self)->i64{
+		self.next_id += 1;
+		self.next_id
+	}
+
+	fn task(&mut self, corresponding_type:&str)->serde_json::Value{
+		json!({
+			"Type":format!("BehaviorDesigner.Runtime.Tasks.{}", corresponding_type),
+			"ID":self.next(),
+			"Name":corresponding_type,
+			"Instant":true,
+		})
+	}
+
+	fn play_ani(&mut self)->serde_json::Value{
+		let mut task = self.task("PlayAniForSync");
+		task["String,AnimationName"] = json!("run");
+		task["Boolean,isLoop"] = json!(true);
+		task
+	}
+
+	fn parent(&mut self, corresponding_type:&str, abort_type:&str, children:Vec<serde_json::Value>)->serde_json::Value{
+		let mut task = self.task(corresponding_type);
+		task["BehaviorDesigner.Runtime.Tasks.AbortType,abortType"] = json!(abort_type);
+		task["Children"] = json!(children);
+		task
+	}
+}
+
+//	生成JsonParser能解析的配置
+pub fn generate_tree(shape:BenchTreeShape)->Vec<u8>{
+	let mut ids = TaskIdGenerator{next_id:0};
+	let root_task = match shape{
+		BenchTreeShape::DeepSequence(depth) => {
+			let root_id = ids.next();
+			let conditionals:Vec<serde_json::Value> = (0..depth).map(|_| ids.task("Role.MainRole.NeedFollowJoystick")).collect();
+			let mut task = ids.play_ani();
+			for conditional in conditionals.into_iter().rev(){
+				task = ids.parent("Sequence", "None", vec![conditional, task]);
+			}
+			json!({
+				"Type":"BehaviorDesigner.Runtime.Tasks.Sequence",
+				"ID":root_id,
+				"Name":"Sequence",
+				"Instant":true,
+				"Children":[task],
+			})
+		},
+		BenchTreeShape::WideParallel(width) => {
+			let children = (0..width).map(|i| if i % 2 == 0 { ids.play_ani() } else { ids.task("Idle") }).collect();
+			ids.parent("Parallel", "None", children)
+		},
+		BenchTreeShape::ConditionalAborts(count) => {
+			let children = (0..count).map(|_|{
+				let conditional = ids.task("Role.MainRole.NeedFollowJoystick");
+				let idle = ids.task("Idle");
+				ids.parent("Sequence", "Both", vec![conditional, idle])
+			}).collect();
+			ids.parent("Parallel", "None", children)
+		},
+	};
+
+	json!({"RootTask":root_task}).to_string().into_bytes()
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct BenchConfig{
+	//	每种形状的规模
+	pub size:usize,
+	//	spawn/update/disable同时跑的树的数量
+	pub trees:usize,
+	//	parse/update/rebuild_sync重复的次数
+	pub iterations:usize,
+}
+
+impl Default for BenchConfig{
+	fn default()->Self{
+		Self{
+			size:32,
+			trees:100,
+			iterations:100,
+		}
+	}
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct BenchResult{
+	pub name:String,
+	pub iterations:u64,
+	pub total:Duration,
+	//	产生的同步消息字节数，不产生同步消息的为0
+	pub sync_bytes:u64,
+}
+
+impl BenchResult{
+	pub fn per_iteration(&self)->Duration{
+		if self.iterations == 0{
+			return Duration::ZERO;
+		}
+		self.total / self.iterations as u32
+	}
+}
+
+impl std::fmt::Display for BenchResult{
+	fn fmt(&self, f:&mut std::fmt::Formatter<'_>)->std::fmt::Result{
+		write!(f, "{:<48} {:>10} iters {:>12.3?}/iter", self.name, self.iterations, self.per_iteration())?;
+		if self.sync_bytes > 0{
+			write!(f, " {:>10} sync bytes/iter", self.sync_bytes / self.iterations.max(1))?;
+		}
+		Ok(())
+	}
+}
+
+struct BenchClock;
+
+impl IClock for BenchClock{
+	fn timestamp_in_mill(&self)->u64{
+		0
+	}
+}
+
+struct BenchTrees{
+	trees:Vec<Rc<RefCell<Box<dyn IBehaviorTree>>>>,
+	messages:Rc<RefCell<Vec<Vec<u8>>>>,
+	sequences:Rc<RefCell<SyncSequences>>,
+}
+
+impl BenchTrees{
+	fn new(config:&Vec<u8>, count:usize, clock:&Rc<RefCell<Box<dyn IClock>>>, parser:&Rc<RefCell<Box<dyn IParser>>>)->Self{
+		let messages = Rc::new(RefCell::new(Vec::new()));
+		let sequences = SyncSequences::new();
+		let trees = (0..count).map(|id| BehaviorTree::new(id as u64, config, id as u64, &Rc::downgrade(clock),
+			Box::new(WireEncodeRuntimeEventHandle::new(messages.clone(), sequences.clone())), Rc::downgrade(parser))).collect();
+		Self{trees, messages, sequences}
+	}
+
+	fn take_sync_bytes(&self)->u64{
+		self.messages.borrow_mut().drain(..).map(|bytes| bytes.len() as u64).sum()
+	}
+}
+
+fn measure(name:String, iterations:usize, mut f:impl FnMut()->u64)->BenchResult{
+	let start = Instant::now();
+	let mut sync_bytes = 0;
+	for _ in 0..iterations{
+		sync_bytes += f();
+	}
+	BenchResult{
+		name,
+		iterations:iterations as u64,
+		total:start.elapsed(),
+		sync_bytes,
+	}
+}
+
+//	一种形状的parse、spawn+enable、update、rebuild_sync、disable
+pub fn run_shape(shape:BenchTreeShape, config:&BenchConfig)->Result<Vec<BenchResult>, Box<dyn std::error::Error>>{
+	let tree_config = generate_tree(shape);
+	let parser = JsonParser::new();
+	let clock:Rc<RefCell<Box<dyn IClock>>> = Rc::new(RefCell::new(Box::new(BenchClock)));
+	let shape_name = shape.name();
+	let mut results = Vec::new();
+
+	parser.borrow().deserialize(&tree_config, &mut TaskAddData::new())?;
+	results.push(measure(format!("parse/{}", shape_name), config.iterations, ||{
+		let _ = parser.borrow().deserialize(&tree_config, &mut TaskAddData::new());
+		0
+	}));
+
+	//	同样的树编译成二进制格式以后的加载时间
+	let registry = TaskRegistry::new();
+	let compiled_config = compile_json(&tree_config, &*registry.read().map_err(|_| "task registry lock poisoned")?)?;
+	let compiled_parser = CompiledParser::from_registry(registry);
+	compiled_parser.deserialize(&compiled_config, &mut TaskAddData::new())?;
+	results.push(measure(format!("parse_compiled/{}", shape_name), config.iterations, ||{
+		let _ = compiled_parser.deserialize(&compiled_config, &mut TaskAddData::new());
+		0
+	}));
+
+	let start = Instant::now();
+	let bench_trees = BenchTrees::new(&tree_config, config.trees, &clock, &parser);
+	for tree in bench_trees.trees.iter(){
+		tree.borrow_mut().enable()?;
+	}
+	results.push(BenchResult{
+		name:format!("new+enable/{}", shape_name),
+		iterations:config.trees as u64,
+		total:start.elapsed(),
+		sync_bytes:bench_trees.take_sync_bytes(),
+	});
+
+	//	第一帧会创建执行栈，不算在稳定状态里
+	for tree in bench_trees.trees.iter(){
+		tree.borrow_mut().update();
+	}
+	bench_trees.take_sync_bytes();
+	results.push(measure(format!("update/{} x{}", shape_name, config.trees), config.iterations, ||{
+		for tree in bench_trees.trees.iter(){
+			tree.borrow_mut().update();
+		}
+		bench_trees.take_sync_bytes()
+	}));
+
+	results.push(measure(format!("rebuild_sync/{} x{}", shape_name, config.trees), config.iterations, ||{
+		let sequences = bench_trees.sequences.borrow();
+		bench_trees.trees.iter().map(|tree| FullStateCollector::collect(tree.borrow().as_ref(), &sequences).encode().len() as u64).sum()
+	}));
+
+	let start = Instant::now();
+	for tree in bench_trees.trees.iter(){
+		tree.borrow_mut().disable()?;
+	}
+	results.push(BenchResult{
+		name:format!("disable/{}", shape_name),
+		iterations:config.trees as u64,
+		total:start.elapsed(),
+		sync_bytes:bench_trees.take_sync_bytes(),
+	});
+
+	Ok(results)
+}
+
+pub fn run_benchmarks(config:&BenchConfig)->Result<Vec<BenchResult>, Box<dyn std::error::Error>>{
+	let mut results = Vec::new();
+	for shape in [BenchTreeShape::DeepSequence(config.size), BenchTreeShape::WideParallel(config.size), BenchTreeShape::ConditionalAborts(config.size)]{
+		results.extend(run_shape(shape, config)?);
+	}
+	Ok(results)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_run_benchmarks() {
+		let config = BenchConfig{size:4, trees:2, iterations:2};
+		let results = run_benchmarks(&config).unwrap();
+		assert_eq!(results.len(), 18);
+		assert!(results.iter().all(|result| result.iterations > 0));
+		assert!(results.iter().find(|result| result.name == "rebuild_sync/wide_parallel(4) x2").unwrap().sync_bytes > 0);
+		assert!(results[0].to_string().starts_with("parse/deep_sequence(4)"));
+		assert!(results[1].to_string().starts_with("parse_compiled/deep_sequence(4)"));
+
+		//	生成的树跑起来都是Running，执行栈的数量跟形状对应
+		let parser = JsonParser::new();
+		let clock:Rc<RefCell<Box<dyn IClock>>> = Rc::new(RefCell::new(Box::new(BenchClock)));
+		for (shape, stacks) in [(BenchTreeShape::DeepSequence(8), 1), (BenchTreeShape::WideParallel(8), 9), (BenchTreeShape::ConditionalAborts(8), 9)]{
+			let bench_trees = BenchTrees::new(&generate_tree(shape), 1, &clock, &parser);
+			let mut tree = bench_trees.trees[0].borrow_mut();
+			tree.enable().unwrap();
+			tree.update();
+			tree.update();
+			assert!(tree.is_runnning());
+			assert_eq!(tree.snapshot().unwrap().active_stack.len(), stacks);
+		}
+	}
+}
@@ -0,0 +1,310 @@
+use std::collections::HashMap;
+
+use super::consts::AbortType;
+use super::interface::{ITaskProxy, TaskAddData};
+use super::registry::{TaskConfig, TaskRegistry, TreeConfig};
+
+//	在代码里拼任务，生成的TreeConfig交给TaskRegistry，得到的任务跟JsonParser解析出来的一样
+//	没有调用id的任务按前序自动分配ID
+pub struct TaskBuilder{
+	corresponding_type:String,
+	name:String,
+	id:Option<i32>,
+	instant:Option<bool>,
+	disabled:Option<bool>,
+	abort_type:Option<AbortType>,
+	variables:HashMap<String, serde_json::Value>,
+	children:Vec<TaskBuilder>,
+}
+
+impl TaskBuilder{
+	pub fn new(corresponding_type:&str)->Self{
+		Self{
+			corresponding_type:corresponding_type.to_string(),
+			name:String::new(),
+			id:None,
+			instant:None,
+			disabled:None,
+			abort_type:None,
+			variables:HashMap::new(),
+			children:Vec::new(),
+		}
+	}
+
+	pub fn name(mut self, name:&str)->Self{
+		self.name = name.to_string();
+		self
+	}
+
+	//	需要被其它任务引用的时候手动指定，比如PerformInterruption的interruptTasks
+	pub fn id(mut self, id:i32)->Self{
+		self.id = Some(id);
+		self
+	}
+
+	pub fn instant(mut self, instant:bool)->Self{
+		self.instant = Some(instant);
+		self
+	}
+
+	pub fn disabled(mut self, disabled:bool)->Self{
+		self.disabled = Some(disabled);
+		self
+	}
+
+	pub fn abort(mut self, abort_type:AbortType)->Self{
+		self.abort_type = Some(abort_type);
+		self
+	}
+
+	//	key跟json配置里一样带类型前缀，比如"String,AnimationName"
+	pub fn variable(mut self, key:&str, value:impl Into<serde_json::Value>)->Self{
+		self.variables.insert(key.to_string(), value.into());
+		self
+	}
+
+	pub fn child(mut self, child:TaskBuilder)->Self{
+		self.children.push(child);
+		self
+	}
+
+	pub fn children(mut self, children:impl IntoIterator<Item = TaskBuilder>)->Self{
+		self.children.extend(children);
+		self
+	}
+
+	fn into_task_config(self)->TaskConfig{
+		let mut task_config = TaskConfig::new(&self.corresponding_type, &self.name, self.id.unwrap_or(0));
+		task_config.instant = self.instant;
+		task_config.disabled = self.disabled;
+		task_config.abort_type = self.abort_type;
+		task_config.variables = self.variables;
+		task_config.children = self.children.into_iter().map(|child| child.into_task_config()).collect();
+		task_config
+	}
+}
+
+pub struct Tree{
+	root_task:TaskBuilder,
+	detached_tasks:Vec<TaskBuilder>,
+}
+
+impl From<TaskBuilder> for Tree{
+	fn from(root_task:TaskBuilder)->Self{
+		Self::new(root_task)
+	}
+}
+
+impl Tree{
+	pub fn new(root_task:TaskBuilder)->Self{
+		Self{
+			root_task,
+			detached_tasks:Vec::new(),
+		}
+	}
+
+	pub fn detached(mut self, task:TaskBuilder)->Self{
+		self.detached_tasks.push(task);
+		self
+	}
+
+	pub fn into_config(self)->Result<TreeConfig, Box<dyn std::error::Error>>{
+		let mut tree_config = TreeConfig{
+			root_task:self.root_task.into_task_config(),
+			detached_tasks:self.detached_tasks.into_iter().map(|task| task.into_task_config()).collect(),
+		};
+		tree_config.assign_task_ids()?;
+		Ok(tree_config)
+	}
+
+	//	生成根任务，交给BehaviorTree::with_root
+	pub fn build(self, registry:&TaskRegistry)->Result<Box<dyn ITaskProxy>, Box<dyn std::error::Error>>{
+		registry.build(self.into_config()?, &mut TaskAddData::new())
+	}
+
+	fn task(corresponding_type:&str)->TaskBuilder{
+		TaskBuilder::new(&format!("BehaviorDesigner.Runtime.Tasks.{}", corresponding_type))
+	}
+
+	pub fn sequence()->TaskBuilder{
+		Self::task("Sequence")
+	}
+
+	pub fn selector()->TaskBuilder{
+		Self::task("Selector")
+	}
+
+	pub fn parallel()->TaskBuilder{
+		Self::task("Parallel")
+	}
+
+	pub fn parallel_selector()->TaskBuilder{
+		Self::task("ParallelSelector")
+	}
+
+	pub fn if_else()->TaskBuilder{
+		Self::task("If")
+	}
+
+	pub fn idle()->TaskBuilder{
+		Self::task("Idle")
+	}
+
+	pub fn play_ani_for_sync(animation_name:&str, is_loop:bool)->TaskBuilder{
+		Self::task("PlayAniForSync").variable("String,AnimationName", animation_name).variable("Boolean,isLoop", is_loop)
+	}
+
+	pub fn role_follow_joystick()->TaskBuilder{
+		Self::task("RoleFollowJoystick")
+	}
+
+	pub fn perform_interruption(interrupt_task_ids:&[i32], interrupt_success:bool)->TaskBuilder{
+		Self::task("PerformInterruption")
+			.variable("BehaviorDesigner.Runtime.Tasks.Interrupt[],interruptTasks", interrupt_task_ids.to_vec())
+			.variable("Boolean,interruptSuccess", interrupt_success)
+	}
+
+	pub fn return_failure()->TaskBuilder{
+		Self::task("ReturnFailure")
+	}
+
+	pub fn return_success()->TaskBuilder{
+		Self::task("ReturnSuccess")
+	}
+
+	pub fn until_failure()->TaskBuilder{
+		Self::task("UntilFailure")
+	}
+
+	pub fn until_success()->TaskBuilder{
+		Self::task("UntilSuccess")
+	}
+
+	pub fn until_forever()->TaskBuilder{
+		Self::task("UntilForever")
+	}
+
+	pub fn interrupt()->TaskBuilder{
+		Self::task("Interrupt")
+	}
+
+	pub fn need_follow_joystick()->TaskBuilder{
+		Self::task("Role.MainRole.NeedFollowJoystick")
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::{rc::Rc, cell::RefCell};
+	use serde_json::json;
+	use AbortType::{Both, LowerPriority};
+	use super::super::consts::TaskStatus;
+	use super::super::interface::IClock;
+	use super::super::json_parser::JsonParser;
+	use super::super::runtime::BehaviorTree;
+	use super::super::wire::{SyncSequences, WireEncodeRuntimeEventHandle};
+
+	struct DummyClock;
+	impl IClock for DummyClock {
+		fn timestamp_in_mill(&self) -> u64 {
+			0
+		}
+	}
+
+	fn build_tree()->Tree{
+		Tree::new(Tree::selector().abort(LowerPriority)
+			.child(Tree::sequence().abort(Both)
+				.child(Tree::need_follow_joystick())
+				.child(Tree::role_follow_joystick()))
+			.child(Tree::play_ani_for_sync("run", true).name("Run").instant(true))
+			.child(Tree::perform_interruption(&[10], false)))
+			.detached(Tree::interrupt().id(10).child(Tree::idle()))
+	}
+
+	fn json_tree()->Vec<u8>{
+		json!({
+			"RootTask": {
+				"Type": "BehaviorDesigner.Runtime.Tasks.Selector",
+				"ID": 1,
+				"BehaviorDesigner.Runtime.Tasks.AbortType,abortType": "LowerPriority",
+				"Children": [
+					{
+						"Type": "BehaviorDesigner.Runtime.Tasks.Sequence",
+						"ID": 2,
+						"BehaviorDesigner.Runtime.Tasks.AbortType,abortType": "Both",
+						"Children": [
+							{"Type": "BehaviorDesigner.Runtime.Tasks.Role.MainRole.NeedFollowJoystick", "ID": 3},
+							{"Type": "BehaviorDesigner.Runtime.Tasks.RoleFollowJoystick", "ID": 4}
+						]
+					},
+					{
+						"Type": "BehaviorDesigner.Runtime.Tasks.PlayAniForSync",
+						"Name": "Run",
+						"ID": 5,
+						"Instant": true,
+						"String,AnimationName": "run",
+						"Boolean,isLoop": true
+					},
+					{
+						"Type": "BehaviorDesigner.Runtime.Tasks.PerformInterruption",
+						"ID": 6,
+						"Boolean,interruptSuccess": false,
+						"BehaviorDesigner.Runtime.Tasks.Interrupt[],interruptTasks": [10]
+					}
+				]
+			},
+			"DetachedTasksConfigs": [{
+				"Type": "BehaviorDesigner.Runtime.Tasks.Interrupt",
+				"ID": 10,
+				"Children": [{"Type": "BehaviorDesigner.Runtime.Tasks.Idle", "ID": 7}]
+			}]
+		}).to_string().into_bytes()
+	}
+
+	#[test]
+	fn test_builder_matches_json_parser() {
+		assert_eq!(build_tree().into_config().unwrap(), JsonParser::parse_tree_config(&json_tree()).unwrap());
+
+		//	用生成好的根任务启动的树跟解析json的树执行起来一样
+		let registry = TaskRegistry::new();
+		let parser = JsonParser::with_registry(registry.clone());
+		let clock:Rc<RefCell<Box<dyn IClock>>> = Rc::new(RefCell::new(Box::new(DummyClock)));
+		let json_messages = Rc::new(RefCell::new(Vec::new()));
+		let json_tree_bytes = json_tree();
+		let json_behavior_tree = BehaviorTree::new(1, &json_tree_bytes, 1, &Rc::downgrade(&clock), Box::new(WireEncodeRuntimeEventHandle::new(json_messages.clone(), SyncSequences::new())), Rc::downgrade(&parser));
+		let built_messages = Rc::new(RefCell::new(Vec::new()));
+		let root_task = build_tree().build(&registry.read().unwrap()).unwrap();
+		let built_behavior_tree = BehaviorTree::with_root(1, root_task, 1, &Rc::downgrade(&clock), Box::new(WireEncodeRuntimeEventHandle::new(built_messages.clone(), SyncSequences::new())));
+
+		for behavior_tree in [&json_behavior_tree, &built_behavior_tree]{
+			let mut behavior_tree = behavior_tree.borrow_mut();
+			behavior_tree.enable().unwrap();
+			behavior_tree.update();
+			behavior_tree.interrupt(4, TaskStatus::Failure, true).unwrap();
+			behavior_tree.update();
+			behavior_tree.disable().unwrap();
+			//	根任务只在第一次enable的时候用掉，之后重复enable不需要再生成
+			behavior_tree.enable().unwrap();
+			behavior_tree.update();
+		}
+		assert_eq!(serde_json::to_vec(&json_behavior_tree.borrow().snapshot().unwrap()).unwrap(), serde_json::to_vec(&built_behavior_tree.borrow().snapshot().unwrap()).unwrap());
+		assert_eq!(*json_messages.borrow(), *built_messages.borrow());
+		assert!(!built_messages.borrow().is_empty());
+	}
+
+	#[test]
+	fn test_builder_ids_and_errors() {
+		let tree_config = Tree::from(Tree::sequence().child(Tree::idle().id(2)).child(Tree::idle())).into_config().unwrap();
+		assert_eq!(tree_config.root_task.id, 1);
+		assert_eq!(tree_config.root_task.children[1].id, 3);
+
+		let registry = TaskRegistry::new();
+		let registry = registry.read().unwrap();
+		assert!(Tree::from(Tree::sequence().child(Tree::idle().id(2)).child(Tree::idle().id(2))).into_config().is_err());
+		assert!(Tree::from(TaskBuilder::new("Unknown")).build(&registry).is_err());
+		let root_task = Tree::from(Tree::until_forever().child(Tree::idle())).build(&registry).unwrap();
+		assert_eq!(root_task.corresponding_type(), "BehaviorDesigner.Runtime.Tasks.UntilForever");
+		assert_eq!(root_task.children().len(), 1);
+	}
+}
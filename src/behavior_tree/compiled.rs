@@ -0,0 +1,630 @@
+use std::{rc::Rc, cell::RefCell, collections::HashMap, sync::{Arc, RwLock}};
+
+use super::consts::AbortType;
+use super::interface::{IParser, ITaskProxy, TaskAddData, TaskIndexTables};
+use super::json_parser::JsonParser;
+use super::registry::{TaskConfig, TaskKind, TaskRegistry, TreeConfig};
+use super::wire::{WireReader, WireWriter};
+
+//	编译后的格式：魔数 版本(1字节) 字符串表 根任务 游离任务个数 游离任务... 索引表
+//	字符串表是 个数 + (长度 + utf8)，类型、名字、变量名和字符串变量都只存表里的下标
+//	任务按前序排列，跟运行时的任务索引顺序一致：类型 名字 ID 标记 [abortType] 变量个数 (变量名 值)... 子任务个数 子任务...
+//	变量存成带类型的二进制值，加载的时候不用解析json文本，直接生成serde_json::Value移交给任务的创建函数
+//	索引表是BehaviorTree初始化用的父子关系表，编译的时候按注册表里的任务类型算好，加载以后树只检查不再计算
+//	索引表：行数 每行(父任务 父组合任务 abortType 在父任务里的序号 子任务个数 子任务... 条件子任务个数 条件子任务...)，第0行是EntryRoot
+pub const COMPILED_TREE_MAGIC:&[u8; 4] = b"BTC\0";
+pub const COMPILED_TREE_VERSION:u8 = 2;
+//	任务和变量各自最多嵌套的层数，跟serde_json的默认限制一样，损坏的文件返回错误而不是栈溢出
+pub const MAX_COMPILED_TREE_DEPTH:usize = 128;
+
+const FLAG_HAS_INSTANT:u8 = 1;
+const FLAG_INSTANT:u8 = 1 << 1;
+const FLAG_HAS_DISABLED:u8 = 1 << 2;
+const FLAG_DISABLED:u8 = 1 << 3;
+const FLAG_HAS_ABORT_TYPE:u8 = 1 << 4;
+
+const VALUE_NULL:u8 = 0;
+const VALUE_FALSE:u8 = 1;
+const VALUE_TRUE:u8 = 2;
+const VALUE_U64:u8 = 3;
+const VALUE_I64:u8 = 4;
+const VALUE_F64:u8 = 5;
+const VALUE_STRING:u8 = 6;
+const VALUE_ARRAY:u8 = 7;
+const VALUE_OBJECT:u8 = 8;
+
+#[derive(Default)]
+struct StringTable{
+	strings:Vec<String>,
+	indexes:HashMap<String, u64>,
+}
+
+impl StringTable{
+	fn intern(&mut self, value:&str)->u64{
+		if let Some(index) = self.indexes.get(value){
+			return *index;
+		}
+		let index = self.strings.len() as u64;
+		self.strings.push(value.to_string());
+		self.indexes.insert(value.to_string(), index);
+		index
+	}
+}
+
+fn abort_type_to_u8(abort_type:AbortType)->u8{
+	match abort_type{
+		AbortType::None => 0,
+		AbortType::Self_ => 1,
+		AbortType::LowerPriority => 2,
+		AbortType::Both => 3,
+	}
+}
+
+fn abort_type_from_u8(value:u8)->Result<AbortType, Box<dyn std::error::Error>>{
+	match value{
+		0 => Ok(AbortType::None),
+		1 => Ok(AbortType::Self_),
+		2 => Ok(AbortType::LowerPriority),
+		3 => Ok(AbortType::Both),
+		_ => Err(format!("unknown abort type {}", value).into()),
+	}
+}
+
+fn write_value(writer:&mut WireWriter, strings:&mut StringTable, value:&serde_json::Value){
+	match value{
+		serde_json::Value::Null => writer.write_u8(VALUE_NULL),
+		serde_json::Value::Bool(false) => writer.write_u8(VALUE_FALSE),
+		serde_json::Value::Bool(true) => writer.write_u8(VALUE_TRUE),
+		serde_json::Value::Number(number) => {
+			if let Some(value) = number.as_u64(){
+				writer.write_u8(VALUE_U64);
+				writer.write_varint(value);
+			}else if let Some(value) = number.as_i64(){
+				writer.write_u8(VALUE_I64);
+				writer.write_zigzag(value);
+			}else{
+				writer.write_u8(VALUE_F64);
+				for byte in number.as_f64().unwrap_or_default().to_le_bytes(){
+					writer.write_u8(byte);
+				}
+			}
+		},
+		serde_json::Value::String(value) => {
+			writer.write_u8(VALUE_STRING);
+			writer.write_varint(strings.intern(value));
+		},
+		serde_json::Value::Array(values) => {
+			writer.write_u8(VALUE_ARRAY);
+			writer.write_varint(values.len() as u64);
+			for value in values.iter(){
+				write_value(writer, strings, value);
+			}
+		},
+		serde_json::Value::Object(values) => {
+			writer.write_u8(VALUE_OBJECT);
+			writer.write_varint(values.len() as u64);
+			for (key, value) in values.iter(){
+				writer.write_varint(strings.intern(key));
+				write_value(writer, strings, value);
+			}
+		},
+	}
+}
+
+fn write_task(writer:&mut WireWriter, strings:&mut StringTable, task_config:&TaskConfig){
+	writer.write_varint(strings.intern(&task_config.corresponding_type));
+	writer.write_varint(strings.intern(&task_config.name));
+	writer.write_zigzag(task_config.id as i64);
+
+	let mut flags = 0;
+	if let Some(instant) = task_config.instant{
+		flags |= FLAG_HAS_INSTANT;
+		if instant{
+			flags |= FLAG_INSTANT;
+		}
+	}
+	if let Some(disabled) = task_config.disabled{
+		flags |= FLAG_HAS_DISABLED;
+		if disabled{
+			flags |= FLAG_DISABLED;
+		}
+	}
+	if task_config.abort_type.is_some(){
+		flags |= FLAG_HAS_ABORT_TYPE;
+	}
+	writer.write_u8(flags);
+	if let Some(abort_type) = task_config.abort_type{
+		writer.write_u8(abort_type_to_u8(abort_type));
+	}
+
+	//	变量按名字排序，同样的配置编译出来的字节一样
+	let mut variables:Vec<(&String, &serde_json::Value)> = task_config.variables.iter().collect();
+	variables.sort_by_key(|(key, _)| *key);
+	writer.write_varint(variables.len() as u64);
+	for (key, value) in variables.into_iter(){
+		writer.write_varint(strings.intern(key));
+		write_value(writer, strings, value);
+	}
+
+	writer.write_varint(task_config.children.len() as u64);
+	for child in task_config.children.iter(){
+		write_task(writer, strings, child);
+	}
+}
+
+//	跟BehaviorTree::parse_child_task一样按前序算索引表，不是父任务的任务的子任务不在树上
+fn add_index_rows(index_tables:&mut TaskIndexTables, registry:&TaskRegistry, task_config:&TaskConfig, parent_index:i32, mut parent_composite_index:i32)->Result<(), Box<dyn std::error::Error>>{
+	let task_kind = registry.task_kind(&task_config.corresponding_type)
+		.ok_or_else(|| format!("task type {} is not registered", task_config.corresponding_type))?;
+	let index = index_tables.push(parent_index, parent_composite_index, task_config.abort_type.unwrap_or(AbortType::None));
+
+	if task_kind.is_parent_task(){
+		if task_kind == TaskKind::Composite{
+			parent_composite_index = index;
+		}
+		for child in task_config.children.iter(){
+			add_index_rows(index_tables, registry, child, index, parent_composite_index)?;
+		}
+	}else if task_kind == TaskKind::Conditional && parent_composite_index != -1{
+		index_tables.child_conditional_index[parent_composite_index as usize].push(index);
+	}
+	Ok(())
+}
+
+fn write_indexes(writer:&mut WireWriter, indexes:&[i32]){
+	writer.write_varint(indexes.len() as u64);
+	for index in indexes.iter(){
+		writer.write_zigzag(*index as i64);
+	}
+}
+
+fn write_index_tables(writer:&mut WireWriter, index_tables:&TaskIndexTables){
+	writer.write_varint(index_tables.len() as u64);
+	for row in 0..index_tables.len(){
+		writer.write_zigzag(index_tables.parent_index[row] as i64);
+		writer.write_zigzag(index_tables.parent_composite_index[row] as i64);
+		writer.write_u8(abort_type_to_u8(index_tables.composite_abort_task[row]));
+		writer.write_zigzag(index_tables.relative_child_index[row] as i64);
+		write_indexes(writer, &index_tables.children_index[row]);
+		write_indexes(writer, &index_tables.child_conditional_index[row]);
+	}
+}
+
+//	任务类型从注册表里查，自定义的任务要先注册再编译
+pub fn encode_tree_config(tree_config:&TreeConfig, registry:&TaskRegistry)->Result<Vec<u8>, Box<dyn std::error::Error>>{
+	let mut index_tables = TaskIndexTables::new();
+	add_index_rows(&mut index_tables, registry, &tree_config.root_task, 0, -1)?;
+
+	let mut strings = StringTable::default();
+	let mut body = WireWriter::new();
+	write_task(&mut body, &mut strings, &tree_config.root_task);
+	body.write_varint(tree_config.detached_tasks.len() as u64);
+	for detached_task in tree_config.detached_tasks.iter(){
+		write_task(&mut body, &mut strings, detached_task);
+	}
+	write_index_tables(&mut body, &index_tables);
+
+	let mut writer = WireWriter::new();
+	for byte in COMPILED_TREE_MAGIC.iter(){
+		writer.write_u8(*byte);
+	}
+	writer.write_u8(COMPILED_TREE_VERSION);
+	writer.write_varint(strings.strings.len() as u64);
+	for value in strings.strings.iter(){
+		writer.write_bytes(value.as_bytes());
+	}
+
+	let mut bytes = writer.into_bytes();
+	bytes.extend_from_slice(&body.into_bytes());
+	Ok(bytes)
+}
+
+//	json配置编译成二进制格式
+pub fn compile_json(config:&Vec<u8>, registry:&TaskRegistry)->Result<Vec<u8>, Box<dyn std::error::Error>>{
+	encode_tree_config(&JsonParser::parse_tree_config(config)?, registry)
+}
+
+pub fn is_compiled_tree(config:&[u8])->bool{
+	config.starts_with(COMPILED_TREE_MAGIC)
+}
+
+struct CompiledTreeReader<'a>{
+	reader:WireReader<'a>,
+	strings:Vec<String>,
+}
+
+impl<'a> CompiledTreeReader<'a>{
+	fn string(&mut self)->Result<String, Box<dyn std::error::Error>>{
+		let index = self.reader.read_varint()? as usize;
+		self.strings.get(index).cloned().ok_or_else(|| format!("string index {} out of range", index).into())
+	}
+
+	fn value(&mut self, depth:usize)->Result<serde_json::Value, Box<dyn std::error::Error>>{
+		if depth > MAX_COMPILED_TREE_DEPTH{
+			return Err("compiled behavior tree value nested too deep".into());
+		}
+		let value = match self.reader.read_u8()?{
+			VALUE_NULL => serde_json::Value::Null,
+			VALUE_FALSE => serde_json::Value::Bool(false),
+			VALUE_TRUE => serde_json::Value::Bool(true),
+			VALUE_U64 => serde_json::Value::from(self.reader.read_varint()?),
+			VALUE_I64 => serde_json::Value::from(self.reader.read_zigzag()?),
+			VALUE_F64 => {
+				let mut bytes = [0u8; 8];
+				for byte in bytes.iter_mut(){
+					*byte = self.reader.read_u8()?;
+				}
+				serde_json::Number::from_f64(f64::from_le_bytes(bytes)).map(serde_json::Value::Number).ok_or("invalid float value")?
+			},
+			VALUE_STRING => serde_json::Value::String(self.string()?),
+			VALUE_ARRAY => {
+				let count = self.reader.read_varint()? as usize;
+				let mut values = Vec::new();
+				for _ in 0..count{
+					values.push(self.value(depth + 1)?);
+				}
+				serde_json::Value::Array(values)
+			},
+			VALUE_OBJECT => {
+				let count = self.reader.read_varint()? as usize;
+				let mut values = serde_json::Map::new();
+				for _ in 0..count{
+					let key = self.string()?;
+					values.insert(key, self.value(depth + 1)?);
+				}
+				serde_json::Value::Object(values)
+			},
+			kind => return Err(format!("unknown value kind {}", kind).into()),
+		};
+		Ok(value)
+	}
+
+	fn task(&mut self, depth:usize)->Result<TaskConfig, Box<dyn std::error::Error>>{
+		if depth > MAX_COMPILED_TREE_DEPTH{
+			return Err("compiled behavior tree task nested too deep".into());
+		}
+		let corresponding_type = self.string()?;
+		let name = self.string()?;
+		let id = i32::try_from(self.reader.read_zigzag()?).map_err(|_| "task id out of range")?;
+		let mut task_config = TaskConfig::new(&corresponding_type, &name, id);
+
+		let flags = self.reader.read_u8()?;
+		if flags & FLAG_HAS_INSTANT != 0{
+			task_config.instant = Some(flags & FLAG_INSTANT != 0);
+		}
+		if flags & FLAG_HAS_DISABLED != 0{
+			task_config.disabled = Some(flags & FLAG_DISABLED != 0);
+		}
+		if flags & FLAG_HAS_ABORT_TYPE != 0{
+			task_config.abort_type = Some(abort_type_from_u8(self.reader.read_u8()?)?);
+		}
+
+		let variable_count = self.reader.read_varint()? as usize;
+		for _ in 0..variable_count{
+			let key = self.string()?;
+			let value = self.value(0)?;
+			task_config.variables.insert(key, value);
+		}
+
+		let child_count = self.reader.read_varint()? as usize;
+		for _ in 0..child_count{
+			task_config.children.push(self.task(depth + 1)?);
+		}
+		Ok(task_config)
+	}
+
+	fn index(&mut self)->Result<i32, Box<dyn std::error::Error>>{
+		Ok(i32::try_from(self.reader.read_zigzag()?).map_err(|_| "task index out of range")?)
+	}
+
+	fn indexes(&mut self)->Result<Vec<i32>, Box<dyn std::error::Error>>{
+		let count = self.reader.read_varint()? as usize;
+		let mut indexes = Vec::new();
+		for _ in 0..count{
+			indexes.push(self.index()?);
+		}
+		Ok(indexes)
+	}
+
+	fn index_tables(&mut self)->Result<TaskIndexTables, Box<dyn std::error::Error>>{
+		let mut index_tables = TaskIndexTables{
+			parent_index:Vec::new(),
+			children_index:Vec::new(),
+			relative_child_index:Vec::new(),
+			parent_composite_index:Vec::new(),
+			composite_abort_task:Vec::new(),
+			child_conditional_index:Vec::new(),
+		};
+		let row_count = self.reader.read_varint()? as usize;
+		for _ in 0..row_count{
+			index_tables.parent_index.push(self.index()?);
+			index_tables.parent_composite_index.push(self.index()?);
+			index_tables.composite_abort_task.push(abort_type_from_u8(self.reader.read_u8()?)?);
+			index_tables.relative_child_index.push(self.index()?);
+			index_tables.children_index.push(self.indexes()?);
+			index_tables.child_conditional_index.push(self.indexes()?);
+		}
+		Ok(index_tables)
+	}
+}
+
+//	解码出来的任务配置和编译时算好的索引表
+#[derive(Clone, PartialEq, Debug)]
+pub struct CompiledTree{
+	pub tree_config:TreeConfig,
+	pub index_tables:TaskIndexTables,
+}
+
+pub fn decode_tree_config(bytes:&[u8])->Result<TreeConfig, Box<dyn std::error::Error>>{
+	Ok(decode_compiled_tree(bytes)?.tree_config)
+}
+
+pub fn decode_compiled_tree(bytes:&[u8])->Result<CompiledTree, Box<dyn std::error::Error>>{
+	if !is_compiled_tree(bytes){
+		return Err("not a compiled behavior tree".into());
+	}
+
+	let mut reader = WireReader::new(&bytes[COMPILED_TREE_MAGIC.len()..]);
+	let version = reader.read_u8()?;
+	if version != COMPILED_TREE_VERSION{
+		return Err(format!("unsupported compiled behavior tree version: {}", version).into());
+	}
+
+	let string_count = reader.read_varint()? as usize;
+	let mut strings = Vec::new();
+	for _ in 0..string_count{
+		strings.push(std::str::from_utf8(reader.read_bytes()?)?.to_string());
+	}
+
+	let mut reader = CompiledTreeReader{reader, strings};
+	let root_task = reader.task(0)?;
+	let detached_count = reader.reader.read_varint()? as usize;
+	let mut detached_tasks = Vec::new();
+	for _ in 0..detached_count{
+		detached_tasks.push(reader.task(0)?);
+	}
+	let index_tables = reader.index_tables()?;
+	if !reader.reader.is_empty(){
+		return Err("trailing bytes after compiled behavior tree".into());
+	}
+
+	Ok(CompiledTree{tree_config:TreeConfig{root_task, detached_tasks}, index_tables})
+}
+
+//	加载编译后的二进制格式，跟JsonParser共用任务注册表
+pub struct CompiledParser{
+	registry:Arc<RwLock<TaskRegistry>>,
+}
+
+impl CompiledParser{
+	pub fn new()->Self{
+		Self::from_registry(TaskRegistry::new())
+	}
+
+	pub fn from_registry(registry:Arc<RwLock<TaskRegistry>>)->Self{
+		Self{registry}
+	}
+
+	pub fn with_registry(registry:Arc<RwLock<TaskRegistry>>)->Rc<RefCell<Box<dyn IParser>>>{
+		Rc::new(RefCell::new(Box::new(Self::from_registry(registry))))
+	}
+}
+
+impl Default for CompiledParser{
+	fn default()->Self{
+		Self::new()
+	}
+}
+
+impl IParser for CompiledParser{
+	fn deserialize(&self, config:&Vec<u8>, task_add_data:&mut TaskAddData)->Result<Box<dyn ITaskProxy>, Box<dyn std::error::Error>>{
+		let compiled_tree = decode_compiled_tree(config)?;
+		let root_task = self.registry.read().map_err(|_| "task registry lock poisoned")?.build(compiled_tree.tree_config, task_add_data)?;
+		task_add_data.index_tables = Some(compiled_tree.index_tables);
+		Ok(root_task)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use serde_json::json;
+	use super::super::consts::TaskStatus;
+	use super::super::interface::{IBehaviorTree, IClock, IComposite, IDecorator, IRuntimeEventHandle};
+	use super::super::runtime::{BehaviorTree, SendBehaviorTree};
+	use super::super::composite::sequence::Sequence;
+	use super::super::decorator::until_forever::UntilForever;
+	use super::super::wire::{SyncSequences, WireEncodeRuntimeEventHandle};
+
+	struct DummyClock;
+	impl IClock for DummyClock {
+		fn timestamp_in_mill(&self) -> u64 {
+			0
+		}
+	}
+
+	struct EmptyRuntimeEventHandle;
+	impl IRuntimeEventHandle for EmptyRuntimeEventHandle {}
+
+	//	用解析器初始化一棵树，返回树算出来或者检查过的索引表
+	fn initialized_index_tables(config:&Vec<u8>, parser:Arc<dyn IParser + Send + Sync>)->Result<TaskIndexTables, Box<dyn std::error::Error>> {
+		let clock:Arc<dyn IClock + Send + Sync> = Arc::new(DummyClock);
+		let mut behavior_tree = SendBehaviorTree::from_config(1, config, 1, clock, Box::new(EmptyRuntimeEventHandle), parser);
+		behavior_tree.enable()?;
+		Ok(behavior_tree.index_tables().clone())
+	}
+
+	#[test]
+	fn test_compiled_tree_round_trip() {
+		let file_bytes = std::fs::read("src/behavior_tree/test_behaviortree.json").unwrap();
+		let registry = TaskRegistry::new();
+		let compiled = compile_json(&file_bytes, &registry.read().unwrap()).unwrap();
+		assert!(is_compiled_tree(&compiled));
+		assert!(compiled.len() < file_bytes.len());
+		assert_eq!(decode_tree_config(&compiled).unwrap(), JsonParser::parse_tree_config(&file_bytes).unwrap());
+		assert_eq!(compile_json(&file_bytes, &registry.read().unwrap()).unwrap(), compiled);
+
+		//	编译时存的索引表跟json加载时树自己算的一样，加载编译格式的树用的就是存的表
+		let json_index_tables = initialized_index_tables(&file_bytes, Arc::new(JsonParser::from_registry(registry.clone()))).unwrap();
+		let compiled_tree = decode_compiled_tree(&compiled).unwrap();
+		assert_eq!(compiled_tree.index_tables, json_index_tables);
+		assert_eq!(initialized_index_tables(&compiled, Arc::new(CompiledParser::from_registry(registry.clone()))).unwrap(), json_index_tables);
+		assert!(json_index_tables.child_conditional_index.iter().any(|conditionals| !conditionals.is_empty()));
+
+		//	两种格式生成的树执行起来完全一样
+		let json_parser = JsonParser::with_registry(registry.clone());
+		let compiled_parser = CompiledParser::with_registry(registry);
+		let clock:Rc<RefCell<Box<dyn IClock>>> = Rc::new(RefCell::new(Box::new(DummyClock)));
+		let mut runs = Vec::new();
+		for (config, parser) in [(&file_bytes, &json_parser), (&compiled, &compiled_parser)]{
+			let messages = Rc::new(RefCell::new(Vec::new()));
+			let behavior_tree = BehaviorTree::new(1, config, 1, &Rc::downgrade(&clock), Box::new(WireEncodeRuntimeEventHandle::new(messages.clone(), SyncSequences::new())), Rc::downgrade(parser));
+			let mut behavior_tree = behavior_tree.borrow_mut();
+			behavior_tree.enable().unwrap();
+			behavior_tree.update();
+			behavior_tree.update();
+			let snapshot = serde_json::to_vec(&behavior_tree.snapshot().unwrap()).unwrap();
+			behavior_tree.interrupt(4, TaskStatus::Failure, true).unwrap();
+			behavior_tree.update();
+			behavior_tree.disable().unwrap();
+			runs.push((snapshot, messages.borrow().clone()));
+		}
+		assert_eq!(runs[0], runs[1]);
+		assert!(!runs[0].1.is_empty());
+	}
+
+	#[test]
+	fn test_compiled_tree_values_and_errors() {
+		let tree_json = json!({
+			"RootTask": {
+				"Type": "BehaviorDesigner.Runtime.Tasks.Sequence",
+				"ID": 1,
+				"Children": [{
+					"Type": "BehaviorDesigner.Runtime.Tasks.PlayAniForSync",
+					"ID": 2,
+					"Disabled": true,
+					"String,AnimationName": "run",
+					"Data": {"Name": "a", "Age": -3, "Time": 1.5, "Big": u64::MAX, "List": [null, true, false]}
+				}]
+			},
+			"DetachedTasksConfigs": [{"Type": "BehaviorDesigner.Runtime.Tasks.Idle", "ID": 3}]
+		});
+		let tree_bytes = tree_json.to_string().into_bytes();
+		let registry = TaskRegistry::new();
+		let registry = registry.read().unwrap();
+		let compiled = compile_json(&tree_bytes, &registry).unwrap();
+		let tree_config = decode_tree_config(&compiled).unwrap();
+		assert_eq!(tree_config, JsonParser::parse_tree_config(&tree_bytes).unwrap());
+		assert_eq!(tree_config.root_task.children[0].variables["Data"], tree_json["RootTask"]["Children"][0]["Data"]);
+		assert_eq!(tree_config.detached_tasks.len(), 1);
+
+		assert!(decode_tree_config(&tree_bytes).is_err());
+		assert!(decode_tree_config(&compiled[..compiled.len() - 1]).is_err());
+		let mut wrong_version = compiled.clone();
+		wrong_version[COMPILED_TREE_MAGIC.len()] = COMPILED_TREE_VERSION + 1;
+		assert!(decode_tree_config(&wrong_version).is_err());
+		let mut trailing = compiled.clone();
+		trailing.push(0);
+		assert!(decode_tree_config(&trailing).is_err());
+
+		//	未注册的类型在编译的时候就报错
+		let unknown = json!({"RootTask": {"Type": "Unknown", "ID": 1}}).to_string().into_bytes();
+		assert!(compile_json(&unknown, &registry).is_err());
+	}
+
+	#[test]
+	fn test_compiled_tree_index_tables_checked() {
+		//	编译和加载用的注册表里同一个类型不一样，索引表对不上，初始化报错而不是按错的表执行
+		let tree_bytes = json!({
+			"RootTask": {
+				"Type": "Custom",
+				"ID": 1,
+				"Children": [{"Type": "BehaviorDesigner.Runtime.Tasks.Role.MainRole.NeedFollowJoystick", "ID": 2}]
+			}
+		}).to_string().into_bytes();
+		let composite_registry = TaskRegistry::new();
+		composite_registry.write().unwrap().register_composite_fn("Custom", |_, _| -> Box<dyn IComposite> {Box::new(Sequence::new())});
+		let decorator_registry = TaskRegistry::new();
+		decorator_registry.write().unwrap().register_decorator_fn("Custom", |_, _| -> Box<dyn IDecorator> {Box::new(UntilForever::new())});
+
+		let compiled = compile_json(&tree_bytes, &composite_registry.read().unwrap()).unwrap();
+		assert_eq!(decode_compiled_tree(&compiled).unwrap().index_tables.child_conditional_index[1], vec![2]);
+		assert!(initialized_index_tables(&compiled, Arc::new(CompiledParser::from_registry(composite_registry.clone()))).is_ok());
+		let err = initialized_index_tables(&compiled, Arc::new(CompiledParser::from_registry(decorator_registry))).unwrap_err();
+		assert!(err.to_string().contains("index tables"));
+
+		//	索引表在文件最后，换成多了一个子任务的表也报错
+		let mut index_tables = decode_compiled_tree(&compiled).unwrap().index_tables;
+		let mut writer = WireWriter::new();
+		write_index_tables(&mut writer, &index_tables);
+		let mut tampered = compiled[..compiled.len() - writer.into_bytes().len()].to_vec();
+		index_tables.children_index[2].push(1);
+		let mut writer = WireWriter::new();
+		write_index_tables(&mut writer, &index_tables);
+		tampered.extend_from_slice(&writer.into_bytes());
+		let err = initialized_index_tables(&tampered, Arc::new(CompiledParser::from_registry(composite_registry))).unwrap_err();
+		assert!(err.to_string().contains("index tables"));
+	}
+
+	fn compiled_header(strings:&[&str])->WireWriter{
+		let mut writer = WireWriter::new();
+		for byte in COMPILED_TREE_MAGIC.iter(){
+			writer.write_u8(*byte);
+		}
+		writer.write_u8(COMPILED_TREE_VERSION);
+		writer.write_varint(strings.len() as u64);
+		for value in strings.iter(){
+			writer.write_bytes(value.as_bytes());
+		}
+		writer
+	}
+
+	//	类型 名字 ID 标记
+	fn write_task_header(writer:&mut WireWriter){
+		writer.write_varint(0);
+		writer.write_varint(0);
+		writer.write_zigzag(1);
+		writer.write_u8(0);
+	}
+
+	#[test]
+	fn test_compiled_tree_nesting_limit() {
+		//	嵌套很深的数组变量
+		let mut writer = compiled_header(&["Idle"]);
+		write_task_header(&mut writer);
+		writer.write_varint(1);
+		writer.write_varint(0);
+		for _ in 0..100000{
+			writer.write_u8(VALUE_ARRAY);
+			writer.write_varint(1);
+		}
+		writer.write_u8(VALUE_NULL);
+		let err = decode_tree_config(&writer.into_bytes()).unwrap_err();
+		assert!(err.to_string().contains("nested too deep"));
+
+		//	嵌套很深的子任务
+		let mut writer = compiled_header(&["Sequence"]);
+		for _ in 0..100000{
+			write_task_header(&mut writer);
+			writer.write_varint(0);
+			writer.write_varint(1);
+		}
+		let err = decode_tree_config(&writer.into_bytes()).unwrap_err();
+		assert!(err.to_string().contains("nested too deep"));
+
+		//	刚好在限制以内的正常解析，多一层就报错
+		let mut root_task = TaskConfig::new("BehaviorDesigner.Runtime.Tasks.Idle", "", 0);
+		for id in 1..=MAX_COMPILED_TREE_DEPTH as i32{
+			let mut parent_task = TaskConfig::new("BehaviorDesigner.Runtime.Tasks.Sequence", "", id);
+			parent_task.children.push(root_task);
+			root_task = parent_task;
+		}
+		let tree_config = TreeConfig{root_task, detached_tasks:Vec::new()};
+		let registry = TaskRegistry::new();
+		let registry = registry.read().unwrap();
+		assert_eq!(decode_tree_config(&encode_tree_config(&tree_config, &registry).unwrap()).unwrap(), tree_config);
+		let mut parent_task = TaskConfig::new("BehaviorDesigner.Runtime.Tasks.Sequence", "", -1);
+		parent_task.children.push(tree_config.root_task);
+		assert!(decode_tree_config(&encode_tree_config(&TreeConfig{root_task:parent_task, detached_tasks:Vec::new()}, &registry).unwrap()).is_err());
+	}
+}
@@ -1,6 +1,9 @@
 use super::super::interface::{IComposite, ITaskProxy, IBehaviorTree, IParentTask};
 use super::super::consts::TaskStatus;
+use super::super::snapshot::serde_task_state;
+use serde::{Serialize, Deserialize};
 
+#[derive(Serialize, Deserialize)]
 pub struct Selector{
     current_child_index :u32,
 	execution_status :TaskStatus,
@@ -18,6 +21,8 @@ impl Selector{
 }
 
 impl IParentTask for Selector{
+    serde_task_state!();
+
     fn initialize_variables(&mut self, task_proxy:&dyn ITaskProxy)->Result<(), Box<dyn std::error::Error>> {
         self.children_len = task_proxy.children().len() as u32;
         Ok(())
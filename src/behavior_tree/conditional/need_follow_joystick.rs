@@ -1,7 +1,10 @@
 use super::super::interface::{IConditional, ITaskProxy, IBehaviorTree};
 use super::super::consts::TaskStatus;
+use super::super::snapshot::serde_task_state;
+use serde::{Serialize, Deserialize};
 
 
+#[derive(Serialize, Deserialize)]
 pub struct NeedFollowJoystick{
     need_follow_joystick_flag:bool,
 }
@@ -15,6 +18,8 @@ impl NeedFollowJoystick{
 }
 
 impl IConditional for NeedFollowJoystick{
+    serde_task_state!();
+
     fn on_update(&mut self, task_proxy:&dyn ITaskProxy, behavior_tree:&dyn IBehaviorTree)->TaskStatus {
         if self.need_follow_joystick_flag {
             TaskStatus::Success
@@ -1,4 +1,6 @@
-#[derive(Clone, PartialEq)]
+use serde::{Serialize, Deserialize};
+
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
 pub enum TaskStatus{
     Inactive,
 	Running,
@@ -17,7 +19,24 @@ impl TaskStatus {
     }
 }
 
-#[derive(Clone, PartialEq, Copy)]
+//	需要同步的action对哪些客户端可见
+#[derive(Clone, PartialEq, Copy, Debug, Serialize, Deserialize)]
+pub enum SyncVisibility {
+	Everyone,
+	Team,
+	Owner,
+}
+
+//	需要同步的action在update时的同步策略，开始和结束总是会同步
+#[derive(Clone, PartialEq, Copy, Debug, Serialize, Deserialize)]
+pub enum SyncUpdatePolicy {
+	EveryUpdate,		//	每次update都同步
+	OnChange,			//	状态或者数据跟上次同步的不一样才同步
+	Interval(u64),		//	最多每隔多少毫秒同步一次，中间收集的数据只保留最后一次
+	StartEndOnly,		//	不同步update，update里收集的数据会丢掉
+}
+
+#[derive(Clone, PartialEq, Copy, Debug, Serialize, Deserialize)]
 pub enum AbortType {
     None,
 	Self_,
@@ -2,4 +2,5 @@ pub mod return_failure;
 pub mod return_success;
 pub mod until_failure;
 pub mod until_success;
-pub mod until_forever;
\ No newline at end of file
+pub mod until_forever;
+pub mod interrupt;
\ No newline at end of file
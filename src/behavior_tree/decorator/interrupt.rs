@@ -0,0 +1,59 @@
+use super::super::interface::{IDecorator, IParentTask, ITaskProxy, IBehaviorTree};
+use super::super::consts::TaskStatus;
+use super::super::snapshot::serde_task_state;
+use serde::{Serialize, Deserialize};
+
+//  配合PerformInterruption使用，被打断以后以打断时指定的状态结束
+#[derive(Serialize, Deserialize)]
+pub struct Interrupt{
+    pub execution_status:TaskStatus,
+}
+
+impl Interrupt{
+    pub fn new() -> Self{
+        Self{
+            execution_status:TaskStatus::Inactive,
+        }
+    }
+}
+
+impl IParentTask for Interrupt{
+    serde_task_state!();
+
+    fn on_awake(&mut self, task_proxy:&dyn ITaskProxy, behavior_tree:&dyn IBehaviorTree) {
+        self.execution_status = TaskStatus::Inactive;
+    }
+
+    fn can_execute(&self, task_proxy:&dyn ITaskProxy, behavior_tree:&dyn IBehaviorTree)->bool {
+        self.execution_status == TaskStatus::Running || self.execution_status == TaskStatus::Inactive
+    }
+
+    fn can_run_parallel_children(&self)->bool {
+        false
+    }
+
+    fn  on_child_executed1(&mut self, child_status:TaskStatus, task_proxy:&dyn ITaskProxy, behavior_tree:&dyn IBehaviorTree) {
+        self.execution_status = child_status;
+    }
+
+    fn on_end(&mut self, task_proxy:&dyn ITaskProxy, behavior_tree:&dyn IBehaviorTree) {
+        self.execution_status = TaskStatus::Inactive;
+    }
+
+    fn current_child_index(&self, task_proxy:&dyn ITaskProxy, behavior_tree:&dyn IBehaviorTree)->u32 {0}
+
+    //  子任务被打断以后，下一帧不会再执行子任务，这里返回打断时的状态
+    fn override_status1(&mut self, status:TaskStatus, task_proxy:&dyn ITaskProxy, behavior_tree:&dyn IBehaviorTree)->TaskStatus {
+        if status == TaskStatus::Inactive && (self.execution_status == TaskStatus::Success || self.execution_status == TaskStatus::Failure) {
+            self.execution_status.clone()
+        }
+        else
+        {
+            status
+        }
+    }
+}
+
+impl IDecorator for Interrupt{
+
+}
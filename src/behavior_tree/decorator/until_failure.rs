@@ -1,6 +1,9 @@
 use super::super::interface::{IDecorator, IParentTask, ITaskProxy, IBehaviorTree};
 use super::super::consts::TaskStatus;
+use super::super::snapshot::serde_task_state;
+use serde::{Serialize, Deserialize};
 
+#[derive(Serialize, Deserialize)]
 pub struct UntilFailure{
     pub execution_status:TaskStatus,
 }
@@ -14,6 +17,8 @@ impl UntilFailure{
 }
 
 impl IParentTask for UntilFailure{
+    serde_task_state!();
+
     fn on_awake(&mut self, task_proxy:&dyn ITaskProxy, behavior_tree:&dyn IBehaviorTree) {
         self.execution_status = TaskStatus::Inactive;
     }
@@ -0,0 +1,293 @@
+use std::collections::HashSet;
+
+use super::consts::TaskStatus;
+use super::interface::{IBehaviorTree, IRuntimeEventHandle, ITaskProxy, StackRuntimeData, TaskRuntimeData};
+
+//	把回调按顺序转发给多个处理，比如网络同步、调试器、统计
+pub struct MultiplexRuntimeEventHandle{
+	handles:Vec<Box<dyn IRuntimeEventHandle>>,
+}
+
+impl MultiplexRuntimeEventHandle{
+	pub fn new(handles:Vec<Box<dyn IRuntimeEventHandle>>)->Self{
+		Self{handles}
+	}
+
+	pub fn add(&mut self, handle:Box<dyn IRuntimeEventHandle>){
+		self.handles.push(handle);
+	}
+
+	pub fn len(&self)->usize{
+		self.handles.len()
+	}
+
+	pub fn is_empty(&self)->bool{
+		self.handles.is_empty()
+	}
+}
+
+impl IRuntimeEventHandle for MultiplexRuntimeEventHandle{
+	fn post_initialize(&self, behavior_tree:&dyn IBehaviorTree, now_timestamp_in_milli:u64){
+		self.handles.iter().for_each(|handle| handle.post_initialize(behavior_tree, now_timestamp_in_milli));
+	}
+
+	fn post_on_complete(&self, behavior_tree:&dyn IBehaviorTree, now_timestamp_in_milli:u64){
+		self.handles.iter().for_each(|handle| handle.post_on_complete(behavior_tree, now_timestamp_in_milli));
+	}
+
+	fn new_stack(&self, behavior_tree:&dyn IBehaviorTree, data:&StackRuntimeData){
+		self.handles.iter().for_each(|handle| handle.new_stack(behavior_tree, data));
+	}
+
+	fn remove_stack(&self, behavior_tree:&dyn IBehaviorTree, data:&StackRuntimeData, now_timestamp_in_milli:u64){
+		self.handles.iter().for_each(|handle| handle.remove_stack(behavior_tree, data, now_timestamp_in_milli));
+	}
+
+	fn pre_on_start(&self, behavior_tree:&dyn IBehaviorTree, task_runtime_data:&TaskRuntimeData, stack_runtime_data:&StackRuntimeData, task:&dyn ITaskProxy){
+		self.handles.iter().for_each(|handle| handle.pre_on_start(behavior_tree, task_runtime_data, stack_runtime_data, task));
+	}
+
+	fn pre_on_update(&self, behavior_tree:&dyn IBehaviorTree, task_runtime_data:&TaskRuntimeData, stack_runtime_data:&StackRuntimeData, task:&dyn ITaskProxy){
+		self.handles.iter().for_each(|handle| handle.pre_on_update(behavior_tree, task_runtime_data, stack_runtime_data, task));
+	}
+
+	fn post_on_update(&self, behavior_tree:&dyn IBehaviorTree, task_runtime_data:&TaskRuntimeData, stack_runtime_data:&StackRuntimeData, task:&dyn ITaskProxy, now_timestamp_in_milli:u64, status:TaskStatus){
+		self.handles.iter().for_each(|handle| handle.post_on_update(behavior_tree, task_runtime_data, stack_runtime_data, task, now_timestamp_in_milli, status.clone()));
+	}
+
+	fn post_on_end(&self, behavior_tree:&dyn IBehaviorTree, task_runtime_data:&TaskRuntimeData, stack_runtime_data:&StackRuntimeData, task:&dyn ITaskProxy, now_timestamp_in_milli:u64){
+		self.handles.iter().for_each(|handle| handle.post_on_end(behavior_tree, task_runtime_data, stack_runtime_data, task, now_timestamp_in_milli));
+	}
+
+	fn action_post_on_start(&self, behavior_tree:&dyn IBehaviorTree, task_runtime_data:&TaskRuntimeData, stack_runtime_data:&StackRuntimeData, task:&dyn ITaskProxy, datas:&[Vec<u8>]){
+		self.handles.iter().for_each(|handle| handle.action_post_on_start(behavior_tree, task_runtime_data, stack_runtime_data, task, datas));
+	}
+
+	fn action_post_on_update(&self, behavior_tree:&dyn IBehaviorTree, task_runtime_data:&TaskRuntimeData, stack_runtime_data:&StackRuntimeData, task:&dyn ITaskProxy, now_timestamp_in_milli:u64, status:TaskStatus, datas:&[Vec<u8>]){
+		self.handles.iter().for_each(|handle| handle.action_post_on_update(behavior_tree, task_runtime_data, stack_runtime_data, task, now_timestamp_in_milli, status.clone(), datas));
+	}
+
+	fn action_post_on_end(&self, behavior_tree:&dyn IBehaviorTree, task_runtime_data:&TaskRuntimeData, stack_runtime_data:&StackRuntimeData, task:&dyn ITaskProxy, now_timestamp_in_milli:u64, datas:&[Vec<u8>]){
+		self.handles.iter().for_each(|handle| handle.action_post_on_end(behavior_tree, task_runtime_data, stack_runtime_data, task, now_timestamp_in_milli, datas));
+	}
+
+	fn parallel_pre_on_start(&self, behavior_tree:&dyn IBehaviorTree, task_runtime_data:&TaskRuntimeData, stack_runtime_data:&StackRuntimeData, task:&dyn ITaskProxy){
+		self.handles.iter().for_each(|handle| handle.parallel_pre_on_start(behavior_tree, task_runtime_data, stack_runtime_data, task));
+	}
+
+	fn parallel_post_on_end(&self, behavior_tree:&dyn IBehaviorTree, task_runtime_data:&TaskRuntimeData, stack_runtime_data:&StackRuntimeData, task:&dyn ITaskProxy, now_timestamp_in_milli:u64){
+		self.handles.iter().for_each(|handle| handle.parallel_post_on_end(behavior_tree, task_runtime_data, stack_runtime_data, task, now_timestamp_in_milli));
+	}
+
+	fn parallel_add_child_stack(&self, behavior_tree:&dyn IBehaviorTree, task_runtime_data:&TaskRuntimeData, stack_runtime_data:&StackRuntimeData, task:&dyn ITaskProxy, child_stack_runtime_data:&StackRuntimeData){
+		self.handles.iter().for_each(|handle| handle.parallel_add_child_stack(behavior_tree, task_runtime_data, stack_runtime_data, task, child_stack_runtime_data));
+	}
+
+	fn parallel_remove_child_stack(&self, behavior_tree:&dyn IBehaviorTree, task_runtime_data:&TaskRuntimeData, stack_runtime_data:&StackRuntimeData, task:&dyn ITaskProxy, child_stack_runtime_data:&StackRuntimeData, now_timestamp_in_milli:u64){
+		self.handles.iter().for_each(|handle| handle.parallel_remove_child_stack(behavior_tree, task_runtime_data, stack_runtime_data, task, child_stack_runtime_data, now_timestamp_in_milli));
+	}
+
+	fn on_task_error(&self, behavior_tree:&dyn IBehaviorTree, task:&dyn ITaskProxy, error:&dyn std::error::Error){
+		self.handles.iter().for_each(|handle| handle.on_task_error(behavior_tree, task, error));
+	}
+}
+
+//	只转发同步需要的回调：执行栈、需要同步的action、并发任务
+pub struct SyncFilterRuntimeEventHandle{
+	handle:Box<dyn IRuntimeEventHandle>,
+}
+
+impl SyncFilterRuntimeEventHandle{
+	pub fn new(handle:Box<dyn IRuntimeEventHandle>)->Self{
+		Self{handle}
+	}
+}
+
+impl IRuntimeEventHandle for SyncFilterRuntimeEventHandle{
+	fn new_stack(&self, behavior_tree:&dyn IBehaviorTree, data:&StackRuntimeData){
+		self.handle.new_stack(behavior_tree, data);
+	}
+
+	fn remove_stack(&self, behavior_tree:&dyn IBehaviorTree, data:&StackRuntimeData, now_timestamp_in_milli:u64){
+		self.handle.remove_stack(behavior_tree, data, now_timestamp_in_milli);
+	}
+
+	fn action_post_on_start(&self, behavior_tree:&dyn IBehaviorTree, task_runtime_data:&TaskRuntimeData, stack_runtime_data:&StackRuntimeData, task:&dyn ITaskProxy, datas:&[Vec<u8>]){
+		self.handle.action_post_on_start(behavior_tree, task_runtime_data, stack_runtime_data, task, datas);
+	}
+
+	fn action_post_on_update(&self, behavior_tree:&dyn IBehaviorTree, task_runtime_data:&TaskRuntimeData, stack_runtime_data:&StackRuntimeData, task:&dyn ITaskProxy, now_timestamp_in_milli:u64, status:TaskStatus, datas:&[Vec<u8>]){
+		self.handle.action_post_on_update(behavior_tree, task_runtime_data, stack_runtime_data, task, now_timestamp_in_milli, status, datas);
+	}
+
+	fn action_post_on_end(&self, behavior_tree:&dyn IBehaviorTree, task_runtime_data:&TaskRuntimeData, stack_runtime_data:&StackRuntimeData, task:&dyn ITaskProxy, now_timestamp_in_milli:u64, datas:&[Vec<u8>]){
+		self.handle.action_post_on_end(behavior_tree, task_runtime_data, stack_runtime_data, task, now_timestamp_in_milli, datas);
+	}
+
+	fn parallel_pre_on_start(&self, behavior_tree:&dyn IBehaviorTree, task_runtime_data:&TaskRuntimeData, stack_runtime_data:&StackRuntimeData, task:&dyn ITaskProxy){
+		self.handle.parallel_pre_on_start(behavior_tree, task_runtime_data, stack_runtime_data, task);
+	}
+
+	fn parallel_post_on_end(&self, behavior_tree:&dyn IBehaviorTree, task_runtime_data:&TaskRuntimeData, stack_runtime_data:&StackRuntimeData, task:&dyn ITaskProxy, now_timestamp_in_milli:u64){
+		self.handle.parallel_post_on_end(behavior_tree, task_runtime_data, stack_runtime_data, task, now_timestamp_in_milli);
+	}
+
+	fn parallel_add_child_stack(&self, behavior_tree:&dyn IBehaviorTree, task_runtime_data:&TaskRuntimeData, stack_runtime_data:&StackRuntimeData, task:&dyn ITaskProxy, child_stack_runtime_data:&StackRuntimeData){
+		self.handle.parallel_add_child_stack(behavior_tree, task_runtime_data, stack_runtime_data, task, child_stack_runtime_data);
+	}
+
+	fn parallel_remove_child_stack(&self, behavior_tree:&dyn IBehaviorTree, task_runtime_data:&TaskRuntimeData, stack_runtime_data:&StackRuntimeData, task:&dyn ITaskProxy, child_stack_runtime_data:&StackRuntimeData, now_timestamp_in_milli:u64){
+		self.handle.parallel_remove_child_stack(behavior_tree, task_runtime_data, stack_runtime_data, task, child_stack_runtime_data, now_timestamp_in_milli);
+	}
+}
+
+//	只转发指定任务的回调，没有任务的回调（树、执行栈）不转发
+pub struct TaskFilterRuntimeEventHandle{
+	handle:Box<dyn IRuntimeEventHandle>,
+	task_ids:HashSet<i32>,
+}
+
+impl TaskFilterRuntimeEventHandle{
+	pub fn new(handle:Box<dyn IRuntimeEventHandle>, task_ids:HashSet<i32>)->Self{
+		Self{handle, task_ids}
+	}
+
+	fn accept(&self, task_runtime_data:&TaskRuntimeData)->bool{
+		self.task_ids.contains(&task_runtime_data.task_id)
+	}
+}
+
+impl IRuntimeEventHandle for TaskFilterRuntimeEventHandle{
+	fn pre_on_start(&self, behavior_tree:&dyn IBehaviorTree, task_runtime_data:&TaskRuntimeData, stack_runtime_data:&StackRuntimeData, task:&dyn ITaskProxy){
+		if self.accept(task_runtime_data){
+			self.handle.pre_on_start(behavior_tree, task_runtime_data, stack_runtime_data, task);
+		}
+	}
+
+	fn pre_on_update(&self, behavior_tree:&dyn IBehaviorTree, task_runtime_data:&TaskRuntimeData, stack_runtime_data:&StackRuntimeData, task:&dyn ITaskProxy){
+		if self.accept(task_runtime_data){
+			self.handle.pre_on_update(behavior_tree, task_runtime_data, stack_runtime_data, task);
+		}
+	}
+
+	fn post_on_update(&self, behavior_tree:&dyn IBehaviorTree, task_runtime_data:&TaskRuntimeData, stack_runtime_data:&StackRuntimeData, task:&dyn ITaskProxy, now_timestamp_in_milli:u64, status:TaskStatus){
+		if self.accept(task_runtime_data){
+			self.handle.post_on_update(behavior_tree, task_runtime_data, stack_runtime_data, task, now_timestamp_in_milli, status);
+		}
+	}
+
+	fn post_on_end(&self, behavior_tree:&dyn IBehaviorTree, task_runtime_data:&TaskRuntimeData, stack_runtime_data:&StackRuntimeData, task:&dyn ITaskProxy, now_timestamp_in_milli:u64){
+		if self.accept(task_runtime_data){
+			self.handle.post_on_end(behavior_tree, task_runtime_data, stack_runtime_data, task, now_timestamp_in_milli);
+		}
+	}
+
+	fn action_post_on_start(&self, behavior_tree:&dyn IBehaviorTree, task_runtime_data:&TaskRuntimeData, stack_runtime_data:&StackRuntimeData, task:&dyn ITaskProxy, datas:&[Vec<u8>]){
+		if self.accept(task_runtime_data){
+			self.handle.action_post_on_start(behavior_tree, task_runtime_data, stack_runtime_data, task, datas);
+		}
+	}
+
+	fn action_post_on_update(&self, behavior_tree:&dyn IBehaviorTree, task_runtime_data:&TaskRuntimeData, stack_runtime_data:&StackRuntimeData, task:&dyn ITaskProxy, now_timestamp_in_milli:u64, status:TaskStatus, datas:&[Vec<u8>]){
+		if self.accept(task_runtime_data){
+			self.handle.action_post_on_update(behavior_tree, task_runtime_data, stack_runtime_data, task, now_timestamp_in_milli, status, datas);
+		}
+	}
+
+	fn action_post_on_end(&self, behavior_tree:&dyn IBehaviorTree, task_runtime_data:&TaskRuntimeData, stack_runtime_data:&StackRuntimeData, task:&dyn ITaskProxy, now_timestamp_in_milli:u64, datas:&[Vec<u8>]){
+		if self.accept(task_runtime_data){
+			self.handle.action_post_on_end(behavior_tree, task_runtime_data, stack_runtime_data, task, now_timestamp_in_milli, datas);
+		}
+	}
+
+	fn parallel_pre_on_start(&self, behavior_tree:&dyn IBehaviorTree, task_runtime_data:&TaskRuntimeData, stack_runtime_data:&StackRuntimeData, task:&dyn ITaskProxy){
+		if self.accept(task_runtime_data){
+			self.handle.parallel_pre_on_start(behavior_tree, task_runtime_data, stack_runtime_data, task);
+		}
+	}
+
+	fn parallel_post_on_end(&self, behavior_tree:&dyn IBehaviorTree, task_runtime_data:&TaskRuntimeData, stack_runtime_data:&StackRuntimeData, task:&dyn ITaskProxy, now_timestamp_in_milli:u64){
+		if self.accept(task_runtime_data){
+			self.handle.parallel_post_on_end(behavior_tree, task_runtime_data, stack_runtime_data, task, now_timestamp_in_milli);
+		}
+	}
+
+	fn parallel_add_child_stack(&self, behavior_tree:&dyn IBehaviorTree, task_runtime_data:&TaskRuntimeData, stack_runtime_data:&StackRuntimeData, task:&dyn ITaskProxy, child_stack_runtime_data:&StackRuntimeData){
+		if self.accept(task_runtime_data){
+			self.handle.parallel_add_child_stack(behavior_tree, task_runtime_data, stack_runtime_data, task, child_stack_runtime_data);
+		}
+	}
+
+	fn parallel_remove_child_stack(&self, behavior_tree:&dyn IBehaviorTree, task_runtime_data:&TaskRuntimeData, stack_runtime_data:&StackRuntimeData, task:&dyn ITaskProxy, child_stack_runtime_data:&StackRuntimeData, now_timestamp_in_milli:u64){
+		if self.accept(task_runtime_data){
+			self.handle.parallel_remove_child_stack(behavior_tree, task_runtime_data, stack_runtime_data, task, child_stack_runtime_data, now_timestamp_in_milli);
+		}
+	}
+
+	fn on_task_error(&self, behavior_tree:&dyn IBehaviorTree, task:&dyn ITaskProxy, error:&dyn std::error::Error){
+		if self.task_ids.contains(&task.id()){
+			self.handle.on_task_error(behavior_tree, task, error);
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::{rc::Rc, cell::RefCell};
+	use super::super::interface::IClock;
+	use super::super::json_parser::JsonParser;
+	use super::super::runtime::BehaviorTree;
+	use super::super::trace::{Trace, TraceEventKind, TraceRuntimeEventHandle};
+
+	struct DummyClock;
+
+	impl IClock for DummyClock{
+		fn timestamp_in_mill(&self)->u64{
+			0
+		}
+	}
+
+	struct EmptyRuntimeEventHandle;
+
+	impl IRuntimeEventHandle for EmptyRuntimeEventHandle{}
+
+	#[test]
+	fn test_multiplex_and_filter_runtime_event_handle() {
+		let file_bytes = std::fs::read("src/behavior_tree/test_behaviortree.json").unwrap();
+		let parser = JsonParser::new();
+		let clock:Rc<RefCell<Box<dyn IClock>>> = Rc::new(RefCell::new(Box::new(DummyClock)));
+
+		let all_trace = Rc::new(RefCell::new(Trace::new()));
+		let sync_trace = Rc::new(RefCell::new(Trace::new()));
+		let task_trace = Rc::new(RefCell::new(Trace::new()));
+		let mut runtime_event_handle = MultiplexRuntimeEventHandle::new(vec![
+			Box::new(TraceRuntimeEventHandle::new(all_trace.clone())),
+			Box::new(SyncFilterRuntimeEventHandle::new(Box::new(TraceRuntimeEventHandle::new(sync_trace.clone())))),
+			Box::new(TaskFilterRuntimeEventHandle::new(Box::new(TraceRuntimeEventHandle::new(task_trace.clone())), HashSet::from([0, 1]))),
+		]);
+		runtime_event_handle.add(Box::new(EmptyRuntimeEventHandle));
+		assert_eq!(runtime_event_handle.len(), 4);
+
+		let behavior_tree = BehaviorTree::new(1, &file_bytes, 1, &Rc::downgrade(&clock), Box::new(runtime_event_handle), Rc::downgrade(&parser));
+		behavior_tree.borrow_mut().enable().unwrap();
+		behavior_tree.borrow_mut().update();
+		behavior_tree.borrow_mut().update();
+		behavior_tree.borrow_mut().disable().unwrap();
+
+		let all_trace = all_trace.borrow();
+		let sync_trace = sync_trace.borrow();
+		let task_trace = task_trace.borrow();
+
+		let sync_kinds = [TraceEventKind::NewStack, TraceEventKind::RemoveStack, TraceEventKind::ActionPostOnStart, TraceEventKind::ActionPostOnUpdate,
+			TraceEventKind::ActionPostOnEnd, TraceEventKind::ParallelPreOnStart, TraceEventKind::ParallelPostOnEnd,
+			TraceEventKind::ParallelAddChildStack, TraceEventKind::ParallelRemoveChildStack];
+		let expected_sync:Vec<_> = all_trace.events().iter().filter(|event| sync_kinds.contains(&event.kind)).cloned().collect();
+		assert!(expected_sync.len() > 0);
+		assert!(sync_trace.events() == &expected_sync);
+
+		let expected_task:Vec<_> = all_trace.events().iter().filter(|event| event.task_id == Some(0) || event.task_id == Some(1)).cloned().collect();
+		assert!(expected_task.len() > 0);
+		assert!(task_trace.events() == &expected_task);
+	}
+}
@@ -1,12 +1,14 @@
-use  std::{rc::{Rc, Weak}, cell::RefCell};
-use super::consts::{TaskStatus, AbortType};
+use  std::cell::RefCell;
+use super::consts::{TaskStatus, AbortType, SyncVisibility, SyncUpdatePolicy};
+use super::snapshot::BehaviorTreeSnapshot;
+use serde::{Serialize, Deserialize};
 
 
 pub trait IClock{
 	fn timestamp_in_mill(&self)->u64;
 }
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
 pub struct StackRuntimeData{
 	pub stack_id:usize,
 	pub start_time:u64,
@@ -21,7 +23,7 @@ impl StackRuntimeData{
 	}
 }
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
 pub struct TaskRuntimeData{
 	pub task_id:i32,
 	pub start_time:u64,
@@ -71,12 +73,15 @@ impl  RunningStack {
 }
 
 pub struct TaskAddData{
-	pub parent:Option<Weak<RefCell<Box<dyn ITaskProxy>>>>,
+	//	正在初始化的父任务的配置ID
+	pub parent:Option<i32>,
 	pub parent_index:i32,
 	pub depth:u32,
 	pub composite_parent_index:u32,
 	pub error_task:i32,
 	pub error_task_name:String,
+	//	解析器已经算好的索引表，BehaviorTree初始化的时候直接用，没有就自己算
+	pub index_tables:Option<TaskIndexTables>,
 }
 
 impl TaskAddData{
@@ -88,12 +93,73 @@ impl TaskAddData{
 			composite_parent_index:0,
 			error_task:-1,
 			error_task_name:"".to_string(),
+			index_tables:None,
 		}
 	}
 }
 
+//	按运行时任务索引排列的父子关系表，0是EntryRoot，根任务是1，其余任务按前序排列
+#[derive(Clone, PartialEq, Debug)]
+pub struct TaskIndexTables{
+	pub parent_index:Vec<i32>,
+	pub children_index:Vec<Vec<i32>>,
+	pub relative_child_index:Vec<i32>,
+	pub parent_composite_index:Vec<i32>,
+	pub composite_abort_task:Vec<AbortType>,
+	pub child_conditional_index:Vec<Vec<i32>>,
+}
+
+impl TaskIndexTables{
+	//	只有EntryRoot一项
+	pub fn new() -> Self{
+		let mut index_tables = Self{
+			parent_index:Vec::new(),
+			children_index:Vec::new(),
+			relative_child_index:Vec::new(),
+			parent_composite_index:Vec::new(),
+			composite_abort_task:Vec::new(),
+			child_conditional_index:Vec::new(),
+		};
+		index_tables.push(-1, -1, AbortType::None);
+		index_tables
+	}
+
+	pub fn len(&self) -> usize{
+		self.parent_index.len()
+	}
+
+	pub fn is_empty(&self) -> bool{
+		self.parent_index.is_empty()
+	}
+
+	//	按前序加一个任务，返回它的任务索引
+	pub fn push(&mut self, parent_index:i32, parent_composite_index:i32, abort_type:AbortType) -> i32{
+		let index = self.len() as i32;
+		let relative_child_index = match self.children_index.get_mut(parent_index as usize){
+			Some(siblings) if parent_index >= 0 => {
+				siblings.push(index);
+				siblings.len() as i32 - 1
+			},
+			_ => -1,
+		};
+		self.parent_index.push(parent_index);
+		self.children_index.push(Vec::with_capacity(10));
+		self.relative_child_index.push(relative_child_index);
+		self.parent_composite_index.push(parent_composite_index);
+		self.composite_abort_task.push(abort_type);
+		self.child_conditional_index.push(Vec::with_capacity(10));
+		index
+	}
+}
+
+impl Default for TaskIndexTables{
+	fn default() -> Self{
+		Self::new()
+	}
+}
+
 pub trait IParser{
-	fn deserialize(&self, config:&Vec<u8>, task_add_data:&mut TaskAddData) -> Result<Rc<RefCell<Box<dyn ITaskProxy>>>, Box<dyn std::error::Error>>;
+	fn deserialize(&self, config:&Vec<u8>, task_add_data:&mut TaskAddData) -> Result<Box<dyn ITaskProxy>, Box<dyn std::error::Error>>;
 }
 
 
@@ -107,7 +173,24 @@ pub trait IBehaviorTree{
 
 	fn unit_id(&self)->u64;
 	fn rebuild_sync(&self, collector:&mut dyn IRebuildSyncDataCollector);
-	fn clock(&self)->Weak<RefCell<Box<dyn IClock>>>;
+	//	树的时钟当前的时间
+	fn timestamp_in_mill(&self)->u64;
+
+	//	外部打断指定任务，被打断的任务用status结束；include_self为false时只打断它下面正在执行的任务
+	fn interrupt(&mut self, task_id:i32, status:TaskStatus, include_self:bool)->Result<(), Box<dyn std::error::Error>>;
+	//	任务执行过程中请求打断，本帧update结束的时候再处理
+	fn request_interrupt(&self, task_id:i32, status:TaskStatus, include_self:bool);
+
+	//	配置里的任务ID对应的运行时任务ID，运行时ID在树初始化的时候重新分配
+	fn task_id_by_config_id(&self, config_id:i32)->Option<i32>;
+
+	//	保存运行时状态，用于存档或者把单位迁移到别的进程
+	fn snapshot(&self)->Result<BehaviorTreeSnapshot, Box<dyn std::error::Error>>;
+	//	用同样配置创建的树从快照恢复，恢复以后从快照的位置继续update
+	fn restore(&mut self, snapshot:&BehaviorTreeSnapshot)->Result<(), Box<dyn std::error::Error>>;
+
+	//	任务回调里没办法返回的错误，比如同步消息编码失败，交给IRuntimeEventHandle::on_task_error
+	fn report_task_error(&self, task:&dyn ITaskProxy, error:&dyn std::error::Error);
 }
 
 
@@ -116,40 +199,60 @@ pub trait IRebuildSyncDataCollector{
 	fn stack(&mut self, behavior_tree: &dyn IBehaviorTree, data: &StackRuntimeData);
 
 	//	需要同步的action的回调
-	fn action(&mut self, behavior_tree:&dyn IBehaviorTree, task_runtime_data:&TaskRuntimeData, stack_runtime_data:&StackRuntimeData, task:&dyn ITaskProxy, datas:&Vec<Vec<u8>>);
+	fn action(&mut self, behavior_tree:&dyn IBehaviorTree, task_runtime_data:&TaskRuntimeData, stack_runtime_data:&StackRuntimeData, task:&dyn ITaskProxy, datas:&[Vec<u8>]);
 
 	//	并发任务相关的执行栈恢复同步数据
 	fn parallel(&mut self, behavior_tree:&dyn IBehaviorTree, task_runtime_data:&TaskRuntimeData, stack_runtime_data:&StackRuntimeData, task:&dyn ITaskProxy, child_stack_runtime_datas:&Vec<StackRuntimeData>);
 }
 
+//	清空的时候数据的内存留着给下一次用，一直在重新开始的action不用每次都分配
+#[derive(Default)]
 pub struct SyncDataCollector {
 	datas:Vec<Vec<u8>>,
+	spare_buffers:Vec<Vec<u8>>,
 }
 
 impl SyncDataCollector{
-	pub fn new() -> Rc<RefCell<Box<Self>>>{
-		Rc::new(RefCell::new(Box::new(Self{
+	pub fn new() -> Self{
+		Self{
 			datas: Vec::new(),
-		})))
+			spare_buffers: Vec::new(),
+		}
 	}
 
 	pub fn add_data(&mut self, data:Vec<u8>){
 		self.datas.push(data);
 	}
-	pub fn get_and_clear(&mut self)->Vec<Vec<u8>>{
-		let datas = self.datas.clone();
-		self.datas.clear();
-		datas
+
+	//	一个空的缓冲区，写好以后用add_data加进来
+	pub fn buffer(&mut self)->Vec<u8>{
+		self.spare_buffers.pop().unwrap_or_default()
+	}
+
+	pub fn datas(&self)->&[Vec<u8>]{
+		&self.datas
+	}
+
+	pub fn clear(&mut self){
+		//	外面一直add_data的话留下的缓冲区不超过一次最多的数据个数
+		let max_spare_buffers = self.datas.capacity();
+		for mut data in self.datas.drain(..){
+			if self.spare_buffers.len() < max_spare_buffers{
+				data.clear();
+				self.spare_buffers.push(data);
+			}
+		}
 	}
 }
 
 
-pub trait ITaskProxy{
+//	任务树归BehaviorTree独占，整棵树可以移动到别的线程
+pub trait ITaskProxy:Send{
 	fn set_instant(&mut self, instant:bool);
 	fn instant(&self)->bool;
 	
 	fn initialize_variables(&mut self)->Result<(), Box<dyn std::error::Error>>;
-	fn corresponding_type(&self)->String;
+	fn corresponding_type(&self)->&str;
 
 	fn name(&self)->String;
 
@@ -168,11 +271,13 @@ pub trait ITaskProxy{
 	//提供给Action与Conditional使用
 	fn on_update(&mut self, behavior_tree:&dyn IBehaviorTree)->TaskStatus;
 	fn is_sync_to_client(&self)->bool;
+	fn sync_visibility(&self)->SyncVisibility;
+	fn sync_update_policy(&self)->SyncUpdatePolicy;
 	
 	fn rebuild_sync_datas(&self, behavior_tree:&dyn IBehaviorTree);
 	
-	fn set_sync_data_collector(&mut self, collector:Option<Rc<RefCell<Box<SyncDataCollector>>>>);
-	fn sync_data_collector(&self)->Option<Rc<RefCell<Box<SyncDataCollector>>>>;
+	fn set_sync_data_collector(&mut self, collector:Option<RefCell<SyncDataCollector>>);
+	fn sync_data_collector(&self)->Option<&RefCell<SyncDataCollector>>;
 
 	//	IParentTask接口
 	fn can_run_parallel_children(&self)->bool;
@@ -206,11 +311,11 @@ pub trait ITaskProxy{
 
 	fn on_cancel_conditional_abort(&mut self, behavior_tree:&dyn IBehaviorTree);
 
-	fn children(&self)->&Vec<Rc<RefCell<Box<dyn ITaskProxy>>>>;
+	fn children(&self)->&Vec<Box<dyn ITaskProxy>>;
 
-	fn children_mut(&mut self)->&mut Vec<Rc<RefCell<Box<dyn ITaskProxy>>>>;
+	fn children_mut(&mut self)->&mut Vec<Box<dyn ITaskProxy>>;
 	
-	fn add_child(&mut self, task:&Rc<RefCell<Box<dyn ITaskProxy>>>);
+	fn add_child(&mut self, task:Box<dyn ITaskProxy>);
 	fn abort_type(&self)->AbortType;
 	
 	fn set_abort_type(&mut self, abort_type:AbortType);
@@ -229,40 +334,49 @@ pub trait ITaskProxy{
 	fn is_implements_iparenttask(&self)-> bool;
 
 	fn send_sync_data(&mut self, data:Vec<u8>);
+
+	//	快照相关，任务自己的状态
+	fn save_state(&self)->Option<serde_json::Value>;
+	fn load_state(&mut self, state:&serde_json::Value)->Result<(), Box<dyn std::error::Error>>;
 }
 
+//	所有回调默认什么都不做，只实现需要的回调即可
+#[allow(unused_variables)]
 pub trait IRuntimeEventHandle {
-	fn post_initialize(&self, behavior_tree:&dyn IBehaviorTree, now_timestamp_in_milli:u64);
+	fn post_initialize(&self, behavior_tree:&dyn IBehaviorTree, now_timestamp_in_milli:u64){}
 	//	树结束
-	fn post_on_complete(&self, behavior_tree:&dyn IBehaviorTree, now_timestamp_in_milli:u64);
+	fn post_on_complete(&self, behavior_tree:&dyn IBehaviorTree, now_timestamp_in_milli:u64){}
 
 	//	同步需要
-	fn new_stack(&self, behavior_tree:&dyn IBehaviorTree, data:&StackRuntimeData);
-	fn remove_stack(&self, behavior_tree:&dyn IBehaviorTree, data:&StackRuntimeData, now_timestamp_in_milli:u64);
+	fn new_stack(&self, behavior_tree:&dyn IBehaviorTree, data:&StackRuntimeData){}
+	fn remove_stack(&self, behavior_tree:&dyn IBehaviorTree, data:&StackRuntimeData, now_timestamp_in_milli:u64){}
 
-	//	以下3个回调可以用于追踪树的执行
-	fn pre_on_start(&self, behavior_tree:&dyn IBehaviorTree, task_runtime_data:&TaskRuntimeData, stack_runtime_data:&StackRuntimeData, task:&dyn ITaskProxy);
-	fn post_on_update(&self, behavior_tree:&dyn IBehaviorTree, task_runtime_data:&TaskRuntimeData, stack_runtime_data:&StackRuntimeData, task:&dyn ITaskProxy, now_timestamp_in_milli:u64, status:TaskStatus); //	任何的任务每帧调用的结果
-	fn post_on_end(&self, behavior_tree:&dyn IBehaviorTree, task_runtime_data:&TaskRuntimeData, stack_runtime_data:&StackRuntimeData, task:&dyn ITaskProxy, now_timestamp_in_milli:u64);
+	//	以下几个回调可以用于追踪树的执行
+	fn pre_on_start(&self, behavior_tree:&dyn IBehaviorTree, task_runtime_data:&TaskRuntimeData, stack_runtime_data:&StackRuntimeData, task:&dyn ITaskProxy){}
+	fn pre_on_update(&self, behavior_tree:&dyn IBehaviorTree, task_runtime_data:&TaskRuntimeData, stack_runtime_data:&StackRuntimeData, task:&dyn ITaskProxy){} //	action和条件任务调用on_update之前，跟post_on_update配对可以统计耗时
+	fn post_on_update(&self, behavior_tree:&dyn IBehaviorTree, task_runtime_data:&TaskRuntimeData, stack_runtime_data:&StackRuntimeData, task:&dyn ITaskProxy, now_timestamp_in_milli:u64, status:TaskStatus){} //	任何的任务每帧调用的结果
+	fn post_on_end(&self, behavior_tree:&dyn IBehaviorTree, task_runtime_data:&TaskRuntimeData, stack_runtime_data:&StackRuntimeData, task:&dyn ITaskProxy, now_timestamp_in_milli:u64){}
 
 	//	需要同步的action的回调，同步需要
-	fn action_post_on_start(&self, behavior_tree:&dyn IBehaviorTree, task_runtime_data:&TaskRuntimeData, stack_runtime_data:&StackRuntimeData, task:&dyn ITaskProxy, datas:Vec<Vec<u8>>);
-	fn action_post_on_update(&self, behavior_tree:&dyn IBehaviorTree, task_runtime_data:&TaskRuntimeData, stack_runtime_data:&StackRuntimeData, task:&dyn ITaskProxy, now_timestamp_in_milli:u64, status:TaskStatus, datas:Vec<Vec<u8>>); //	任何的任务每帧调用的结果
-	fn action_post_on_end(&self, behavior_tree:&dyn IBehaviorTree, task_runtime_data:&TaskRuntimeData, stack_runtime_data:&StackRuntimeData, task:&dyn ITaskProxy, now_timestamp_in_milli:u64, datas:Vec<Vec<u8>>);
+	fn action_post_on_start(&self, behavior_tree:&dyn IBehaviorTree, task_runtime_data:&TaskRuntimeData, stack_runtime_data:&StackRuntimeData, task:&dyn ITaskProxy, datas:&[Vec<u8>]){}
+	fn action_post_on_update(&self, behavior_tree:&dyn IBehaviorTree, task_runtime_data:&TaskRuntimeData, stack_runtime_data:&StackRuntimeData, task:&dyn ITaskProxy, now_timestamp_in_milli:u64, status:TaskStatus, datas:&[Vec<u8>]){} //	任何的任务每帧调用的结果
+	fn action_post_on_end(&self, behavior_tree:&dyn IBehaviorTree, task_runtime_data:&TaskRuntimeData, stack_runtime_data:&StackRuntimeData, task:&dyn ITaskProxy, now_timestamp_in_milli:u64, datas:&[Vec<u8>]){}
 
 	//	需要同步的并发任务进入调用，同步需要
-	fn parallel_pre_on_start(&self, behavior_tree:&dyn IBehaviorTree, task_runtime_data:&TaskRuntimeData, stack_runtime_data:&StackRuntimeData, task:&dyn ITaskProxy);
-	fn parallel_post_on_end(&self, behavior_tree:&dyn IBehaviorTree, task_runtime_data:&TaskRuntimeData, stack_runtime_data:&StackRuntimeData, task:&dyn ITaskProxy, now_timestamp_in_milli:u64);
+	fn parallel_pre_on_start(&self, behavior_tree:&dyn IBehaviorTree, task_runtime_data:&TaskRuntimeData, stack_runtime_data:&StackRuntimeData, task:&dyn ITaskProxy){}
+	fn parallel_post_on_end(&self, behavior_tree:&dyn IBehaviorTree, task_runtime_data:&TaskRuntimeData, stack_runtime_data:&StackRuntimeData, task:&dyn ITaskProxy, now_timestamp_in_milli:u64){}
 
 	//	并发任务相关的执行栈的增加/减少，调用顺序是NewStack/ParallelAddChildStack/ParallelRemoveChildStack/RemoveStack
-	fn parallel_add_child_stack(&self, behavior_tree:&dyn IBehaviorTree, task_runtime_data:&TaskRuntimeData, stack_runtime_data:&StackRuntimeData, task:&dyn ITaskProxy, child_stack_runtime_data:&StackRuntimeData);
-	fn parallel_remove_child_stack(&self, behavior_tree:&dyn IBehaviorTree, task_runtime_data:&TaskRuntimeData, stack_runtime_data:&StackRuntimeData, task:&dyn ITaskProxy, child_stack_runtime_data:&StackRuntimeData, now_timestamp_in_milli:u64);
-	
+	fn parallel_add_child_stack(&self, behavior_tree:&dyn IBehaviorTree, task_runtime_data:&TaskRuntimeData, stack_runtime_data:&StackRuntimeData, task:&dyn ITaskProxy, child_stack_runtime_data:&StackRuntimeData){}
+	fn parallel_remove_child_stack(&self, behavior_tree:&dyn IBehaviorTree, task_runtime_data:&TaskRuntimeData, stack_runtime_data:&StackRuntimeData, task:&dyn ITaskProxy, child_stack_runtime_data:&StackRuntimeData, now_timestamp_in_milli:u64){}
+
+	//	任务通过IBehaviorTree::report_task_error报告的错误
+	fn on_task_error(&self, behavior_tree:&dyn IBehaviorTree, task:&dyn ITaskProxy, error:&dyn std::error::Error){}
 }
 
 
 #[allow(unused_variables)]
-pub trait IAction {
+pub trait IAction:Send {
 	fn initialize_variables(&mut self, task_proxy:&mut dyn ITaskProxy)->Result<(), Box<dyn std::error::Error>>{Ok(())}
 	fn on_awake(&mut self, task_proxy:&mut dyn ITaskProxy, behavior_tree:&dyn IBehaviorTree){}
     fn on_start(&mut self, task_proxy:&mut dyn ITaskProxy, behavior_tree:&dyn IBehaviorTree){}
@@ -275,22 +389,39 @@ pub trait IAction {
 		false
 	}
 
+	//	默认所有客户端都能看到
+	fn sync_visibility(&self)->SyncVisibility{
+		SyncVisibility::Everyone
+	}
+
+	//	默认每次update都同步
+	fn sync_update_policy(&self)->SyncUpdatePolicy{
+		SyncUpdatePolicy::EveryUpdate
+	}
+
 	fn rebuild_sync_datas(&self, task_proxy:&dyn ITaskProxy, behavior_tree:&dyn IBehaviorTree){}
+
+	//	快照的时候保存任务自己的状态，默认没有需要保存的状态
+	fn save_state(&self, task_proxy:&dyn ITaskProxy)->Option<serde_json::Value>{None}
+	fn load_state(&mut self, state:&serde_json::Value, task_proxy:&mut dyn ITaskProxy)->Result<(), Box<dyn std::error::Error>>{Ok(())}
 }
 
 #[allow(unused_variables)]
-pub trait IConditional{
+pub trait IConditional:Send{
 	fn initialize_variables(&mut self, task_proxy:&dyn ITaskProxy)->Result<(), Box<dyn std::error::Error>>{Ok(())}
 	fn on_awake(&mut self, task_proxy:&dyn ITaskProxy, behavior_tree:&dyn IBehaviorTree){}
     fn on_start(&mut self, task_proxy:&dyn ITaskProxy, behavior_tree:&dyn IBehaviorTree){}
     fn on_update(&mut self, task_proxy:&dyn ITaskProxy, behavior_tree:&dyn IBehaviorTree)->TaskStatus;
     fn on_end(&mut self, task_proxy:&dyn ITaskProxy, behavior_tree:&dyn IBehaviorTree){}
     fn on_complete(&mut self, task_proxy:&dyn ITaskProxy, behavior_tree:&dyn IBehaviorTree){}
+
+	fn save_state(&self, task_proxy:&dyn ITaskProxy)->Option<serde_json::Value>{None}
+	fn load_state(&mut self, state:&serde_json::Value, task_proxy:&dyn ITaskProxy)->Result<(), Box<dyn std::error::Error>>{Ok(())}
 }
 
 
 #[allow(unused_variables)]
-pub trait  IParentTask {
+pub trait  IParentTask:Send {
 	fn initialize_variables(&mut self, task_proxy:&dyn ITaskProxy)->Result<(), Box<dyn std::error::Error>>{Ok(())}
 	fn on_awake(&mut self, task_proxy:&dyn ITaskProxy, behavior_tree:&dyn IBehaviorTree){}
     fn on_start(&mut self, task_proxy:&dyn ITaskProxy, behavior_tree:&dyn IBehaviorTree){}   
@@ -323,6 +454,10 @@ pub trait  IParentTask {
 
 	fn on_conditional_abort(&mut self, index:u32,task_proxy:&dyn ITaskProxy, behavior_tree:&dyn IBehaviorTree){}
 	fn on_cancel_conditional_abort(&mut self, task_proxy:&dyn ITaskProxy, behavior_tree:&dyn IBehaviorTree){} //当Abort取消的时候，会调用这个接口
+
+	//	快照的时候保存任务自己的状态，默认没有需要保存的状态
+	fn save_state(&self, task_proxy:&dyn ITaskProxy)->Option<serde_json::Value>{None}
+	fn load_state(&mut self, state:&serde_json::Value, task_proxy:&dyn ITaskProxy)->Result<(), Box<dyn std::error::Error>>{Ok(())}
 }
 
 pub trait IComposite:IParentTask{
@@ -1,27 +1,12 @@
-use std::rc::{Rc, Weak};
+use std::rc::Rc;
 use serde_json::from_str;
 use std::collections::HashMap;
-use std::cell::{Ref, RefCell};
+use std::cell::RefCell;
+use std::sync::{Arc, RwLock};
 
-use super::interface::{IParser, TaskAddData,ITaskProxy, IAction, IConditional, IComposite, IDecorator, RealTaskType};
-use super::runtime::TaskProxy;
+use super::interface::{IParser, TaskAddData,ITaskProxy, IAction, IConditional, IComposite, IDecorator};
+use super::registry::{TaskRegistry, TaskConfig, TreeConfig, TaskIds};
 use super::consts::AbortType;
-use super::composite::sequence::Sequence;
-use super::composite::selector::Selector;
-use super::composite::parallel::Parallel;
-use super::composite::parallel_selector::ParallelSelector;
-use super::composite::if_else::If;
-use super::action::idle::Idle;
-use super::action::play_ani_for_sync::PlayAniForSync;
-use super::action::role_follow_joystick::RoleFollowJoystick;
-
-use super::decorator::return_failure::ReturnFailure;
-use super::decorator::return_success::ReturnSuccess;
-use super::decorator::until_failure::UntilFailure;
-use super::decorator::until_success::UntilSuccess;
-use super::decorator::until_forever::UntilForever;
-
-use super::conditional::need_follow_joystick::NeedFollowJoystick;
 use super::interface::IClock;
 use super::runtime::BehaviorTree;
 use super::interface::IRuntimeEventHandle;
@@ -31,200 +16,104 @@ use super::interface::TaskRuntimeData;
 use super::consts::TaskStatus;
 
 pub struct JsonParser{
-    action_fn: HashMap<String, fn(variables:HashMap<String, serde_json::Value>,id_2_task:Rc<RefCell<Box<HashMap<i32, Weak<RefCell<Box<dyn ITaskProxy>>>>>>>) -> Box<dyn IAction>>,
-    conditional_fn: HashMap<String, fn(variables:HashMap<String, serde_json::Value>,id_2_task:Rc<RefCell<Box<HashMap<i32, Weak<RefCell<Box<dyn ITaskProxy>>>>>>>) -> Box<dyn IConditional>>,
-    composite_fn: HashMap<String, fn(variables:HashMap<String, serde_json::Value>,id_2_task:Rc<RefCell<Box<HashMap<i32, Weak<RefCell<Box<dyn ITaskProxy>>>>>>>) -> Box<dyn IComposite>>,
-    decorator_fn: HashMap<String, fn(variables:HashMap<String, serde_json::Value>,id_2_task:Rc<RefCell<Box<HashMap<i32, Weak<RefCell<Box<dyn ITaskProxy>>>>>>>) -> Box<dyn IDecorator>>,
+    registry:Arc<RwLock<TaskRegistry>>,
 }
 
 impl JsonParser{
     pub fn new() -> Rc<RefCell<Box<dyn IParser>>>{
-        let mut parser = Self{
-            action_fn: HashMap::new(),
-            conditional_fn: HashMap::new(),
-            composite_fn: HashMap::new(),
-            decorator_fn: HashMap::new(),
-        };
-
-        //  注册默认节点
-        parser.register_composite_fn("BehaviorDesigner.Runtime.Tasks.Sequence", |variables, id_2_task| -> Box<dyn IComposite> {Box::new(Sequence::new())});
-        parser.register_composite_fn("BehaviorDesigner.Runtime.Tasks.Selector", |variables, id_2_task| -> Box<dyn IComposite> {Box::new(Selector::new())});
-        parser.register_composite_fn("BehaviorDesigner.Runtime.Tasks.Parallel", |variables, id_2_task| -> Box<dyn IComposite> {Box::new(Parallel::new())});
-        parser.register_composite_fn("BehaviorDesigner.Runtime.Tasks.ParallelSelector", |variables, id_2_task| -> Box<dyn IComposite> {Box::new(ParallelSelector::new())});
-        parser.register_composite_fn("BehaviorDesigner.Runtime.Tasks.If", |variables, id_2_task| -> Box<dyn IComposite> {Box::new(If::new())});
-
-        parser.register_action_fn("BehaviorDesigner.Runtime.Tasks.Idle", |variables, id_2_task| -> Box<dyn IAction> {Box::new(Idle::new())});
-        parser.register_action_fn("BehaviorDesigner.Runtime.Tasks.PlayAniForSync", |variables, id_2_task| -> Box<dyn IAction> {Box::new(PlayAniForSync::new())});
-        parser.register_action_fn("BehaviorDesigner.Runtime.Tasks.RoleFollowJoystick", |variables, id_2_task| -> Box<dyn IAction> {Box::new(RoleFollowJoystick::new())});
-        parser.register_action_fn("BehaviorDesigner.Runtime.Tasks.Role.MainRole.RoleFollowJoystick", |variables, id_2_task| -> Box<dyn IAction> {Box::new(RoleFollowJoystick::new())});
-
-        parser.register_decorator_fn("BehaviorDesigner.Runtime.Tasks.ReturnFailure", |variables, id_2_task| -> Box<dyn IDecorator> {Box::new(ReturnFailure::new())});
-        parser.register_decorator_fn("BehaviorDesigner.Runtime.Tasks.ReturnSuccess", |variables, id_2_task| -> Box<dyn IDecorator> {Box::new(ReturnSuccess::new())});
-        parser.register_decorator_fn("BehaviorDesigner.Runtime.Tasks.UntilFailure", |variables, id_2_task| -> Box<dyn IDecorator> {Box::new(UntilFailure::new())});
-        parser.register_decorator_fn("BehaviorDesigner.Runtime.Tasks.UntilSuccess", |variables, id_2_task| -> Box<dyn IDecorator> {Box::new(UntilSuccess::new())});
-        parser.register_decorator_fn("BehaviorDesigner.Runtime.Tasks.UntilForever", |variables, id_2_task| -> Box<dyn IDecorator> {Box::new(UntilForever::new())});
-
-        parser.register_conditional_fn("BehaviorDesigner.Runtime.Tasks.Role.MainRole.NeedFollowJoystick", |variables, id_2_task| -> Box<dyn IConditional> {Box::new(NeedFollowJoystick::new())});
-        Rc::new(RefCell::new(Box::new(parser)))
+        Self::with_registry(TaskRegistry::new())
     }
 
-    pub fn register_action_fn(&mut self, name:&str, action_generate_fn:fn(variables:HashMap<String, serde_json::Value>,id_2_task:Rc<RefCell<Box<HashMap<i32, Weak<RefCell<Box<dyn ITaskProxy>>>>>>>) -> Box<dyn IAction>){
-        self.action_fn.insert(name.to_string(), action_generate_fn);
+    //  跟其它解析器共用同一个注册表
+    pub fn with_registry(registry:Arc<RwLock<TaskRegistry>>) -> Rc<RefCell<Box<dyn IParser>>>{
+        Rc::new(RefCell::new(Box::new(Self::from_registry(registry))))
     }
 
-    pub fn register_conditional_fn(&mut self, name:&str, conditional_generate_fn:fn(variables:HashMap<String, serde_json::Value>,id_2_task:Rc<RefCell<Box<HashMap<i32, Weak<RefCell<Box<dyn ITaskProxy>>>>>>>) -> Box<dyn IConditional>){
-        self.conditional_fn.insert(name.to_string(), conditional_generate_fn);
+    //  不包在Rc里，可以放到Arc里给多个线程共用
+    pub fn from_registry(registry:Arc<RwLock<TaskRegistry>>) -> Self{
+        Self{registry}
     }
 
-    pub fn register_composite_fn(&mut self, name:&str, composite_generate_fn:fn(variables:HashMap<String, serde_json::Value>,id_2_task:Rc<RefCell<Box<HashMap<i32, Weak<RefCell<Box<dyn ITaskProxy>>>>>>>) -> Box<dyn IComposite>){
-        self.composite_fn.insert(name.to_string(), composite_generate_fn);
+    pub fn registry(&self) -> Arc<RwLock<TaskRegistry>>{
+        self.registry.clone()
     }
 
-    pub fn register_decorator_fn(&mut self, name:&str, decorator_generate_fn:fn(variables:HashMap<String, serde_json::Value>,id_2_task:Rc<RefCell<Box<HashMap<i32, Weak<RefCell<Box<dyn ITaskProxy>>>>>>>) -> Box<dyn IDecorator>){
-        self.decorator_fn.insert(name.to_string(), decorator_generate_fn);
+    pub fn register_action_fn(&mut self, name:&str, action_generate_fn:fn(variables:HashMap<String, serde_json::Value>,task_ids:TaskIds) -> Box<dyn IAction>){
+        self.registry.write().unwrap().register_action_fn(name, action_generate_fn);
     }
 
-
-    fn generate_real_task(&self, corresponding_type:&str, variables:HashMap<String, serde_json::Value>,id_2_task:Rc<RefCell<Box<HashMap<i32, Weak<RefCell<Box<dyn ITaskProxy>>>>>>>) -> Result<RealTaskType, Box<dyn std::error::Error>>{
-        if self.action_fn.contains_key(corresponding_type){
-            return Ok(RealTaskType::Action(self.action_fn.get(corresponding_type).unwrap()(variables, id_2_task)));
-        }
-        if self.conditional_fn.contains_key(corresponding_type){
-            return Ok(RealTaskType::Conditional(self.conditional_fn.get(corresponding_type).unwrap()(variables, id_2_task)));
-        }
-        if self.composite_fn.contains_key(corresponding_type){
-            return Ok(RealTaskType::Composite(self.composite_fn.get(corresponding_type).unwrap()(variables, id_2_task)));
-        }
-        if self.decorator_fn.contains_key(corresponding_type){
-            return Ok(RealTaskType::Decorator(self.decorator_fn.get(corresponding_type).unwrap()(variables, id_2_task)));
-        }
-
-        Err(format!("generate_real_task not implemented for corresponding_type: {}", corresponding_type).into())
+    pub fn register_conditional_fn(&mut self, name:&str, conditional_generate_fn:fn(variables:HashMap<String, serde_json::Value>,task_ids:TaskIds) -> Box<dyn IConditional>){
+        self.registry.write().unwrap().register_conditional_fn(name, conditional_generate_fn);
     }
 
-    fn generate_task_proxy(&self, task_json:&serde_json::Value, id_2_task:&Rc<RefCell<Box<HashMap<i32, Weak<RefCell<Box<dyn ITaskProxy>>>>>>>, all_tasks:&mut Vec<Weak<RefCell<Box<dyn ITaskProxy>>>>) -> Result<Rc<RefCell<Box<dyn ITaskProxy>>>, Box<dyn std::error::Error>>{
-        let corresponding_type = task_json["Type"].as_str().unwrap();
-
-        let mut variables:HashMap<String, serde_json::Value> = HashMap::new();
-        for (key, value) in task_json.as_object().unwrap().iter() {
-            match key.as_str() {
-                "Type"|"Children"|"Name"|"ID"|"Instant"|"Disabled"|"BehaviorDesigner.Runtime.Tasks.AbortType,abortType"=> {
-                    ()
-                },
-                _ => {
-                    variables.insert(key.to_string(), value.clone());
-                },
-            }
-        }
+    pub fn register_composite_fn(&mut self, name:&str, composite_generate_fn:fn(variables:HashMap<String, serde_json::Value>,task_ids:TaskIds) -> Box<dyn IComposite>){
+        self.registry.write().unwrap().register_composite_fn(name, composite_generate_fn);
+    }
 
-        let real_task: RealTaskType = self.generate_real_task(corresponding_type, variables, id_2_task.clone())?;
+    pub fn register_decorator_fn(&mut self, name:&str, decorator_generate_fn:fn(variables:HashMap<String, serde_json::Value>,task_ids:TaskIds) -> Box<dyn IDecorator>){
+        self.registry.write().unwrap().register_decorator_fn(name, decorator_generate_fn);
+    }
 
+    fn parse_task_config(task_json:&serde_json::Value) -> Result<TaskConfig, Box<dyn std::error::Error>>{
+        let corresponding_type = task_json["Type"].as_str().ok_or("task Type is missing")?;
         let name = match task_json["Name"].as_str(){
             Some(name) => name,
             None => "",
         };
+        let mut task_config = TaskConfig::new(corresponding_type, name, 0);
 
-        let mut task_proxy: TaskProxy =TaskProxy::new(corresponding_type, name, real_task);
-
-        for (key, value) in task_json.as_object().unwrap().iter() {
+        for (key, value) in task_json.as_object().ok_or("task config is not an object")?.iter() {
             match key.as_str() {
-                "ID" => task_proxy.set_id(value.as_i64().unwrap() as i32),
-                "Instant" => task_proxy.set_instant(value.as_bool().unwrap()),
-                "Disabled" => task_proxy.set_disabled(value.as_bool().unwrap()),
-                "BehaviorDesigner.Runtime.Tasks.AbortType,abortType" => 
+                "Type"|"Children"|"Name" => (),
+                "ID" => task_config.id = value.as_i64().ok_or("task ID is not an integer")? as i32,
+                "Instant" => task_config.instant = Some(value.as_bool().ok_or("task Instant is not a bool")?),
+                "Disabled" => task_config.disabled = Some(value.as_bool().ok_or("task Disabled is not a bool")?),
+                "BehaviorDesigner.Runtime.Tasks.AbortType,abortType" =>
                 {
-                    let abort_type = match value.as_str().unwrap(){
-                        "None" => AbortType::None,
-                        "Self" => AbortType::Self_,
-                        "LowerPriority" => AbortType::LowerPriority,
-                        "Both" => AbortType::Both,
+                    let abort_type = match value.as_str(){
+                        Some("Self") => AbortType::Self_,
+                        Some("LowerPriority") => AbortType::LowerPriority,
+                        Some("Both") => AbortType::Both,
                         _ => AbortType::None,
                     };
-                    task_proxy.set_abort_type(abort_type);
+                    task_config.abort_type = Some(abort_type);
+                },
+                _ => {
+                    task_config.variables.insert(key.to_string(), value.clone());
                 },
-                _ => (),
             }
         }
 
-
-
-        if task_proxy.id() == 0{
-            return Err("ID is 0".into());
-        }
-
-        let task_proxy:Rc<RefCell<Box<dyn ITaskProxy>>> = Rc::new(RefCell::new(Box::new(task_proxy)));
-
-        if id_2_task.borrow().contains_key(&task_proxy.borrow().id()){
-            return Err("ID already exists".into());
-        }
-
-        id_2_task.borrow_mut().insert(task_proxy.borrow().id(), Rc::downgrade(&task_proxy));
-        all_tasks.push(Rc::downgrade(&task_proxy));
-
-        match task_json["Children"].as_array(){
-            Some(children) => 
+        if let Some(children) = task_json["Children"].as_array(){
             for child in children.iter(){
-                let child = self.generate_task_proxy(child, id_2_task, all_tasks)?;
-                task_proxy.borrow_mut().add_child(&child);
-                //Rc::get_mut(&mut task_proxy).unwrap().add_child(&child);
-            },
-            None => (),
-        };
-
-        Ok(task_proxy)
-    }
-
-    fn initialize_task(&self, task_json:&serde_json::Value, id_2_task:&Rc<RefCell<Box<HashMap<i32, Weak<RefCell<Box<dyn ITaskProxy>>>>>>>, all_tasks:&mut Vec<Weak<RefCell<Box<dyn ITaskProxy>>>>) -> Result<Rc<RefCell<Box<dyn ITaskProxy>>>, Box<dyn std::error::Error>>{
-        self.generate_task_proxy(task_json, id_2_task, all_tasks)
-    }
-
-    fn initialize_parent_task(&self, task_proxy:&mut Rc<RefCell<Box<dyn ITaskProxy>>>, task_add_data:&mut TaskAddData){
-        let task_proxy_bak = task_proxy.clone();
-        //let task_proxy = task_proxy.borrow();
-        
-        if task_proxy.borrow().is_implements_iparenttask(){
-            let old_parent = std::mem::replace(&mut task_add_data.parent, Some(Rc::downgrade(&task_proxy_bak)));
-
-            task_proxy.borrow_mut().children_mut().iter_mut().for_each(|child|{
-                self.initialize_parent_task(child, task_add_data);
-            });
-        
-            task_add_data.parent = old_parent;
+                task_config.children.push(Self::parse_task_config(child)?);
+            }
         }
 
+        Ok(task_config)
     }
-}
 
-impl IParser for JsonParser{
-    fn deserialize(&self, config:&Vec<u8>, task_add_data:&mut TaskAddData) -> Result<Rc<RefCell<Box<dyn ITaskProxy>>>, Box<dyn std::error::Error>>{
-        let json: serde_json::Value = from_str(std::str::from_utf8(config)?).unwrap();
+    //  json配置转成跟格式无关的配置，编译成二进制格式的时候也用这个
+    pub fn parse_tree_config(config:&Vec<u8>) -> Result<TreeConfig, Box<dyn std::error::Error>>{
+        let json: serde_json::Value = from_str(std::str::from_utf8(config)?)?;
         let root_task_json: &serde_json::Value = json.get("RootTask").ok_or("json文件缺少RootTask的配置")?;
-        let mut all_tasks:Vec<Weak<RefCell<Box<dyn ITaskProxy>>>> = Vec::new();
-        let id_2_task = Rc::new(RefCell::new(Box::new(HashMap::new())));
-        let mut root_task = self.initialize_task(root_task_json, &id_2_task,&mut all_tasks)?;
+        let root_task = Self::parse_task_config(root_task_json)?;
 
-        let mut detached_tasks:Vec<Rc<RefCell<Box<dyn ITaskProxy>>>> = Vec::new();
-        if let Some(_) = json.get("DetachedTasksConfigs"){
-            let detached_tasks_configs = json.get("DetachedTasksConfigs").unwrap().as_array().unwrap();
+        let mut detached_tasks = Vec::new();
+        if let Some(detached_tasks_configs) = json.get("DetachedTasksConfigs").and_then(|configs| configs.as_array()){
             for detached_task_config in detached_tasks_configs.iter(){
-                let detached_task = self.initialize_task(detached_task_config, &id_2_task,&mut all_tasks)?;
-                detached_tasks.push(detached_task);
+                detached_tasks.push(Self::parse_task_config(detached_task_config)?);
             }
         }
 
-        //  初始化任务变量
-        for task in all_tasks.iter(){
-            let task = task.upgrade().unwrap();
-            task.borrow_mut().initialize_variables()?;
-        }
-
-        self.initialize_parent_task(&mut root_task,task_add_data);
-
-        for detached_task in detached_tasks.iter_mut(){
-            self.initialize_parent_task(detached_task,task_add_data);
-        }
+        Ok(TreeConfig{root_task, detached_tasks})
+    }
+}
 
-        Ok(root_task)
+impl IParser for JsonParser{
+    fn deserialize(&self, config:&Vec<u8>, task_add_data:&mut TaskAddData) -> Result<Box<dyn ITaskProxy>, Box<dyn std::error::Error>>{
+        let tree_config = Self::parse_tree_config(config)?;
+        self.registry.read().map_err(|_| "task registry lock poisoned")?.build(tree_config, task_add_data)
     }
 }
 
@@ -261,7 +150,6 @@ mod tests {
 
         assert!(result.is_ok());
         let root_task = result.unwrap();
-        let root_task = root_task.borrow();
 
         // 检查根节点类型名称
         assert_eq!(root_task.corresponding_type(), "BehaviorDesigner.Runtime.Tasks.Idle");
@@ -288,7 +176,6 @@ mod tests {
 
         assert!(result.is_ok());
         let root_task = result.unwrap();
-        let root_task = root_task.borrow();
 
         assert_eq!(root_task.corresponding_type(), "BehaviorDesigner.Runtime.Tasks.Role.MainRole.NeedFollowJoystick");
     }
@@ -347,11 +234,11 @@ mod tests {
         }
         fn post_on_end(&self, behavior_tree: &dyn IBehaviorTree, task_runtime_data: &TaskRuntimeData, stack_runtime_data: &StackRuntimeData, task: &dyn ITaskProxy, timestamp_in_mill: u64) {
         }
-        fn action_post_on_start(&self, behavior_tree: &dyn IBehaviorTree, task_runtime_data: &TaskRuntimeData, stack_runtime_data: &StackRuntimeData, task: &dyn ITaskProxy, datas: Vec<Vec<u8>>) {
+        fn action_post_on_start(&self, behavior_tree: &dyn IBehaviorTree, task_runtime_data: &TaskRuntimeData, stack_runtime_data: &StackRuntimeData, task: &dyn ITaskProxy, datas: &[Vec<u8>]) {
         }
-        fn action_post_on_update(&self, behavior_tree: &dyn IBehaviorTree, task_runtime_data: &TaskRuntimeData, stack_runtime_data: &StackRuntimeData, task: &dyn ITaskProxy, timestamp_in_mill: u64, status: TaskStatus, datas: Vec<Vec<u8>>) {
+        fn action_post_on_update(&self, behavior_tree: &dyn IBehaviorTree, task_runtime_data: &TaskRuntimeData, stack_runtime_data: &StackRuntimeData, task: &dyn ITaskProxy, timestamp_in_mill: u64, status: TaskStatus, datas: &[Vec<u8>]) {
         }
-        fn action_post_on_end(&self, behavior_tree: &dyn IBehaviorTree, task_runtime_data: &TaskRuntimeData, stack_runtime_data: &StackRuntimeData, task: &dyn ITaskProxy, timestamp_in_mill: u64, datas: Vec<Vec<u8>>) {
+        fn action_post_on_end(&self, behavior_tree: &dyn IBehaviorTree, task_runtime_data: &TaskRuntimeData, stack_runtime_data: &StackRuntimeData, task: &dyn ITaskProxy, timestamp_in_mill: u64, datas: &[Vec<u8>]) {
         }
         fn parallel_pre_on_start(&self, behavior_tree: &dyn IBehaviorTree, task_runtime_data: &TaskRuntimeData, stack_runtime_data: &StackRuntimeData, task: &dyn ITaskProxy) {
         }
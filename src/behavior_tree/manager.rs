@@ -0,0 +1,792 @@
+use std::{rc::{Rc, Weak}, cell::RefCell, collections::{BTreeMap, BTreeSet, HashMap}, time::{Duration, Instant}};
+
+use super::interface::{IBehaviorTree, IClock, IParser, IRuntimeEventHandle, TaskAddData};
+use super::profile::{ProfileRuntimeEventHandle, TaskProfile, TaskTypeCost};
+use super::runtime::BehaviorTree;
+
+//	manager里的树，单线程共享
+pub type SharedBehaviorTree = Rc<RefCell<Box<dyn IBehaviorTree>>>;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum PendingOperation{
+	Enable,
+	Disable,
+	//	已经从manager里移除，还在运行的话要disable
+	Remove,
+}
+
+//	树正在被借用的时候返回None
+fn try_run_operation(behavior_tree:&SharedBehaviorTree, operation:PendingOperation)->Option<Result<(), Box<dyn std::error::Error>>>{
+	let mut behavior_tree = behavior_tree.try_borrow_mut().ok()?;
+	Some(match operation{
+		PendingOperation::Enable => behavior_tree.enable(),
+		PendingOperation::Disable => behavior_tree.disable(),
+		PendingOperation::Remove if behavior_tree.is_runnning() => behavior_tree.disable(),
+		PendingOperation::Remove => Ok(()),
+	})
+}
+
+struct ManagedTree{
+	template:String,
+	unit_id:u64,
+	behavior_tree:SharedBehaviorTree,
+	//	0表示每次update都执行
+	tick_interval_in_milli:u64,
+	lod:Option<usize>,
+	next_tick_time:u64,
+	last_tick_time:Option<u64>,
+	priority:i32,
+	//	到时间但因为预算用完没执行的连续帧数
+	deferred_frames:u32,
+	cost:TreeTickCost,
+	profile:Rc<RefCell<TaskProfile>>,
+}
+
+//	时间预算用完的时候，到时间但没执行的树按什么顺序留到下一帧
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ScheduleOrder{
+	//	从上一帧最后执行的树后面接着执行
+	RoundRobin,
+	//	优先级高的先执行，相同的按树ID
+	//	被推迟的树每推迟一帧优先级临时加1，执行以后恢复，避免低优先级的树一直轮不到
+	Priority,
+}
+
+#[derive(Clone, Copy, Default, PartialEq, Debug)]
+pub struct TreeTickCost{
+	pub ticks:u64,
+	pub total:Duration,
+	pub last:Duration,
+	pub max:Duration,
+	//	连续超过阈值的次数
+	pub over_threshold_ticks:u32,
+}
+
+impl TreeTickCost{
+	pub fn average(&self)->Duration{
+		self.total / self.ticks.max(1) as u32
+	}
+}
+
+#[derive(Clone, Copy, Default, PartialEq, Debug)]
+pub struct FrameStats{
+	pub due:usize,
+	pub ticked:usize,
+	pub deferred:usize,
+	pub elapsed:Duration,
+}
+
+//	连续多次执行超过阈值的树和它最耗时的任务类型
+#[derive(Clone, PartialEq, Debug)]
+pub struct HotTree{
+	pub tree_id:u64,
+	pub template:String,
+	pub cost:TreeTickCost,
+	pub hot_task_types:Vec<(String, TaskTypeCost)>,
+}
+
+//	间隔相同的树按黄金分割错开第一次执行的时间，避免挤在同一帧
+const STAGGER_RATIO:f64 = 0.618_033_988_75;
+
+//	按模板创建树，按树ID和单位ID索引，每帧按树ID从小到大update
+//	update过程中可以增删树或者开关树：正在update的树上的操作延后到它update结束，
+//	本帧新创建的树下一帧才会update，本帧被关掉或者移除的树不会再update
+//	每棵树可以有自己的执行间隔或者LOD档位，时间都用IClock，任务看到的时间跟每帧执行是一样的
+//	设置了每帧的时间预算以后，用完预算剩下的树留到下一帧
+pub struct BehaviorTreeManager{
+	clock:Weak<RefCell<Box<dyn IClock>>>,
+	parser:Weak<RefCell<Box<dyn IParser>>>,
+	templates:HashMap<String, Vec<u8>>,
+	trees:BTreeMap<u64, ManagedTree>,
+	unit_trees:HashMap<u64, BTreeSet<u64>>,
+	pending_operations:Vec<(u64, SharedBehaviorTree, PendingOperation)>,
+	errors:Vec<(u64, Box<dyn std::error::Error>)>,
+	//	LOD档位对应的执行间隔
+	lod_tick_intervals:Vec<u64>,
+	stagger_counters:HashMap<u64, u64>,
+
+	frame_budget:Option<Duration>,
+	schedule_order:ScheduleOrder,
+	round_robin_cursor:Option<u64>,
+	//	(阈值, 连续次数)
+	hot_tree_threshold:Option<(Duration, u32)>,
+	frame_stats:FrameStats,
+}
+
+impl BehaviorTreeManager{
+	pub fn new(clock:&Weak<RefCell<Box<dyn IClock>>>, parser:Weak<RefCell<Box<dyn IParser>>>)->Rc<RefCell<Self>>{
+		Rc::new(RefCell::new(Self{
+			clock:clock.clone(),
+			parser,
+			templates:HashMap::new(),
+			trees:BTreeMap::new(),
+			unit_trees:HashMap::new(),
+			pending_operations:Vec::new(),
+			errors:Vec::new(),
+			lod_tick_intervals:Vec::new(),
+			stagger_counters:HashMap::new(),
+			frame_budget:None,
+			schedule_order:ScheduleOrder::RoundRobin,
+			round_robin_cursor:None,
+			hot_tree_threshold:None,
+			frame_stats:FrameStats::default(),
+		}))
+	}
+
+	//	注册的时候先解析一遍，配置有问题直接报错
+	pub fn register_template(&mut self, name:&str, config:Vec<u8>)->Result<(), Box<dyn std::error::Error>>{
+		if self.templates.contains_key(name){
+			return Err(format!("behavior tree template {} already registered", name).into());
+		}
+		let parser = self.parser.upgrade().ok_or("parser is released")?;
+		parser.borrow().deserialize(&config, &mut TaskAddData::new())?;
+		self.templates.insert(name.to_string(), config);
+		Ok(())
+	}
+
+	pub fn has_template(&self, name:&str)->bool{
+		self.templates.contains_key(name)
+	}
+
+	//	创建出来的树还没有enable
+	pub fn spawn(&mut self, tree_id:u64, template:&str, unit_id:u64, runtime_event_handle:Box<dyn IRuntimeEventHandle>)->Result<SharedBehaviorTree, Box<dyn std::error::Error>>{
+		if self.trees.contains_key(&tree_id){
+			return Err(format!("behavior tree {} already exists", tree_id).into());
+		}
+		let config = self.templates.get(template).ok_or_else(|| format!("unknown behavior tree template {}", template))?;
+
+		let profile = TaskProfile::new();
+		profile.borrow_mut().set_enabled(self.hot_tree_threshold.is_some());
+		let runtime_event_handle = Box::new(ProfileRuntimeEventHandle::new(profile.clone(), runtime_event_handle));
+		let behavior_tree = BehaviorTree::new(tree_id, config, unit_id, &self.clock, runtime_event_handle, self.parser.clone());
+		self.trees.insert(tree_id, ManagedTree{
+			template:template.to_string(),
+			unit_id,
+			behavior_tree:behavior_tree.clone(),
+			tick_interval_in_milli:0,
+			lod:None,
+			next_tick_time:0,
+			last_tick_time:None,
+			priority:0,
+			deferred_frames:0,
+			cost:TreeTickCost::default(),
+			profile,
+		});
+		self.unit_trees.entry(unit_id).or_default().insert(tree_id);
+		Ok(behavior_tree)
+	}
+
+	pub fn get(&self, tree_id:u64)->Option<SharedBehaviorTree>{
+		self.trees.get(&tree_id).map(|tree| tree.behavior_tree.clone())
+	}
+
+	pub fn template_of(&self, tree_id:u64)->Option<&str>{
+		self.trees.get(&tree_id).map(|tree| tree.template.as_str())
+	}
+
+	pub fn tree_ids(&self)->Vec<u64>{
+		self.trees.keys().cloned().collect()
+	}
+
+	pub fn trees_of_unit(&self, unit_id:u64)->Vec<u64>{
+		match self.unit_trees.get(&unit_id){
+			Some(tree_ids) => tree_ids.iter().cloned().collect(),
+			None => Vec::new(),
+		}
+	}
+
+	pub fn len(&self)->usize{
+		self.trees.len()
+	}
+
+	pub fn is_empty(&self)->bool{
+		self.trees.is_empty()
+	}
+
+	fn now(&self)->u64{
+		match self.clock.upgrade(){
+			Some(clock) => clock.borrow().timestamp_in_mill(),
+			None => 0,
+		}
+	}
+
+	//	执行过的树从上次执行的时间算，没执行过的按间隔错开
+	pub fn set_tick_interval(&mut self, tree_id:u64, tick_interval_in_milli:u64)->Result<(), Box<dyn std::error::Error>>{
+		let now = self.now();
+		let tree = self.trees.get(&tree_id).ok_or_else(|| format!("unknown behavior tree {}", tree_id))?;
+		let next_tick_time = match tree.last_tick_time{
+			Some(last_tick_time) => last_tick_time + tick_interval_in_milli,
+			None if tick_interval_in_milli == 0 => now,
+			None => {
+				let counter = self.stagger_counters.entry(tick_interval_in_milli).or_insert(0);
+				let offset = (*counter as f64 * STAGGER_RATIO).fract() * tick_interval_in_milli as f64;
+				*counter += 1;
+				now + offset as u64
+			},
+		};
+
+		let tree = self.trees.get_mut(&tree_id).unwrap();
+		tree.tick_interval_in_milli = tick_interval_in_milli;
+		tree.lod = None;
+		tree.next_tick_time = next_tick_time;
+		Ok(())
+	}
+
+	pub fn tick_interval(&self, tree_id:u64)->Option<u64>{
+		self.trees.get(&tree_id).map(|tree| tree.tick_interval_in_milli)
+	}
+
+	//	档位从0开始，一般0是离玩家最近的
+	pub fn set_lod_tick_intervals(&mut self, tick_intervals_in_milli:Vec<u64>){
+		self.lod_tick_intervals = tick_intervals_in_milli;
+	}
+
+	pub fn set_lod(&mut self, tree_id:u64, lod:usize)->Result<(), Box<dyn std::error::Error>>{
+		let tick_interval_in_milli = *self.lod_tick_intervals.get(lod).ok_or_else(|| format!("unknown lod {}", lod))?;
+		if self.trees.get(&tree_id).is_some_and(|tree| tree.lod == Some(lod)){
+			return Ok(());
+		}
+		self.set_tick_interval(tree_id, tick_interval_in_milli)?;
+		self.trees.get_mut(&tree_id).unwrap().lod = Some(lod);
+		Ok(())
+	}
+
+	pub fn lod(&self, tree_id:u64)->Option<usize>{
+		self.trees.get(&tree_id).and_then(|tree| tree.lod)
+	}
+
+	//	None表示不限制，预算用完之前至少会执行一棵树
+	pub fn set_frame_budget(&mut self, frame_budget:Option<Duration>){
+		self.frame_budget = frame_budget;
+	}
+
+	pub fn set_schedule_order(&mut self, schedule_order:ScheduleOrder){
+		self.schedule_order = schedule_order;
+	}
+
+	pub fn set_priority(&mut self, tree_id:u64, priority:i32)->Result<(), Box<dyn std::error::Error>>{
+		let tree = self.trees.get_mut(&tree_id).ok_or_else(|| format!("unknown behavior tree {}", tree_id))?;
+		tree.priority = priority;
+		Ok(())
+	}
+
+	pub fn last_frame_stats(&self)->FrameStats{
+		self.frame_stats
+	}
+
+	pub fn tick_cost(&self, tree_id:u64)->Option<TreeTickCost>{
+		self.trees.get(&tree_id).map(|tree| tree.cost)
+	}
+
+	//	设置以后开始按任务类型统计耗时，None关闭统计
+	pub fn set_hot_tree_threshold(&mut self, threshold:Option<(Duration, u32)>){
+		self.hot_tree_threshold = threshold;
+		for tree in self.trees.values_mut(){
+			tree.cost.over_threshold_ticks = 0;
+			let mut profile = tree.profile.borrow_mut();
+			profile.clear();
+			profile.set_enabled(threshold.is_some());
+		}
+	}
+
+	//	连续超过阈值的树，按平均耗时从大到小，每棵树带上最耗时的task_type_count个任务类型
+	pub fn hot_trees(&self, task_type_count:usize)->Vec<HotTree>{
+		let consecutive_ticks = match self.hot_tree_threshold{
+			Some((_, consecutive_ticks)) => consecutive_ticks,
+			None => return Vec::new(),
+		};
+
+		let mut hot_trees:Vec<HotTree> = self.trees.iter()
+			.filter(|(_, tree)| tree.cost.over_threshold_ticks >= consecutive_ticks)
+			.map(|(tree_id, tree)| HotTree{
+				tree_id:*tree_id,
+				template:tree.template.clone(),
+				cost:tree.cost,
+				hot_task_types:tree.profile.borrow().hottest(task_type_count),
+			})
+			.collect();
+		hot_trees.sort_by(|a, b| b.cost.average().cmp(&a.cost.average()).then_with(|| a.tree_id.cmp(&b.tree_id)));
+		hot_trees
+	}
+
+	fn record_tick_cost(&mut self, tree_id:u64, cost:Duration){
+		let threshold = self.hot_tree_threshold.map(|(threshold, _)| threshold);
+		if let Some(tree) = self.trees.get_mut(&tree_id){
+			tree.cost.ticks += 1;
+			tree.cost.total += cost;
+			tree.cost.last = cost;
+			tree.cost.max = tree.cost.max.max(cost);
+			match threshold{
+				Some(threshold) if cost >= threshold => tree.cost.over_threshold_ticks += 1,
+				_ => tree.cost.over_threshold_ticks = 0,
+			}
+		}
+	}
+
+	//	到时间的树按调度顺序排好
+	fn due_tree_ids(&self, now:u64)->Vec<u64>{
+		let mut tree_ids:Vec<u64> = self.trees.iter().filter(|(_, tree)| tree.next_tick_time <= now).map(|(tree_id, _)| *tree_id).collect();
+		match self.schedule_order{
+			ScheduleOrder::RoundRobin => {
+				if let Some(cursor) = self.round_robin_cursor{
+					let start = tree_ids.partition_point(|tree_id| *tree_id <= cursor);
+					tree_ids.rotate_left(start);
+				}
+			},
+			ScheduleOrder::Priority => {
+				tree_ids.sort_by_key(|tree_id| {
+					let tree = &self.trees[tree_id];
+					-(tree.priority as i64 + tree.deferred_frames as i64)
+				});
+			},
+		}
+		tree_ids
+	}
+
+	//	到时间的树记下这次执行的时间
+	fn take_due_tree(&mut self, tree_id:u64, now:u64)->Option<SharedBehaviorTree>{
+		let tree = self.trees.get_mut(&tree_id)?;
+		if now < tree.next_tick_time{
+			return None;
+		}
+		self.round_robin_cursor = Some(tree_id);
+		tree.deferred_frames = 0;
+		tree.last_tick_time = Some(now);
+		tree.next_tick_time += tree.tick_interval_in_milli;
+		//	落后超过一个间隔的不补执行
+		if tree.next_tick_time <= now{
+			tree.next_tick_time = now + tree.tick_interval_in_milli;
+		}
+		Some(tree.behavior_tree.clone())
+	}
+
+	fn managed_tree(&self, tree_id:u64)->Result<SharedBehaviorTree, Box<dyn std::error::Error>>{
+		self.get(tree_id).ok_or_else(|| format!("unknown behavior tree {}", tree_id).into())
+	}
+
+	//	树正在被借用(比如在它自己的update里)的时候延后执行，延后执行的错误通过take_errors取
+	fn run_or_defer(&mut self, tree_id:u64, behavior_tree:SharedBehaviorTree, operation:PendingOperation)->Result<(), Box<dyn std::error::Error>>{
+		match try_run_operation(&behavior_tree, operation){
+			Some(result) => result,
+			None => {
+				self.pending_operations.push((tree_id, behavior_tree, operation));
+				Ok(())
+			},
+		}
+	}
+
+	pub fn enable(&mut self, tree_id:u64)->Result<(), Box<dyn std::error::Error>>{
+		let behavior_tree = self.managed_tree(tree_id)?;
+		self.run_or_defer(tree_id, behavior_tree, PendingOperation::Enable)
+	}
+
+	pub fn disable(&mut self, tree_id:u64)->Result<(), Box<dyn std::error::Error>>{
+		let behavior_tree = self.managed_tree(tree_id)?;
+		self.run_or_defer(tree_id, behavior_tree, PendingOperation::Disable)
+	}
+
+	//	还在运行的树会先disable，让客户端收到执行栈的移除
+	pub fn remove(&mut self, tree_id:u64)->Result<(), Box<dyn std::error::Error>>{
+		let tree = self.trees.remove(&tree_id).ok_or_else(|| format!("unknown behavior tree {}", tree_id))?;
+		if let Some(tree_ids) = self.unit_trees.get_mut(&tree.unit_id){
+			tree_ids.remove(&tree_id);
+			if tree_ids.is_empty(){
+				self.unit_trees.remove(&tree.unit_id);
+			}
+		}
+		self.run_or_defer(tree_id, tree.behavior_tree, PendingOperation::Remove)
+	}
+
+	//	单位的所有树都会处理，返回第一个错误
+	pub fn enable_unit(&mut self, unit_id:u64)->Result<(), Box<dyn std::error::Error>>{
+		self.for_each_unit_tree(unit_id, |manager, tree_id| manager.enable(tree_id))
+	}
+
+	pub fn disable_unit(&mut self, unit_id:u64)->Result<(), Box<dyn std::error::Error>>{
+		self.for_each_unit_tree(unit_id, |manager, tree_id| manager.disable(tree_id))
+	}
+
+	pub fn remove_unit(&mut self, unit_id:u64)->Result<(), Box<dyn std::error::Error>>{
+		self.for_each_unit_tree(unit_id, |manager, tree_id| manager.remove(tree_id))
+	}
+
+	fn for_each_unit_tree(&mut self, unit_id:u64, mut operation:impl FnMut(&mut Self, u64)->Result<(), Box<dyn std::error::Error>>)->Result<(), Box<dyn std::error::Error>>{
+		let mut result = Ok(());
+		for tree_id in self.trees_of_unit(unit_id){
+			let operation_result = operation(self, tree_id);
+			if result.is_ok(){
+				result = operation_result;
+			}
+		}
+		result
+	}
+
+	pub fn take_errors(&mut self)->Vec<(u64, Box<dyn std::error::Error>)>{
+		std::mem::take(&mut self.errors)
+	}
+
+	//	manager不在借用中的时候执行延后的操作，树的事件回调里可以再调用manager
+	fn flush_pending_operations(manager:&Rc<RefCell<Self>>){
+		let operations = std::mem::take(&mut manager.borrow_mut().pending_operations);
+		for (tree_id, behavior_tree, operation) in operations{
+			match try_run_operation(&behavior_tree, operation){
+				Some(Err(err)) => manager.borrow_mut().errors.push((tree_id, err)),
+				Some(Ok(())) => {},
+				None => manager.borrow_mut().pending_operations.push((tree_id, behavior_tree, operation)),
+			}
+		}
+	}
+
+	//	每帧调用一次，update期间manager不处于借用状态
+	pub fn update(manager:&Rc<RefCell<Self>>){
+		Self::flush_pending_operations(manager);
+
+		let frame_start = Instant::now();
+		let (tree_ids, now, frame_budget) = {
+			let manager = manager.borrow();
+			let now = manager.now();
+			(manager.due_tree_ids(now), now, manager.frame_budget)
+		};
+
+		let mut frame_stats = FrameStats{due:tree_ids.len(), ..FrameStats::default()};
+		for (index, tree_id) in tree_ids.iter().enumerate(){
+			//	没执行的树不更新下次执行的时间，下一帧还是到时间的
+			if let Some(frame_budget) = frame_budget && index > 0 && frame_start.elapsed() >= frame_budget{
+				frame_stats.deferred = tree_ids.len() - index;
+				let mut manager = manager.borrow_mut();
+				for tree_id in &tree_ids[index..]{
+					if let Some(tree) = manager.trees.get_mut(tree_id){
+						tree.deferred_frames += 1;
+					}
+				}
+				break;
+			}
+
+			let due_tree = manager.borrow_mut().take_due_tree(*tree_id, now);
+			let behavior_tree = match due_tree{
+				Some(behavior_tree) => behavior_tree,
+				None => continue,
+			};
+
+			let tick_start = Instant::now();
+			let ticked = {
+				let mut behavior_tree = behavior_tree.borrow_mut();
+				let running = behavior_tree.is_runnning();
+				if running{
+					behavior_tree.update();
+				}
+				running
+			};
+			if ticked{
+				manager.borrow_mut().record_tick_cost(*tree_id, tick_start.elapsed());
+				frame_stats.ticked += 1;
+			}
+			Self::flush_pending_operations(manager);
+		}
+
+		frame_stats.elapsed = frame_start.elapsed();
+		manager.borrow_mut().frame_stats = frame_stats;
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::cell::Cell;
+	use super::super::consts::TaskStatus;
+	use super::super::interface::{IAction, ITaskProxy, StackRuntimeData, TaskRuntimeData};
+	use super::super::json_parser::JsonParser;
+	use super::super::registry::TaskRegistry;
+
+	struct DummyClock;
+
+	impl IClock for DummyClock{
+		fn timestamp_in_mill(&self)->u64{
+			0
+		}
+	}
+
+	//	记录每棵树update的顺序，第一次update的时候可以执行一次回调
+	struct RecordRuntimeEventHandle{
+		records:Rc<RefCell<Vec<u64>>>,
+		on_first_update:Cell<Option<Box<dyn FnOnce()>>>,
+	}
+
+	#[allow(unused_variables)]
+	impl IRuntimeEventHandle for RecordRuntimeEventHandle{
+		fn post_on_update(&self, behavior_tree:&dyn IBehaviorTree, task_runtime_data:&TaskRuntimeData, stack_runtime_data:&StackRuntimeData, task:&dyn ITaskProxy, now_timestamp_in_milli:u64, status:TaskStatus){
+			let mut records = self.records.borrow_mut();
+			if records.last() != Some(&behavior_tree.id()){
+				records.push(behavior_tree.id());
+			}
+			drop(records);
+			if let Some(callback) = self.on_first_update.take(){
+				callback();
+			}
+		}
+	}
+
+	fn record_handle(records:&Rc<RefCell<Vec<u64>>>, on_first_update:Option<Box<dyn FnOnce()>>)->Box<dyn IRuntimeEventHandle>{
+		Box::new(RecordRuntimeEventHandle{records:records.clone(), on_first_update:Cell::new(on_first_update)})
+	}
+
+	#[test]
+	fn test_behavior_tree_manager() {
+		let file_bytes = std::fs::read("src/behavior_tree/test_behaviortree.json").unwrap();
+		let parser = JsonParser::new();
+		let clock:Rc<RefCell<Box<dyn IClock>>> = Rc::new(RefCell::new(Box::new(DummyClock)));
+		let manager = BehaviorTreeManager::new(&Rc::downgrade(&clock), Rc::downgrade(&parser));
+		let records = Rc::new(RefCell::new(Vec::new()));
+
+		assert!(manager.borrow_mut().register_template("bad", b"{}".to_vec()).is_err());
+		manager.borrow_mut().register_template("role", file_bytes.clone()).unwrap();
+		assert!(manager.borrow_mut().register_template("role", file_bytes.clone()).is_err());
+		assert!(manager.borrow_mut().spawn(1, "monster", 10, record_handle(&records, None)).is_err());
+
+		for (tree_id, unit_id) in [(3, 20), (1, 10), (2, 10)]{
+			manager.borrow_mut().spawn(tree_id, "role", unit_id, record_handle(&records, None)).unwrap();
+		}
+		assert!(manager.borrow_mut().spawn(1, "role", 10, record_handle(&records, None)).is_err());
+		assert_eq!(manager.borrow().trees_of_unit(10), vec![1, 2]);
+		assert_eq!(manager.borrow().template_of(3), Some("role"));
+
+		manager.borrow_mut().enable_unit(10).unwrap();
+		manager.borrow_mut().enable(3).unwrap();
+		BehaviorTreeManager::update(&manager);
+		assert_eq!(*records.borrow(), vec![1, 2, 3]);
+
+		manager.borrow_mut().disable_unit(10).unwrap();
+		records.borrow_mut().clear();
+		BehaviorTreeManager::update(&manager);
+		assert_eq!(*records.borrow(), vec![3]);
+
+		manager.borrow_mut().remove_unit(10).unwrap();
+		assert_eq!(manager.borrow().tree_ids(), vec![3]);
+		assert!(manager.borrow().trees_of_unit(10).is_empty());
+		assert!(manager.borrow_mut().enable(1).is_err());
+	}
+
+	#[test]
+	fn test_behavior_tree_manager_change_during_update() {
+		let file_bytes = std::fs::read("src/behavior_tree/test_behaviortree.json").unwrap();
+		let parser = JsonParser::new();
+		let clock:Rc<RefCell<Box<dyn IClock>>> = Rc::new(RefCell::new(Box::new(DummyClock)));
+		let manager = BehaviorTreeManager::new(&Rc::downgrade(&clock), Rc::downgrade(&parser));
+		let records = Rc::new(RefCell::new(Vec::new()));
+		manager.borrow_mut().register_template("role", file_bytes).unwrap();
+
+		//	树1第一次update的时候关掉自己和树3，移除树2，再创建并打开树0
+		let weak_manager = Rc::downgrade(&manager);
+		let spawn_records = records.clone();
+		let on_first_update:Box<dyn FnOnce()> = Box::new(move ||{
+			let manager = weak_manager.upgrade().unwrap();
+			let mut manager = manager.borrow_mut();
+			manager.disable(1).unwrap();
+			manager.disable(3).unwrap();
+			manager.remove(2).unwrap();
+			manager.spawn(0, "role", 0, record_handle(&spawn_records, None)).unwrap();
+			manager.enable(0).unwrap();
+		});
+		manager.borrow_mut().spawn(1, "role", 1, record_handle(&records, Some(on_first_update))).unwrap();
+		for tree_id in 2..4{
+			manager.borrow_mut().spawn(tree_id, "role", tree_id, record_handle(&records, None)).unwrap();
+		}
+		for tree_id in 1..4{
+			manager.borrow_mut().enable(tree_id).unwrap();
+		}
+
+		BehaviorTreeManager::update(&manager);
+		assert_eq!(*records.borrow(), vec![1]);
+		assert!(!manager.borrow().get(1).unwrap().borrow().is_runnning());
+		assert!(!manager.borrow().get(3).unwrap().borrow().is_runnning());
+		assert!(manager.borrow().get(2).is_none());
+		assert!(manager.borrow_mut().take_errors().is_empty());
+
+		records.borrow_mut().clear();
+		BehaviorTreeManager::update(&manager);
+		assert_eq!(*records.borrow(), vec![0]);
+	}
+
+	struct ManualClock{
+		now:Rc<Cell<u64>>,
+	}
+
+	impl IClock for ManualClock{
+		fn timestamp_in_mill(&self)->u64{
+			self.now.get()
+		}
+	}
+
+	thread_local!{
+		//	WaitAction每次update通过IClock看到的(当前时间, 开始后经过的时间)
+		static WAIT_ELAPSED:RefCell<Vec<(u64, u64)>> = const { RefCell::new(Vec::new()) };
+	}
+
+	//	开始以后等150毫秒成功
+	struct WaitAction{
+		start_time:u64,
+	}
+
+	fn clock_now(behavior_tree:&dyn IBehaviorTree)->u64{
+		behavior_tree.timestamp_in_mill()
+	}
+
+	impl IAction for WaitAction{
+		fn on_start(&mut self, _task_proxy:&mut dyn ITaskProxy, behavior_tree:&dyn IBehaviorTree){
+			self.start_time = clock_now(behavior_tree);
+		}
+
+		fn on_update(&mut self, _task_proxy:&mut dyn ITaskProxy, behavior_tree:&dyn IBehaviorTree)->TaskStatus{
+			let now = clock_now(behavior_tree);
+			let elapsed = now - self.start_time;
+			WAIT_ELAPSED.with(|records| records.borrow_mut().push((now, elapsed)));
+			if elapsed >= 150 {TaskStatus::Success} else {TaskStatus::Running}
+		}
+	}
+
+	#[test]
+	fn test_behavior_tree_manager_tick_interval() {
+		let file_bytes = std::fs::read("src/behavior_tree/test_behaviortree.json").unwrap();
+		let registry = TaskRegistry::new();
+		registry.write().unwrap().register_action_fn("WaitAction", |_variables, _task_ids| -> Box<dyn IAction> {Box::new(WaitAction{start_time:0})});
+		let parser = JsonParser::with_registry(registry);
+		let now = Rc::new(Cell::new(0));
+		let clock:Rc<RefCell<Box<dyn IClock>>> = Rc::new(RefCell::new(Box::new(ManualClock{now:now.clone()})));
+		let manager = BehaviorTreeManager::new(&Rc::downgrade(&clock), Rc::downgrade(&parser));
+		let records = Rc::new(RefCell::new(Vec::new()));
+		manager.borrow_mut().register_template("role", file_bytes).unwrap();
+		manager.borrow_mut().set_lod_tick_intervals(vec![0, 100, 200]);
+		for tree_id in 1..=5{
+			manager.borrow_mut().spawn(tree_id, "role", tree_id, record_handle(&records, None)).unwrap();
+			manager.borrow_mut().enable(tree_id).unwrap();
+		}
+		for tree_id in 1..=4{
+			manager.borrow_mut().set_lod(tree_id, 1).unwrap();
+		}
+		assert!(manager.borrow_mut().set_lod(5, 3).is_err());
+		assert_eq!(manager.borrow().tick_interval(1), Some(100));
+		assert_eq!(manager.borrow().tick_interval(5), Some(0));
+
+		//	每帧25毫秒
+		let mut tick_times:BTreeMap<u64, Vec<u64>> = BTreeMap::new();
+		let mut run_frames = |frames:u64, tick_times:&mut BTreeMap<u64, Vec<u64>>|{
+			for _ in 0..frames{
+				BehaviorTreeManager::update(&manager);
+				let ticked:Vec<u64> = records.borrow_mut().drain(..).collect();
+				assert!(ticked.iter().filter(|tree_id| **tree_id != 5).count() <= 2);
+				for tree_id in ticked{
+					tick_times.entry(tree_id).or_default().push(now.get());
+				}
+				now.set(now.get() + 25);
+			}
+		};
+		run_frames(16, &mut tick_times);
+
+		assert_eq!(tick_times[&5].len(), 16);
+		let first_tick_times:Vec<u64> = (1..=4).map(|tree_id| tick_times[&tree_id][0]).collect();
+		assert_eq!(first_tick_times, vec![0, 75, 25, 100]);
+		for tree_id in 1..=4{
+			assert!(tick_times[&tree_id].windows(2).all(|times| times[1] - times[0] == 100));
+		}
+
+		//	运行中切换档位，从上次执行的时间开始算新的间隔
+		manager.borrow_mut().set_lod(1, 2).unwrap();
+		assert_eq!(manager.borrow().lod(1), Some(2));
+		let last_tick_time = *tick_times[&1].last().unwrap();
+		tick_times.clear();
+		run_frames(16, &mut tick_times);
+		assert_eq!(tick_times[&1][0], last_tick_time + 200);
+		assert!(tick_times[&1].windows(2).all(|times| times[1] - times[0] == 200));
+
+		//	按间隔执行的树里，任务通过IClock看到的经过时间是两次执行之间真实的时间
+		let wait_config = serde_json::json!({"RootTask": {"Type": "BehaviorDesigner.Runtime.Tasks.UntilForever", "ID": 1, "Children": [{"Type": "WaitAction", "ID": 2}]}}).to_string().into_bytes();
+		manager.borrow_mut().register_template("wait", wait_config).unwrap();
+		manager.borrow_mut().spawn(6, "wait", 6, record_handle(&records, None)).unwrap();
+		manager.borrow_mut().enable(6).unwrap();
+		manager.borrow_mut().set_lod(6, 1).unwrap();
+		WAIT_ELAPSED.with(|records| records.borrow_mut().clear());
+		let start = now.get();
+		for _ in 0..16{
+			BehaviorTreeManager::update(&manager);
+			now.set(now.get() + 25);
+		}
+		let elapsed:Vec<(u64, u64)> = WAIT_ELAPSED.with(|records| records.borrow().iter().map(|(time, elapsed)| (time - start, *elapsed)).collect());
+		//	第一次执行错开50毫秒，之后每100毫秒执行一次，等满150毫秒的时候已经过了200毫秒，同一帧里重新开始
+		assert_eq!(elapsed, vec![(50, 0), (150, 100), (250, 200), (250, 0), (350, 100)]);
+	}
+
+	#[test]
+	fn test_behavior_tree_manager_frame_budget() {
+		let file_bytes = std::fs::read("src/behavior_tree/test_behaviortree.json").unwrap();
+		let parser = JsonParser::new();
+		let clock:Rc<RefCell<Box<dyn IClock>>> = Rc::new(RefCell::new(Box::new(DummyClock)));
+		let manager = BehaviorTreeManager::new(&Rc::downgrade(&clock), Rc::downgrade(&parser));
+		let records = Rc::new(RefCell::new(Vec::new()));
+		manager.borrow_mut().register_template("role", file_bytes).unwrap();
+		for tree_id in 1..=3{
+			manager.borrow_mut().spawn(tree_id, "role", tree_id, record_handle(&records, None)).unwrap();
+			manager.borrow_mut().enable(tree_id).unwrap();
+		}
+
+		//	预算为0的时候每帧只执行一棵树，剩下的轮到下一帧
+		manager.borrow_mut().set_frame_budget(Some(Duration::ZERO));
+		for _ in 0..4{
+			BehaviorTreeManager::update(&manager);
+		}
+		assert_eq!(*records.borrow(), vec![1, 2, 3, 1]);
+		assert_eq!(manager.borrow().last_frame_stats().due, 3);
+		assert_eq!(manager.borrow().last_frame_stats().ticked, 1);
+		assert_eq!(manager.borrow().last_frame_stats().deferred, 2);
+
+		manager.borrow_mut().set_schedule_order(ScheduleOrder::Priority);
+		manager.borrow_mut().set_priority(3, 10).unwrap();
+		manager.borrow_mut().set_hot_tree_threshold(Some((Duration::ZERO, 2)));
+		records.borrow_mut().clear();
+		BehaviorTreeManager::update(&manager);
+		assert!(manager.borrow().hot_trees(3).is_empty());
+		BehaviorTreeManager::update(&manager);
+		assert_eq!(*records.borrow(), vec![3]);
+
+		let hot_trees = manager.borrow().hot_trees(3);
+		assert_eq!(hot_trees.len(), 1);
+		assert_eq!(hot_trees[0].tree_id, 3);
+		assert_eq!(hot_trees[0].cost.over_threshold_ticks, 2);
+		assert!(!hot_trees[0].hot_task_types.is_empty());
+		assert!(hot_trees[0].hot_task_types.windows(2).all(|types| types[0].1.total >= types[1].1.total));
+		assert_eq!(manager.borrow().tick_cost(1).unwrap().ticks, 2);
+		assert_eq!(manager.borrow().tick_cost(3).unwrap().ticks, 3);
+
+		//	不限制预算的时候到时间的树都执行，树2比树1多推迟了两帧，排在前面
+		manager.borrow_mut().set_frame_budget(None);
+		records.borrow_mut().clear();
+		BehaviorTreeManager::update(&manager);
+		assert_eq!(*records.borrow(), vec![3, 2, 1]);
+		assert_eq!(manager.borrow().last_frame_stats().deferred, 0);
+	}
+
+	#[test]
+	fn test_behavior_tree_manager_priority_aging() {
+		let file_bytes = std::fs::read("src/behavior_tree/test_behaviortree.json").unwrap();
+		let parser = JsonParser::new();
+		let clock:Rc<RefCell<Box<dyn IClock>>> = Rc::new(RefCell::new(Box::new(DummyClock)));
+		let manager = BehaviorTreeManager::new(&Rc::downgrade(&clock), Rc::downgrade(&parser));
+		let records = Rc::new(RefCell::new(Vec::new()));
+		manager.borrow_mut().register_template("role", file_bytes).unwrap();
+		for tree_id in 1..=2{
+			manager.borrow_mut().spawn(tree_id, "role", tree_id, record_handle(&records, None)).unwrap();
+			manager.borrow_mut().enable(tree_id).unwrap();
+		}
+
+		//	每帧只够执行一棵树，低优先级的树推迟两帧以后追上高优先级的树
+		manager.borrow_mut().set_frame_budget(Some(Duration::ZERO));
+		manager.borrow_mut().set_schedule_order(ScheduleOrder::Priority);
+		manager.borrow_mut().set_priority(2, 2).unwrap();
+		let mut ticked = Vec::new();
+		for _ in 0..6{
+			BehaviorTreeManager::update(&manager);
+			ticked.extend(records.borrow_mut().drain(..));
+		}
+		assert_eq!(ticked, vec![2, 2, 1, 2, 2, 1]);
+		assert_eq!(manager.borrow().tick_cost(1).unwrap().ticks, 2);
+	}
+}
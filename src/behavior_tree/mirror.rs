@@ -0,0 +1,663 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use super::consts::TaskStatus;
+use super::interface::{StackRuntimeData, TaskRuntimeData};
+use super::wire::{FullStateAction, FullStateParallel, SyncMessage, SyncPacket};
+
+//	客户端看到的一个正在执行的同步action
+#[derive(Clone, PartialEq, Debug)]
+pub struct MirrorAction{
+	pub task:TaskRuntimeData,
+	pub corresponding_type:String,
+	//	action_post_on_start带过来的数据
+	pub start_datas:Vec<Vec<u8>>,
+	//	最近一次action_post_on_update的状态和数据
+	pub status:TaskStatus,
+	pub update_datas:Vec<Vec<u8>>,
+	pub last_update_time:u64,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct MirrorStack{
+	pub stack:StackRuntimeData,
+	//	并发任务创建的子栈才有
+	pub parallel_task:Option<TaskRuntimeData>,
+	pub parent_stack_id:Option<usize>,
+	pub child_stack_ids:Vec<usize>,
+	//	execute_id -> action
+	pub actions:BTreeMap<u32, MirrorAction>,
+}
+
+impl MirrorStack{
+	fn new(stack:StackRuntimeData)->Self{
+		Self{
+			stack,
+			parallel_task:None,
+			parent_stack_id:None,
+			child_stack_ids:Vec::new(),
+			actions:BTreeMap::new(),
+		}
+	}
+}
+
+#[derive(Clone, Default, PartialEq, Debug)]
+pub struct TreeMirror{
+	stacks:BTreeMap<usize, MirrorStack>,
+}
+
+impl TreeMirror{
+	pub fn stacks(&self)->impl Iterator<Item = &MirrorStack>{
+		self.stacks.values()
+	}
+
+	pub fn stack(&self, stack_id:usize)->Option<&MirrorStack>{
+		self.stacks.get(&stack_id)
+	}
+
+	pub fn running_actions(&self, stack_id:usize)->Vec<&MirrorAction>{
+		match self.stacks.get(&stack_id){
+			Some(stack) => stack.actions.values().collect(),
+			None => Vec::new(),
+		}
+	}
+
+	fn stack_mut(&mut self, stack_id:usize)->Result<&mut MirrorStack, Box<dyn std::error::Error>>{
+		self.stacks.get_mut(&stack_id).ok_or_else(|| format!("unknown stack {}", stack_id).into())
+	}
+
+	fn from_full_state(stacks:&[StackRuntimeData], parallels:&[FullStateParallel], actions:&[FullStateAction])->Result<Self, Box<dyn std::error::Error>>{
+		let mut tree = Self::default();
+		for stack in stacks.iter(){
+			tree.stacks.insert(stack.stack_id, MirrorStack::new(*stack));
+		}
+
+		for parallel in parallels.iter(){
+			for child_stack in parallel.child_stacks.iter(){
+				let child = tree.stack_mut(child_stack.stack_id)?;
+				child.parallel_task = Some(parallel.task);
+				child.parent_stack_id = Some(parallel.stack.stack_id);
+				tree.stack_mut(parallel.stack.stack_id)?.child_stack_ids.push(child_stack.stack_id);
+			}
+		}
+
+		for action in actions.iter(){
+			tree.stack_mut(action.stack.stack_id)?.actions.insert(action.task.execute_id, MirrorAction{
+				task:action.task,
+				corresponding_type:action.corresponding_type.clone(),
+				start_datas:action.datas.clone(),
+				status:TaskStatus::Running,
+				update_datas:Vec::new(),
+				last_update_time:action.task.start_time,
+			});
+		}
+		Ok(tree)
+	}
+
+	fn apply(&mut self, message:&SyncMessage)->Result<(), Box<dyn std::error::Error>>{
+		match message{
+			SyncMessage::FullState{stacks, parallels, actions, ..} => {
+				*self = Self::from_full_state(stacks, parallels, actions)?;
+			},
+			SyncMessage::NewStack{stack, ..} => {
+				if self.stacks.contains_key(&stack.stack_id){
+					return Err(format!("stack {} already exists", stack.stack_id).into());
+				}
+				self.stacks.insert(stack.stack_id, MirrorStack::new(*stack));
+			},
+			SyncMessage::RemoveStack{stack, ..} => {
+				let removed = self.stacks.remove(&stack.stack_id).ok_or_else(|| format!("unknown stack {}", stack.stack_id))?;
+				if let Some(parent_stack) = removed.parent_stack_id.and_then(|parent_stack_id| self.stacks.get_mut(&parent_stack_id)){
+					parent_stack.child_stack_ids.retain(|child_stack_id| *child_stack_id != stack.stack_id);
+				}
+			},
+			SyncMessage::ParallelAddChildStack{task, stack, child_stack, ..} => {
+				let child = self.stack_mut(child_stack.stack_id)?;
+				child.parallel_task = Some(*task);
+				child.parent_stack_id = Some(stack.stack_id);
+				self.stack_mut(stack.stack_id)?.child_stack_ids.push(child_stack.stack_id);
+			},
+			SyncMessage::ParallelRemoveChildStack{stack, child_stack, ..} => {
+				self.stack_mut(stack.stack_id)?.child_stack_ids.retain(|child_stack_id| *child_stack_id != child_stack.stack_id);
+				if let Some(child) = self.stacks.get_mut(&child_stack.stack_id){
+					child.parallel_task = None;
+					child.parent_stack_id = None;
+				}
+			},
+			SyncMessage::ActionStart{task, stack, corresponding_type, datas, ..} => {
+				self.stack_mut(stack.stack_id)?.actions.insert(task.execute_id, MirrorAction{
+					task:*task,
+					corresponding_type:corresponding_type.clone(),
+					start_datas:datas.clone(),
+					status:TaskStatus::Running,
+					update_datas:Vec::new(),
+					last_update_time:task.start_time,
+				});
+			},
+			SyncMessage::ActionUpdate{task, stack, now, status, datas, ..} => {
+				let action = self.stack_mut(stack.stack_id)?.actions.get_mut(&task.execute_id).ok_or_else(|| format!("unknown action execute {}", task.execute_id))?;
+				action.status = status.clone();
+				action.update_datas = datas.clone();
+				action.last_update_time = *now;
+			},
+			SyncMessage::ActionEnd{task, stack, ..} => {
+				self.stack_mut(stack.stack_id)?.actions.remove(&task.execute_id).ok_or_else(|| format!("unknown action execute {}", task.execute_id))?;
+			},
+		}
+		Ok(())
+	}
+}
+
+//	客户端在服务器确认之前先在本地开始的action，比如主角的RoleFollowJoystick
+#[derive(Clone, PartialEq, Debug)]
+pub struct PredictedAction{
+	pub prediction_id:u32,
+	pub tree_id:u64,
+	pub task_id:i32,
+	pub corresponding_type:String,
+	pub datas:Vec<Vec<u8>>,
+	pub predicted_time:u64,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum RollbackReason{
+	//	超时没有等到服务器的action_post_on_start
+	Timeout,
+	//	还没确认服务器就发了action_post_on_end
+	EndedByServer,
+	//	树在服务器上已经结束了
+	TreeRemoved,
+	Cleared,
+}
+
+//	预测的结果，由客户端取走做表现上的修正
+#[derive(Clone, PartialEq, Debug)]
+pub enum PredictionEvent{
+	Confirmed{prediction:PredictedAction, task:TaskRuntimeData},
+	//	服务器开始了同一个任务，但是数据跟本地预测的不一样，以服务器为准
+	Corrected{prediction:PredictedAction, task:TaskRuntimeData, datas:Vec<Vec<u8>>},
+	RolledBack{prediction:PredictedAction, reason:RollbackReason},
+}
+
+//	客户端根据同步消息还原服务器上的执行情况，按树分开
+//	序号不连续的树会停止应用增量消息，等收到全量状态以后再继续
+//	开启预测以后可以先在本地开始action，收到服务器的消息时按任务ID确认或者回滚
+#[derive(Clone, Default)]
+pub struct SyncMirror{
+	trees:HashMap<u64, TreeMirror>,
+	last_sequences:HashMap<u64, u64>,
+	resync_trees:BTreeSet<u64>,
+	resync_requests:Vec<u64>,
+
+	prediction_timeout_in_milli:Option<u64>,
+	next_prediction_id:u32,
+	//	tree_id -> task_id -> 预测
+	predictions:HashMap<u64, BTreeMap<i32, PredictedAction>>,
+	prediction_events:Vec<PredictionEvent>,
+}
+
+impl SyncMirror{
+	pub fn new()->Self{
+		Self::default()
+	}
+
+	pub fn apply(&mut self, message:&SyncMessage)->Result<(), Box<dyn std::error::Error>>{
+		let tree_id = message.tree_id();
+		//	新的树应用成功了才加进来，失败的消息不能让树出现或者消失
+		match self.trees.get_mut(&tree_id){
+			Some(tree) => tree.apply(message)?,
+			None => {
+				let mut tree = TreeMirror::default();
+				tree.apply(message)?;
+				self.trees.insert(tree_id, tree);
+			},
+		}
+		self.reconcile(message);
+		//	树的所有栈都移除了就是树结束了
+		if self.trees.get(&tree_id).is_some_and(|tree| tree.stacks.is_empty()){
+			self.trees.remove(&tree_id);
+			self.rollback_predictions(tree_id, RollbackReason::TreeRemoved);
+		}
+		Ok(())
+	}
+
+	pub fn apply_packet(&mut self, packet:&SyncPacket)->Result<(), Box<dyn std::error::Error>>{
+		let tree_id = packet.message.tree_id();
+		let last_sequence = self.last_sequences.get(&tree_id).cloned().unwrap_or(0);
+		let resyncing = self.resync_trees.contains(&tree_id);
+
+		if let SyncMessage::FullState{..} = packet.message{
+			//	比当前状态还旧的全量状态不要
+			if packet.sequence < last_sequence && !resyncing{
+				return Ok(());
+			}
+			self.resync_trees.remove(&tree_id);
+			self.last_sequences.insert(tree_id, packet.sequence);
+			return self.apply(&packet.message);
+		}
+
+		//	等全量状态的时候增量消息都丢掉，重复的或者过期的也丢掉
+		if resyncing || packet.sequence <= last_sequence{
+			return Ok(());
+		}
+
+		if packet.sequence != last_sequence + 1{
+			self.request_resync(tree_id);
+			return Ok(());
+		}
+
+		self.last_sequences.insert(tree_id, packet.sequence);
+		let result = self.apply(&packet.message);
+		if result.is_err(){
+			self.request_resync(tree_id);
+		}
+		result
+	}
+
+	pub fn apply_bytes(&mut self, bytes:&[u8])->Result<(), Box<dyn std::error::Error>>{
+		self.apply_packet(&SyncPacket::decode(bytes)?)
+	}
+
+	//	需要服务器用FullStateCollector重新发全量状态的树，由网络层取走发给服务器
+	pub fn take_resync_requests(&mut self)->Vec<u64>{
+		std::mem::take(&mut self.resync_requests)
+	}
+
+	pub fn is_resyncing(&self, tree_id:u64)->bool{
+		self.resync_trees.contains(&tree_id)
+	}
+
+	fn request_resync(&mut self, tree_id:u64){
+		if self.resync_trees.insert(tree_id){
+			self.resync_requests.push(tree_id);
+		}
+	}
+
+	pub fn tree(&self, tree_id:u64)->Option<&TreeMirror>{
+		self.trees.get(&tree_id)
+	}
+
+	pub fn tree_ids(&self)->Vec<u64>{
+		let mut tree_ids:Vec<u64> = self.trees.keys().cloned().collect();
+		tree_ids.sort();
+		tree_ids
+	}
+
+	pub fn running_actions(&self, tree_id:u64, stack_id:usize)->Vec<&MirrorAction>{
+		match self.trees.get(&tree_id){
+			Some(tree) => tree.running_actions(stack_id),
+			None => Vec::new(),
+		}
+	}
+
+	//	还没确认的预测都会回滚
+	pub fn clear(&mut self){
+		self.trees.clear();
+		self.last_sequences.clear();
+		self.resync_trees.clear();
+		self.resync_requests.clear();
+		let mut tree_ids:Vec<u64> = self.predictions.keys().cloned().collect();
+		tree_ids.sort();
+		for tree_id in tree_ids{
+			self.rollback_predictions(tree_id, RollbackReason::Cleared);
+		}
+	}
+
+	//	超过timeout_in_milli还没确认的预测会回滚
+	pub fn enable_prediction(&mut self, timeout_in_milli:u64){
+		self.prediction_timeout_in_milli = Some(timeout_in_milli);
+	}
+
+	pub fn is_prediction_enabled(&self)->bool{
+		self.prediction_timeout_in_milli.is_some()
+	}
+
+	//	本地先开始一个action，同一个任务同时只能有一个预测
+	pub fn predict_action_start(&mut self, tree_id:u64, task_id:i32, corresponding_type:&str, datas:Vec<Vec<u8>>, now:u64)->Result<u32, Box<dyn std::error::Error>>{
+		if !self.is_prediction_enabled(){
+			return Err("prediction is not enabled".into());
+		}
+		let predictions = self.predictions.entry(tree_id).or_default();
+		if predictions.contains_key(&task_id){
+			return Err(format!("task {} of tree {} is already predicted", task_id, tree_id).into());
+		}
+
+		self.next_prediction_id += 1;
+		predictions.insert(task_id, PredictedAction{
+			prediction_id:self.next_prediction_id,
+			tree_id,
+			task_id,
+			corresponding_type:corresponding_type.to_string(),
+			datas,
+			predicted_time:now,
+		});
+		Ok(self.next_prediction_id)
+	}
+
+	pub fn predicted_actions(&self, tree_id:u64)->Vec<&PredictedAction>{
+		match self.predictions.get(&tree_id){
+			Some(predictions) => predictions.values().collect(),
+			None => Vec::new(),
+		}
+	}
+
+	//	每帧调用，now用预测时同一个时钟
+	pub fn expire_predictions(&mut self, now:u64){
+		let timeout = match self.prediction_timeout_in_milli{
+			Some(timeout) => timeout,
+			None => return,
+		};
+
+		let mut tree_ids:Vec<u64> = self.predictions.keys().cloned().collect();
+		tree_ids.sort();
+		for tree_id in tree_ids{
+			let predictions = self.predictions.get_mut(&tree_id).unwrap();
+			let expired:Vec<i32> = predictions.values().filter(|prediction| now >= prediction.predicted_time.saturating_add(timeout)).map(|prediction| prediction.task_id).collect();
+			for task_id in expired{
+				let prediction = predictions.remove(&task_id).unwrap();
+				self.prediction_events.push(PredictionEvent::RolledBack{prediction, reason:RollbackReason::Timeout});
+			}
+			if predictions.is_empty(){
+				self.predictions.remove(&tree_id);
+			}
+		}
+	}
+
+	pub fn take_prediction_events(&mut self)->Vec<PredictionEvent>{
+		std::mem::take(&mut self.prediction_events)
+	}
+
+	fn take_prediction(&mut self, tree_id:u64, task_id:i32)->Option<PredictedAction>{
+		let predictions = self.predictions.get_mut(&tree_id)?;
+		let prediction = predictions.remove(&task_id);
+		if predictions.is_empty(){
+			self.predictions.remove(&tree_id);
+		}
+		prediction
+	}
+
+	fn confirm_prediction(&mut self, prediction:PredictedAction, task:&TaskRuntimeData, datas:&Vec<Vec<u8>>){
+		if prediction.datas == *datas{
+			self.prediction_events.push(PredictionEvent::Confirmed{prediction, task:*task});
+		}else{
+			self.prediction_events.push(PredictionEvent::Corrected{prediction, task:*task, datas:datas.clone()});
+		}
+	}
+
+	fn rollback_predictions(&mut self, tree_id:u64, reason:RollbackReason){
+		if let Some(predictions) = self.predictions.remove(&tree_id){
+			for prediction in predictions.into_values(){
+				self.prediction_events.push(PredictionEvent::RolledBack{prediction, reason});
+			}
+		}
+	}
+
+	//	全量状态里没有的预测先留着，可能服务器还没处理到对应的输入
+	fn reconcile(&mut self, message:&SyncMessage){
+		let tree_id = message.tree_id();
+		if !self.predictions.contains_key(&tree_id){
+			return;
+		}
+
+		match message{
+			SyncMessage::ActionStart{task, datas, ..} => {
+				if let Some(prediction) = self.take_prediction(tree_id, task.task_id){
+					self.confirm_prediction(prediction, task, datas);
+				}
+			},
+			SyncMessage::ActionEnd{task, ..} => {
+				if let Some(prediction) = self.take_prediction(tree_id, task.task_id){
+					self.prediction_events.push(PredictionEvent::RolledBack{prediction, reason:RollbackReason::EndedByServer});
+				}
+			},
+			SyncMessage::FullState{actions, ..} => {
+				for action in actions.iter(){
+					if let Some(prediction) = self.take_prediction(tree_id, action.task.task_id){
+						self.confirm_prediction(prediction, &action.task, &action.datas);
+					}
+				}
+			},
+			_ => {},
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::{rc::Rc, cell::RefCell};
+	use super::super::interface::IClock;
+	use super::super::json_parser::JsonParser;
+	use super::super::runtime::BehaviorTree;
+	use super::super::wire::{FullStateCollector, SyncSequences, WireEncodeRuntimeEventHandle};
+
+	struct DummyClock;
+
+	impl IClock for DummyClock{
+		fn timestamp_in_mill(&self)->u64{
+			0
+		}
+	}
+
+	#[test]
+	fn test_sync_mirror_actions() {
+		let mut mirror = SyncMirror::new();
+		let stack = StackRuntimeData::new(1, 0);
+		let child_stack = StackRuntimeData::new(2, 10);
+		let parallel = TaskRuntimeData::new(3, 10, 5, 1);
+		let action = TaskRuntimeData::new(7, 10, 6, 2);
+
+		mirror.apply(&SyncMessage::NewStack{tree_id:1, stack}).unwrap();
+		mirror.apply(&SyncMessage::NewStack{tree_id:1, stack:child_stack}).unwrap();
+		mirror.apply(&SyncMessage::ParallelAddChildStack{tree_id:1, task:parallel, stack, child_stack}).unwrap();
+		mirror.apply(&SyncMessage::ActionStart{tree_id:1, task:action, stack:child_stack, corresponding_type:"Ani".to_string(), datas:vec![vec![1]]}).unwrap();
+		mirror.apply(&SyncMessage::ActionUpdate{tree_id:1, task:action, stack:child_stack, now:20, status:TaskStatus::Running, datas:vec![vec![2]]}).unwrap();
+
+		let tree = mirror.tree(1).unwrap();
+		assert_eq!(tree.stack(1).unwrap().child_stack_ids, vec![2]);
+		assert_eq!(tree.stack(2).unwrap().parent_stack_id, Some(1));
+		let actions = mirror.running_actions(1, 2);
+		assert_eq!(actions.len(), 1);
+		assert_eq!(actions[0].corresponding_type, "Ani");
+		assert_eq!(actions[0].start_datas, vec![vec![1]]);
+		assert_eq!(actions[0].update_datas, vec![vec![2]]);
+		assert_eq!(actions[0].last_update_time, 20);
+
+		mirror.apply(&SyncMessage::ActionEnd{tree_id:1, task:action, stack:child_stack, now:30, datas:Vec::new()}).unwrap();
+		assert!(mirror.running_actions(1, 2).is_empty());
+		assert!(mirror.apply(&SyncMessage::ActionEnd{tree_id:1, task:action, stack:child_stack, now:30, datas:Vec::new()}).is_err());
+
+		mirror.apply(&SyncMessage::ParallelRemoveChildStack{tree_id:1, task:parallel, stack, child_stack, now:40}).unwrap();
+		mirror.apply(&SyncMessage::RemoveStack{tree_id:1, stack:child_stack, now:40}).unwrap();
+		assert!(mirror.tree(1).unwrap().stack(1).unwrap().child_stack_ids.is_empty());
+		mirror.apply(&SyncMessage::RemoveStack{tree_id:1, stack, now:50}).unwrap();
+		assert!(mirror.tree(1).is_none());
+	}
+
+	#[test]
+	fn test_sync_mirror_prediction() {
+		let mut mirror = SyncMirror::new();
+		let stack = StackRuntimeData::new(1, 0);
+		let follow = TaskRuntimeData::new(7, 10, 1, 1);
+		let ani = TaskRuntimeData::new(8, 10, 2, 1);
+		mirror.apply(&SyncMessage::NewStack{tree_id:1, stack}).unwrap();
+		assert!(mirror.predict_action_start(1, 7, "Follow", Vec::new(), 0).is_err());
+
+		mirror.enable_prediction(100);
+		let follow_prediction = mirror.predict_action_start(1, 7, "Follow", vec![vec![1]], 0).unwrap();
+		let ani_prediction = mirror.predict_action_start(1, 8, "Ani", vec![vec![1]], 0).unwrap();
+		assert!(mirror.predict_action_start(1, 7, "Follow", Vec::new(), 0).is_err());
+		assert_eq!(mirror.predicted_actions(1).len(), 2);
+
+		//	服务器开始了同样的action就是确认，数据不一样要修正
+		mirror.apply(&SyncMessage::ActionStart{tree_id:1, task:follow, stack, corresponding_type:"Follow".to_string(), datas:vec![vec![1]]}).unwrap();
+		mirror.apply(&SyncMessage::ActionStart{tree_id:1, task:ani, stack, corresponding_type:"Ani".to_string(), datas:vec![vec![2]]}).unwrap();
+		let events = mirror.take_prediction_events();
+		assert_eq!(events.len(), 2);
+		assert!(matches!(&events[0], PredictionEvent::Confirmed{prediction, task} if prediction.prediction_id == follow_prediction && *task == follow));
+		assert!(matches!(&events[1], PredictionEvent::Corrected{prediction, datas, ..} if prediction.prediction_id == ani_prediction && *datas == vec![vec![2]]));
+		assert!(mirror.predicted_actions(1).is_empty());
+		assert_eq!(mirror.running_actions(1, 1).len(), 2);
+
+		//	服务器结束了还没确认的预测
+		mirror.predict_action_start(1, 7, "Follow", Vec::new(), 20).unwrap();
+		mirror.apply(&SyncMessage::ActionEnd{tree_id:1, task:follow, stack, now:20, datas:Vec::new()}).unwrap();
+		assert!(matches!(&mirror.take_prediction_events()[..], [PredictionEvent::RolledBack{reason:RollbackReason::EndedByServer, ..}]));
+		mirror.predict_action_start(1, 7, "Follow", Vec::new(), 20).unwrap();
+		mirror.apply(&SyncMessage::ActionEnd{tree_id:1, task:ani, stack, now:20, datas:Vec::new()}).unwrap();
+		assert!(mirror.take_prediction_events().is_empty());
+		mirror.predict_action_start(1, 8, "Ani", Vec::new(), 20).unwrap();
+		mirror.apply(&SyncMessage::ActionStart{tree_id:1, task:TaskRuntimeData::new(8, 20, 3, 1), stack, corresponding_type:"Ani".to_string(), datas:Vec::new()}).unwrap();
+		mirror.apply(&SyncMessage::ActionEnd{tree_id:1, task:TaskRuntimeData::new(8, 20, 3, 1), stack, now:30, datas:Vec::new()}).unwrap();
+		assert!(matches!(&mirror.take_prediction_events()[..], [PredictionEvent::Confirmed{..}]));
+
+		//	超时回滚
+		mirror.expire_predictions(119);
+		assert!(mirror.take_prediction_events().is_empty());
+		mirror.expire_predictions(120);
+		assert!(matches!(&mirror.take_prediction_events()[..], [PredictionEvent::RolledBack{reason:RollbackReason::Timeout, ..}]));
+
+		//	全量状态里有的预测也算确认，树结束的时候剩下的都回滚
+		mirror.predict_action_start(1, 7, "Follow", Vec::new(), 200).unwrap();
+		mirror.predict_action_start(1, 9, "Idle", Vec::new(), 200).unwrap();
+		mirror.apply(&SyncMessage::FullState{tree_id:1, stacks:vec![stack], parallels:Vec::new(), actions:vec![FullStateAction{task:follow, stack, corresponding_type:"Follow".to_string(), datas:Vec::new()}]}).unwrap();
+		assert!(matches!(&mirror.take_prediction_events()[..], [PredictionEvent::Confirmed{..}]));
+		mirror.apply(&SyncMessage::RemoveStack{tree_id:1, stack, now:300}).unwrap();
+		assert!(mirror.tree(1).is_none());
+		assert!(matches!(&mirror.take_prediction_events()[..], [PredictionEvent::RolledBack{reason:RollbackReason::TreeRemoved, ..}]));
+
+		//	还没有的树收到应用失败的消息，不会出现这棵树，也不回滚预测
+		mirror.predict_action_start(2, 7, "Follow", Vec::new(), u64::MAX - 10).unwrap();
+		assert!(mirror.apply(&SyncMessage::ActionUpdate{tree_id:2, task:follow, stack, now:400, status:TaskStatus::Running, datas:Vec::new()}).is_err());
+		assert!(mirror.tree(2).is_none());
+		assert!(mirror.take_prediction_events().is_empty());
+		assert_eq!(mirror.predicted_actions(2).len(), 1);
+
+		//	超时时间加上去不能溢出
+		mirror.expire_predictions(u64::MAX - 1);
+		assert!(mirror.take_prediction_events().is_empty());
+		assert_eq!(mirror.predicted_actions(2).len(), 1);
+	}
+
+	#[test]
+	fn test_sync_mirror_follow_behavior_tree() {
+		let file_bytes = std::fs::read("src/behavior_tree/test_behaviortree.json").unwrap();
+		let parser = JsonParser::new();
+		let clock:Rc<RefCell<Box<dyn IClock>>> = Rc::new(RefCell::new(Box::new(DummyClock)));
+		let messages = Rc::new(RefCell::new(Vec::new()));
+		let behavior_tree = BehaviorTree::new(2, &file_bytes, 1, &Rc::downgrade(&clock), Box::new(WireEncodeRuntimeEventHandle::new(messages.clone(), SyncSequences::new())), Rc::downgrade(&parser));
+		let mut mirror = SyncMirror::new();
+
+		behavior_tree.borrow_mut().enable().unwrap();
+		for _ in 0..3{
+			behavior_tree.borrow_mut().update();
+			for bytes in messages.borrow_mut().drain(..){
+				mirror.apply_bytes(&bytes).unwrap();
+			}
+
+			let snapshot = behavior_tree.borrow().snapshot().unwrap();
+			let mut stack_ids:Vec<usize> = snapshot.active_stack.iter().map(|stack| stack.stack_id).collect();
+			stack_ids.sort();
+			let mirror_stack_ids:Vec<usize> = mirror.tree(2).unwrap().stacks().map(|stack| stack.stack.stack_id).collect();
+			assert_eq!(mirror_stack_ids, stack_ids);
+		}
+
+		behavior_tree.borrow_mut().disable().unwrap();
+		for bytes in messages.borrow_mut().drain(..){
+			mirror.apply_bytes(&bytes).unwrap();
+		}
+		assert!(mirror.tree(2).is_none());
+	}
+
+	#[test]
+	fn test_sync_mirror_late_join() {
+		let file_bytes = std::fs::read("src/behavior_tree/test_behaviortree.json").unwrap();
+		let parser = JsonParser::new();
+		let clock:Rc<RefCell<Box<dyn IClock>>> = Rc::new(RefCell::new(Box::new(DummyClock)));
+		let messages = Rc::new(RefCell::new(Vec::new()));
+		let sequences = SyncSequences::new();
+		let behavior_tree = BehaviorTree::new(3, &file_bytes, 1, &Rc::downgrade(&clock), Box::new(WireEncodeRuntimeEventHandle::new(messages.clone(), sequences.clone())), Rc::downgrade(&parser));
+		let mut mirror = SyncMirror::new();
+
+		behavior_tree.borrow_mut().enable().unwrap();
+		behavior_tree.borrow_mut().update();
+		let early_messages:Vec<Vec<u8>> = messages.borrow_mut().drain(..).collect();
+		for bytes in early_messages.iter(){
+			mirror.apply_bytes(bytes).unwrap();
+		}
+
+		//	中途加入的客户端先拿全量状态，再接着收后面的增量消息
+		let mut late_mirror = SyncMirror::new();
+		let full_state = FullStateCollector::collect(behavior_tree.borrow().as_ref(), &sequences.borrow());
+		late_mirror.apply_bytes(&full_state.encode()).unwrap();
+		assert!(late_mirror.tree(3) == mirror.tree(3));
+
+		//	全量状态之前的消息迟到了也不会重复应用
+		for bytes in early_messages.iter(){
+			late_mirror.apply_bytes(bytes).unwrap();
+		}
+		assert!(late_mirror.tree(3) == mirror.tree(3));
+
+		behavior_tree.borrow_mut().interrupt(4, TaskStatus::Failure, true).unwrap();
+		behavior_tree.borrow_mut().update();
+		for bytes in messages.borrow_mut().drain(..){
+			mirror.apply_bytes(&bytes).unwrap();
+			late_mirror.apply_bytes(&bytes).unwrap();
+		}
+		assert!(late_mirror.tree(3).is_some());
+		assert!(late_mirror.tree(3) == mirror.tree(3));
+
+		//	已有状态的客户端收到全量状态会整棵替换
+		mirror.apply(&SyncMessage::FullState{tree_id:3, stacks:Vec::new(), parallels:Vec::new(), actions:Vec::new()}).unwrap();
+		assert!(mirror.tree(3).is_none());
+	}
+
+	#[test]
+	fn test_sync_mirror_gap_resync() {
+		let file_bytes = std::fs::read("src/behavior_tree/test_behaviortree.json").unwrap();
+		let parser = JsonParser::new();
+		let clock:Rc<RefCell<Box<dyn IClock>>> = Rc::new(RefCell::new(Box::new(DummyClock)));
+		let messages = Rc::new(RefCell::new(Vec::new()));
+		let sequences = SyncSequences::new();
+		let behavior_tree = BehaviorTree::new(4, &file_bytes, 1, &Rc::downgrade(&clock), Box::new(WireEncodeRuntimeEventHandle::new(messages.clone(), sequences.clone())), Rc::downgrade(&parser));
+		let mut server_view = SyncMirror::new();
+		let mut mirror = SyncMirror::new();
+
+		behavior_tree.borrow_mut().enable().unwrap();
+		behavior_tree.borrow_mut().update();
+		for bytes in messages.borrow_mut().drain(..){
+			server_view.apply_bytes(&bytes).unwrap();
+			mirror.apply_bytes(&bytes).unwrap();
+		}
+		assert!(mirror.take_resync_requests().is_empty());
+
+		//	丢掉一个包
+		behavior_tree.borrow_mut().interrupt(4, TaskStatus::Failure, true).unwrap();
+		behavior_tree.borrow_mut().update();
+		let lost_messages:Vec<Vec<u8>> = messages.borrow_mut().drain(..).collect();
+		assert!(lost_messages.len() > 1);
+		for (index, bytes) in lost_messages.iter().enumerate(){
+			server_view.apply_bytes(bytes).unwrap();
+			if index != 0{
+				mirror.apply_bytes(bytes).unwrap();
+			}
+		}
+		assert!(mirror.is_resyncing(4));
+		assert_eq!(mirror.take_resync_requests(), vec![4]);
+		assert!(mirror.take_resync_requests().is_empty());
+
+		//	服务器收到请求后发全量状态，客户端恢复以后继续收增量消息
+		let full_state = FullStateCollector::collect(behavior_tree.borrow().as_ref(), &sequences.borrow());
+		mirror.apply_bytes(&full_state.encode()).unwrap();
+		assert!(!mirror.is_resyncing(4));
+		assert!(mirror.tree(4) == server_view.tree(4));
+
+		behavior_tree.borrow_mut().update();
+		behavior_tree.borrow_mut().disable().unwrap();
+		for bytes in messages.borrow_mut().drain(..){
+			server_view.apply_bytes(&bytes).unwrap();
+			mirror.apply_bytes(&bytes).unwrap();
+		}
+		assert!(mirror.tree(4).is_none());
+		assert!(mirror.take_resync_requests().is_empty());
+	}
+}
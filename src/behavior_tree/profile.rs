@@ -0,0 +1,146 @@
+use std::{rc::Rc, cell::RefCell, collections::HashMap, time::{Duration, Instant}};
+
+use super::consts::TaskStatus;
+use super::interface::{IBehaviorTree, IRuntimeEventHandle, ITaskProxy, StackRuntimeData, TaskRuntimeData};
+
+#[derive(Clone, Copy, Default, PartialEq, Debug)]
+pub struct TaskTypeCost{
+	pub total:Duration,
+	pub count:u64,
+}
+
+//	一棵树里按任务类型统计action和条件任务on_update的耗时，默认不开启
+#[derive(Default)]
+pub struct TaskProfile{
+	enabled:bool,
+	running:Vec<(i32, Instant)>,
+	costs:HashMap<String, TaskTypeCost>,
+}
+
+impl TaskProfile{
+	pub fn new()->Rc<RefCell<Self>>{
+		Rc::new(RefCell::new(Self::default()))
+	}
+
+	pub fn set_enabled(&mut self, enabled:bool){
+		self.enabled = enabled;
+		self.running.clear();
+	}
+
+	pub fn is_enabled(&self)->bool{
+		self.enabled
+	}
+
+	pub fn costs(&self)->&HashMap<String, TaskTypeCost>{
+		&self.costs
+	}
+
+	//	按总耗时从大到小
+	pub fn hottest(&self, count:usize)->Vec<(String, TaskTypeCost)>{
+		let mut costs:Vec<(String, TaskTypeCost)> = self.costs.iter().map(|(corresponding_type, cost)| (corresponding_type.clone(), *cost)).collect();
+		costs.sort_by(|(a_type, a), (b_type, b)| b.total.cmp(&a.total).then_with(|| a_type.cmp(b_type)));
+		costs.truncate(count);
+		costs
+	}
+
+	pub fn clear(&mut self){
+		self.running.clear();
+		self.costs.clear();
+	}
+
+	fn begin(&mut self, task_id:i32){
+		if self.enabled{
+			self.running.push((task_id, Instant::now()));
+		}
+	}
+
+	//	父任务没有begin，这里直接忽略
+	fn end(&mut self, task:&dyn ITaskProxy){
+		if self.running.last().is_some_and(|(task_id, _)| *task_id == task.id()){
+			let (_, start) = self.running.pop().unwrap();
+			let cost = self.costs.entry(task.corresponding_type().to_string()).or_default();
+			cost.total += start.elapsed();
+			cost.count += 1;
+		}
+	}
+}
+
+//	包在原来的回调外面统计耗时，统计不包括原来回调自己的耗时
+pub struct ProfileRuntimeEventHandle{
+	profile:Rc<RefCell<TaskProfile>>,
+	handle:Box<dyn IRuntimeEventHandle>,
+}
+
+impl ProfileRuntimeEventHandle{
+	pub fn new(profile:Rc<RefCell<TaskProfile>>, handle:Box<dyn IRuntimeEventHandle>)->Self{
+		Self{profile, handle}
+	}
+}
+
+impl IRuntimeEventHandle for ProfileRuntimeEventHandle{
+	fn post_initialize(&self, behavior_tree:&dyn IBehaviorTree, now_timestamp_in_milli:u64){
+		self.handle.post_initialize(behavior_tree, now_timestamp_in_milli);
+	}
+
+	fn post_on_complete(&self, behavior_tree:&dyn IBehaviorTree, now_timestamp_in_milli:u64){
+		self.handle.post_on_complete(behavior_tree, now_timestamp_in_milli);
+	}
+
+	fn new_stack(&self, behavior_tree:&dyn IBehaviorTree, data:&StackRuntimeData){
+		self.handle.new_stack(behavior_tree, data);
+	}
+
+	fn remove_stack(&self, behavior_tree:&dyn IBehaviorTree, data:&StackRuntimeData, now_timestamp_in_milli:u64){
+		self.handle.remove_stack(behavior_tree, data, now_timestamp_in_milli);
+	}
+
+	fn pre_on_start(&self, behavior_tree:&dyn IBehaviorTree, task_runtime_data:&TaskRuntimeData, stack_runtime_data:&StackRuntimeData, task:&dyn ITaskProxy){
+		self.handle.pre_on_start(behavior_tree, task_runtime_data, stack_runtime_data, task);
+	}
+
+	fn pre_on_update(&self, behavior_tree:&dyn IBehaviorTree, task_runtime_data:&TaskRuntimeData, stack_runtime_data:&StackRuntimeData, task:&dyn ITaskProxy){
+		self.handle.pre_on_update(behavior_tree, task_runtime_data, stack_runtime_data, task);
+		self.profile.borrow_mut().begin(task.id());
+	}
+
+	fn post_on_update(&self, behavior_tree:&dyn IBehaviorTree, task_runtime_data:&TaskRuntimeData, stack_runtime_data:&StackRuntimeData, task:&dyn ITaskProxy, now_timestamp_in_milli:u64, status:TaskStatus){
+		self.profile.borrow_mut().end(task);
+		self.handle.post_on_update(behavior_tree, task_runtime_data, stack_runtime_data, task, now_timestamp_in_milli, status);
+	}
+
+	fn post_on_end(&self, behavior_tree:&dyn IBehaviorTree, task_runtime_data:&TaskRuntimeData, stack_runtime_data:&StackRuntimeData, task:&dyn ITaskProxy, now_timestamp_in_milli:u64){
+		self.handle.post_on_end(behavior_tree, task_runtime_data, stack_runtime_data, task, now_timestamp_in_milli);
+	}
+
+	fn action_post_on_start(&self, behavior_tree:&dyn IBehaviorTree, task_runtime_data:&TaskRuntimeData, stack_runtime_data:&StackRuntimeData, task:&dyn ITaskProxy, datas:&[Vec<u8>]){
+		self.handle.action_post_on_start(behavior_tree, task_runtime_data, stack_runtime_data, task, datas);
+	}
+
+	fn action_post_on_update(&self, behavior_tree:&dyn IBehaviorTree, task_runtime_data:&TaskRuntimeData, stack_runtime_data:&StackRuntimeData, task:&dyn ITaskProxy, now_timestamp_in_milli:u64, status:TaskStatus, datas:&[Vec<u8>]){
+		self.handle.action_post_on_update(behavior_tree, task_runtime_data, stack_runtime_data, task, now_timestamp_in_milli, status, datas);
+	}
+
+	fn action_post_on_end(&self, behavior_tree:&dyn IBehaviorTree, task_runtime_data:&TaskRuntimeData, stack_runtime_data:&StackRuntimeData, task:&dyn ITaskProxy, now_timestamp_in_milli:u64, datas:&[Vec<u8>]){
+		self.handle.action_post_on_end(behavior_tree, task_runtime_data, stack_runtime_data, task, now_timestamp_in_milli, datas);
+	}
+
+	fn parallel_pre_on_start(&self, behavior_tree:&dyn IBehaviorTree, task_runtime_data:&TaskRuntimeData, stack_runtime_data:&StackRuntimeData, task:&dyn ITaskProxy){
+		self.handle.parallel_pre_on_start(behavior_tree, task_runtime_data, stack_runtime_data, task);
+	}
+
+	fn parallel_post_on_end(&self, behavior_tree:&dyn IBehaviorTree, task_runtime_data:&TaskRuntimeData, stack_runtime_data:&StackRuntimeData, task:&dyn ITaskProxy, now_timestamp_in_milli:u64){
+		self.handle.parallel_post_on_end(behavior_tree, task_runtime_data, stack_runtime_data, task, now_timestamp_in_milli);
+	}
+
+	fn parallel_add_child_stack(&self, behavior_tree:&dyn IBehaviorTree, task_runtime_data:&TaskRuntimeData, stack_runtime_data:&StackRuntimeData, task:&dyn ITaskProxy, child_stack_runtime_data:&StackRuntimeData){
+		self.handle.parallel_add_child_stack(behavior_tree, task_runtime_data, stack_runtime_data, task, child_stack_runtime_data);
+	}
+
+	fn parallel_remove_child_stack(&self, behavior_tree:&dyn IBehaviorTree, task_runtime_data:&TaskRuntimeData, stack_runtime_data:&StackRuntimeData, task:&dyn ITaskProxy, child_stack_runtime_data:&StackRuntimeData, now_timestamp_in_milli:u64){
+		self.handle.parallel_remove_child_stack(behavior_tree, task_runtime_data, stack_runtime_data, task, child_stack_runtime_data, now_timestamp_in_milli);
+	}
+
+	fn on_task_error(&self, behavior_tree:&dyn IBehaviorTree, task:&dyn ITaskProxy, error:&dyn std::error::Error){
+		self.handle.on_task_error(behavior_tree, task, error);
+	}
+}
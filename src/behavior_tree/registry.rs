@@ -0,0 +1,331 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex, RwLock};
+
+use super::interface::{TaskAddData, ITaskProxy, IAction, IConditional, IComposite, IDecorator, RealTaskType};
+use super::runtime::TaskProxy;
+use super::consts::AbortType;
+use super::composite::sequence::Sequence;
+use super::composite::selector::Selector;
+use super::composite::parallel::Parallel;
+use super::composite::parallel_selector::ParallelSelector;
+use super::composite::if_else::If;
+use super::action::idle::Idle;
+use super::action::play_ani_for_sync::PlayAniForSync;
+use super::action::role_follow_joystick::RoleFollowJoystick;
+use super::action::perform_interruption::PerformInterruption;
+
+use super::decorator::return_failure::ReturnFailure;
+use super::decorator::return_success::ReturnSuccess;
+use super::decorator::until_failure::UntilFailure;
+use super::decorator::until_success::UntilSuccess;
+use super::decorator::until_forever::UntilForever;
+use super::decorator::interrupt::Interrupt;
+
+use super::conditional::need_follow_joystick::NeedFollowJoystick;
+
+//  解析器无关的任务配置，各种格式的解析器先转成这个，再由TaskRegistry生成任务
+#[derive(Clone, PartialEq, Debug)]
+pub struct TaskConfig{
+    pub corresponding_type:String,
+    pub name:String,
+    pub id:i32,
+    //  没有配置的时候用TaskProxy的默认值
+    pub instant:Option<bool>,
+    pub disabled:Option<bool>,
+    pub abort_type:Option<AbortType>,
+    pub variables:HashMap<String, serde_json::Value>,
+    pub children:Vec<TaskConfig>,
+}
+
+impl TaskConfig{
+    pub fn new(corresponding_type:&str, name:&str, id:i32) -> Self{
+        Self{
+            corresponding_type:corresponding_type.to_string(),
+            name:name.to_string(),
+            id,
+            instant:None,
+            disabled:None,
+            abort_type:None,
+            variables:HashMap::new(),
+            children:Vec::new(),
+        }
+    }
+
+    fn collect_task_ids(&self, used:&mut HashSet<i32>) -> Result<(), Box<dyn std::error::Error>>{
+        if self.id != 0 && !used.insert(self.id){
+            return Err(format!("task id {} is used more than once", self.id).into());
+        }
+        for child in self.children.iter(){
+            child.collect_task_ids(used)?;
+        }
+        Ok(())
+    }
+
+    fn assign_task_ids(&mut self, used:&HashSet<i32>, next_id:&mut i32){
+        if self.id == 0{
+            loop{
+                *next_id += 1;
+                if !used.contains(next_id){
+                    break;
+                }
+            }
+            self.id = *next_id;
+        }
+        for child in self.children.iter_mut(){
+            child.assign_task_ids(used, next_id);
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct TreeConfig{
+    pub root_task:TaskConfig,
+    //  不在树上的任务，只会生成和初始化变量
+    pub detached_tasks:Vec<TaskConfig>,
+}
+
+impl TreeConfig{
+    //  ID为0的任务按前序自动分配ID，跳过已经配置了的ID，手写的树和代码生成的树用
+    pub fn assign_task_ids(&mut self) -> Result<(), Box<dyn std::error::Error>>{
+        let mut used = HashSet::new();
+        self.root_task.collect_task_ids(&mut used)?;
+        for detached_task in self.detached_tasks.iter(){
+            detached_task.collect_task_ids(&mut used)?;
+        }
+
+        let mut next_id = 0;
+        self.root_task.assign_task_ids(&used, &mut next_id);
+        for detached_task in self.detached_tasks.iter_mut(){
+            detached_task.assign_task_ids(&used, &mut next_id);
+        }
+        Ok(())
+    }
+}
+
+//  一棵树里已经生成的任务的配置ID，生成任务的时候传给任务，全部生成以后可以用来检查引用的任务
+pub type TaskIds = Arc<Mutex<HashSet<i32>>>;
+
+//  注册的任务创建函数，T是IAction、IConditional、IComposite、IDecorator
+pub type TaskGenerateFn<T> = fn(variables:HashMap<String, serde_json::Value>,task_ids:TaskIds) -> Box<T>;
+
+//  注册的任务是哪一类，编译树的时候不生成任务也能算索引表
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum TaskKind{
+    Action,
+    Conditional,
+    Composite,
+    Decorator,
+}
+
+impl TaskKind{
+    pub fn is_parent_task(&self) -> bool{
+        matches!(self, TaskKind::Composite | TaskKind::Decorator)
+    }
+}
+
+//  所有解析器共用的任务注册表
+pub struct TaskRegistry{
+    action_fn: HashMap<String, TaskGenerateFn<dyn IAction>>,
+    conditional_fn: HashMap<String, TaskGenerateFn<dyn IConditional>>,
+    composite_fn: HashMap<String, TaskGenerateFn<dyn IComposite>>,
+    decorator_fn: HashMap<String, TaskGenerateFn<dyn IDecorator>>,
+    //  手写格式里的类型简称 -> 完整类型名
+    aliases: HashMap<String, String>,
+}
+
+#[allow(unused_variables)]
+impl TaskRegistry{
+    //  注册了默认节点
+    pub fn new() -> Arc<RwLock<Self>>{
+        let mut registry = Self{
+            action_fn: HashMap::new(),
+            conditional_fn: HashMap::new(),
+            composite_fn: HashMap::new(),
+            decorator_fn: HashMap::new(),
+            aliases: HashMap::new(),
+        };
+
+        registry.register_composite_fn("BehaviorDesigner.Runtime.Tasks.Sequence", |variables, task_ids| -> Box<dyn IComposite> {Box::new(Sequence::new())});
+        registry.register_composite_fn("BehaviorDesigner.Runtime.Tasks.Selector", |variables, task_ids| -> Box<dyn IComposite> {Box::new(Selector::new())});
+        registry.register_composite_fn("BehaviorDesigner.Runtime.Tasks.Parallel", |variables, task_ids| -> Box<dyn IComposite> {Box::new(Parallel::new())});
+        registry.register_composite_fn("BehaviorDesigner.Runtime.Tasks.ParallelSelector", |variables, task_ids| -> Box<dyn IComposite> {Box::new(ParallelSelector::new())});
+        registry.register_composite_fn("BehaviorDesigner.Runtime.Tasks.If", |variables, task_ids| -> Box<dyn IComposite> {Box::new(If::new())});
+
+        registry.register_action_fn("BehaviorDesigner.Runtime.Tasks.Idle", |variables, task_ids| -> Box<dyn IAction> {Box::new(Idle::new())});
+        registry.register_action_fn("BehaviorDesigner.Runtime.Tasks.PlayAniForSync", |variables, task_ids| -> Box<dyn IAction> {Box::new(PlayAniForSync::new(variables))});
+        registry.register_action_fn("BehaviorDesigner.Runtime.Tasks.RoleFollowJoystick", |variables, task_ids| -> Box<dyn IAction> {Box::new(RoleFollowJoystick::new())});
+        registry.register_action_fn("BehaviorDesigner.Runtime.Tasks.Role.MainRole.RoleFollowJoystick", |variables, task_ids| -> Box<dyn IAction> {Box::new(RoleFollowJoystick::new())});
+        registry.register_action_fn("BehaviorDesigner.Runtime.Tasks.PerformInterruption", |variables, task_ids| -> Box<dyn IAction> {Box::new(PerformInterruption::new(variables, task_ids))});
+
+        registry.register_decorator_fn("BehaviorDesigner.Runtime.Tasks.ReturnFailure", |variables, task_ids| -> Box<dyn IDecorator> {Box::new(ReturnFailure::new())});
+        registry.register_decorator_fn("BehaviorDesigner.Runtime.Tasks.ReturnSuccess", |variables, task_ids| -> Box<dyn IDecorator> {Box::new(ReturnSuccess::new())});
+        registry.register_decorator_fn("BehaviorDesigner.Runtime.Tasks.UntilFailure", |variables, task_ids| -> Box<dyn IDecorator> {Box::new(UntilFailure::new())});
+        registry.register_decorator_fn("BehaviorDesigner.Runtime.Tasks.UntilSuccess", |variables, task_ids| -> Box<dyn IDecorator> {Box::new(UntilSuccess::new())});
+        registry.register_decorator_fn("BehaviorDesigner.Runtime.Tasks.UntilForever", |variables, task_ids| -> Box<dyn IDecorator> {Box::new(UntilForever::new())});
+        registry.register_decorator_fn("BehaviorDesigner.Runtime.Tasks.Interrupt", |variables, task_ids| -> Box<dyn IDecorator> {Box::new(Interrupt::new())});
+
+        registry.register_conditional_fn("BehaviorDesigner.Runtime.Tasks.Role.MainRole.NeedFollowJoystick", |variables, task_ids| -> Box<dyn IConditional> {Box::new(NeedFollowJoystick::new())});
+
+        registry.register_alias("need_follow_joystick", "BehaviorDesigner.Runtime.Tasks.Role.MainRole.NeedFollowJoystick");
+        Arc::new(RwLock::new(registry))
+    }
+
+    pub fn register_action_fn(&mut self, name:&str, action_generate_fn:TaskGenerateFn<dyn IAction>){
+        self.action_fn.insert(name.to_string(), action_generate_fn);
+    }
+
+    pub fn register_conditional_fn(&mut self, name:&str, conditional_generate_fn:TaskGenerateFn<dyn IConditional>){
+        self.conditional_fn.insert(name.to_string(), conditional_generate_fn);
+    }
+
+    pub fn register_composite_fn(&mut self, name:&str, composite_generate_fn:TaskGenerateFn<dyn IComposite>){
+        self.composite_fn.insert(name.to_string(), composite_generate_fn);
+    }
+
+    pub fn register_decorator_fn(&mut self, name:&str, decorator_generate_fn:TaskGenerateFn<dyn IDecorator>){
+        self.decorator_fn.insert(name.to_string(), decorator_generate_fn);
+    }
+
+    pub fn register_alias(&mut self, alias:&str, corresponding_type:&str){
+        self.aliases.insert(alias.to_string(), corresponding_type.to_string());
+    }
+
+    //  完整类型名原样返回，其次查简称，最后把snake_case转成BehaviorDesigner.Runtime.Tasks下的类型
+    pub fn resolve_type(&self, name:&str) -> Option<String>{
+        if self.contains(name){
+            return Some(name.to_string());
+        }
+        if let Some(corresponding_type) = self.aliases.get(name){
+            return Some(corresponding_type.clone());
+        }
+
+        let pascal_case:String = name.split('_').map(|word|{
+            let mut chars = word.chars();
+            match chars.next(){
+                Some(first) => first.to_uppercase().chain(chars).collect::<String>(),
+                None => String::new(),
+            }
+        }).collect();
+        let corresponding_type = format!("BehaviorDesigner.Runtime.Tasks.{}", pascal_case);
+        if self.contains(&corresponding_type){
+            return Some(corresponding_type);
+        }
+        None
+    }
+
+    pub fn task_kind(&self, corresponding_type:&str) -> Option<TaskKind>{
+        if self.action_fn.contains_key(corresponding_type){
+            Some(TaskKind::Action)
+        }else if self.conditional_fn.contains_key(corresponding_type){
+            Some(TaskKind::Conditional)
+        }else if self.composite_fn.contains_key(corresponding_type){
+            Some(TaskKind::Composite)
+        }else if self.decorator_fn.contains_key(corresponding_type){
+            Some(TaskKind::Decorator)
+        }else{
+            None
+        }
+    }
+
+    pub fn contains(&self, corresponding_type:&str) -> bool{
+        self.action_fn.contains_key(corresponding_type) || self.conditional_fn.contains_key(corresponding_type)
+            || self.composite_fn.contains_key(corresponding_type) || self.decorator_fn.contains_key(corresponding_type)
+    }
+
+    fn generate_real_task(&self, corresponding_type:&str, variables:HashMap<String, serde_json::Value>,task_ids:TaskIds) -> Result<RealTaskType, Box<dyn std::error::Error>>{
+        if let Some(action_fn) = self.action_fn.get(corresponding_type){
+            return Ok(RealTaskType::Action(action_fn(variables, task_ids)));
+        }
+        if let Some(conditional_fn) = self.conditional_fn.get(corresponding_type){
+            return Ok(RealTaskType::Conditional(conditional_fn(variables, task_ids)));
+        }
+        if let Some(composite_fn) = self.composite_fn.get(corresponding_type){
+            return Ok(RealTaskType::Composite(composite_fn(variables, task_ids)));
+        }
+        if let Some(decorator_fn) = self.decorator_fn.get(corresponding_type){
+            return Ok(RealTaskType::Decorator(decorator_fn(variables, task_ids)));
+        }
+
+        Err(format!("generate_real_task not implemented for corresponding_type: {}", corresponding_type).into())
+    }
+
+    //  变量直接交给任务的创建函数，不复制
+    fn generate_task_proxy(&self, task_config:TaskConfig, task_ids:&TaskIds) -> Result<Box<dyn ITaskProxy>, Box<dyn std::error::Error>>{
+        let real_task: RealTaskType = self.generate_real_task(&task_config.corresponding_type, task_config.variables, task_ids.clone())?;
+
+        let mut task_proxy: TaskProxy =TaskProxy::new(&task_config.corresponding_type, &task_config.name, real_task);
+        task_proxy.set_id(task_config.id);
+        if let Some(instant) = task_config.instant{
+            task_proxy.set_instant(instant);
+        }
+        if let Some(disabled) = task_config.disabled{
+            task_proxy.set_disabled(disabled);
+        }
+        if let Some(abort_type) = task_config.abort_type{
+            task_proxy.set_abort_type(abort_type);
+        }
+
+        if task_proxy.id() == 0{
+            return Err("ID is 0".into());
+        }
+
+        if !task_ids.lock().map_err(|_| "task ids lock poisoned")?.insert(task_proxy.id()){
+            return Err("ID already exists".into());
+        }
+
+        let mut task_proxy:Box<dyn ITaskProxy> = Box::new(task_proxy);
+        for child in task_config.children.into_iter(){
+            let child = self.generate_task_proxy(child, task_ids)?;
+            task_proxy.add_child(child);
+        }
+
+        Ok(task_proxy)
+    }
+
+    //  按前序初始化任务变量，跟生成任务的顺序一样
+    fn initialize_variables(task_proxy:&mut Box<dyn ITaskProxy>) -> Result<(), Box<dyn std::error::Error>>{
+        task_proxy.initialize_variables()?;
+        for child in task_proxy.children_mut().iter_mut(){
+            Self::initialize_variables(child)?;
+        }
+        Ok(())
+    }
+
+    fn initialize_parent_task(&self, task_proxy:&mut Box<dyn ITaskProxy>, task_add_data:&mut TaskAddData){
+        if task_proxy.is_implements_iparenttask(){
+            let old_parent = task_add_data.parent.replace(task_proxy.id());
+
+            task_proxy.children_mut().iter_mut().for_each(|child|{
+                self.initialize_parent_task(child, task_add_data);
+            });
+
+            task_add_data.parent = old_parent;
+        }
+    }
+
+    //  生成任务并初始化变量，返回根任务
+    pub fn build(&self, tree_config:TreeConfig, task_add_data:&mut TaskAddData) -> Result<Box<dyn ITaskProxy>, Box<dyn std::error::Error>>{
+        let task_ids:TaskIds = Arc::new(Mutex::new(HashSet::new()));
+        let mut root_task = self.generate_task_proxy(tree_config.root_task, &task_ids)?;
+
+        let mut detached_tasks:Vec<Box<dyn ITaskProxy>> = Vec::new();
+        for detached_task_config in tree_config.detached_tasks.into_iter(){
+            let detached_task = self.generate_task_proxy(detached_task_config, &task_ids)?;
+            detached_tasks.push(detached_task);
+        }
+
+        //  初始化任务变量
+        Self::initialize_variables(&mut root_task)?;
+        for detached_task in detached_tasks.iter_mut(){
+            Self::initialize_variables(detached_task)?;
+        }
+
+        self.initialize_parent_task(&mut root_task,task_add_data);
+
+        for detached_task in detached_tasks.iter_mut(){
+            self.initialize_parent_task(detached_task,task_add_data);
+        }
+
+        Ok(root_task)
+    }
+}
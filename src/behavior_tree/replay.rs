@@ -0,0 +1,606 @@
+use std::{rc::{Rc, Weak}, cell::RefCell};
+use serde::{Serialize, Deserialize};
+
+use super::consts::TaskStatus;
+use super::interface::{IClock, IBehaviorTree, IRuntimeEventHandle, IParser, IRebuildSyncDataCollector, ITaskProxy, StackRuntimeData, TaskRuntimeData};
+use super::runtime::BehaviorTree;
+use super::snapshot::BehaviorTreeSnapshot;
+
+pub const RECORDING_VERSION:u32 = 1;
+
+//	行为树看到的所有输入，按发生的顺序记录
+#[derive(Clone, Serialize, Deserialize)]
+pub enum RecordedInput{
+	Enable,
+	Disable,
+	Update,
+	Interrupt{task_id:i32, status:TaskStatus, include_self:bool},
+	RequestInterrupt{task_id:i32, status:TaskStatus, include_self:bool},
+	Restore(Box<BehaviorTreeSnapshot>),
+	//	IClock的读数
+	Clock(u64),
+	//	通过input接口读取的外部数据，比如黑板的值、随机数
+	Input{channel:String, data:Vec<u8>},
+}
+
+impl RecordedInput{
+	fn describe(&self)->String{
+		serde_json::to_string(self).unwrap_or_default()
+	}
+}
+
+//	录像文件：树的配置、全部输入以及当时产生的事件流
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Recording{
+	pub version:u32,
+	pub id:u64,
+	pub unit_id:u64,
+	pub config:Vec<u8>,
+	pub inputs:Vec<RecordedInput>,
+	pub events:Vec<String>,
+}
+
+impl Recording{
+	pub fn to_bytes(&self)->Result<Vec<u8>, Box<dyn std::error::Error>>{
+		Ok(serde_json::to_vec(self)?)
+	}
+
+	pub fn from_bytes(bytes:&[u8])->Result<Self, Box<dyn std::error::Error>>{
+		let recording:Recording = serde_json::from_slice(bytes)?;
+		if recording.version != RECORDING_VERSION{
+			return Err(format!("unsupported recording version: {}", recording.version).into());
+		}
+		Ok(recording)
+	}
+}
+
+//	回放出现的第一个不一致，expected是录像里的内容，actual是回放产生的内容
+#[derive(Clone, PartialEq)]
+pub struct ReplayDivergence{
+	pub index:usize,
+	pub expected:Option<String>,
+	pub actual:Option<String>,
+}
+
+impl std::fmt::Display for ReplayDivergence{
+	fn fmt(&self, f:&mut std::fmt::Formatter<'_>)->std::fmt::Result{
+		writeln!(f, "replay diverged at event {}", self.index)?;
+		writeln!(f, "- {}", self.expected.as_deref().unwrap_or("<none>"))?;
+		write!(f, "+ {}", self.actual.as_deref().unwrap_or("<none>"))
+	}
+}
+
+impl std::fmt::Debug for ReplayDivergence{
+	fn fmt(&self, f:&mut std::fmt::Formatter<'_>)->std::fmt::Result{
+		std::fmt::Display::fmt(self, f)
+	}
+}
+
+impl std::error::Error for ReplayDivergence{}
+
+enum SessionMode{
+	Record,
+	Replay,
+}
+
+pub struct ReplaySession{
+	mode:SessionMode,
+	recording:Recording,
+	input_cursor:usize,
+	event_cursor:usize,
+	divergence:Option<ReplayDivergence>,
+}
+
+impl ReplaySession{
+	pub fn record(id:u64, config:&[u8], unit_id:u64)->Rc<RefCell<Self>>{
+		Rc::new(RefCell::new(Self{
+			mode:SessionMode::Record,
+			recording:Recording{
+				version:RECORDING_VERSION,
+				id,
+				unit_id,
+				config:config.to_vec(),
+				inputs:Vec::new(),
+				events:Vec::new(),
+			},
+			input_cursor:0,
+			event_cursor:0,
+			divergence:None,
+		}))
+	}
+
+	pub fn replay(recording:Recording)->Rc<RefCell<Self>>{
+		Rc::new(RefCell::new(Self{
+			mode:SessionMode::Replay,
+			recording,
+			input_cursor:0,
+			event_cursor:0,
+			divergence:None,
+		}))
+	}
+
+	pub fn recording(&self)->&Recording{
+		&self.recording
+	}
+
+	pub fn divergence(&self)->Option<&ReplayDivergence>{
+		self.divergence.as_ref()
+	}
+
+	//	录制的时候调用live取得数据并记下来，回放的时候直接返回录像里的数据
+	pub fn input(&mut self, channel:&str, live:impl FnOnce()->Vec<u8>)->Vec<u8>{
+		match self.mode{
+			SessionMode::Record => {
+				let data = live();
+				self.recording.inputs.push(RecordedInput::Input{channel:channel.to_string(), data:data.clone()});
+				data
+			},
+			SessionMode::Replay => {
+				match self.next_input(){
+					Some(RecordedInput::Input{channel:recorded_channel, data}) if recorded_channel == channel => data,
+					recorded => {
+						self.diverge_input(recorded, format!("input {}", channel));
+						Vec::new()
+					},
+				}
+			},
+		}
+	}
+
+	fn clock(&mut self, live:impl FnOnce()->u64)->u64{
+		match self.mode{
+			SessionMode::Record => {
+				let timestamp_in_mill = live();
+				self.recording.inputs.push(RecordedInput::Clock(timestamp_in_mill));
+				timestamp_in_mill
+			},
+			SessionMode::Replay => {
+				match self.next_input(){
+					Some(RecordedInput::Clock(timestamp_in_mill)) => timestamp_in_mill,
+					recorded => {
+						self.diverge_input(recorded, "clock".to_string());
+						0
+					},
+				}
+			},
+		}
+	}
+
+	fn call(&mut self, input:RecordedInput){
+		if let SessionMode::Record = self.mode{
+			self.recording.inputs.push(input);
+		}
+	}
+
+	fn event(&mut self, line:String){
+		match self.mode{
+			SessionMode::Record => self.recording.events.push(line),
+			SessionMode::Replay => {
+				let index = self.event_cursor;
+				self.event_cursor += 1;
+				let expected = self.recording.events.get(index);
+				if expected != Some(&line) && self.divergence.is_none(){
+					self.divergence = Some(ReplayDivergence{
+						index,
+						expected:expected.cloned(),
+						actual:Some(line),
+					});
+				}
+			},
+		}
+	}
+
+	fn next_input(&mut self)->Option<RecordedInput>{
+		let input = self.recording.inputs.get(self.input_cursor).cloned();
+		if input.is_some(){
+			self.input_cursor += 1;
+		}
+		input
+	}
+
+	fn diverge_input(&mut self, recorded:Option<RecordedInput>, actual:String){
+		if self.divergence.is_none(){
+			self.divergence = Some(ReplayDivergence{
+				index:self.event_cursor,
+				expected:recorded.map(|input| format!("input {}", input.describe())),
+				actual:Some(actual),
+			});
+		}
+	}
+}
+
+thread_local! {
+	static ACTIVE_SESSION:RefCell<Option<Rc<RefCell<ReplaySession>>>> = const { RefCell::new(None) };
+}
+
+//	任务读取黑板、随机数之类的外部数据时走这个接口，录制/回放中的树会记录或者重放这些数据
+pub fn input(channel:&str, live:impl FnOnce()->Vec<u8>)->Vec<u8>{
+	let session = ACTIVE_SESSION.with(|session| session.borrow().clone());
+	match session{
+		Some(session) => session.borrow_mut().input(channel, live),
+		None => live(),
+	}
+}
+
+fn with_session<R>(session:&Rc<RefCell<ReplaySession>>, f:impl FnOnce()->R)->R{
+	let previous = ACTIVE_SESSION.with(|active| active.replace(Some(session.clone())));
+	let result = f();
+	ACTIVE_SESSION.with(|active| *active.borrow_mut() = previous);
+	result
+}
+
+struct ReplayClock{
+	session:Rc<RefCell<ReplaySession>>,
+	clock:Option<Weak<RefCell<Box<dyn IClock>>>>,
+}
+
+impl IClock for ReplayClock{
+	fn timestamp_in_mill(&self)->u64{
+		let clock = self.clock.as_ref().and_then(|clock| clock.upgrade());
+		self.session.borrow_mut().clock(|| clock.map(|clock| clock.borrow().timestamp_in_mill()).unwrap_or(0))
+	}
+}
+
+fn hex(datas:&[Vec<u8>])->String{
+	datas.iter().map(|data| data.iter().map(|byte| format!("{:02x}", byte)).collect::<String>()).collect::<Vec<String>>().join(",")
+}
+
+fn task_line(kind:&str, behavior_tree:&dyn IBehaviorTree, task_runtime_data:&TaskRuntimeData, stack_runtime_data:&StackRuntimeData, task:&dyn ITaskProxy)->String{
+	format!("{} tree={} task={} name={} execute={} stack={} start={} stack_start={}", kind, behavior_tree.id(), task_runtime_data.task_id, task.name(),
+		task_runtime_data.execute_id, stack_runtime_data.stack_id, task_runtime_data.start_time, stack_runtime_data.start_time)
+}
+
+//	把每个回调转成一行文本记到录像里，回放时逐行比较，同时转发给真正的事件处理
+struct ReplayRuntimeEventHandle{
+	session:Rc<RefCell<ReplaySession>>,
+	runtime_event_handle:Option<Box<dyn IRuntimeEventHandle>>,
+}
+
+impl IRuntimeEventHandle for ReplayRuntimeEventHandle{
+	fn post_initialize(&self, behavior_tree:&dyn IBehaviorTree, now_timestamp_in_milli:u64){
+		self.session.borrow_mut().event(format!("post_initialize tree={} now={}", behavior_tree.id(), now_timestamp_in_milli));
+		if let Some(handle) = &self.runtime_event_handle{
+			handle.post_initialize(behavior_tree, now_timestamp_in_milli);
+		}
+	}
+
+	fn post_on_complete(&self, behavior_tree:&dyn IBehaviorTree, now_timestamp_in_milli:u64){
+		self.session.borrow_mut().event(format!("post_on_complete tree={} now={}", behavior_tree.id(), now_timestamp_in_milli));
+		if let Some(handle) = &self.runtime_event_handle{
+			handle.post_on_complete(behavior_tree, now_timestamp_in_milli);
+		}
+	}
+
+	fn new_stack(&self, behavior_tree:&dyn IBehaviorTree, data:&StackRuntimeData){
+		self.session.borrow_mut().event(format!("new_stack tree={} stack={} start={}", behavior_tree.id(), data.stack_id, data.start_time));
+		if let Some(handle) = &self.runtime_event_handle{
+			handle.new_stack(behavior_tree, data);
+		}
+	}
+
+	fn remove_stack(&self, behavior_tree:&dyn IBehaviorTree, data:&StackRuntimeData, now_timestamp_in_milli:u64){
+		self.session.borrow_mut().event(format!("remove_stack tree={} stack={} start={} now={}", behavior_tree.id(), data.stack_id, data.start_time, now_timestamp_in_milli));
+		if let Some(handle) = &self.runtime_event_handle{
+			handle.remove_stack(behavior_tree, data, now_timestamp_in_milli);
+		}
+	}
+
+	fn pre_on_start(&self, behavior_tree:&dyn IBehaviorTree, task_runtime_data:&TaskRuntimeData, stack_runtime_data:&StackRuntimeData, task:&dyn ITaskProxy){
+		self.session.borrow_mut().event(task_line("pre_on_start", behavior_tree, task_runtime_data, stack_runtime_data, task));
+		if let Some(handle) = &self.runtime_event_handle{
+			handle.pre_on_start(behavior_tree, task_runtime_data, stack_runtime_data, task);
+		}
+	}
+
+	//	只用于统计耗时，不记录，录像里的事件跟以前保持一致
+	fn pre_on_update(&self, behavior_tree:&dyn IBehaviorTree, task_runtime_data:&TaskRuntimeData, stack_runtime_data:&StackRuntimeData, task:&dyn ITaskProxy){
+		if let Some(handle) = &self.runtime_event_handle{
+			handle.pre_on_update(behavior_tree, task_runtime_data, stack_runtime_data, task);
+		}
+	}
+
+	fn post_on_update(&self, behavior_tree:&dyn IBehaviorTree, task_runtime_data:&TaskRuntimeData, stack_runtime_data:&StackRuntimeData, task:&dyn ITaskProxy, now_timestamp_in_milli:u64, status:TaskStatus){
+		self.session.borrow_mut().event(format!("{} now={} status={}", task_line("post_on_update", behavior_tree, task_runtime_data, stack_runtime_data, task), now_timestamp_in_milli, status.to_string()));
+		if let Some(handle) = &self.runtime_event_handle{
+			handle.post_on_update(behavior_tree, task_runtime_data, stack_runtime_data, task, now_timestamp_in_milli, status);
+		}
+	}
+
+	fn post_on_end(&self, behavior_tree:&dyn IBehaviorTree, task_runtime_data:&TaskRuntimeData, stack_runtime_data:&StackRuntimeData, task:&dyn ITaskProxy, now_timestamp_in_milli:u64){
+		self.session.borrow_mut().event(format!("{} now={}", task_line("post_on_end", behavior_tree, task_runtime_data, stack_runtime_data, task), now_timestamp_in_milli));
+		if let Some(handle) = &self.runtime_event_handle{
+			handle.post_on_end(behavior_tree, task_runtime_data, stack_runtime_data, task, now_timestamp_in_milli);
+		}
+	}
+
+	fn action_post_on_start(&self, behavior_tree:&dyn IBehaviorTree, task_runtime_data:&TaskRuntimeData, stack_runtime_data:&StackRuntimeData, task:&dyn ITaskProxy, datas:&[Vec<u8>]){
+		self.session.borrow_mut().event(format!("{} datas={}", task_line("action_post_on_start", behavior_tree, task_runtime_data, stack_runtime_data, task), hex(datas)));
+		if let Some(handle) = &self.runtime_event_handle{
+			handle.action_post_on_start(behavior_tree, task_runtime_data, stack_runtime_data, task, datas);
+		}
+	}
+
+	fn action_post_on_update(&self, behavior_tree:&dyn IBehaviorTree, task_runtime_data:&TaskRuntimeData, stack_runtime_data:&StackRuntimeData, task:&dyn ITaskProxy, now_timestamp_in_milli:u64, status:TaskStatus, datas:&[Vec<u8>]){
+		self.session.borrow_mut().event(format!("{} now={} status={} datas={}", task_line("action_post_on_update", behavior_tree, task_runtime_data, stack_runtime_data, task), now_timestamp_in_milli, status.to_string(), hex(datas)));
+		if let Some(handle) = &self.runtime_event_handle{
+			handle.action_post_on_update(behavior_tree, task_runtime_data, stack_runtime_data, task, now_timestamp_in_milli, status, datas);
+		}
+	}
+
+	fn action_post_on_end(&self, behavior_tree:&dyn IBehaviorTree, task_runtime_data:&TaskRuntimeData, stack_runtime_data:&StackRuntimeData, task:&dyn ITaskProxy, now_timestamp_in_milli:u64, datas:&[Vec<u8>]){
+		self.session.borrow_mut().event(format!("{} now={} datas={}", task_line("action_post_on_end", behavior_tree, task_runtime_data, stack_runtime_data, task), now_timestamp_in_milli, hex(datas)));
+		if let Some(handle) = &self.runtime_event_handle{
+			handle.action_post_on_end(behavior_tree, task_runtime_data, stack_runtime_data, task, now_timestamp_in_milli, datas);
+		}
+	}
+
+	fn parallel_pre_on_start(&self, behavior_tree:&dyn IBehaviorTree, task_runtime_data:&TaskRuntimeData, stack_runtime_data:&StackRuntimeData, task:&dyn ITaskProxy){
+		self.session.borrow_mut().event(task_line("parallel_pre_on_start", behavior_tree, task_runtime_data, stack_runtime_data, task));
+		if let Some(handle) = &self.runtime_event_handle{
+			handle.parallel_pre_on_start(behavior_tree, task_runtime_data, stack_runtime_data, task);
+		}
+	}
+
+	fn parallel_post_on_end(&self, behavior_tree:&dyn IBehaviorTree, task_runtime_data:&TaskRuntimeData, stack_runtime_data:&StackRuntimeData, task:&dyn ITaskProxy, now_timestamp_in_milli:u64){
+		self.session.borrow_mut().event(format!("{} now={}", task_line("parallel_post_on_end", behavior_tree, task_runtime_data, stack_runtime_data, task), now_timestamp_in_milli));
+		if let Some(handle) = &self.runtime_event_handle{
+			handle.parallel_post_on_end(behavior_tree, task_runtime_data, stack_runtime_data, task, now_timestamp_in_milli);
+		}
+	}
+
+	fn parallel_add_child_stack(&self, behavior_tree:&dyn IBehaviorTree, task_runtime_data:&TaskRuntimeData, stack_runtime_data:&StackRuntimeData, task:&dyn ITaskProxy, child_stack_runtime_data:&StackRuntimeData){
+		self.session.borrow_mut().event(format!("{} child_stack={} child_stack_start={}", task_line("parallel_add_child_stack", behavior_tree, task_runtime_data, stack_runtime_data, task), child_stack_runtime_data.stack_id, child_stack_runtime_data.start_time));
+		if let Some(handle) = &self.runtime_event_handle{
+			handle.parallel_add_child_stack(behavior_tree, task_runtime_data, stack_runtime_data, task, child_stack_runtime_data);
+		}
+	}
+
+	fn parallel_remove_child_stack(&self, behavior_tree:&dyn IBehaviorTree, task_runtime_data:&TaskRuntimeData, stack_runtime_data:&StackRuntimeData, task:&dyn ITaskProxy, child_stack_runtime_data:&StackRuntimeData, now_timestamp_in_milli:u64){
+		self.session.borrow_mut().event(format!("{} child_stack={} child_stack_start={} now={}", task_line("parallel_remove_child_stack", behavior_tree, task_runtime_data, stack_runtime_data, task), child_stack_runtime_data.stack_id, child_stack_runtime_data.start_time, now_timestamp_in_milli));
+		if let Some(handle) = &self.runtime_event_handle{
+			handle.parallel_remove_child_stack(behavior_tree, task_runtime_data, stack_runtime_data, task, child_stack_runtime_data, now_timestamp_in_milli);
+		}
+	}
+
+	fn on_task_error(&self, behavior_tree:&dyn IBehaviorTree, task:&dyn ITaskProxy, error:&dyn std::error::Error){
+		self.session.borrow_mut().event(format!("on_task_error tree={} task={} error={}", behavior_tree.id(), task.id(), error));
+		if let Some(handle) = &self.runtime_event_handle{
+			handle.on_task_error(behavior_tree, task, error);
+		}
+	}
+}
+
+//	录制用的行为树和记录它的session
+pub type RecordingBehaviorTree = (Rc<RefCell<Box<dyn IBehaviorTree>>>, Rc<RefCell<ReplaySession>>);
+
+//	录制用的行为树，用法跟BehaviorTree一样，所有对树的调用和时钟读数都会记到session里
+pub struct RecordBehaviorTree{
+	session:Rc<RefCell<ReplaySession>>,
+	clock:Rc<RefCell<Box<dyn IClock>>>,
+	behavior_tree:Rc<RefCell<Box<dyn IBehaviorTree>>>,
+}
+
+impl RecordBehaviorTree{
+	pub fn record(id: u64, config:&Vec<u8>,	unit_id:u64,  clock:&Weak<RefCell<Box<dyn IClock>>>,
+		runtime_event_handle:Box<dyn IRuntimeEventHandle>,parser:Weak<RefCell<Box<dyn IParser>>>) -> RecordingBehaviorTree{
+		let session = ReplaySession::record(id, config, unit_id);
+		let record_clock:Rc<RefCell<Box<dyn IClock>>> = Rc::new(RefCell::new(Box::new(ReplayClock{
+			session:session.clone(),
+			clock:Some(clock.clone()),
+		})));
+		let record_event_handle = Box::new(ReplayRuntimeEventHandle{
+			session:session.clone(),
+			runtime_event_handle:Some(runtime_event_handle),
+		});
+
+		let behavior_tree = BehaviorTree::new(id, config, unit_id, &Rc::downgrade(&record_clock), record_event_handle, parser);
+		let record_behavior_tree:Rc<RefCell<Box<dyn IBehaviorTree>>> = Rc::new(RefCell::new(Box::new(Self{
+			session:session.clone(),
+			clock:record_clock,
+			behavior_tree,
+		})));
+		(record_behavior_tree, session)
+	}
+
+	fn call<R>(&self, input:RecordedInput, f:impl FnOnce(&mut dyn IBehaviorTree)->R)->R{
+		self.session.borrow_mut().call(input);
+		with_session(&self.session, || f(self.behavior_tree.borrow_mut().as_mut()))
+	}
+}
+
+impl IBehaviorTree for RecordBehaviorTree{
+	fn id(&self)->u64{
+		self.behavior_tree.borrow().id()
+	}
+
+	fn enable(&mut self)->Result<(), Box<dyn std::error::Error>>{
+		self.call(RecordedInput::Enable, |behavior_tree| behavior_tree.enable())
+	}
+
+	fn disable(&mut self)->Result<(), Box<dyn std::error::Error>>{
+		self.call(RecordedInput::Disable, |behavior_tree| behavior_tree.disable())
+	}
+
+	fn update(&mut self){
+		self.call(RecordedInput::Update, |behavior_tree| behavior_tree.update())
+	}
+
+	fn is_runnning(&self)->bool{
+		self.behavior_tree.borrow().is_runnning()
+	}
+
+	fn unit_id(&self)->u64{
+		self.behavior_tree.borrow().unit_id()
+	}
+
+	fn rebuild_sync(&self, collector:&mut dyn IRebuildSyncDataCollector){
+		with_session(&self.session, || self.behavior_tree.borrow().rebuild_sync(collector))
+	}
+
+	fn timestamp_in_mill(&self)->u64{
+		self.clock.borrow().timestamp_in_mill()
+	}
+
+	fn interrupt(&mut self, task_id:i32, status:TaskStatus, include_self:bool)->Result<(), Box<dyn std::error::Error>>{
+		self.call(RecordedInput::Interrupt{task_id, status:status.clone(), include_self}, |behavior_tree| behavior_tree.interrupt(task_id, status, include_self))
+	}
+
+	fn request_interrupt(&self, task_id:i32, status:TaskStatus, include_self:bool){
+		self.session.borrow_mut().call(RecordedInput::RequestInterrupt{task_id, status:status.clone(), include_self});
+		self.behavior_tree.borrow().request_interrupt(task_id, status, include_self);
+	}
+
+	fn task_id_by_config_id(&self, config_id:i32)->Option<i32>{
+		self.behavior_tree.borrow().task_id_by_config_id(config_id)
+	}
+
+	fn snapshot(&self)->Result<BehaviorTreeSnapshot, Box<dyn std::error::Error>>{
+		self.behavior_tree.borrow().snapshot()
+	}
+
+	fn restore(&mut self, snapshot:&BehaviorTreeSnapshot)->Result<(), Box<dyn std::error::Error>>{
+		self.call(RecordedInput::Restore(Box::new(snapshot.clone())), |behavior_tree| behavior_tree.restore(snapshot))
+	}
+
+	fn report_task_error(&self, task:&dyn ITaskProxy, error:&dyn std::error::Error){
+		self.behavior_tree.borrow().report_task_error(task, error);
+	}
+}
+
+//	不需要游戏逻辑，按照录像里的输入重新跑一遍，事件流必须跟录像完全一致
+pub fn replay(recording:&Recording, parser:Weak<RefCell<Box<dyn IParser>>>)->Result<(), ReplayDivergence>{
+	let session = ReplaySession::replay(recording.clone());
+	let clock:Rc<RefCell<Box<dyn IClock>>> = Rc::new(RefCell::new(Box::new(ReplayClock{
+		session:session.clone(),
+		clock:None,
+	})));
+	let runtime_event_handle = Box::new(ReplayRuntimeEventHandle{
+		session:session.clone(),
+		runtime_event_handle:None,
+	});
+	let behavior_tree = BehaviorTree::new(recording.id, &recording.config, recording.unit_id, &Rc::downgrade(&clock), runtime_event_handle, parser);
+	let mut behavior_tree = behavior_tree.borrow_mut();
+
+	loop{
+		let input = session.borrow_mut().next_input();
+		let input = match input{
+			Some(input) => input,
+			None => break,
+		};
+
+		with_session(&session, ||{
+			match input{
+				RecordedInput::Enable => { let _ = behavior_tree.enable(); },
+				RecordedInput::Disable => { let _ = behavior_tree.disable(); },
+				RecordedInput::Update => behavior_tree.update(),
+				RecordedInput::Interrupt{task_id, status, include_self} => { let _ = behavior_tree.interrupt(task_id, status, include_self); },
+				RecordedInput::RequestInterrupt{task_id, status, include_self} => behavior_tree.request_interrupt(task_id, status, include_self),
+				RecordedInput::Restore(snapshot) => { let _ = behavior_tree.restore(&snapshot); },
+				recorded => session.borrow_mut().diverge_input(Some(recorded), "tree call".to_string()),
+			}
+		});
+
+		if let Some(divergence) = session.borrow().divergence(){
+			return Err(divergence.clone());
+		}
+	}
+
+	let session = session.borrow();
+	if let Some(divergence) = session.divergence(){
+		return Err(divergence.clone());
+	}
+
+	//	录像里还有没回放出来的事件
+	if session.event_cursor < recording.events.len(){
+		return Err(ReplayDivergence{
+			index:session.event_cursor,
+			expected:recording.events.get(session.event_cursor).cloned(),
+			actual:None,
+		});
+	}
+
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use super::super::json_parser::JsonParser;
+	use std::cell::Cell;
+
+	struct StepClock{
+		now:Cell<u64>,
+	}
+
+	impl IClock for StepClock{
+		fn timestamp_in_mill(&self)->u64{
+			self.now.set(self.now.get() + 33);
+			self.now.get()
+		}
+	}
+
+	struct EmptyRuntimeEventHandle;
+
+	impl IRuntimeEventHandle for EmptyRuntimeEventHandle{}
+
+	fn record_test_tree(parser:&Rc<RefCell<Box<dyn IParser>>>)->Recording{
+		let file_bytes = std::fs::read("src/behavior_tree/test_behaviortree.json").unwrap();
+		let clock:Rc<RefCell<Box<dyn IClock>>> = Rc::new(RefCell::new(Box::new(StepClock{now:Cell::new(1000)})));
+		let (behavior_tree, session) = RecordBehaviorTree::record(7, &file_bytes, 9, &Rc::downgrade(&clock), Box::new(EmptyRuntimeEventHandle), Rc::downgrade(parser));
+		let mut behavior_tree = behavior_tree.borrow_mut();
+		behavior_tree.enable().unwrap();
+		behavior_tree.update();
+		behavior_tree.update();
+		behavior_tree.interrupt(4, TaskStatus::Failure, true).unwrap();
+		behavior_tree.update();
+		behavior_tree.disable().unwrap();
+
+		session.borrow().recording().clone()
+	}
+
+	#[test]
+	fn test_replay_recording() {
+		let parser = JsonParser::new();
+		let recording = record_test_tree(&parser);
+		assert!(!recording.events.is_empty());
+
+		let recording = Recording::from_bytes(&recording.to_bytes().unwrap()).unwrap();
+		assert!(replay(&recording, Rc::downgrade(&parser)).is_ok());
+	}
+
+	#[test]
+	fn test_replay_divergence() {
+		let parser = JsonParser::new();
+		let recording = record_test_tree(&parser);
+
+		//	改掉一次时钟读数，回放产生的事件跟录像对不上
+		let mut changed_recording = recording.clone();
+		let clock_index = changed_recording.inputs.iter().rposition(|input| matches!(input, RecordedInput::Clock(_))).unwrap();
+		changed_recording.inputs[clock_index] = RecordedInput::Clock(0);
+		let divergence = replay(&changed_recording, Rc::downgrade(&parser)).unwrap_err();
+		assert!(divergence.actual.unwrap().contains("now=0"));
+
+		//	录像里少了事件
+		let mut changed_recording = recording.clone();
+		changed_recording.events.pop();
+		let divergence = replay(&changed_recording, Rc::downgrade(&parser)).unwrap_err();
+		assert_eq!(divergence.index, recording.events.len() - 1);
+		assert!(divergence.expected.is_none());
+	}
+
+	#[test]
+	fn test_replay_session_input() {
+		let session = ReplaySession::record(0, &Vec::new(), 0);
+		let data = with_session(&session, || input("random", || vec![4, 2]));
+		assert_eq!(data, vec![4, 2]);
+
+		let replay_session = ReplaySession::replay(session.borrow().recording().clone());
+		let data = with_session(&replay_session, || input("random", || vec![0]));
+		assert_eq!(data, vec![4, 2]);
+		assert!(replay_session.borrow().divergence().is_none());
+
+		let data = with_session(&replay_session, || input("random", || vec![0]));
+		assert!(data.is_empty());
+		assert!(replay_session.borrow().divergence().is_some());
+	}
+}
@@ -0,0 +1,371 @@
+use std::{rc::Rc, cell::RefCell, collections::{BTreeMap, HashMap}};
+
+use super::bandwidth::BandwidthMeter;
+use super::consts::{TaskStatus, SyncVisibility};
+use super::interface::{IBehaviorTree, IRebuildSyncDataCollector, IRuntimeEventHandle, ITaskProxy, StackRuntimeData, TaskRuntimeData};
+use super::wire::{FullStateCollector, SyncMessage, SyncPacket};
+
+//	树的主人，用来判断owner/team可见的action
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct TreeOwner{
+	pub owner_id:u64,
+	pub team:u32,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct SyncObserver{
+	pub observer_id:u64,
+	pub team:u32,
+}
+
+pub fn is_visible(visibility:SyncVisibility, owner:Option<&TreeOwner>, observer:&SyncObserver)->bool{
+	match visibility{
+		SyncVisibility::Everyone => true,
+		SyncVisibility::Team => owner.is_some_and(|owner| owner.team == observer.team),
+		SyncVisibility::Owner => owner.is_some_and(|owner| owner.owner_id == observer.observer_id),
+	}
+}
+
+//	按树记录订阅的客户端，增量消息只发给订阅了的客户端
+//	序号按(客户端, 树)递增，看不到的action不占序号，客户端不会误判丢包
+#[derive(Default)]
+pub struct SyncRouter{
+	owners:HashMap<u64, TreeOwner>,
+	observers:HashMap<u64, BTreeMap<u64, SyncObserver>>,
+	sequences:HashMap<(u64, u64), u64>,
+	outboxes:BTreeMap<u64, Vec<Vec<u8>>>,
+	meter:Option<Rc<RefCell<BandwidthMeter>>>,
+}
+
+impl SyncRouter{
+	pub fn new()->Rc<RefCell<Self>>{
+		Rc::new(RefCell::new(Self::default()))
+	}
+
+	pub fn set_tree_owner(&mut self, tree_id:u64, owner:TreeOwner){
+		self.owners.insert(tree_id, owner);
+	}
+
+	//	每个客户端收到的消息都单独统计
+	pub fn set_meter(&mut self, meter:Rc<RefCell<BandwidthMeter>>){
+		self.meter = Some(meter);
+	}
+
+	fn push(&mut self, behavior_tree:&dyn IBehaviorTree, observer_id:u64, packet:SyncPacket, task:Option<&dyn ITaskProxy>){
+		let bytes = packet.encode();
+		if let Some(meter) = &self.meter{
+			meter.borrow_mut().record_packet(behavior_tree, &packet, task, bytes.len());
+		}
+		self.outboxes.entry(observer_id).or_default().push(bytes);
+	}
+
+	//	中途订阅的客户端马上收到一份只给它的全量状态
+	pub fn subscribe(&mut self, observer:SyncObserver, behavior_tree:&dyn IBehaviorTree){
+		let tree_id = behavior_tree.id();
+		self.observers.entry(tree_id).or_default().insert(observer.observer_id, observer);
+
+		let mut collector = VisibleCollector{
+			collector:FullStateCollector::new(tree_id),
+			owner:self.owners.get(&tree_id).cloned(),
+			observer,
+		};
+		behavior_tree.rebuild_sync(&mut collector);
+		let sequence = self.sequences.get(&(observer.observer_id, tree_id)).cloned().unwrap_or(0);
+		let packet = SyncPacket::new(sequence, collector.collector.into_message());
+		self.push(behavior_tree, observer.observer_id, packet, None);
+	}
+
+	pub fn unsubscribe(&mut self, observer_id:u64, tree_id:u64){
+		if let Some(observers) = self.observers.get_mut(&tree_id){
+			observers.remove(&observer_id);
+			if observers.is_empty(){
+				self.observers.remove(&tree_id);
+			}
+		}
+	}
+
+	//	树销毁的时候调用
+	pub fn remove_tree(&mut self, tree_id:u64){
+		self.owners.remove(&tree_id);
+		self.observers.remove(&tree_id);
+		self.sequences.retain(|(_, sequence_tree_id), _| *sequence_tree_id != tree_id);
+	}
+
+	//	客户端断开的时候调用
+	pub fn remove_observer(&mut self, observer_id:u64){
+		for observers in self.observers.values_mut(){
+			observers.remove(&observer_id);
+		}
+		self.observers.retain(|_, observers| !observers.is_empty());
+		self.sequences.retain(|(sequence_observer_id, _), _| *sequence_observer_id != observer_id);
+		self.outboxes.remove(&observer_id);
+	}
+
+	pub fn observers(&self, tree_id:u64)->Vec<u64>{
+		match self.observers.get(&tree_id){
+			Some(observers) => observers.keys().cloned().collect(),
+			None => Vec::new(),
+		}
+	}
+
+	pub fn take_messages(&mut self, observer_id:u64)->Vec<Vec<u8>>{
+		self.outboxes.remove(&observer_id).unwrap_or_default()
+	}
+
+	//	所有有消息的客户端，按客户端ID排序
+	pub fn take_all_messages(&mut self)->Vec<(u64, Vec<Vec<u8>>)>{
+		std::mem::take(&mut self.outboxes).into_iter().collect()
+	}
+
+	//	visibility为None的是执行栈、并发任务这些所有订阅者都要的消息
+	fn route(&mut self, behavior_tree:&dyn IBehaviorTree, message:SyncMessage, task:Option<&dyn ITaskProxy>, visibility:Option<SyncVisibility>){
+		let tree_id = message.tree_id();
+		let observers:Vec<SyncObserver> = match self.observers.get(&tree_id){
+			Some(observers) => observers.values().cloned().collect(),
+			None => return,
+		};
+
+		let owner = self.owners.get(&tree_id).cloned();
+		for observer in observers.iter(){
+			if let Some(visibility) = visibility && !is_visible(visibility, owner.as_ref(), observer){
+				continue;
+			}
+
+			let sequence = self.sequences.entry((observer.observer_id, tree_id)).or_insert(0);
+			*sequence += 1;
+			let packet = SyncPacket::new(*sequence, message.clone());
+			self.push(behavior_tree, observer.observer_id, packet, task);
+		}
+	}
+}
+
+struct VisibleCollector{
+	collector:FullStateCollector,
+	owner:Option<TreeOwner>,
+	observer:SyncObserver,
+}
+
+impl IRebuildSyncDataCollector for VisibleCollector{
+	fn stack(&mut self, behavior_tree: &dyn IBehaviorTree, data: &StackRuntimeData){
+		self.collector.stack(behavior_tree, data);
+	}
+
+	fn action(&mut self, behavior_tree:&dyn IBehaviorTree, task_runtime_data:&TaskRuntimeData, stack_runtime_data:&StackRuntimeData, task:&dyn ITaskProxy, datas:&[Vec<u8>]){
+		if is_visible(task.sync_visibility(), self.owner.as_ref(), &self.observer){
+			self.collector.action(behavior_tree, task_runtime_data, stack_runtime_data, task, datas);
+		}
+	}
+
+	fn parallel(&mut self, behavior_tree:&dyn IBehaviorTree, task_runtime_data:&TaskRuntimeData, stack_runtime_data:&StackRuntimeData, task:&dyn ITaskProxy, child_stack_runtime_datas:&Vec<StackRuntimeData>){
+		self.collector.parallel(behavior_tree, task_runtime_data, stack_runtime_data, task, child_stack_runtime_datas);
+	}
+}
+
+//	每棵树一个，把同步回调交给共用的SyncRouter分发
+pub struct SyncRouterRuntimeEventHandle{
+	router:Rc<RefCell<SyncRouter>>,
+}
+
+impl SyncRouterRuntimeEventHandle{
+	pub fn new(router:Rc<RefCell<SyncRouter>>)->Self{
+		Self{router}
+	}
+}
+
+#[allow(unused_variables)]
+impl IRuntimeEventHandle for SyncRouterRuntimeEventHandle{
+	fn new_stack(&self, behavior_tree:&dyn IBehaviorTree, data:&StackRuntimeData){
+		self.router.borrow_mut().route(behavior_tree, SyncMessage::NewStack{tree_id:behavior_tree.id(), stack:*data}, None, None);
+	}
+
+	fn remove_stack(&self, behavior_tree:&dyn IBehaviorTree, data:&StackRuntimeData, now_timestamp_in_milli:u64){
+		self.router.borrow_mut().route(behavior_tree, SyncMessage::RemoveStack{tree_id:behavior_tree.id(), stack:*data, now:now_timestamp_in_milli}, None, None);
+	}
+
+	fn action_post_on_start(&self, behavior_tree:&dyn IBehaviorTree, task_runtime_data:&TaskRuntimeData, stack_runtime_data:&StackRuntimeData, task:&dyn ITaskProxy, datas:&[Vec<u8>]){
+		self.router.borrow_mut().route(behavior_tree, SyncMessage::ActionStart{tree_id:behavior_tree.id(), task:*task_runtime_data, stack:*stack_runtime_data, corresponding_type:task.corresponding_type().to_string(), datas:datas.to_vec()}, Some(task), Some(task.sync_visibility()));
+	}
+
+	fn action_post_on_update(&self, behavior_tree:&dyn IBehaviorTree, task_runtime_data:&TaskRuntimeData, stack_runtime_data:&StackRuntimeData, task:&dyn ITaskProxy, now_timestamp_in_milli:u64, status:TaskStatus, datas:&[Vec<u8>]){
+		self.router.borrow_mut().route(behavior_tree, SyncMessage::ActionUpdate{tree_id:behavior_tree.id(), task:*task_runtime_data, stack:*stack_runtime_data, now:now_timestamp_in_milli, status, datas:datas.to_vec()}, Some(task), Some(task.sync_visibility()));
+	}
+
+	fn action_post_on_end(&self, behavior_tree:&dyn IBehaviorTree, task_runtime_data:&TaskRuntimeData, stack_runtime_data:&StackRuntimeData, task:&dyn ITaskProxy, now_timestamp_in_milli:u64, datas:&[Vec<u8>]){
+		self.router.borrow_mut().route(behavior_tree, SyncMessage::ActionEnd{tree_id:behavior_tree.id(), task:*task_runtime_data, stack:*stack_runtime_data, now:now_timestamp_in_milli, datas:datas.to_vec()}, Some(task), Some(task.sync_visibility()));
+	}
+
+	fn parallel_add_child_stack(&self, behavior_tree:&dyn IBehaviorTree, task_runtime_data:&TaskRuntimeData, stack_runtime_data:&StackRuntimeData, task:&dyn ITaskProxy, child_stack_runtime_data:&StackRuntimeData){
+		self.router.borrow_mut().route(behavior_tree, SyncMessage::ParallelAddChildStack{tree_id:behavior_tree.id(), task:*task_runtime_data, stack:*stack_runtime_data, child_stack:*child_stack_runtime_data}, Some(task), None);
+	}
+
+	fn parallel_remove_child_stack(&self, behavior_tree:&dyn IBehaviorTree, task_runtime_data:&TaskRuntimeData, stack_runtime_data:&StackRuntimeData, task:&dyn ITaskProxy, child_stack_runtime_data:&StackRuntimeData, now_timestamp_in_milli:u64){
+		self.router.borrow_mut().route(behavior_tree, SyncMessage::ParallelRemoveChildStack{tree_id:behavior_tree.id(), task:*task_runtime_data, stack:*stack_runtime_data, child_stack:*child_stack_runtime_data, now:now_timestamp_in_milli}, Some(task), None);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use super::super::interface::{IAction, IClock};
+	use super::super::json_parser::JsonParser;
+	use super::super::mirror::SyncMirror;
+	use super::super::registry::TaskRegistry;
+	use super::super::runtime::BehaviorTree;
+
+	struct DummyClock;
+
+	impl IClock for DummyClock{
+		fn timestamp_in_mill(&self)->u64{
+			0
+		}
+	}
+
+	#[test]
+	fn test_sync_visibility() {
+		let owner = TreeOwner{owner_id:1, team:7};
+		let self_observer = SyncObserver{observer_id:1, team:7};
+		let team_observer = SyncObserver{observer_id:2, team:7};
+		let other_observer = SyncObserver{observer_id:3, team:8};
+
+		assert!(is_visible(SyncVisibility::Everyone, None, &other_observer));
+		assert!(is_visible(SyncVisibility::Team, Some(&owner), &team_observer));
+		assert!(!is_visible(SyncVisibility::Team, Some(&owner), &other_observer));
+		assert!(is_visible(SyncVisibility::Owner, Some(&owner), &self_observer));
+		assert!(!is_visible(SyncVisibility::Owner, Some(&owner), &team_observer));
+		assert!(!is_visible(SyncVisibility::Owner, None, &self_observer));
+	}
+
+	#[test]
+	fn test_sync_router() {
+		let file_bytes = std::fs::read("src/behavior_tree/test_behaviortree.json").unwrap();
+		let parser = JsonParser::new();
+		let clock:Rc<RefCell<Box<dyn IClock>>> = Rc::new(RefCell::new(Box::new(DummyClock)));
+		let router = SyncRouter::new();
+		let behavior_tree = BehaviorTree::new(5, &file_bytes, 1, &Rc::downgrade(&clock), Box::new(SyncRouterRuntimeEventHandle::new(router.clone())), Rc::downgrade(&parser));
+		router.borrow_mut().set_tree_owner(5, TreeOwner{owner_id:1, team:1});
+
+		let early = SyncObserver{observer_id:1, team:1};
+		let late = SyncObserver{observer_id:2, team:2};
+		let mut early_mirror = SyncMirror::new();
+		let mut late_mirror = SyncMirror::new();
+
+		router.borrow_mut().subscribe(early, behavior_tree.borrow().as_ref());
+		behavior_tree.borrow_mut().enable().unwrap();
+		behavior_tree.borrow_mut().update();
+		assert!(router.borrow_mut().take_messages(late.observer_id).is_empty());
+		for bytes in router.borrow_mut().take_messages(early.observer_id){
+			early_mirror.apply_bytes(&bytes).unwrap();
+		}
+		assert!(early_mirror.tree(5).is_some());
+
+		//	中途订阅只给这个客户端发全量状态
+		router.borrow_mut().subscribe(late, behavior_tree.borrow().as_ref());
+		assert!(router.borrow_mut().take_messages(early.observer_id).is_empty());
+		for bytes in router.borrow_mut().take_messages(late.observer_id){
+			late_mirror.apply_bytes(&bytes).unwrap();
+		}
+		assert!(late_mirror.tree(5) == early_mirror.tree(5));
+
+		behavior_tree.borrow_mut().interrupt(4, TaskStatus::Failure, true).unwrap();
+		behavior_tree.borrow_mut().update();
+		for (observer_id, messages) in router.borrow_mut().take_all_messages(){
+			let mirror = if observer_id == early.observer_id { &mut early_mirror } else { &mut late_mirror };
+			for bytes in messages{
+				mirror.apply_bytes(&bytes).unwrap();
+			}
+		}
+		assert!(late_mirror.tree(5) == early_mirror.tree(5));
+		assert!(early_mirror.take_resync_requests().is_empty());
+		assert!(late_mirror.take_resync_requests().is_empty());
+
+		router.borrow_mut().unsubscribe(late.observer_id, 5);
+		assert_eq!(router.borrow().observers(5), vec![early.observer_id]);
+		behavior_tree.borrow_mut().disable().unwrap();
+		assert!(router.borrow_mut().take_messages(late.observer_id).is_empty());
+		assert!(router.borrow_mut().take_messages(early.observer_id).len() > 0);
+	}
+
+	//	只有树的主人能看到的action
+	struct OwnerOnlyAction;
+
+	impl IAction for OwnerOnlyAction{
+		fn on_start(&mut self, task_proxy:&mut dyn ITaskProxy, _behavior_tree:&dyn IBehaviorTree){
+			task_proxy.send_sync_data(vec![1]);
+		}
+
+		fn on_update(&mut self, _task_proxy:&mut dyn ITaskProxy, _behavior_tree:&dyn IBehaviorTree)->TaskStatus{
+			TaskStatus::Running
+		}
+
+		fn is_sync_to_client(&self)->bool{
+			true
+		}
+
+		fn sync_visibility(&self)->SyncVisibility{
+			SyncVisibility::Owner
+		}
+
+		fn rebuild_sync_datas(&self, task_proxy:&dyn ITaskProxy, _behavior_tree:&dyn IBehaviorTree){
+			if let Some(collector) = task_proxy.sync_data_collector(){
+				collector.borrow_mut().add_data(vec![1]);
+			}
+		}
+	}
+
+	fn action_messages(messages:&[Vec<u8>])->Vec<&'static str>{
+		let mut kinds = Vec::new();
+		for bytes in messages.iter(){
+			match SyncPacket::decode(bytes).unwrap().message{
+				SyncMessage::ActionStart{..} => kinds.push("start"),
+				SyncMessage::ActionUpdate{..} => kinds.push("update"),
+				SyncMessage::ActionEnd{..} => kinds.push("end"),
+				SyncMessage::FullState{actions, ..} => kinds.extend(actions.iter().map(|_| "full")),
+				_ => (),
+			}
+		}
+		kinds
+	}
+
+	#[test]
+	fn test_sync_router_owner_only_action() {
+		let registry = TaskRegistry::new();
+		registry.write().unwrap().register_action_fn("OwnerOnlyAction", |_variables, _task_ids| -> Box<dyn IAction> {Box::new(OwnerOnlyAction)});
+		let parser = JsonParser::with_registry(registry);
+		let config = serde_json::json!({"RootTask": {"Type": "OwnerOnlyAction", "ID": 1}}).to_string().into_bytes();
+		let clock:Rc<RefCell<Box<dyn IClock>>> = Rc::new(RefCell::new(Box::new(DummyClock)));
+		let router = SyncRouter::new();
+		let behavior_tree = BehaviorTree::new(6, &config, 1, &Rc::downgrade(&clock), Box::new(SyncRouterRuntimeEventHandle::new(router.clone())), Rc::downgrade(&parser));
+		router.borrow_mut().set_tree_owner(6, TreeOwner{owner_id:1, team:1});
+
+		//	同队的也看不到
+		let owner = SyncObserver{observer_id:1, team:1};
+		let teammate = SyncObserver{observer_id:2, team:1};
+		router.borrow_mut().subscribe(owner, behavior_tree.borrow().as_ref());
+		router.borrow_mut().subscribe(teammate, behavior_tree.borrow().as_ref());
+		router.borrow_mut().take_all_messages();
+
+		behavior_tree.borrow_mut().enable().unwrap();
+		behavior_tree.borrow_mut().update();
+		behavior_tree.borrow_mut().update();
+		let owner_messages = router.borrow_mut().take_messages(owner.observer_id);
+		let teammate_messages = router.borrow_mut().take_messages(teammate.observer_id);
+		assert_eq!(action_messages(&owner_messages), ["start", "update", "update"]);
+		assert!(action_messages(&teammate_messages).is_empty());
+		//	执行栈的消息大家都有
+		assert!(!teammate_messages.is_empty());
+
+		//	中途订阅的全量状态也按可见性过滤
+		let late_owner = SyncObserver{observer_id:1, team:1};
+		let late_teammate = SyncObserver{observer_id:3, team:1};
+		router.borrow_mut().subscribe(late_owner, behavior_tree.borrow().as_ref());
+		router.borrow_mut().subscribe(late_teammate, behavior_tree.borrow().as_ref());
+		assert_eq!(action_messages(&router.borrow_mut().take_messages(late_owner.observer_id)), ["full"]);
+		assert!(action_messages(&router.borrow_mut().take_messages(late_teammate.observer_id)).is_empty());
+
+		behavior_tree.borrow_mut().disable().unwrap();
+		assert_eq!(action_messages(&router.borrow_mut().take_messages(owner.observer_id)), ["end"]);
+		let teammate_messages = router.borrow_mut().take_messages(teammate.observer_id);
+		assert!(!teammate_messages.is_empty());
+		assert!(action_messages(&teammate_messages).is_empty());
+	}
+}
@@ -1,11 +1,12 @@
-use std::{collections::HashMap, rc::{Rc,Weak}, cell::RefCell};
+use std::{collections::HashMap, rc::{Rc,Weak}, cell::RefCell, sync::Arc};
 use crate::behavior_tree;
 
-use super::consts::{TaskStatus, AbortType};
-use super::interface::{IClock, ITaskProxy,IBehaviorTree, 
+use super::consts::{TaskStatus, AbortType, SyncVisibility, SyncUpdatePolicy};
+use super::interface::{IClock, ITaskProxy,IBehaviorTree, TaskIndexTables, 
 	SyncDataCollector, RunningStack, TaskRuntimeData, 
 	IRuntimeEventHandle, IParser,TaskAddData, IRebuildSyncDataCollector, IAction, 
 	IConditional, RealTaskType, IParentTask,IDecorator,StackRuntimeData};
+use super::snapshot::{BehaviorTreeSnapshot, RunningStackSnapshot, ConditionalReevaluateSnapshot, TaskStateSnapshot, SNAPSHOT_VERSION};
 
 
 pub struct EmptyAction;
@@ -24,9 +25,9 @@ pub struct TaskProxy{
 
 	//	IComposite专用
 	abort_type:AbortType,
-	children:Vec<Rc<RefCell<Box<dyn ITaskProxy>>>>,
+	children:Vec<Box<dyn ITaskProxy>>,
 	real_task:RealTaskType,
-	sync_data_collector:Option<Rc<RefCell<Box<SyncDataCollector>>>>,
+	sync_data_collector:Option<RefCell<SyncDataCollector>>,
 	instant:bool,
 }
 
@@ -69,8 +70,8 @@ impl ITaskProxy for TaskProxy {
 		result
 	}	
 
-	fn corresponding_type(&self)->String{
-		self.corresponding_type.clone()
+	fn corresponding_type(&self)->&str{
+		&self.corresponding_type
 	}
 
 	fn name(&self)->String{
@@ -168,6 +169,20 @@ impl ITaskProxy for TaskProxy {
 				},
 		}
 	}
+
+	fn sync_visibility(&self)->SyncVisibility{
+		match &self.real_task {
+			RealTaskType::Action(action) => action.sync_visibility(),
+			_ => SyncVisibility::Everyone,
+		}
+	}
+
+	fn sync_update_policy(&self)->SyncUpdatePolicy{
+		match &self.real_task {
+			RealTaskType::Action(action) => action.sync_update_policy(),
+			_ => SyncUpdatePolicy::EveryUpdate,
+		}
+	}
 	
 	fn rebuild_sync_datas(&self, behavior_tree:&dyn IBehaviorTree){
 		match &self.real_task {
@@ -176,15 +191,15 @@ impl ITaskProxy for TaskProxy {
 		}
 	}
 	
-	fn set_sync_data_collector(&mut self, collector:Option<Rc<RefCell<Box<SyncDataCollector>>>>){
+	fn set_sync_data_collector(&mut self, collector:Option<RefCell<SyncDataCollector>>){
 		match &self.real_task {
 			RealTaskType::Action(_) => self.sync_data_collector = collector,
 			_ => {panic!("error");},
 		}
 	}
 	
-	fn sync_data_collector(&self)->Option<Rc<RefCell<Box<SyncDataCollector>>>>{
-		self.sync_data_collector.clone()
+	fn sync_data_collector(&self)->Option<&RefCell<SyncDataCollector>>{
+		self.sync_data_collector.as_ref()
 	}
 
 	//	IParentTask接口
@@ -253,7 +268,7 @@ impl ITaskProxy for TaskProxy {
 	fn current_child_index(&self, behavior_tree:&dyn IBehaviorTree)->u32{
 		let result = match &self.real_task {
 			RealTaskType::Composite(composite) => composite.current_child_index(self, behavior_tree),
-			//RealTaskType::Decorator(decorator) => decorator.on_child_executed2(index, child_status,self, behavior_tree),
+			RealTaskType::Decorator(decorator) => decorator.current_child_index(self, behavior_tree),
 			_ => {panic!("error");  },
 		};
 		
@@ -321,16 +336,16 @@ impl ITaskProxy for TaskProxy {
 		result
 	}
 
-	fn children(&self)->&Vec<Rc<RefCell<Box<dyn ITaskProxy>>>>{
+	fn children(&self)->&Vec<Box<dyn ITaskProxy>>{
 		&self.children
 	}
 
-	fn children_mut(&mut self)->&mut Vec<Rc<RefCell<Box<dyn ITaskProxy>>>>{
+	fn children_mut(&mut self)->&mut Vec<Box<dyn ITaskProxy>>{
 		&mut self.children
 	}
 	
-	fn add_child(&mut self, task:&Rc<RefCell<Box<dyn ITaskProxy>>>){
-		self.children.push(task.clone());
+	fn add_child(&mut self, task:Box<dyn ITaskProxy>){
+		self.children.push(task);
 	}
 
 	fn abort_type(&self)->AbortType{
@@ -387,6 +402,28 @@ impl ITaskProxy for TaskProxy {
 			collector.borrow_mut().add_data(data.clone());
 		}
 	}
+
+	fn save_state(&self)->Option<serde_json::Value>{
+		match &self.real_task {
+			RealTaskType::Action(action) => action.save_state(self),
+			RealTaskType::Composite(composite) => composite.save_state(self),
+			RealTaskType::Decorator(decorator) => decorator.save_state(self),
+			RealTaskType::Conditional(conditional) => conditional.save_state(self),
+		}
+	}
+
+	fn load_state(&mut self, state:&serde_json::Value)->Result<(), Box<dyn std::error::Error>>{
+		let mut real_task =std::mem::replace(&mut self.real_task, RealTaskType::Action(Box::new(EmptyAction)));
+		let result = match &mut real_task {
+			RealTaskType::Action(action) => action.load_state(state, self),
+			RealTaskType::Composite(composite) => composite.load_state(state, self),
+			RealTaskType::Decorator(decorator) => decorator.load_state(state, self),
+			RealTaskType::Conditional(conditional) => conditional.load_state(state, self),
+		};
+
+		self.real_task = real_task;
+		result
+	}
 }
 
 pub struct ConditionalReevaluate{
@@ -411,6 +448,62 @@ impl ConditionalReevaluate{
 	}
 }
 
+//	需要同步的action上次同步update的情况，用来执行SyncUpdatePolicy
+pub struct SyncUpdateState{
+	last_timestamp:u64,
+	last_status:TaskStatus,
+	last_datas:Vec<Vec<u8>>,
+	pending_datas:Vec<Vec<u8>>,
+	//	攒下的数据已经发出去了，下次filter的时候再清掉
+	pending_sent:bool,
+}
+
+impl SyncUpdateState{
+	pub fn new(start_timestamp:u64) -> Self{
+		Self{
+			last_timestamp:start_timestamp,
+			last_status:TaskStatus::Running,
+			last_datas:Vec::new(),
+			pending_datas:Vec::new(),
+			pending_sent:false,
+		}
+	}
+
+	//	返回None表示这次不调用action_post_on_update
+	pub fn filter<'a>(&'a mut self, policy:SyncUpdatePolicy, now_timestamp:u64, status:&TaskStatus, datas:&'a [Vec<u8>])->Option<&'a [Vec<u8>]>{
+		match policy{
+			SyncUpdatePolicy::EveryUpdate => Some(datas),
+			SyncUpdatePolicy::StartEndOnly => None,
+			SyncUpdatePolicy::OnChange => {
+				if self.last_status == *status && self.last_datas == datas{
+					return None;
+				}
+				self.last_status = status.clone();
+				self.last_datas.clear();
+				self.last_datas.extend_from_slice(datas);
+				Some(datas)
+			},
+			SyncUpdatePolicy::Interval(interval) => {
+				if self.pending_sent{
+					self.pending_datas.clear();
+					self.pending_sent = false;
+				}
+				if !datas.is_empty(){
+					self.pending_datas.clear();
+					self.pending_datas.extend_from_slice(datas);
+				}
+				//	结束的状态不能延后
+				if *status == TaskStatus::Running && now_timestamp < self.last_timestamp.saturating_add(interval){
+					return None;
+				}
+				self.last_timestamp = now_timestamp;
+				self.pending_sent = true;
+				Some(&self.pending_datas)
+			},
+		}
+	}
+}
+
 struct EntryRoot{
 	execution_status:TaskStatus,
 }
@@ -429,11 +522,7 @@ impl IParentTask for EntryRoot {
 	}	
 
 	fn can_execute(&self, task_proxy:&dyn ITaskProxy, behavior_tree:&dyn IBehaviorTree)->bool {
-		if let TaskStatus::Failure = self.execution_status {
-			true
-		}else{
-			false
-		}
+		self.execution_status == TaskStatus::Inactive || self.execution_status == TaskStatus::Running
 	}
 
 	fn current_child_index(&self, task_proxy:&dyn ITaskProxy, behavior_tree:&dyn IBehaviorTree)->u32{
@@ -452,186 +541,519 @@ impl IParentTask for EntryRoot {
 impl IDecorator for EntryRoot {
 }
 
-pub struct BehaviorTree{
-    id: u64,
+//	树用到的外部对象。LocalTreeEnv跟原来一样用Rc，只能在创建的线程里执行
+//	SendTreeEnv里都是Arc，整棵树是Send的，可以交给别的线程update
+pub trait ITreeEnv:'static{
+	type Clock;
+	type Parser;
+	type RuntimeEventHandle:IRuntimeEventHandle + ?Sized;
 
-    task_list: Vec<Weak<RefCell<Box<dyn ITaskProxy>>>>,
-	composite_abort_task:Vec<AbortType>,
-    parent_index:Vec<i32>,
+	fn timestamp_in_mill(clock:&Self::Clock)->u64;
+	fn deserialize(parser:&Self::Parser, config:&Vec<u8>, task_add_data:&mut TaskAddData)->Result<Box<dyn ITaskProxy>, Box<dyn std::error::Error>>;
+}
 
-    children_index :Vec<Vec<i32>>,
-	relative_child_index:Vec<i32>,
+pub struct LocalTreeEnv;
 
-    active_stack :Vec<Rc<RefCell<Box<RunningStack>>>>,
-	non_instant_task_status:Vec<TaskStatus>,
-	conditional_reevaluate:Vec<Rc<RefCell<Box<ConditionalReevaluate>>>>,
-	conditional_reevaluate_map:HashMap<i32, Rc<RefCell<Box<ConditionalReevaluate>>>>,
+impl ITreeEnv for LocalTreeEnv{
+	type Clock = Weak<RefCell<Box<dyn IClock>>>;
+	type Parser = Weak<RefCell<Box<dyn IParser>>>;
+	type RuntimeEventHandle = dyn IRuntimeEventHandle;
+
+	fn timestamp_in_mill(clock:&Self::Clock)->u64{
+		clock.upgrade().as_ref().unwrap().borrow().timestamp_in_mill()
+	}
+
+	fn deserialize(parser:&Self::Parser, config:&Vec<u8>, task_add_data:&mut TaskAddData)->Result<Box<dyn ITaskProxy>, Box<dyn std::error::Error>>{
+		let parser = parser.upgrade().ok_or("parser has been dropped")?;
+		let parser = parser.borrow();
+		parser.deserialize(config, task_add_data)
+	}
+}
+
+pub struct SendTreeEnv;
+
+impl ITreeEnv for SendTreeEnv{
+	type Clock = Arc<dyn IClock + Send + Sync>;
+	//	用from_root创建的树没有解析器
+	type Parser = Option<Arc<dyn IParser + Send + Sync>>;
+	type RuntimeEventHandle = dyn IRuntimeEventHandle + Send;
+
+	fn timestamp_in_mill(clock:&Self::Clock)->u64{
+		clock.timestamp_in_mill()
+	}
+
+	fn deserialize(parser:&Self::Parser, config:&Vec<u8>, task_add_data:&mut TaskAddData)->Result<Box<dyn ITaskProxy>, Box<dyn std::error::Error>>{
+		parser.as_ref().ok_or("behavior tree has no parser")?.deserialize(config, task_add_data)
+	}
+}
 
-	parent_composite_index:Vec<i32>,
-	child_conditional_index:Vec<Vec<i32>>,
+pub type SendBehaviorTree = BehaviorTree<SendTreeEnv>;
+
+//	执行栈执行的时候从active_stack取出来，原来的位置只留下栈id，其它栈的下标不变
+enum StackSlot{
+	Present(Box<RunningStack>),
+	Taken(usize),
+}
+
+impl StackSlot{
+	fn stack_id(&self)->usize{
+		match self{
+			StackSlot::Present(stack) => stack.stack_id,
+			StackSlot::Taken(stack_id) => *stack_id,
+		}
+	}
+
+	fn stack(&self)->Option<&RunningStack>{
+		match self{
+			StackSlot::Present(stack) => Some(stack.as_ref()),
+			StackSlot::Taken(_) => None,
+		}
+	}
+}
+
+pub struct BehaviorTree<E:ITreeEnv = LocalTreeEnv>{
+    id: u64,
+
+	//	按任务索引平铺的任务，执行的时候取出来，执行完放回去
+    task_list: Vec<Option<Box<dyn ITaskProxy>>>,
+	config_id_to_task_id:HashMap<i32, i32>,
+	//	父子关系的索引表，按任务索引排列
+	index_tables:TaskIndexTables,
+
+    active_stack :Vec<StackSlot>,
+	non_instant_task_status:Vec<TaskStatus>,
+	//	每个条件任务最多一项
+	conditional_reevaluate:Vec<ConditionalReevaluate>,
 
     is_running:bool,
 	initialize_first_stack_and_first_task:bool, //	是否需要初始化第一个执行栈和第一个任务
 	execution_status:TaskStatus,
 	config:Vec<u8>,
-	root_task:Option<Rc<RefCell<Box<dyn ITaskProxy>>>>,
-	clock:Weak<RefCell<Box<dyn IClock>>>,                            
+	clock:E::Clock,
 	stack_id:usize,
-    stack_id_to_stack_data:HashMap<usize, Box<StackRuntimeData>>,
+    stack_id_to_stack_data:HashMap<usize, StackRuntimeData>,
 
-	task_datas:HashMap<i32, Box<TaskRuntimeData>>,
+	task_datas:HashMap<i32, TaskRuntimeData>,
+	sync_update_states:HashMap<i32, SyncUpdateState>,
 
 	stack_id_to_parallel_task_id:HashMap<u32, u32>,
 	parallel_task_id_to_stack_ids:HashMap<i32, Vec<u32>>,
 
-	runtime_event_handle:Box<dyn IRuntimeEventHandle>,
+	runtime_event_handle:Box<E::RuntimeEventHandle>,
 	initialize_for_base_flag:bool,
     
-	parser:Weak<RefCell<Box<dyn IParser>>>,
+	parser:E::Parser,
+	//	with_root传进来的根任务，第一次初始化的时候用掉
+	prebuilt_root:Option<Box<dyn ITaskProxy>>,
+	//	用根任务创建的树没有配置，根任务用掉以后不能再回退到解析器
+	has_prebuilt_root:bool,
 	task_execute_id:u32,
 	unit_id:u64,
+	complete_status:Option<TaskStatus>,
+	pending_interrupts:RefCell<Vec<(i32, TaskStatus, bool)>>,
+
+	//	删掉的执行栈留着给add_stack复用，并发任务反复开子栈的时候不用重新分配
+	//	存Box本身，放回active_stack的时候也不用分配
+	#[allow(clippy::vec_box)]
+	stack_pool:Vec<Box<RunningStack>>,
+	//	reevaluate_conditional_tasks每帧复用的临时数组
+	update_condition_indexes:Vec<(i32, i32)>,
+	conditional_parent_indexes:Vec<i32>,
 }
 
 
-#[allow(unused_variables)]
 impl BehaviorTree{
 	pub fn new(id: u64, config:&Vec<u8>,	unit_id:u64,  clock:&Weak<RefCell<Box<dyn IClock>>>, 
 		runtime_event_handle:Box<dyn IRuntimeEventHandle>,parser:Weak<RefCell<Box<dyn IParser>>>) -> Rc<RefCell<Box<dyn IBehaviorTree>>>{
-		let behavior_tree = Self{
+		Rc::new(RefCell::new(Box::new(Self::create(id, config.clone(), unit_id, clock.clone(), runtime_event_handle, parser, None))))
+	}
+
+	//	用已经生成好的根任务启动，不需要配置和解析器，比如Tree::build生成的任务
+	//	根任务整个交给树，调用方不要再持有它或者它子任务的引用；根任务只能初始化一次，之后enable复用初始化好的任务
+	pub fn with_root(id: u64, root_task:Box<dyn ITaskProxy>, unit_id:u64, clock:&Weak<RefCell<Box<dyn IClock>>>,
+		runtime_event_handle:Box<dyn IRuntimeEventHandle>) -> Rc<RefCell<Box<dyn IBehaviorTree>>>{
+		Rc::new(RefCell::new(Box::new(Self::create(id, Vec::new(), unit_id, clock.clone(), runtime_event_handle, Weak::new(), Some(root_task)))))
+	}
+}
+
+//	可以跨线程移动的树，由调用方持有，不包在Rc里
+impl SendBehaviorTree{
+	pub fn from_config(id: u64, config:&Vec<u8>, unit_id:u64, clock:Arc<dyn IClock + Send + Sync>,
+		runtime_event_handle:Box<dyn IRuntimeEventHandle + Send>, parser:Arc<dyn IParser + Send + Sync>) -> Self{
+		Self::create(id, config.clone(), unit_id, clock, runtime_event_handle, Some(parser), None)
+	}
+
+	//	跟with_root一样，根任务只能初始化一次
+	pub fn from_root(id: u64, root_task:Box<dyn ITaskProxy>, unit_id:u64, clock:Arc<dyn IClock + Send + Sync>,
+		runtime_event_handle:Box<dyn IRuntimeEventHandle + Send>) -> Self{
+		Self::create(id, Vec::new(), unit_id, clock, runtime_event_handle, None, Some(root_task))
+	}
+}
+
+//	编译期检查可以跨线程移动
+const _: fn() = ||{
+	fn assert_send<T:Send>(){}
+	assert_send::<SendBehaviorTree>();
+	assert_send::<TaskProxy>();
+};
+
+#[allow(unused_variables)]
+impl<E:ITreeEnv> BehaviorTree<E>{
+	fn create(id: u64, config:Vec<u8>, unit_id:u64, clock:E::Clock, runtime_event_handle:Box<E::RuntimeEventHandle>,
+		parser:E::Parser, prebuilt_root:Option<Box<dyn ITaskProxy>>) -> Self{
+		Self{
 			id,
 			task_list: Vec::new(),
-			composite_abort_task: Vec::new(),
-			parent_index: Vec::new(),
-			children_index: Vec::new(),
-			relative_child_index: Vec::new(),
+			config_id_to_task_id: HashMap::new(),
+			index_tables: TaskIndexTables::new(),
 			active_stack: Vec::new(),
 			non_instant_task_status: Vec::new(),
 			conditional_reevaluate: Vec::new(),
-			conditional_reevaluate_map: HashMap::new(),
-			parent_composite_index:Vec::new(),
-			child_conditional_index:Vec::new(),
 			is_running: false,
 			initialize_first_stack_and_first_task: false,
 			execution_status: TaskStatus::Inactive,
-			config: config.clone(),
+			config,
 			unit_id:unit_id,
-			root_task:None,
-			clock:clock.clone(),
+			clock,
 			stack_id: 0,
 			stack_id_to_stack_data: HashMap::new(),
 			task_datas: HashMap::new(),
+			sync_update_states: HashMap::new(),
 			stack_id_to_parallel_task_id: HashMap::new(),
 			parallel_task_id_to_stack_ids: HashMap::new(),
 			runtime_event_handle: runtime_event_handle,
 			initialize_for_base_flag: false,
 			parser:parser,
+			has_prebuilt_root:prebuilt_root.is_some(),
+			prebuilt_root,
 			task_execute_id:1,
-		};
-
-		let behavior_tree:Rc<RefCell<Box<dyn IBehaviorTree>>> = Rc::new(RefCell::new(Box::new(behavior_tree)));
-		behavior_tree
+			complete_status:None,
+			pending_interrupts:RefCell::new(Vec::new()),
+			stack_pool:Vec::new(),
+			update_condition_indexes:Vec::new(),
+			conditional_parent_indexes:Vec::with_capacity(10),
+		}
 	}
 
 
 
 	fn initialize_for_base(&mut self) ->Result<(), Box<dyn std::error::Error>>{
 		self.task_list.clear();
-		self.parent_index.clear();
-		self.children_index.clear();
-		self.relative_child_index.clear();
-		self.parent_composite_index.clear();
-		self.child_conditional_index.clear();
-		self.root_task = None;
+		self.config_id_to_task_id.clear();
 		let mut task_add_data: TaskAddData = TaskAddData::new();
 
-		let parser =self.parser.upgrade().unwrap();
-		let parser = parser.borrow();
-
+		let root_task = match self.prebuilt_root.take(){
+			Some(root_task) => root_task,
+			None if self.has_prebuilt_root => return Err("prebuilt root already consumed, a tree created from a root task can only initialize it once".into()),
+			None => E::deserialize(&self.parser, &self.config, &mut task_add_data)?,
+		};
+		//	编译后的格式带了算好的索引表，只检查跟任务树对得上，不用重新算
+		let precomputed = task_add_data.index_tables.is_some();
+		self.index_tables = task_add_data.index_tables.take().unwrap_or_default();
+		if self.index_tables.is_empty(){
+			return Err("index tables have no entry root".into());
+		}
 
-		let root_task = parser.deserialize(&self.config, &mut task_add_data)?;
 		let entry_root = EntryRoot::new();
 		let mut root_proxy = TaskProxy::new("EntryRoot", "EntryRoot", RealTaskType::Decorator(entry_root));
-		root_proxy.add_child(&root_task);
-				
-	
-		self.root_task = Some(Rc::new(RefCell::new(Box::new(root_proxy))));
-		self.task_list.push(Rc::downgrade(&self.root_task.clone().unwrap()));
-		self.composite_abort_task.push(AbortType::None);
-		self.parent_index.push(-1);
-		self.parent_composite_index.push(-1);
-		self.child_conditional_index.push(Vec::with_capacity(10));
-		self.children_index.push(Vec::with_capacity(10));
-		self.relative_child_index.push(-1);
-		let mut parent_composite_index = -1;
-
-		self.root_task.as_mut().unwrap().borrow_mut().set_id(0);
-		//Rc::get_mut(self.root_task.as_mut().unwrap()).unwrap().set_id(0);
-
-		if self.root_task.as_mut().unwrap().borrow_mut().is_implements_iparenttask(){
-			if self.root_task.as_mut().unwrap().borrow_mut().is_implements_icomposite(){
-				parent_composite_index = self.root_task.as_mut().unwrap().borrow_mut().id();
-			}
+		root_proxy.set_id(0);
+		self.task_list.push(Some(Box::new(root_proxy)));
 
-			let parent_task = self.root_task.as_mut().unwrap().clone();
-			let mut children = parent_task.borrow_mut().children_mut().clone();
-
-
-			for child in children.iter_mut(){
-				self.parse_child_task(child, &parent_task, parent_composite_index)?;
-			}
+		self.parse_child_task(root_task, 0, -1, precomputed)?;
+		if precomputed{
+			self.check_index_tables()?;
 		}
-		
 		Ok(())
 	}
 
-	fn parse_child_task(&mut self, child_task:&mut Rc<RefCell<Box<dyn ITaskProxy>>>, parent_task:&Rc<RefCell<Box<dyn ITaskProxy>>>, mut parent_composite_index: i32)->Result<(), Box<dyn std::error::Error>>{
+	//	解析出来的任务从任务树里拿出来放到task_list，父子关系只保留在index_tables里
+	fn parse_child_task(&mut self, mut child_task:Box<dyn ITaskProxy>, parent_index:i32, mut parent_composite_index: i32, precomputed:bool)->Result<(), Box<dyn std::error::Error>>{
 		let index = self.task_list.len() as i32;
-		let parent_index = parent_task.borrow().id();
-
-		self.children_index[parent_index as usize].push(index);
-		self.relative_child_index.push(self.children_index[parent_index as usize].len() as i32 - 1);
-		self.composite_abort_task.push(child_task.borrow().abort_type());
-		self.task_list.push(Rc::downgrade(child_task));
-		self.parent_index.push(parent_task.borrow().id());
-		self.parent_composite_index.push(parent_composite_index);
-		self.child_conditional_index.push(Vec::with_capacity(10));
-		self.children_index.push(Vec::with_capacity(10));
-
-		child_task.borrow_mut().set_id(index);
-		
+		let children = std::mem::take(child_task.children_mut());
+
+		if precomputed{
+			self.check_index_row(index, parent_index, parent_composite_index, child_task.abort_type())?;
+		}else{
+			self.index_tables.push(parent_index, parent_composite_index, child_task.abort_type());
+		}
+
+		//	解析的时候任务ID是配置里的ID，with_root传进来的根任务没有经过注册表检查，可能有重复的
+		if self.config_id_to_task_id.insert(child_task.id(), index).is_some(){
+			return Err(format!("task id {} is used more than once", child_task.id()).into());
+		}
+		child_task.set_id(index);
 
-		if child_task.borrow().is_implements_iparenttask(){
-			if child_task.borrow().is_implements_icomposite(){
-				parent_composite_index = child_task.borrow().id();
+		let is_parent_task = child_task.is_implements_iparenttask();
+		let is_composite = child_task.is_implements_icomposite();
+		let is_conditional = child_task.is_implements_iconditional();
+		self.task_list.push(Some(child_task));
+
+		if is_parent_task{
+			if is_composite{
+				parent_composite_index = index;
 			}
 
-			let mut children = child_task.borrow_mut().children_mut().clone();
-			for child in children.iter_mut(){
-				self.parse_child_task(child, child_task, parent_composite_index)?;
+			for child in children.into_iter(){
+				self.parse_child_task(child, index, parent_composite_index, precomputed)?;
 			}
-		}else{
-			if child_task.borrow().is_implements_iconditional(){
-				if parent_composite_index != -1{
-					self.child_conditional_index[parent_composite_index as usize].push(child_task.borrow().id());
-				}
+		}else if is_conditional && parent_composite_index != -1{
+			let child_conditional_index = self.index_tables.child_conditional_index.get_mut(parent_composite_index as usize).ok_or("index tables are missing a composite task")?;
+			if !precomputed{
+				child_conditional_index.push(index);
+			}else if !child_conditional_index.contains(&index){
+				return Err(format!("index tables do not list conditional task {}", index).into());
 			}
 		}
 		Ok(())
 	}
 
-	fn initialize(&mut self)->Result<(), Box<dyn std::error::Error>>{
+	#[cfg(test)]
+	pub(crate) fn index_tables(&self)->&TaskIndexTables{
+		&self.index_tables
+	}
+
+	//	解析器给的索引表里这个任务的一行要跟任务树一样，注册表跟编译的时候不一致会对不上
+	fn check_index_row(&self, index:i32, parent_index:i32, parent_composite_index:i32, abort_type:AbortType)->Result<(), Box<dyn std::error::Error>>{
+		let index_tables = &self.index_tables;
+		let row = index as usize;
+		let matches = index_tables.parent_index.get(row) == Some(&parent_index)
+			&& index_tables.parent_composite_index.get(row) == Some(&parent_composite_index)
+			&& index_tables.composite_abort_task.get(row) == Some(&abort_type)
+			&& index_tables.relative_child_index.get(row).and_then(|relative_child_index| usize::try_from(*relative_child_index).ok())
+				.and_then(|relative_child_index| index_tables.children_index.get(parent_index as usize)?.get(relative_child_index)) == Some(&index);
+		if !matches{
+			return Err(format!("index tables do not match task {}", index).into());
+		}
+		Ok(())
+	}
+
+	//	每一行都检查过了，再确认表里没有多出来的任务和子任务
+	fn check_index_tables(&self)->Result<(), Box<dyn std::error::Error>>{
+		let index_tables = &self.index_tables;
+		let task_count = self.task_list.len();
+		let conditional_count = (0..task_count).filter(|index|{
+			index_tables.parent_composite_index[*index] != -1 && self.task_list[*index].as_ref().is_some_and(|task| task.is_implements_iconditional())
+		}).count();
+		let consistent = index_tables.len() == task_count
+			&& index_tables.children_index.len() == task_count
+			&& index_tables.child_conditional_index.len() == task_count
+			&& index_tables.children_index.iter().map(|children| children.len()).sum::<usize>() == task_count - 1
+			&& index_tables.child_conditional_index.iter().map(|conditionals| conditionals.len()).sum::<usize>() == conditional_count;
+		if !consistent{
+			return Err(format!("index tables do not match the tree of {} tasks", task_count).into());
+		}
+		Ok(())
+	}
+
+	//	任务执行的时候从task_list取出来，调用方负责用put_task放回去
+	//	调用栈上层已经取出来的任务返回None，调用方跳过这一步，不会panic
+	fn take_task(&mut self, task_index:i32)->Option<Box<dyn ITaskProxy>>{
+		self.task_list[task_index as usize].take()
+	}
+
+	fn put_task(&mut self, task_index:i32, task:Box<dyn ITaskProxy>){
+		self.task_list[task_index as usize] = Some(task);
+	}
+
+	//	回调里通过&dyn IBehaviorTree重入的时候任务可能正在执行，这时候返回None
+	fn task(&self, task_index:i32)->Option<&dyn ITaskProxy>{
+		self.task_list[task_index as usize].as_deref()
+	}
+
+	//	执行栈执行的时候从active_stack取出来，调用方负责用put_stack放回去
+	//	已经取出来的栈返回None
+	fn take_stack(&mut self, stack_index:usize)->Option<Box<RunningStack>>{
+		let stack_id = self.active_stack[stack_index].stack_id();
+		match std::mem::replace(&mut self.active_stack[stack_index], StackSlot::Taken(stack_id)){
+			StackSlot::Present(stack) => Some(stack),
+			StackSlot::Taken(_) => None,
+		}
+	}
+
+	//	执行的过程中栈可能已经被remove_stack删掉了，这时候放到stack_pool里复用
+	fn put_stack(&mut self, mut stack:Box<RunningStack>){
+		let stack_id = stack.stack_id;
+		if let Some(slot) = self.active_stack.iter_mut().find(|slot| matches!(slot, StackSlot::Taken(id) if *id == stack_id)){
+			*slot = StackSlot::Present(stack);
+		}else{
+			stack.stack.clear();
+			self.stack_pool.push(stack);
+		}
+	}
+
+	//	取出来的栈是不是还在stack_index的位置上
+	fn is_stack_at(&self, stack_index:usize, stack_id:usize)->bool{
+		stack_index < self.active_stack.len() && self.active_stack[stack_index].stack_id() == stack_id
+	}
+
+	fn stack_len(&self, stack_index:usize)->usize{
+		self.active_stack[stack_index].stack().map(|stack| stack.len()).unwrap_or(0)
+	}
+
+	//	取出任务和它的父任务执行f，执行完都放回去
+	//	任务或者父任务已经在调用栈上层执行的时候不执行f，返回None，调用方跳过
+	fn with_task_and_parent<R>(&mut self, task_index:i32, f:impl FnOnce(&mut Self, &mut dyn ITaskProxy, Option<&mut dyn ITaskProxy>)->R)->Option<R>{
+		let mut task = self.take_task(task_index)?;
+		let parent_index = self.index_tables.parent_index[task_index as usize];
+		let result = if parent_index == -1{
+			Some(f(self, task.as_mut(), None))
+		}else if let Some(mut parent_task) = self.take_task(parent_index){
+			let result = f(self, task.as_mut(), Some(parent_task.as_mut()));
+			self.put_task(parent_index, parent_task);
+			Some(result)
+		}else{
+			None
+		};
+		self.put_task(task_index, task);
+		result
+	}
+
+	fn ensure_initialize_for_base(&mut self)->Result<(), Box<dyn std::error::Error>>{
 		if !self.initialize_for_base_flag{
 			self.initialize_for_base()?;
 			self.initialize_for_base_flag = true;
 		}
+		Ok(())
+	}
+
+	fn initialize(&mut self)->Result<(), Box<dyn std::error::Error>>{
+		self.ensure_initialize_for_base()?;
 
 		self.stack_id = 1;
 		self.active_stack.clear();
+		self.non_instant_task_status.clear();
 		self.conditional_reevaluate.clear();
-		self.conditional_reevaluate_map.clear();
+		self.stack_id_to_stack_data.clear();
+		self.task_datas.clear();
+		self.sync_update_states.clear();
+		self.stack_id_to_parallel_task_id.clear();
+		self.parallel_task_id_to_stack_ids.clear();
+		self.complete_status = None;
+		self.pending_interrupts.borrow_mut().clear();
 		Ok(())
 	}
 
+	//	根执行栈清空以后树就结束了，但是pop_task的调用方还持有任务的借用，所以放到外层再disable
+	fn check_complete(&mut self){
+		if let Some(status) = self.complete_status.take(){
+			let _ = self.disable();
+			self.execution_status = status;
+		}
+	}
+
+	fn save_task_states(&self)->Result<Vec<TaskStateSnapshot>, Box<dyn std::error::Error>>{
+		let mut task_states = Vec::new();
+		for task in self.task_list.iter(){
+			let task = task.as_ref().ok_or("can not snapshot while a task is executing")?;
+			if let Some(state) = task.save_state(){
+				task_states.push(TaskStateSnapshot{
+					task_id:task.id(),
+					state,
+				});
+			}
+		}
+		Ok(task_states)
+	}
+
+	fn load_task_states(&mut self, task_states:&[TaskStateSnapshot])->Result<(), Box<dyn std::error::Error>>{
+		for task_state in task_states.iter(){
+			let task = self.task_list.get_mut(task_state.task_id as usize).and_then(|task| task.as_mut()).ok_or("snapshot task id out of range")?;
+			task.load_state(&task_state.state)?;
+		}
+		Ok(())
+	}
+
+	//	restore之前检查快照里所有的任务索引和执行栈id，避免恢复到一半才出错
+	fn validate_snapshot(&self, snapshot:&BehaviorTreeSnapshot)->Result<(), Box<dyn std::error::Error>>{
+		let task_count = self.task_list.len();
+		if snapshot.task_count != task_count{
+			return Err(format!("snapshot has {} tasks but the behavior tree has {}", snapshot.task_count, task_count).into());
+		}
+
+		let check_task_index = |task_index:i64, name:&str|->Result<(), Box<dyn std::error::Error>>{
+			if task_index < 0 || task_index as usize >= task_count{
+				return Err(format!("snapshot {} {} out of range", name, task_index).into());
+			}
+			Ok(())
+		};
+
+		let mut stack_ids = Vec::with_capacity(snapshot.active_stack.len());
+		for stack in snapshot.active_stack.iter(){
+			if stack_ids.contains(&stack.stack_id){
+				return Err(format!("snapshot stack {} is duplicated", stack.stack_id).into());
+			}
+			stack_ids.push(stack.stack_id);
+
+			for task_index in stack.stack.iter(){
+				check_task_index(*task_index as i64, "stack task")?;
+			}
+
+			if !snapshot.stack_datas.iter().any(|stack_data| stack_data.stack_id == stack.stack_id){
+				return Err(format!("snapshot stack {} has no stack data", stack.stack_id).into());
+			}
+		}
+
+		for stack_data in snapshot.stack_datas.iter(){
+			if !stack_ids.contains(&stack_data.stack_id){
+				return Err(format!("snapshot stack data {} has no running stack", stack_data.stack_id).into());
+			}
+		}
+
+		for task_data in snapshot.task_datas.iter(){
+			check_task_index(task_data.task_id as i64, "task data")?;
+			if !stack_ids.contains(&task_data.active_stack_id){
+				return Err(format!("snapshot task data {} references unknown stack {}", task_data.task_id, task_data.active_stack_id).into());
+			}
+		}
+
+		for conditional_reevaluate in snapshot.conditional_reevaluate.iter(){
+			check_task_index(conditional_reevaluate.index as i64, "conditional reevaluate")?;
+			if conditional_reevaluate.composite_index != -1{
+				check_task_index(conditional_reevaluate.composite_index as i64, "conditional reevaluate composite")?;
+			}
+		}
+
+		for (stack_id, task_id) in snapshot.stack_id_to_parallel_task_id.iter(){
+			check_task_index(*task_id as i64, "parallel task")?;
+			if !stack_ids.contains(&(*stack_id as usize)){
+				return Err(format!("snapshot parallel task {} references unknown stack {}", task_id, stack_id).into());
+			}
+		}
+
+		for (task_id, parallel_stack_ids) in snapshot.parallel_task_id_to_stack_ids.iter(){
+			check_task_index(*task_id as i64, "parallel task")?;
+			for stack_id in parallel_stack_ids.iter(){
+				if !stack_ids.contains(&(*stack_id as usize)){
+					return Err(format!("snapshot parallel task {} references unknown stack {}", task_id, stack_id).into());
+				}
+			}
+		}
+
+		for task_state in snapshot.task_states.iter(){
+			check_task_index(task_state.task_id as i64, "task state")?;
+		}
+		Ok(())
+	}
+
+	//	enable跟restore共用：给需要同步的action设置收集器，然后调用on_awake
+	fn awake_tasks(&mut self){
+		for task in self.task_list.iter_mut().flatten(){
+			if task.is_implements_iaction() && task.is_sync_to_client(){
+				task.set_sync_data_collector(Some(RefCell::new(SyncDataCollector::new())));
+			}
+		}
+
+		for task_index in 0..self.task_list.len() as i32{
+			let Some(mut task) = self.take_task(task_index) else {
+				continue;
+			};
+			if !task.disabled(){
+				task.on_awake(self);
+			}
+			self.put_task(task_index, task);
+		}
+	}
+
 	fn next_stack_id(&mut self) -> usize{
 		let stack_id = self.stack_id;
 		self.stack_id += 1;
@@ -641,14 +1063,20 @@ impl BehaviorTree{
 	fn add_stack(&mut self) -> usize{
 		let stack_id = self.next_stack_id();
 		let stack_index = self.active_stack.len();
-		let stack = Rc::new(RefCell::new(Box::new(RunningStack::new(stack_id,10))));
-		self.active_stack.push(stack);
+		let stack = match self.stack_pool.pop(){
+			Some(mut stack) => {
+				stack.stack_id = stack_id;
+				stack
+			},
+			None => Box::new(RunningStack::new(stack_id,10)),
+		};
+		self.active_stack.push(StackSlot::Present(stack));
 		self.non_instant_task_status.push(TaskStatus::Inactive);
 
-		let timestamp_in_mill = self.clock.upgrade().as_ref().unwrap().borrow().timestamp_in_mill();
+		let timestamp_in_mill = self.timestamp_in_mill();
 		let stack_data = StackRuntimeData::new(stack_id, timestamp_in_mill);
 		self.runtime_event_handle.new_stack(self, &stack_data);
-		self.stack_id_to_stack_data.insert(stack_id, Box::new(stack_data));
+		self.stack_id_to_stack_data.insert(stack_id, stack_data);
 		return stack_index;
 	}
 
@@ -661,7 +1089,7 @@ impl BehaviorTree{
 		let mut  child_index = possible_child;
 
 		while child_index != -1 {
-			parent_index = self.parent_index[child_index as usize];
+			parent_index = self.index_tables.parent_index[child_index as usize];
 			if parent_index == possible_parent {
 				return true;
 			}
@@ -678,38 +1106,33 @@ impl BehaviorTree{
 		task_execute_id
 	}
 
-	fn push_task(&mut self, stack_index:usize, task_index:u32, stack:&mut RunningStack){
+	fn push_task(&mut self, stack_index:usize, task_index:u32, stack:&mut RunningStack, task:&mut dyn ITaskProxy){
 		if !self.is_running || stack_index >= self.active_stack.len() {
 			return
 		}
 
-		let stack_data = self.stack_id_to_stack_data.get(&stack.stack_id).unwrap().clone();
-		let stack_data = stack_data.as_ref();
+		let stack_data = *self.stack_id_to_stack_data.get(&stack.stack_id).unwrap();
+		let stack_data = &stack_data;
 	
 		if stack.len() == 0 || stack.peak() != task_index {
 			stack.push(task_index);
 
 			self.non_instant_task_status[stack_index] = TaskStatus::Running;
-			
-			let task = &mut self.task_list.get(task_index as usize).unwrap();
-	        let task = task.clone().upgrade().unwrap();
-			let mut task = task.borrow_mut();
-			let task = task.as_mut();
 
-			let now_timestamp= self.clock.upgrade().as_ref().unwrap().borrow().timestamp_in_mill();
+			let now_timestamp= self.timestamp_in_mill();
 			let task_execute_id= self.next_task_execute_id();
 	
 			let task_runtime_data= TaskRuntimeData::new(task.id(), now_timestamp, task_execute_id, stack_data.stack_id);
-			self.task_datas.insert(task.id(), Box::new(task_runtime_data));
+			self.task_datas.insert(task.id(), task_runtime_data);
 	
 
 			//	TODO:这里需要截获初始化的数据？
-			self.runtime_event_handle.pre_on_start(self, self.task_datas.get(&task.id()).unwrap().as_ref(), &stack_data, task);
+			self.runtime_event_handle.pre_on_start(self, &self.task_datas[&task.id()], &stack_data, task);
 
 			//self.runtimeEventHandle.PreOnStart(p, taskRuntimeData, stackData, task)
 			if task.is_implements_iparenttask() {
 				if task.can_run_parallel_children() {
-					self.runtime_event_handle.parallel_pre_on_start(self, self.task_datas.get(&task.id()).unwrap().as_ref(), &stack_data, task);
+					self.runtime_event_handle.parallel_pre_on_start(self, &self.task_datas[&task.id()], &stack_data, task);
 				}
 			}
 	
@@ -717,7 +1140,7 @@ impl BehaviorTree{
 			if task.is_implements_iaction() {
 				if task.is_sync_to_client() {
 					let sync_data_collector = task.sync_data_collector().unwrap();
-					sync_data_collector.borrow_mut().get_and_clear();
+					sync_data_collector.borrow_mut().clear();
 				}
 			}
             
@@ -727,8 +1150,9 @@ impl BehaviorTree{
 				//action := task.(iface.IAction)
 				if task.is_sync_to_client() {
 					let sync_data_collector = task.sync_data_collector().unwrap();
-					let datas = sync_data_collector.borrow_mut().get_and_clear();
-					self.runtime_event_handle.action_post_on_start(self, self.task_datas.get(&task.id()).unwrap().as_ref(), &stack_data, task, datas);
+					self.runtime_event_handle.action_post_on_start(self, &self.task_datas[&task.id()], &stack_data, task, sync_data_collector.borrow().datas());
+					sync_data_collector.borrow_mut().clear();
+					self.sync_update_states.insert(task.id(), SyncUpdateState::new(now_timestamp));
 				}
 			}
 	
@@ -736,7 +1160,7 @@ impl BehaviorTree{
 				//	可以并发的父节点有特殊处理
 				
 				if task.can_run_parallel_children() {
-					self.parallel_task_id_to_stack_ids.insert(task.id(), Vec::new());
+					self.parallel_task_id_to_stack_ids.entry(task.id()).or_default().clear();
 				}
 
 				 
@@ -744,21 +1168,17 @@ impl BehaviorTree{
 					match task.abort_type() {
 						AbortType::None => (),
 						_ => {
-							let mut conditional_reevaluates = self.conditional_reevaluate.clone();
-						    for conditional_reevaluate in  conditional_reevaluates.iter_mut(){
-								let mut conditional_reevaluate = conditional_reevaluate.borrow_mut();
-								if self.is_parent_task(task.id(), conditional_reevaluate.index) {
-									conditional_reevaluate.composite_index = task.id();
+						    for i in 0..self.conditional_reevaluate.len(){
+								if self.is_parent_task(task.id(), self.conditional_reevaluate[i].index) {
+									self.conditional_reevaluate[i].composite_index = task.id();
 								}
 							}
 							match task.abort_type() {
 								AbortType::LowerPriority => {
-									let child_conditional_indexes = self.child_conditional_index[task.id() as usize].clone();
-									for child_conditional_index in child_conditional_indexes.into_iter(){
-										let child_conditional_indexes = child_conditional_index;
-										if let Some(conditional_reevaluate) = self.conditional_reevaluate_map.get_mut(&child_conditional_indexes){
-											//let conditional_reevaluate = Rc::get_mut(&mut conditional_reevaluate).unwrap();
-											conditional_reevaluate.borrow_mut().composite_index = -1;
+									let child_conditional_index = &self.index_tables.child_conditional_index[task.id() as usize];
+									for conditional_reevaluate in self.conditional_reevaluate.iter_mut(){
+										if child_conditional_index.contains(&conditional_reevaluate.index){
+											conditional_reevaluate.composite_index = -1;
 										}
 									}
 									()
@@ -775,7 +1195,7 @@ impl BehaviorTree{
 	}
 
 	fn pop_task(&mut self, task_index:i32, stack_index:usize,mut status:TaskStatus, pop_children:bool, task:&mut dyn ITaskProxy, stack:&mut RunningStack, mut parent_task:Option<&mut dyn ITaskProxy>)->TaskStatus{
-		if self.is_running{
+		if !self.is_running{
 			return status;
 		}
 
@@ -783,16 +1203,17 @@ impl BehaviorTree{
 			return status;
 		}
 
-		if self.active_stack[stack_index].borrow().len() == 0||self.active_stack[stack_index].borrow().peak()!= task_index as u32{
+		//	stack就是active_stack[stack_index]，调用方已经持有了它的借用
+		if stack.len() == 0|| stack.peak()!= task_index as u32{
 			return status;
 		}
 
-		self.active_stack[stack_index].borrow_mut().pop();
+		stack.pop();
 		self.non_instant_task_status[stack_index] = TaskStatus::Inactive;
 
 		if task.is_implements_iaction(){
 			if task.is_sync_to_client(){
-				task.sync_data_collector().unwrap().borrow_mut().get_and_clear();
+				task.sync_data_collector().unwrap().borrow_mut().clear();
 			}
 		}
 
@@ -800,26 +1221,24 @@ impl BehaviorTree{
 
 		
 		if let Some(parent_task_ref) = &mut parent_task{
-			let parent_index = self.parent_index[task_index as usize];
+			let parent_index = self.index_tables.parent_index[task_index as usize];
 			if task.is_implements_iconditional(){
-				let composite_parent_index = self.parent_composite_index[task_index as usize];
+				let composite_parent_index = self.index_tables.parent_composite_index[task_index as usize];
 				if composite_parent_index != -1{
-					let composite_abort_type = self.composite_abort_task[composite_parent_index as usize];
+					let composite_abort_type = self.index_tables.composite_abort_task[composite_parent_index as usize];
 					if composite_abort_type != AbortType::None{
 						let mut composite = -1;
 						if composite_abort_type != AbortType::LowerPriority{
 							composite = composite_parent_index;
 						}
 
-						match self.conditional_reevaluate_map.get(&task_index){
+						match self.conditional_reevaluate.iter_mut().find(|conditional_reevaluate| conditional_reevaluate.index == task_index){
 							Some(conditional_reevaluate) => {
-								conditional_reevaluate.borrow_mut().initialize(task_index, status.clone(), composite);
+								conditional_reevaluate.initialize(task_index, status.clone(), composite);
 								()
 							},
 							None => {
-								let conditional_reevaluate = Rc::new(RefCell::new(Box::new(ConditionalReevaluate::new(task_index, status.clone(), composite))));
-								self.conditional_reevaluate_map.insert(task_index, conditional_reevaluate.clone());
-								self.conditional_reevaluate.push(conditional_reevaluate.clone());
+								self.conditional_reevaluate.push(ConditionalReevaluate::new(task_index, status.clone(), composite));
 								()
 							},
 						}
@@ -832,22 +1251,21 @@ impl BehaviorTree{
 				parent_task.as_mut().unwrap().on_child_executed1(status.clone(), self);
 				status = parent_task.as_mut().unwrap().decorate(status, self);
 			}else{
-				parent_task.as_mut().unwrap().on_child_executed2(self.relative_child_index[task_index as usize] as u32, status.clone(), self);
+				parent_task.as_mut().unwrap().on_child_executed2(self.index_tables.relative_child_index[task_index as usize] as u32, status.clone(), self);
 			}
 		}
 
 		if task.is_implements_iparenttask(){
 			if task.is_implements_icomposite(){
-				if task.abort_type() != AbortType::Self_|| task.abort_type() != AbortType::None{
+				if task.abort_type() == AbortType::Self_|| task.abort_type() == AbortType::None{
 					self.remove_child_conditional_reevaluate(task_index);
-				}else if task.abort_type() != AbortType::LowerPriority|| task.abort_type() != AbortType::Both{
-					if self.parent_composite_index[task_index as usize] == -1{
+				}else if task.abort_type() == AbortType::LowerPriority|| task.abort_type() == AbortType::Both{
+					if self.index_tables.parent_composite_index[task_index as usize] == -1{
 						self.remove_child_conditional_reevaluate(task_index);
 					}else{
-						let conditional_reevaluates = self.conditional_reevaluate.clone();
-						for conditional_reevaluate in conditional_reevaluates{
-							if self.is_parent_task(task_index, conditional_reevaluate.borrow().index){
-								conditional_reevaluate.borrow_mut().composite_index = self.parent_composite_index[task_index as usize];
+						for i in 0..self.conditional_reevaluate.len(){
+							if self.is_parent_task(task_index, self.conditional_reevaluate[i].index){
+								self.conditional_reevaluate[i].composite_index = self.index_tables.parent_composite_index[task_index as usize];
 							}
 						}
 					}
@@ -855,62 +1273,73 @@ impl BehaviorTree{
 			}
 		}
 
+		//	子任务只可能在并发任务开出来的执行栈里面，当前栈里task已经是栈顶了
 		if pop_children{
-			for i in (stack_index..self.active_stack.len()).rev(){
-				let current_stack = self.active_stack[i].clone();
-				let mut stack = current_stack.borrow_mut();
-
-				while i < self.active_stack.len() && Rc::ptr_eq(&current_stack, &self.active_stack[i]) && stack.len() > 0 {
-					if self.is_parent_task(task_index,  stack.peak() as i32){
-						let child_index = stack.peak();
-						let child_task = self.task_list[child_index as usize].clone().upgrade().unwrap();
-						let mut child_task = child_task.borrow_mut();
-						let child_task = child_task.as_mut();
+			for i in (stack_index + 1..self.active_stack.len()).rev(){
+				if i >= self.active_stack.len(){
+					continue;
+				}
 
+				let Some(mut current_stack) = self.take_stack(i) else {
+					continue;
+				};
+				while current_stack.len() > 0 {
+					if self.is_parent_task(task_index,  current_stack.peak() as i32){
+						let child_index = current_stack.peak() as i32;
 						let child_status = TaskStatus::Failure;
-						if self.parent_index[child_index as usize] == task_index {
-							self.pop_task(child_index as i32, i, child_status, false, child_task, stack.as_mut(), Some(task));
-						}else{
-							let parent_task =  &self.task_list[self.parent_index[child_index as usize] as usize].upgrade().unwrap();
-							let mut parent_task = parent_task.borrow_mut();
-							let parent_task = parent_task.as_mut();
-							self.pop_task(child_index as i32, i, child_status, false, child_task, stack.as_mut(), Some(parent_task));
+						//	task已经取出来了，它是父任务的时候直接传进去
+						if self.index_tables.parent_index[child_index as usize] == task_index {
+							let Some(mut child_task) = self.take_task(child_index) else {
+								break;
+							};
+							self.pop_task(child_index, i, child_status, false, child_task.as_mut(), &mut current_stack, Some(task));
+							self.put_task(child_index, child_task);
+						}else if self.with_task_and_parent(child_index, |behavior_tree, child_task, parent_task|{
+							behavior_tree.pop_task(child_index, i, child_status, false, child_task, &mut current_stack, parent_task);
+						}).is_none(){
+							break;
 						}
 					}else{
 						break;
 					}
 				}
+				self.put_stack(current_stack);
 			}
 		}
 
-		let task_runtime_data = self.task_datas.get(&task.id()).unwrap().clone();
-		let stack_data = self.stack_id_to_stack_data.get(&stack.stack_id).unwrap().clone();
-		let now_timestamp = self.clock.upgrade().as_ref().unwrap().borrow().timestamp_in_mill();
-		self.runtime_event_handle.post_on_end(self, task_runtime_data.as_ref(), stack_data.as_ref(), task, now_timestamp);
+		let task_runtime_data = *self.task_datas.get(&task.id()).unwrap();
+		let stack_data = *self.stack_id_to_stack_data.get(&stack.stack_id).unwrap();
+		let now_timestamp = self.timestamp_in_mill();
+		self.runtime_event_handle.post_on_end(self, &task_runtime_data, &stack_data, task, now_timestamp);
 
 		if task.is_implements_iaction(){
 			if task.is_sync_to_client(){
-				let datas = task.sync_data_collector().unwrap().borrow_mut().get_and_clear();
-				self.runtime_event_handle.action_post_on_end(self, task_runtime_data.as_ref(), stack_data.as_ref(), task, now_timestamp, datas);
+				let sync_data_collector = task.sync_data_collector().unwrap();
+				self.runtime_event_handle.action_post_on_end(self, &task_runtime_data, &stack_data, task, now_timestamp, sync_data_collector.borrow().datas());
+				sync_data_collector.borrow_mut().clear();
 			}
 		}
 
 		if task.is_implements_iparenttask(){
 			if task.can_run_parallel_children(){
-				self.runtime_event_handle.parallel_post_on_end(self, task_runtime_data.as_ref(), stack_data.as_ref(), task, now_timestamp);
-				self.parallel_task_id_to_stack_ids.remove(&(task.id() as i32));
+				self.runtime_event_handle.parallel_post_on_end(self, &task_runtime_data, &stack_data, task, now_timestamp);
+				//	只清空不删除，并发任务重新开始的时候复用
+				if let Some(stack_ids) = self.parallel_task_id_to_stack_ids.get_mut(&(task.id() as i32)){
+					stack_ids.clear();
+				}
 			}
 		}
 
 		self.task_datas.remove(&task.id());
+		self.sync_update_states.remove(&task.id());
 		if stack.len() == 0{
 			if stack_index == 0{
-				self.remove_stack(stack_index, stack);
-				let _ = self.disable();
-				self.execution_status = status;
+				self.remove_stack(stack_index, stack, None);
+				//	调用栈上还持有任务的借用，等update结束后再关闭树
+				self.complete_status = Some(status);
 				status = TaskStatus::Inactive;
 			}else{
-				self.remove_stack(stack_index, stack);
+				self.remove_stack(stack_index, stack, parent_task.as_deref());
 				status = TaskStatus::Running;
 			}
 		}
@@ -919,27 +1348,34 @@ impl BehaviorTree{
 	}
 
 	fn reevaluate_conditional_tasks(&mut self){
-		let mut update_condition_indexes:Vec<Rc<RefCell<Box<ConditionalReevaluate>>>> = Vec::with_capacity(10);
-		//updateConditionIndexes := util.NewList[*ConditionalReevaluate](10)
-		let  mut conditional_reevaluatees = self.conditional_reevaluate.clone();
-		let len = conditional_reevaluatees.len();
-		for i in (0..len).rev(){
-			let conditional_reevaluate: &mut Rc<RefCell<Box<ConditionalReevaluate>>> = &mut conditional_reevaluatees[i];
-			if conditional_reevaluate.borrow().composite_index != -1{
-				let condition_index = conditional_reevaluate.borrow().index;
-				let condition_status = conditional_reevaluate.borrow().task_status.clone();
-
-				let condition_task =&mut self.task_list[condition_index as usize];
-				let condition_task = condition_task.upgrade().unwrap();
-				let mut condition_task = condition_task.borrow_mut();
-				//let condition_task = condition_task.borrow().as_ref();
-
-				if condition_task.on_update(self) != condition_status {
-					let composite_index = conditional_reevaluate.borrow().composite_index;
+		//	只有发生打断的时候才会用到，记录(条件任务, composite_index)
+		let mut update_condition_indexes = std::mem::take(&mut self.update_condition_indexes);
+		update_condition_indexes.clear();
+		//	跟BehaviorDesigner一样倒序遍历，打断的时候只会删掉当前和后面的项
+		for i in (0..self.conditional_reevaluate.len()).rev(){
+			if i >= self.conditional_reevaluate.len(){
+				continue;
+			}
+
+			if self.conditional_reevaluate[i].composite_index != -1{
+				let condition_index = self.conditional_reevaluate[i].index;
+				let condition_status = self.conditional_reevaluate[i].task_status.clone();
+
+				let Some(mut condition_task) = self.take_task(condition_index) else {
+					continue;
+				};
+				let reevaluate_status = condition_task.on_update(self);
+				self.put_task(condition_index, condition_task);
+
+				if reevaluate_status != condition_status {
+					let composite_index = self.conditional_reevaluate[i].composite_index;
 					for j in (0..self.active_stack.len()).rev(){
-						if self.active_stack[j].as_ref().borrow().len() > 0{
-							let task_index = self.active_stack[j].as_ref().borrow().peak();
-							let mut task_index = task_index as i32;
+						if j >= self.active_stack.len(){
+							continue;
+						}
+
+						if self.stack_len(j) > 0{
+							let mut task_index = self.active_stack[j].stack().unwrap().peak() as i32;
 							if !self.is_parent_task(composite_index, task_index){
 								continue;
 							}
@@ -947,111 +1383,123 @@ impl BehaviorTree{
 							let stack_count = self.active_stack.len();
 							while task_index != -1 && task_index != composite_index && self.active_stack.len() == stack_count {
 								let status = TaskStatus::Failure;
-								let task = &mut self.task_list[task_index as usize].upgrade().unwrap();
-								let mut task = task.borrow_mut();
-								let task = task.as_mut();
-								let stack = self.active_stack[j].clone();
-								let mut stack = stack.borrow_mut();
-								let stack_data = self.stack_id_to_stack_data.get(&stack.stack_id).unwrap().clone();
-								self.pop_task(task_index, j, status, false,  task, stack.as_mut(), None);
-								task_index = self.parent_index[task_index as usize];
-							}
-
-							for j in (i..self.conditional_reevaluate.len()).rev(){
-								let j_conditional_reval = self.conditional_reevaluate[j].clone();
-								if self.is_parent_task(composite_index, j_conditional_reval.borrow().index) {
-									let j_index = j_conditional_reval.borrow().index;
-									self.conditional_reevaluate_map.remove(&j_index);
-									self.conditional_reevaluate.remove(j);
+								let Some(mut stack) = self.take_stack(j) else {
+									break;
+								};
+								let parent_index = self.index_tables.parent_index[task_index as usize];
+								let popped = self.with_task_and_parent(task_index, |behavior_tree, task, parent_task|{
+									behavior_tree.pop_task(task_index, j, status, false,  task, &mut stack, parent_task);
+								});
+								self.put_stack(stack);
+								if popped.is_none(){
+									break;
 								}
+								task_index = parent_index;
 							}
+						}
+					}
 
-							//	原先abort过的要设置为原位
-							for j in (0..update_condition_indexes.len()).rev(){
-								let conditional_reevaluate = &update_condition_indexes[j];
-								if self.is_parent_task(composite_index, conditional_reevaluate.borrow().index) {
-									let mut task_index = self.parent_index[conditional_reevaluate.borrow().index as usize];
-									while task_index != -1 && task_index != conditional_reevaluate.borrow().composite_index {
-										let task = &mut self.task_list[task_index as usize];
-										let task = task.upgrade().unwrap();
-										let mut task = task.borrow_mut();
-
-										task.on_cancel_conditional_abort(self);
-										task_index = self.parent_index[task_index as usize];
-									}
-								}
+					//	弹出任务的时候composite_index可能被改过了，用改过以后的
+					let condition_composite_index = self.conditional_reevaluate.iter().find(|conditional_reevaluate| conditional_reevaluate.index == condition_index)
+						.map(|conditional_reevaluate| conditional_reevaluate.composite_index).unwrap_or(composite_index);
 
-								update_condition_indexes.remove(j as usize);
+					for j in (i..self.conditional_reevaluate.len()).rev(){
+						if self.is_parent_task(composite_index, self.conditional_reevaluate[j].index) {
+							self.conditional_reevaluate.remove(j);
+						}
+					}
+
+					//	原先abort过的要设置为原位
+					for j in (0..update_condition_indexes.len()).rev(){
+						let (update_condition_index, update_composite_index) = update_condition_indexes[j];
+						if self.is_parent_task(composite_index, update_condition_index) {
+							let mut task_index = self.index_tables.parent_index[update_condition_index as usize];
+							while task_index != -1 && task_index != update_composite_index {
+								if let Some(mut task) = self.take_task(task_index){
+									task.on_cancel_conditional_abort(self);
+									self.put_task(task_index, task);
+								}
+								task_index = self.index_tables.parent_index[task_index as usize];
 							}
+						}
 
-							update_condition_indexes.push(conditional_reevaluate.clone());
-							//是否需要把当前的conditionalReevaluate也删除掉？需要
-							self.conditional_reevaluate_map.remove(&condition_index);
-							self.conditional_reevaluate.remove(i);
+						update_condition_indexes.remove(j as usize);
+					}
 
-							let mut conditional_parent_indexes :Vec<i32> = Vec::with_capacity(10);
-							let mut parent_index = condition_index;
-							while parent_index != composite_index {
-								parent_index = self.parent_index[parent_index as usize];
-								conditional_parent_indexes.push(parent_index);
-							}
+					update_condition_indexes.push((condition_index, condition_composite_index));
+					//是否需要把当前的conditionalReevaluate也删除掉？需要
+					if let Some(position) = self.conditional_reevaluate.iter().position(|conditional_reevaluate| conditional_reevaluate.index == condition_index){
+						self.conditional_reevaluate.remove(position);
+					}
 
-							for j in (0..conditional_parent_indexes.len()).rev(){
-								let parent_task = &mut self.task_list[conditional_parent_indexes[j] as usize];
-								let mut parent_task = parent_task.upgrade().unwrap();
+					let mut conditional_parent_indexes = std::mem::take(&mut self.conditional_parent_indexes);
+					conditional_parent_indexes.clear();
+					let mut parent_index = condition_index;
+					while parent_index != composite_index {
+						parent_index = self.index_tables.parent_index[parent_index as usize];
+						conditional_parent_indexes.push(parent_index);
+					}
 
-								if j == 0 {
-									parent_task.borrow_mut().on_conditional_abort(self.relative_child_index[condition_index as usize] as u32, self);
-								}else{
-									parent_task.borrow_mut().on_conditional_abort(self.relative_child_index[conditional_parent_indexes[j - 1] as usize] as u32, self);
-								}
-							}
+					for j in (0..conditional_parent_indexes.len()).rev(){
+						let Some(mut parent_task) = self.take_task(conditional_parent_indexes[j]) else {
+							continue;
+						};
+						if j == 0 {
+							parent_task.on_conditional_abort(self.index_tables.relative_child_index[condition_index as usize] as u32, self);
+						}else{
+							parent_task.on_conditional_abort(self.index_tables.relative_child_index[conditional_parent_indexes[j - 1] as usize] as u32, self);
 						}
+						self.put_task(conditional_parent_indexes[j], parent_task);
 					}
+					self.conditional_parent_indexes = conditional_parent_indexes;
 				}
 			}
 		}
+		self.update_condition_indexes = update_condition_indexes;
 	}
 
-	fn remove_stack(&mut self, stack_index:usize, stack:&mut RunningStack) {
+	//	parallel_task是并发子栈所属的并发任务，调用方已经借用了它的时候需要传进来
+	fn remove_stack(&mut self, stack_index:usize, stack:&mut RunningStack, parallel_task:Option<&dyn ITaskProxy>) {
 		if stack_index < self.active_stack.len() {
-			let stack_data = self.stack_id_to_stack_data.get(&stack.stack_id).unwrap().clone();
-			let now_timestamp = self.clock.upgrade().as_ref().unwrap().borrow().timestamp_in_mill();
+			let stack_data = *self.stack_id_to_stack_data.get(&stack.stack_id).unwrap();
+			let now_timestamp = self.timestamp_in_mill();
 			if self.stack_id_to_parallel_task_id.contains_key(&(stack_data.stack_id as u32)) {
 				let parallel_task_id = *self.stack_id_to_parallel_task_id.get(&(stack_data.stack_id as u32)).unwrap();
-				let task_runtime_data = self.task_datas.get(&(parallel_task_id as i32)).unwrap().clone();
-				let task_runtime_data = task_runtime_data.as_ref();
-				//let task_runtime_data = task_runtime_data.as_ref();
-
-				let parent_stack_data = self.stack_id_to_stack_data.get(&task_runtime_data.active_stack_id).unwrap().clone();
-				let parent_stack_data = parent_stack_data.as_ref();
-				let task = self.task_list[task_runtime_data.task_id as usize].clone().upgrade().unwrap();
-				self.runtime_event_handle.parallel_remove_child_stack(self, task_runtime_data, parent_stack_data, task.borrow().as_ref(), &stack_data, now_timestamp);
+				let task_runtime_data = *self.task_datas.get(&(parallel_task_id as i32)).unwrap();
+				let task_runtime_data = &task_runtime_data;
+				//let task_runtime_data = &task_runtime_data;
+
+				let parent_stack_data = *self.stack_id_to_stack_data.get(&task_runtime_data.active_stack_id).unwrap();
+				let parent_stack_data = &parent_stack_data;
+				match parallel_task {
+					Some(task) if task.id() == task_runtime_data.task_id => {
+						self.runtime_event_handle.parallel_remove_child_stack(self, task_runtime_data, parent_stack_data, task, &stack_data, now_timestamp);
+					},
+					_ => {
+						if let Some(task) = self.task(task_runtime_data.task_id){
+							self.runtime_event_handle.parallel_remove_child_stack(self, task_runtime_data, parent_stack_data, task, &stack_data, now_timestamp);
+						}
+					},
+				}
 				
 				self.stack_id_to_parallel_task_id.remove(&(stack_data.stack_id as u32));
-                let old_parallel_task_id_to_stack_ids = self.parallel_task_id_to_stack_ids.get_mut(&(parallel_task_id as i32)).unwrap().clone();
-				self.parallel_task_id_to_stack_ids.get_mut(&(parallel_task_id as i32)).unwrap().clear();
-				for stack_id in old_parallel_task_id_to_stack_ids.iter(){
-					if (*stack_id as usize) != stack_data.stack_id {
-						self.parallel_task_id_to_stack_ids.get_mut(&(parallel_task_id as i32)).unwrap().push(*stack_id);
-					}
-				}
+				self.parallel_task_id_to_stack_ids.get_mut(&(parallel_task_id as i32)).unwrap().retain(|stack_id| (*stack_id as usize) != stack_data.stack_id);
 			}
 
-			self.runtime_event_handle.remove_stack(self, stack_data.as_ref(), now_timestamp);
+			self.runtime_event_handle.remove_stack(self, &stack_data, now_timestamp);
 			self.stack_id_to_stack_data.remove(&(stack_data.stack_id as usize));
 			
-			self.active_stack.remove(stack_index);
+			if let StackSlot::Present(mut stack) = self.active_stack.remove(stack_index){
+				stack.stack.clear();
+				self.stack_pool.push(stack);
+			}
 			self.non_instant_task_status.remove(stack_index);
 		}
 	}
 
 	fn remove_child_conditional_reevaluate(&mut self, composite_index:i32){
 		for i in (0..self.conditional_reevaluate.len()).rev(){
-			let conditional_reevaluate = self.conditional_reevaluate[i].clone();
-			if self.is_parent_task(composite_index, conditional_reevaluate.borrow().composite_index){
-				let conditional_index = conditional_reevaluate.borrow().index;
-				self.conditional_reevaluate_map.remove(&conditional_index);
+			if self.is_parent_task(composite_index, self.conditional_reevaluate[i].composite_index){
 				self.conditional_reevaluate.remove(i);
 			}
 		}
@@ -1062,31 +1510,27 @@ impl BehaviorTree{
 			return previous_status;
 		}
 
-		let task_runtime_data = self.task_datas.get(&task.id()).unwrap().clone();
-		let task_runtime_data = task_runtime_data.as_ref();
-
-		let stack_data = self.stack_id_to_stack_data.get(&stack.stack_id).unwrap().clone();
-		let stack_data = stack_data.as_ref();
+		let stack_data = *self.stack_id_to_stack_data.get(&stack.stack_id).unwrap();
+		let stack_data = &stack_data;
 
 		if task.disabled(){
-			let parent_index = self.parent_index[task_index as usize];
+			let parent_index = self.index_tables.parent_index[task_index as usize];
 			if let Some(parent_task_ref) = &mut parent_task {
 				if !parent_task_ref.can_run_parallel_children(){
 					parent_task_ref.on_child_executed1(TaskStatus::Inactive, self);
 				}else{
-					parent_task_ref.on_child_executed2(self.relative_child_index[task_index as usize] as u32, TaskStatus::Inactive, self);
+					parent_task_ref.on_child_executed2(self.index_tables.relative_child_index[task_index as usize] as u32, TaskStatus::Inactive, self);
 				}
 			}
 
 			let mut status = TaskStatus::Success;
 			if stack.len() == 0{
 				if stack_index == 0{
-					self.remove_stack(stack_index, stack);
-					let _ =  self.disable();
-					self.execution_status = status;
+					self.remove_stack(stack_index, stack, None);
+					self.complete_status = Some(status);
 					status = TaskStatus::Inactive;
 				}else{
-					self.remove_stack(stack_index, stack, );
+					self.remove_stack(stack_index, stack, parent_task.as_deref());
 				}
 			}
 
@@ -1094,13 +1538,16 @@ impl BehaviorTree{
 		}
 
 		let mut status: TaskStatus = previous_status;
-		if task.instant() && (self.non_instant_task_status[stack_index] == TaskStatus::Success || self.non_instant_task_status[stack_index] == TaskStatus::Failure){
+		if !task.instant() && (self.non_instant_task_status[stack_index] == TaskStatus::Success || self.non_instant_task_status[stack_index] == TaskStatus::Failure){
 			status = self.non_instant_task_status[stack_index].clone();
 			status = self.pop_task(task_index as i32, stack_index, status, true, task, stack, parent_task);
 			return status;
 		}
 
-		self.push_task(stack_index, task_index, stack);
+		self.push_task(stack_index, task_index, stack, task);
+		let task_runtime_data = *self.task_datas.get(&task.id()).unwrap();
+		let task_runtime_data = &task_runtime_data;
+
 		if task.is_implements_iparenttask(){
 			status = self.run_parent_task(task_index, stack_index, status, task, stack);
 			status = task.override_status1(status, self);
@@ -1108,21 +1555,28 @@ impl BehaviorTree{
 			if task.is_implements_iaction(){
 				if task.is_sync_to_client(){
 					if task.is_sync_to_client(){
-						task.sync_data_collector().unwrap().borrow_mut().get_and_clear();
+						task.sync_data_collector().unwrap().borrow_mut().clear();
 					}
 				}
 			}
 
+			self.runtime_event_handle.pre_on_update(self, task_runtime_data, stack_data, task);
 			status = task.on_update(self);
 		}
 
-		let now_timestamp = self.clock.upgrade().as_ref().unwrap().borrow().timestamp_in_mill();
+		let now_timestamp = self.timestamp_in_mill();
 		self.runtime_event_handle.post_on_update(self, task_runtime_data, stack_data, task,now_timestamp, status.clone());
 
 		if task.is_implements_iaction(){
 			if task.is_sync_to_client(){
-				let datas = task.sync_data_collector().unwrap().borrow_mut().get_and_clear();
-				self.runtime_event_handle.action_post_on_update(self, task_runtime_data, stack_data, task,now_timestamp, status.clone(), datas);
+				let sync_data_collector = task.sync_data_collector().unwrap();
+				//	快照恢复后没有记录，从任务开始的时间算。先拿出来，回调的时候不能借着self
+				let mut sync_update_state = self.sync_update_states.remove(&task.id()).unwrap_or_else(|| SyncUpdateState::new(task_runtime_data.start_time));
+				if let Some(datas) = sync_update_state.filter(task.sync_update_policy(), now_timestamp, &status, sync_data_collector.borrow().datas()){
+					self.runtime_event_handle.action_post_on_update(self, task_runtime_data, stack_data, task,now_timestamp, status.clone(), datas);
+				}
+				self.sync_update_states.insert(task.id(), sync_update_state);
+				sync_data_collector.borrow_mut().clear();
 			}
 		}
 
@@ -1138,45 +1592,80 @@ impl BehaviorTree{
 		return status;
 	}
 
+	//	从栈顶开始弹出task_id下面的任务，返回是否有任务被打断
+	fn interrupt_task(&mut self, task_id:i32, status:TaskStatus, include_self:bool)->bool{
+		let mut interrupted = false;
+		for j in (0..self.active_stack.len()).rev(){
+			if !self.is_running || j >= self.active_stack.len(){
+				continue;
+			}
+
+			let Some(mut stack) = self.take_stack(j) else {
+				continue;
+			};
+			while stack.len() > 0 && self.is_running {
+				let task_index = stack.peak() as i32;
+				if !(self.is_parent_task(task_id, task_index) || (include_self && task_index == task_id)){
+					break;
+				}
+
+				if self.with_task_and_parent(task_index, |behavior_tree, task, parent_task|{
+					behavior_tree.pop_task(task_index, j, status.clone(), false, task, &mut stack, parent_task);
+				}).is_none(){
+					break;
+				}
+				interrupted = true;
+			}
+			self.put_stack(stack);
+		}
+
+		interrupted
+	}
+
 	fn run_parent_task(&mut self, task_index:u32, stack_index:usize, mut status:TaskStatus, task:&mut dyn ITaskProxy, stack:&mut RunningStack) -> TaskStatus{
-		let stack_data = self.stack_id_to_stack_data.get(&stack.stack_id).unwrap().clone();
-		let stack_data = stack_data.as_ref();
-		let task_runtime_data = self.task_datas.get(&task.id()).unwrap().clone();
-		let task_runtime_data = task_runtime_data.as_ref();
+		let stack_data = *self.stack_id_to_stack_data.get(&stack.stack_id).unwrap();
+		let stack_data = &stack_data;
+		let task_runtime_data = *self.task_datas.get(&task.id()).unwrap();
+		let task_runtime_data = &task_runtime_data;
 
 
 		if !task.can_run_parallel_children() || task.override_status1(TaskStatus::Running, self) != TaskStatus::Running{
 			let mut child_status = TaskStatus::Inactive;
 			let parent_stack = stack_index;
-			let children_indexs = self.children_index[task_index as usize].clone();
 
 			while task.can_execute(self) &&(child_status != TaskStatus::Running||task.can_run_parallel_children())&&self.is_running{
 				let child_index = task.current_child_index(self);
 				if task.can_run_parallel_children(){
 					let child_stack_index = self.add_stack();
-					let child_stack = self.active_stack[child_stack_index].clone();
-					let mut child_stack = child_stack.borrow_mut();
-					let child_stack = child_stack.as_mut();
+					let Some(mut child_stack) = self.take_stack(child_stack_index) else {
+						break;
+					};
 
 					self.stack_id_to_parallel_task_id.insert(child_stack.stack_id as u32, task.id() as u32);
 					self.parallel_task_id_to_stack_ids.get_mut(&(task.id() as i32)).unwrap().push(child_stack.stack_id as u32);
 
-					let child_stack_data = self.stack_id_to_stack_data.get(&child_stack.stack_id).unwrap().clone();
-					let child_stack_data = child_stack_data.as_ref();					
+					let child_stack_data = *self.stack_id_to_stack_data.get(&child_stack.stack_id).unwrap();
+					let child_stack_data = &child_stack_data;					
 					self.runtime_event_handle.parallel_add_child_stack(self, task_runtime_data, stack_data, task, child_stack_data);
 					task.on_child_started1(child_index, self);
 
-					let child_task = self.task_list[children_indexs[child_index as usize] as usize].upgrade().unwrap();
-					let mut child_task = child_task.borrow_mut();
-					let child_task = child_task.as_mut();
-
-					child_status = self.run_task(children_indexs[child_index as usize] as u32, child_stack_index, status,  child_stack, child_task,Some(task));
+					let child_task_index = self.index_tables.children_index[task_index as usize][child_index as usize];
+					let Some(mut child_task) = self.take_task(child_task_index) else {
+						self.put_stack(child_stack);
+						break;
+					};
+					child_status = self.run_task(child_task_index as u32, child_stack_index, status,  &mut child_stack, child_task.as_mut(),Some(task));
+					self.put_task(child_task_index, child_task);
+					self.put_stack(child_stack);
 					status = child_status.clone();
 				}else{
 					task.on_child_started0(self);
-					let child_task = self.task_list[children_indexs[child_index as usize] as usize].upgrade().unwrap();
-					let mut child_task = child_task.borrow_mut();
-					child_status = self.run_task(children_indexs[child_index as usize] as u32, stack_index, child_status,  stack,  child_task.as_mut(),Some(task));
+					let child_task_index = self.index_tables.children_index[task_index as usize][child_index as usize];
+					let Some(mut child_task) = self.take_task(child_task_index) else {
+						break;
+					};
+					child_status = self.run_task(child_task_index as u32, stack_index, child_status,  stack,  child_task.as_mut(),Some(task));
+					self.put_task(child_task_index, child_task);
 					status = child_status.clone();
 				}
 			}
@@ -1189,7 +1678,7 @@ impl BehaviorTree{
 	/* func (p *BehaviorTree) RunTask(taskIndex, stackIndex int, previousStatus iface.TaskStatus) iface.TaskStatus { */
 }
 
-impl IBehaviorTree for BehaviorTree{
+impl<E:ITreeEnv> IBehaviorTree for BehaviorTree<E>{
 	fn id(&self)->u64{
 		self.id
 	}
@@ -1200,32 +1689,12 @@ impl IBehaviorTree for BehaviorTree{
 		}
 
 		self.initialize()?;
-
-		for task in self.task_list.iter_mut(){
-			let action = task.upgrade().unwrap();
-			let mut action =action .borrow_mut();
-
-			//let action = Rc::get_mut(task).unwrap();
-			if action.is_implements_iaction(){
-				if action.is_sync_to_client(){
-					action.set_sync_data_collector(Some(SyncDataCollector::new()));
-				};
-			}
-		}
-
-		let mut task_list = self.task_list.clone();
-		for task in task_list.iter_mut(){
-			let task = task.upgrade().unwrap();
-			let mut task = task.borrow_mut();
-			if !task.disabled(){
-				task.on_awake(self);
-			}
-		}
+		self.awake_tasks();
 
 		self.execution_status = TaskStatus::Inactive;
 		self.is_running = true;
 		
-		let now_timestamp_in_milli = self.clock.upgrade().as_ref().unwrap().borrow().timestamp_in_mill();
+		let now_timestamp_in_milli = self.timestamp_in_mill();
 		self.runtime_event_handle.post_initialize(self, now_timestamp_in_milli);
 		self.initialize_first_stack_and_first_task = true;
 
@@ -1237,47 +1706,42 @@ impl IBehaviorTree for BehaviorTree{
 			let mut status = TaskStatus::Success;
 
 			for i in (0..self.active_stack.len()).rev(){
-				let current_stack = self.active_stack[i].clone();
-				let mut current_stack = current_stack.borrow_mut();
+				let Some(mut current_stack) = self.take_stack(i) else {
+					continue;
+				};
 				while current_stack.len() > 0{
 					let stack_count = current_stack.len();
-					let task_index = current_stack.peak();
-					let task = self.task_list[task_index as usize].upgrade().unwrap();
-					let mut task = task.borrow_mut();
-					let task = task.as_mut();
-
-					let parent_index = self.parent_index[task_index as usize];
-					if parent_index == -1{
-						status = self.pop_task(task_index as i32, i, status.clone(), false,  task, current_stack.as_mut(), None);
-					}else{
-						let parent_task = self.task_list[parent_index as usize].upgrade().unwrap();
-						let mut parent_task = parent_task.borrow_mut();
-						status = self.pop_task(task_index as i32, i, status.clone(), false,  task, current_stack.as_mut(), Some(parent_task.as_mut()));
+					let task_index = current_stack.peak() as i32;
+					match self.with_task_and_parent(task_index, |behavior_tree, task, parent_task|{
+						behavior_tree.pop_task(task_index, i, status.clone(), false,  task, &mut current_stack, parent_task)
+					}){
+						Some(pop_status) => status = pop_status,
+						None => break,
 					}
 
 					if stack_count == 1{
 						break;
 					}
 				}
+				self.put_stack(current_stack);
 			}
 
-			for task in self.task_list.iter(){
-				let task = task.upgrade().unwrap();
-				let mut task = task.borrow_mut();
+			for task_index in 0..self.task_list.len() as i32{
+				let Some(mut task) = self.take_task(task_index) else {
+					continue;
+				};
 				if !task.disabled(){
 					task.on_complete(self);
 				}
+				self.put_task(task_index, task);
 			}
 
 			self.remove_child_conditional_reevaluate(-1);
 
-			for task in self.task_list.iter(){
-				let task = task.upgrade().unwrap();
-				let mut task = task.borrow_mut();
-
+			for task in self.task_list.iter_mut().flatten(){
 				if task.is_implements_iaction(){
 					if task.is_sync_to_client(){
-						task.sync_data_collector().unwrap().borrow_mut().get_and_clear();
+						task.sync_data_collector().unwrap().borrow_mut().clear();
 						task.set_sync_data_collector(None);
 					}
 				}
@@ -1285,7 +1749,8 @@ impl IBehaviorTree for BehaviorTree{
 
 			self.execution_status = status;
 			self.is_running = false;
-			let now_timestamp_in_milli = self.clock.upgrade().as_ref().unwrap().borrow().timestamp_in_mill();
+			self.complete_status = None;
+			let now_timestamp_in_milli = self.timestamp_in_mill();
 			self.runtime_event_handle.post_on_complete(self, now_timestamp_in_milli);
 			Ok(())
 		}else{
@@ -1298,10 +1763,13 @@ impl IBehaviorTree for BehaviorTree{
 		if self.is_running{
 			if self.initialize_first_stack_and_first_task{
 				self.add_stack();
-				let stack = self.active_stack[0].clone();
-				let mut stack = stack.borrow_mut();
-				let stack = stack.as_mut();
-				self.push_task(0,0, stack);
+				if let Some(mut stack) = self.take_stack(0){
+					if let Some(mut root_task) = self.take_task(0){
+						self.push_task(0,0, &mut stack, root_task.as_mut());
+						self.put_task(0, root_task);
+					}
+					self.put_stack(stack);
+				}
 				self.initialize_first_stack_and_first_task = false;
 			}
 
@@ -1313,12 +1781,14 @@ impl IBehaviorTree for BehaviorTree{
 				let mut start_index = -1;
 				let mut task_index;
 
-				let current_stack = self.active_stack[j].clone();
-				let mut stack = current_stack.borrow_mut();
-				let stack = stack.as_mut();
-
+				if j >= self.active_stack.len(){
+					continue;
+				}
 
-				while status != TaskStatus::Running && j < stack.len() && stack.len() > 0 && Rc::ptr_eq(&current_stack, &self.active_stack[j]) {
+				let Some(mut stack) = self.take_stack(j) else {
+					continue;
+				};
+				while status != TaskStatus::Running && stack.len() > 0 && self.is_stack_at(j, stack.stack_id) {
 					task_index = stack.peak();
 					if !self.is_running{
 						break;
@@ -1328,21 +1798,23 @@ impl IBehaviorTree for BehaviorTree{
 						break;
 					}
 
-					let task = self.task_list[task_index as usize].upgrade().unwrap();
-					let mut task = task.borrow_mut();
-					let task = task.as_mut();
-
 					start_index = task_index as i32;
-					if self.parent_index[task_index as usize] == -1 {
-						status = self.run_task(task_index, j, status, stack,  task, None);
-					}else{
-						let parent_task = self.task_list[self.parent_index[task_index as usize] as usize].upgrade().unwrap();
-						let mut parent_task = parent_task.borrow_mut();
-						let parent_task = parent_task.as_mut();
-						status = self.run_task(task_index, j, status, stack,  task, Some(parent_task));
+					match self.with_task_and_parent(task_index as i32, |behavior_tree, task, parent_task|{
+						behavior_tree.run_task(task_index, j, status, &mut stack,  task, parent_task)
+					}){
+						Some(run_status) => status = run_status,
+						None => break,
 					}
 				}
+				self.put_stack(stack);
 			}
+
+			let pending_interrupts = std::mem::take(self.pending_interrupts.get_mut());
+			for (task_id, status, include_self) in pending_interrupts.into_iter(){
+				self.interrupt_task(task_id, status, include_self);
+			}
+
+			self.check_complete();
 		}
 	}
 
@@ -1357,30 +1829,31 @@ impl IBehaviorTree for BehaviorTree{
 	fn rebuild_sync(&self, collector:&mut dyn IRebuildSyncDataCollector){
 		if self.is_running{
 			for stack in self.active_stack.iter(){
-				let stack_runtime_data = self.stack_id_to_stack_data.get(&stack.borrow().stack_id).unwrap().clone();
-				collector.stack(self, stack_runtime_data.as_ref());
+				let stack_runtime_data = *self.stack_id_to_stack_data.get(&stack.stack_id()).unwrap();
+				collector.stack(self, &stack_runtime_data);
 			}
 
-			for stack in self.active_stack.iter(){
-				let stack_runtime_data = self.stack_id_to_stack_data.get(&stack.borrow().stack_id).unwrap().clone();
-				let task_index = stack.borrow().peak();
-				let task = self.task_list[task_index as usize].upgrade().unwrap();
-				let task = task.borrow();
+			for stack in self.active_stack.iter().filter_map(|stack| stack.stack()){
+				let stack_runtime_data = *self.stack_id_to_stack_data.get(&stack.stack_id).unwrap();
+				let task_index = stack.peak();
+				let Some(task) = self.task(task_index as i32) else {
+					continue;
+				};
 				if task.is_implements_iaction(){
 					if task.is_sync_to_client(){
-						let task_runtime_data = self.task_datas.get(&task.id()).unwrap().clone();
-						task.sync_data_collector().unwrap().borrow_mut().get_and_clear();
+						let task_runtime_data = *self.task_datas.get(&task.id()).unwrap();
+						task.sync_data_collector().unwrap().borrow_mut().clear();
 						task.rebuild_sync_datas(self);
-						let sync_datas = task.sync_data_collector().unwrap().borrow_mut().get_and_clear();
-						collector.action(self, task_runtime_data.as_ref(), stack_runtime_data.as_ref(), task.as_ref(), &sync_datas);
+						collector.action(self, &task_runtime_data, &stack_runtime_data, task, task.sync_data_collector().unwrap().borrow().datas());
+						task.sync_data_collector().unwrap().borrow_mut().clear();
 					}
 				}else if task.is_implements_iparenttask(){
 					if task.can_run_parallel_children(){
-						let task_runtime_data = self.task_datas.get(&task.id()).unwrap().clone();
-						let stack_runtime_data = self.stack_id_to_stack_data.get(&stack.borrow().stack_id).unwrap().clone();
+						let task_runtime_data = *self.task_datas.get(&task.id()).unwrap();
+						let stack_runtime_data = *self.stack_id_to_stack_data.get(&stack.stack_id).unwrap();
 						let child_stack_runtime_ids = self.parallel_task_id_to_stack_ids.get(&(task.id() as i32)).unwrap().clone();
-						let child_stack_runtime_datas = child_stack_runtime_ids.iter().map(|id| *(self.stack_id_to_stack_data.get(&(*id as usize)).unwrap().clone())).collect();
-						collector.parallel(self, task_runtime_data.as_ref(), stack_runtime_data.as_ref(), task.as_ref(), &child_stack_runtime_datas);
+						let child_stack_runtime_datas = child_stack_runtime_ids.iter().map(|id| *self.stack_id_to_stack_data.get(&(*id as usize)).unwrap()).collect();
+						collector.parallel(self, &task_runtime_data, &stack_runtime_data, task, &child_stack_runtime_datas);
 					}
 				}
 			}
@@ -1388,7 +1861,908 @@ impl IBehaviorTree for BehaviorTree{
 
 	}
 
-	fn clock(&self)->Weak<RefCell<Box<dyn IClock>>>{
-		self.clock.clone()
+	fn timestamp_in_mill(&self)->u64{
+		E::timestamp_in_mill(&self.clock)
+	}
+
+	fn interrupt(&mut self, task_id:i32, status:TaskStatus, include_self:bool)->Result<(), Box<dyn std::error::Error>>{
+		if !self.is_running{
+			return Err("BehaviorTree is not running".into());
+		}
+
+		if task_id < 0 || task_id as usize >= self.task_list.len(){
+			return Err(format!("interrupt task {} not found", task_id).into());
+		}
+
+		let interrupted = self.interrupt_task(task_id, status, include_self);
+		self.check_complete();
+
+		if interrupted{
+			Ok(())
+		}else{
+			Err(format!("interrupt task {} is not running", task_id).into())
+		}
+	}
+
+	fn request_interrupt(&self, task_id:i32, status:TaskStatus, include_self:bool){
+		self.pending_interrupts.borrow_mut().push((task_id, status, include_self));
+	}
+
+	fn task_id_by_config_id(&self, config_id:i32)->Option<i32>{
+		self.config_id_to_task_id.get(&config_id).cloned()
+	}
+
+	fn report_task_error(&self, task:&dyn ITaskProxy, error:&dyn std::error::Error){
+		self.runtime_event_handle.on_task_error(self, task, error);
+	}
+
+	fn snapshot(&self)->Result<BehaviorTreeSnapshot, Box<dyn std::error::Error>>{
+		let mut active_stack = Vec::with_capacity(self.active_stack.len());
+		for (i, stack) in self.active_stack.iter().enumerate(){
+			let stack = stack.stack().ok_or("can not snapshot while a stack is executing")?;
+			active_stack.push(RunningStackSnapshot{
+				stack_id:stack.stack_id,
+				stack:stack.stack.clone(),
+				non_instant_task_status:self.non_instant_task_status[i].clone(),
+			});
+		}
+
+		let mut stack_datas:Vec<StackRuntimeData> = self.stack_id_to_stack_data.values().cloned().collect();
+		stack_datas.sort_by_key(|data| data.stack_id);
+
+		let mut task_datas:Vec<TaskRuntimeData> = self.task_datas.values().cloned().collect();
+		task_datas.sort_by_key(|data| data.task_id);
+
+		let conditional_reevaluate = self.conditional_reevaluate.iter().map(|conditional_reevaluate|{
+			ConditionalReevaluateSnapshot{
+				index:conditional_reevaluate.index,
+				task_status:conditional_reevaluate.task_status.clone(),
+				composite_index:conditional_reevaluate.composite_index,
+			}
+		}).collect();
+
+		let mut stack_id_to_parallel_task_id:Vec<(u32, u32)> = self.stack_id_to_parallel_task_id.iter().map(|(stack_id, task_id)| (*stack_id, *task_id)).collect();
+		stack_id_to_parallel_task_id.sort();
+
+		let mut parallel_task_id_to_stack_ids:Vec<(i32, Vec<u32>)> = self.parallel_task_id_to_stack_ids.iter().map(|(task_id, stack_ids)| (*task_id, stack_ids.clone())).collect();
+		parallel_task_id_to_stack_ids.sort_by_key(|(task_id, _)| *task_id);
+
+		let task_states = self.save_task_states()?;
+
+		Ok(BehaviorTreeSnapshot{
+			version:SNAPSHOT_VERSION,
+			id:self.id,
+			unit_id:self.unit_id,
+			task_count:self.task_list.len(),
+			is_running:self.is_running,
+			initialize_first_stack_and_first_task:self.initialize_first_stack_and_first_task,
+			execution_status:self.execution_status.clone(),
+			active_stack,
+			stack_datas,
+			task_datas,
+			conditional_reevaluate,
+			stack_id_to_parallel_task_id,
+			parallel_task_id_to_stack_ids,
+			stack_id:self.stack_id,
+			task_execute_id:self.task_execute_id,
+			task_states,
+		})
+	}
+
+	fn restore(&mut self, snapshot:&BehaviorTreeSnapshot)->Result<(), Box<dyn std::error::Error>>{
+		if self.is_running{
+			return Err("BehaviorTree is already running".into());
+		}
+
+		if snapshot.version != SNAPSHOT_VERSION{
+			return Err(format!("unsupported snapshot version: {}", snapshot.version).into());
+		}
+
+		//	先检查快照，全部合法以后才修改树的状态
+		self.ensure_initialize_for_base()?;
+		self.validate_snapshot(snapshot)?;
+		let previous_task_states = self.save_task_states()?;
+
+		if !snapshot.is_running{
+			self.initialize()?;
+			self.execution_status = snapshot.execution_status.clone();
+			return Ok(());
+		}
+
+		self.awake_tasks();
+		if let Err(error) = self.load_task_states(&snapshot.task_states){
+			//	任务状态反序列化失败，已经恢复的任务改回原来的状态
+			let _ = self.load_task_states(&previous_task_states);
+			return Err(error);
+		}
+
+		self.initialize()?;
+		self.execution_status = snapshot.execution_status.clone();
+
+		for stack in snapshot.active_stack.iter(){
+			let mut running_stack = RunningStack::new(stack.stack_id, stack.stack.len().max(10));
+			running_stack.stack.extend_from_slice(&stack.stack);
+			self.active_stack.push(StackSlot::Present(Box::new(running_stack)));
+			self.non_instant_task_status.push(stack.non_instant_task_status.clone());
+		}
+
+		for stack_data in snapshot.stack_datas.iter(){
+			self.stack_id_to_stack_data.insert(stack_data.stack_id, *stack_data);
+		}
+
+		for task_data in snapshot.task_datas.iter(){
+			self.task_datas.insert(task_data.task_id, *task_data);
+		}
+
+		for conditional_reevaluate in snapshot.conditional_reevaluate.iter(){
+			self.conditional_reevaluate.push(ConditionalReevaluate::new(conditional_reevaluate.index, conditional_reevaluate.task_status.clone(), conditional_reevaluate.composite_index));
+		}
+
+		for (stack_id, task_id) in snapshot.stack_id_to_parallel_task_id.iter(){
+			self.stack_id_to_parallel_task_id.insert(*stack_id, *task_id);
+		}
+
+		for (task_id, stack_ids) in snapshot.parallel_task_id_to_stack_ids.iter(){
+			self.parallel_task_id_to_stack_ids.insert(*task_id, stack_ids.clone());
+		}
+
+		self.stack_id = snapshot.stack_id;
+		self.task_execute_id = snapshot.task_execute_id;
+		self.initialize_first_stack_and_first_task = snapshot.initialize_first_stack_and_first_task;
+		self.is_running = true;
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use super::super::json_parser::JsonParser;
+	use serde_json::json;
+
+	struct DummyClock;
+	impl IClock for DummyClock {
+		fn timestamp_in_mill(&self) -> u64 {
+			0
+		}
+	}
+
+	//	只记录任务的开始和结束，用于检查执行顺序
+	struct RecordRuntimeEventHandle{
+		records:Rc<RefCell<Vec<String>>>,
+	}
+
+	#[allow(unused_variables)]
+	impl IRuntimeEventHandle for RecordRuntimeEventHandle {
+		fn post_initialize(&self, behavior_tree: &dyn IBehaviorTree, timestamp_in_mill: u64) {}
+		fn post_on_complete(&self, behavior_tree: &dyn IBehaviorTree, timestamp_in_mill: u64) {
+			self.records.borrow_mut().push("complete".to_string());
+		}
+		fn new_stack(&self, behavior_tree: &dyn IBehaviorTree, data: &StackRuntimeData) {}
+		fn remove_stack(&self, behavior_tree: &dyn IBehaviorTree, data: &StackRuntimeData, timestamp_in_mill: u64) {}
+		fn pre_on_start(&self, behavior_tree: &dyn IBehaviorTree, task_runtime_data: &TaskRuntimeData, stack_runtime_data: &StackRuntimeData, task: &dyn ITaskProxy) {
+			self.records.borrow_mut().push(format!("start {}", task.id()));
+		}
+		fn post_on_update(&self, behavior_tree: &dyn IBehaviorTree, task_runtime_data: &TaskRuntimeData, stack_runtime_data: &StackRuntimeData, task: &dyn ITaskProxy, timestamp_in_mill: u64, status: TaskStatus) {}
+		fn post_on_end(&self, behavior_tree: &dyn IBehaviorTree, task_runtime_data: &TaskRuntimeData, stack_runtime_data: &StackRuntimeData, task: &dyn ITaskProxy, timestamp_in_mill: u64) {
+			self.records.borrow_mut().push(format!("end {}", task.id()));
+		}
+		fn action_post_on_start(&self, behavior_tree: &dyn IBehaviorTree, task_runtime_data: &TaskRuntimeData, stack_runtime_data: &StackRuntimeData, task: &dyn ITaskProxy, datas: &[Vec<u8>]) {}
+		fn action_post_on_update(&self, behavior_tree: &dyn IBehaviorTree, task_runtime_data: &TaskRuntimeData, stack_runtime_data: &StackRuntimeData, task: &dyn ITaskProxy, timestamp_in_mill: u64, status: TaskStatus, datas: &[Vec<u8>]) {}
+		fn action_post_on_end(&self, behavior_tree: &dyn IBehaviorTree, task_runtime_data: &TaskRuntimeData, stack_runtime_data: &StackRuntimeData, task: &dyn ITaskProxy, timestamp_in_mill: u64, datas: &[Vec<u8>]) {}
+		fn parallel_pre_on_start(&self, behavior_tree: &dyn IBehaviorTree, task_runtime_data: &TaskRuntimeData, stack_runtime_data: &StackRuntimeData, task: &dyn ITaskProxy) {}
+		fn parallel_post_on_end(&self, behavior_tree: &dyn IBehaviorTree, task_runtime_data: &TaskRuntimeData, stack_runtime_data: &StackRuntimeData, task: &dyn ITaskProxy, timestamp_in_mill: u64) {}
+		fn parallel_add_child_stack(&self, behavior_tree: &dyn IBehaviorTree, task_runtime_data: &TaskRuntimeData, stack_runtime_data: &StackRuntimeData, task: &dyn ITaskProxy, child_stack_runtime_data: &StackRuntimeData) {}
+		fn parallel_remove_child_stack(&self, behavior_tree: &dyn IBehaviorTree, task_runtime_data: &TaskRuntimeData, stack_runtime_data: &StackRuntimeData, task: &dyn ITaskProxy, child_stack_runtime_data: &StackRuntimeData, timestamp_in_mill: u64) {}
+	}
+
+	fn new_behavior_tree(config:&Vec<u8>, parser:&Rc<RefCell<Box<dyn IParser>>>, clock:&Rc<RefCell<Box<dyn IClock>>>, records:&Rc<RefCell<Vec<String>>>) -> Rc<RefCell<Box<dyn IBehaviorTree>>> {
+		let runtime_event_handle = Box::new(RecordRuntimeEventHandle{records:records.clone()});
+		BehaviorTree::new(0, config, 0, &Rc::downgrade(clock), runtime_event_handle, Rc::downgrade(parser))
+	}
+
+	//	需要检查内部状态的测试直接用BehaviorTree
+	fn create_behavior_tree(config:&Vec<u8>, parser:&Rc<RefCell<Box<dyn IParser>>>, clock:&Rc<RefCell<Box<dyn IClock>>>, records:&Rc<RefCell<Vec<String>>>) -> BehaviorTree {
+		let runtime_event_handle:Box<dyn IRuntimeEventHandle> = Box::new(RecordRuntimeEventHandle{records:records.clone()});
+		BehaviorTree::create(0, config.clone(), 0, Rc::downgrade(clock), runtime_event_handle, Rc::downgrade(parser), None)
+	}
+
+	fn task_json(corresponding_type:&str, id:i32, children:Vec<serde_json::Value>) -> serde_json::Value {
+		json!({
+			"Type": format!("BehaviorDesigner.Runtime.Tasks.{}", corresponding_type),
+			"ID": id,
+			"Children": children,
+		})
+	}
+
+	fn tree_json(root_task:serde_json::Value) -> Vec<u8> {
+		json!({"RootTask": root_task}).to_string().into_bytes()
+	}
+
+	fn new_clock() -> Rc<RefCell<Box<dyn IClock>>> {
+		Rc::new(RefCell::new(Box::new(DummyClock)))
+	}
+
+	#[test]
+	fn test_entry_root_runs_root_task() {
+		let config = tree_json(task_json("Idle", 1, vec![]));
+		let parser = JsonParser::new();
+		let clock = new_clock();
+		let records = Rc::new(RefCell::new(Vec::new()));
+		let behavior_tree = new_behavior_tree(&config, &parser, &clock, &records);
+
+		behavior_tree.borrow_mut().enable().unwrap();
+		behavior_tree.borrow_mut().update();
+		assert!(records.borrow().contains(&"start 1".to_string()));
+		assert!(behavior_tree.borrow().is_runnning());
+	}
+
+	#[test]
+	fn test_finished_task_pops_while_running() {
+		let config = tree_json(task_json("Sequence", 1, vec![
+			task_json("Role.MainRole.NeedFollowJoystick", 2, vec![]),
+			task_json("Idle", 3, vec![]),
+		]));
+		let parser = JsonParser::new();
+		let clock = new_clock();
+		let records = Rc::new(RefCell::new(Vec::new()));
+		let mut behavior_tree = create_behavior_tree(&config, &parser, &clock, &records);
+
+		behavior_tree.enable().unwrap();
+		behavior_tree.update();
+		assert_eq!(records.borrow()[1..], ["start 1", "start 2", "end 2", "start 3"]);
+
+		//	成功的条件在同一帧出栈，之后每帧都停在Idle上
+		for _ in 0..3{
+			assert!(behavior_tree.is_runnning());
+			assert_eq!(behavior_tree.active_stack[0].stack().unwrap().peak(), 3);
+			behavior_tree.update();
+		}
+		assert_eq!(records.borrow().len(), 5);
+	}
+
+	#[test]
+	fn test_decorator_runs_its_child() {
+		let config = tree_json(task_json("ReturnSuccess", 1, vec![task_json("Idle", 2, vec![])]));
+		let parser = JsonParser::new();
+		let clock = new_clock();
+		let records = Rc::new(RefCell::new(Vec::new()));
+		let mut behavior_tree = create_behavior_tree(&config, &parser, &clock, &records);
+
+		//	装饰器也要能取到当前子任务
+		behavior_tree.enable().unwrap();
+		behavior_tree.update();
+		assert_eq!(records.borrow()[1..], ["start 1", "start 2"]);
+		assert_eq!(behavior_tree.active_stack[0].stack().unwrap().peak(), 2);
+	}
+
+	#[test]
+	fn test_non_instant_task_pops_on_next_update() {
+		let mut condition = task_json("Role.MainRole.NeedFollowJoystick", 2, vec![]);
+		condition["Instant"] = json!(false);
+		let config = tree_json(task_json("Sequence", 1, vec![condition, task_json("Idle", 3, vec![])]));
+		let parser = JsonParser::new();
+		let clock = new_clock();
+		let records = Rc::new(RefCell::new(Vec::new()));
+		let behavior_tree = new_behavior_tree(&config, &parser, &clock, &records);
+
+		behavior_tree.borrow_mut().enable().unwrap();
+		behavior_tree.borrow_mut().update();
+		assert_eq!(records.borrow().last().unwrap(), "start 2");
+
+		//	非立即任务的结果等到下一帧才出栈
+		records.borrow_mut().clear();
+		behavior_tree.borrow_mut().update();
+		assert_eq!(*records.borrow(), ["end 2", "start 3"]);
+	}
+
+	#[test]
+	fn test_pop_children_of_parallel_task() {
+		let config = tree_json(task_json("ParallelSelector", 1, vec![
+			task_json("Idle", 2, vec![]),
+			task_json("Role.MainRole.NeedFollowJoystick", 3, vec![]),
+		]));
+		let parser = JsonParser::new();
+		let clock = new_clock();
+		let records = Rc::new(RefCell::new(Vec::new()));
+		let behavior_tree = new_behavior_tree(&config, &parser, &clock, &records);
+
+		behavior_tree.borrow_mut().enable().unwrap();
+		behavior_tree.borrow_mut().update();
+		behavior_tree.borrow_mut().update();
+		//	条件成功后并发选择结束，还在子栈里运行的Idle要跟着出栈
+		let records = records.borrow();
+		let end_idle = records.iter().position(|record| record == "end 2").unwrap();
+		let end_parallel = records.iter().position(|record| record == "end 1").unwrap();
+		assert!(end_idle < end_parallel);
+		assert_eq!(records.last().unwrap(), "complete");
+		assert!(!behavior_tree.borrow().is_runnning());
+	}
+
+	#[test]
+	fn test_tree_completes_when_root_task_finishes() {
+		let config = tree_json(task_json("Sequence", 1, vec![task_json("Role.MainRole.NeedFollowJoystick", 2, vec![])]));
+		let parser = JsonParser::new();
+		let clock = new_clock();
+		let records = Rc::new(RefCell::new(Vec::new()));
+		let behavior_tree = new_behavior_tree(&config, &parser, &clock, &records);
+
+		behavior_tree.borrow_mut().enable().unwrap();
+		behavior_tree.borrow_mut().update();
+		assert_eq!(records.borrow().last().unwrap(), "complete");
+		assert!(!behavior_tree.borrow().is_runnning());
+	}
+
+	#[test]
+	fn test_completed_tree_runs_again_after_enable() {
+		let config = tree_json(task_json("Sequence", 1, vec![task_json("Role.MainRole.NeedFollowJoystick", 2, vec![])]));
+		let parser = JsonParser::new();
+		let clock = new_clock();
+		let records = Rc::new(RefCell::new(Vec::new()));
+		let mut behavior_tree = create_behavior_tree(&config, &parser, &clock, &records);
+
+		behavior_tree.enable().unwrap();
+		behavior_tree.update();
+		assert!(!behavior_tree.is_runnning());
+		//	结束的时候执行栈和任务数据都清掉了
+		assert!(behavior_tree.active_stack.is_empty());
+		assert!(behavior_tree.task_datas.is_empty());
+		assert!(behavior_tree.stack_id_to_stack_data.is_empty());
+
+		//	结束以后重新enable，从根任务再跑一遍
+		let first_run = records.borrow().clone();
+		records.borrow_mut().clear();
+		behavior_tree.enable().unwrap();
+		behavior_tree.update();
+		assert_eq!(*records.borrow(), first_run);
+	}
+
+	#[test]
+	fn test_consumed_prebuilt_root_reports_clear_error() {
+		let config = tree_json(task_json("Sequence", 1, vec![task_json("Idle", 2, vec![])]));
+		let parser = JsonParser::new();
+		let clock = new_clock();
+		let records = Rc::new(RefCell::new(Vec::new()));
+		let root_task = parser.borrow().deserialize(&config, &mut TaskAddData::new()).unwrap();
+		let runtime_event_handle:Box<dyn IRuntimeEventHandle> = Box::new(RecordRuntimeEventHandle{records:records.clone()});
+		let mut behavior_tree:BehaviorTree = BehaviorTree::create(0, Vec::new(), 0, Rc::downgrade(&clock), runtime_event_handle, Weak::new(), Some(root_task));
+
+		behavior_tree.enable().unwrap();
+		behavior_tree.update();
+		behavior_tree.disable().unwrap();
+		behavior_tree.enable().unwrap();
+		behavior_tree.disable().unwrap();
+
+		//	手动拼出ID重复的根任务，根任务取出来以后初始化失败，再次初始化不会去找不存在的解析器
+		let mut root_task = parser.borrow().deserialize(&config, &mut TaskAddData::new()).unwrap();
+		root_task.add_child(parser.borrow().deserialize(&tree_json(task_json("Idle", 2, vec![])), &mut TaskAddData::new()).unwrap());
+		let runtime_event_handle:Box<dyn IRuntimeEventHandle> = Box::new(RecordRuntimeEventHandle{records:records.clone()});
+		let mut behavior_tree:BehaviorTree = BehaviorTree::create(0, Vec::new(), 0, Rc::downgrade(&clock), runtime_event_handle, Weak::new(), Some(root_task));
+		let err = behavior_tree.enable().unwrap_err();
+		assert!(err.to_string().contains("task id 2 is used more than once"));
+		let err = behavior_tree.enable().unwrap_err();
+		assert!(err.to_string().contains("prebuilt root already consumed"));
+	}
+
+	//	Sequence 2失败后Parallel 6在运行，Sequence 2下面的条件还要按LowerPriority继续重新评估
+	fn lower_priority_abort_tree() -> Vec<u8> {
+		let mut sequence = task_json("Sequence", 2, vec![
+			task_json("Role.MainRole.NeedFollowJoystick", 3, vec![]),
+			task_json("ReturnFailure", 4, vec![task_json("Role.MainRole.NeedFollowJoystick", 5, vec![])]),
+		]);
+		sequence["BehaviorDesigner.Runtime.Tasks.AbortType,abortType"] = json!("LowerPriority");
+		tree_json(task_json("Selector", 1, vec![
+			sequence,
+			task_json("Parallel", 6, vec![task_json("Idle", 7, vec![]), task_json("Idle", 8, vec![])]),
+		]))
+	}
+
+	#[test]
+	fn test_lower_priority_composite_keeps_conditional_reevaluate() {
+		let parser = JsonParser::new();
+		let clock = new_clock();
+		let records = Rc::new(RefCell::new(Vec::new()));
+		let mut behavior_tree = create_behavior_tree(&lower_priority_abort_tree(), &parser, &clock, &records);
+
+		behavior_tree.enable().unwrap();
+		behavior_tree.update();
+		assert!(records.borrow().contains(&"start 7".to_string()));
+		let conditional_reevaluate:Vec<(i32, i32)> = behavior_tree.conditional_reevaluate.iter().map(|item| (item.index, item.composite_index)).collect();
+		assert_eq!(conditional_reevaluate, [(3, 1), (5, 1)]);
+	}
+
+	#[test]
+	fn test_conditional_abort_restarts_lower_priority_branch() {
+		let parser = JsonParser::new();
+		let clock = new_clock();
+		let records = Rc::new(RefCell::new(Vec::new()));
+		let mut behavior_tree = create_behavior_tree(&lower_priority_abort_tree(), &parser, &clock, &records);
+
+		behavior_tree.enable().unwrap();
+		behavior_tree.update();
+
+		//	NeedFollowJoystick总是成功，把记录的结果改成失败来模拟条件变化
+		behavior_tree.conditional_reevaluate.iter_mut().find(|item| item.index == 3).unwrap().task_status = TaskStatus::Failure;
+		records.borrow_mut().clear();
+		behavior_tree.update();
+
+		let records = records.borrow();
+		for task_id in [6, 7, 8]{
+			assert!(records.contains(&format!("end {}", task_id)));
+		}
+		assert_eq!(records.iter().filter(|record| *record == "start 3").count(), 1);
+		assert!(records.contains(&"start 7".to_string()));
+		assert!(behavior_tree.is_runnning());
+	}
+
+	#[test]
+	fn test_behavior_tree_interrupt_task() {
+		let file_bytes = std::fs::read("src/behavior_tree/test_behaviortree.json").unwrap();
+		let parser = JsonParser::new();
+		let clock:Rc<RefCell<Box<dyn IClock>>> = Rc::new(RefCell::new(Box::new(DummyClock)));
+		let records = Rc::new(RefCell::new(Vec::new()));
+		let behavior_tree = new_behavior_tree(&file_bytes, &parser, &clock, &records);
+		let mut behavior_tree = behavior_tree.borrow_mut();
+
+		behavior_tree.enable().unwrap();
+		behavior_tree.update();
+		assert!(records.borrow().contains(&"start 5".to_string()));
+		assert!(records.borrow().contains(&"start 6".to_string()));
+		records.borrow_mut().clear();
+
+		//	打断Parallel，两个并发子任务也要跟着结束
+		behavior_tree.interrupt(4, TaskStatus::Failure, true).unwrap();
+		assert_eq!(*records.borrow(), vec!["end 6", "end 5", "end 4"]);
+		assert!(behavior_tree.interrupt(4, TaskStatus::Failure, true).is_err());
+		records.borrow_mut().clear();
+
+		//	If失败以后Selector继续执行Sequence分支
+		behavior_tree.update();
+		assert!(records.borrow().contains(&"start 8".to_string()));
+		assert!(behavior_tree.is_runnning());
+
+		//	打断整棵树
+		behavior_tree.interrupt(0, TaskStatus::Success, true).unwrap();
+		assert!(!behavior_tree.is_runnning());
+		assert_eq!(records.borrow().last().unwrap(), "complete");
+	}
+
+	#[test]
+	fn test_behavior_tree_perform_interruption() {
+		let tree_json = json!({
+			"RootTask": {
+				"Type": "BehaviorDesigner.Runtime.Tasks.Sequence",
+				"ID": 1,
+				"Children": [{
+					"Type": "BehaviorDesigner.Runtime.Tasks.Parallel",
+					"ID": 2,
+					"Children": [{
+						"Type": "BehaviorDesigner.Runtime.Tasks.Interrupt",
+						"ID": 3,
+						"Children": [{
+							"Type": "BehaviorDesigner.Runtime.Tasks.Idle",
+							"ID": 4
+						}]
+					},{
+						"Type": "BehaviorDesigner.Runtime.Tasks.PerformInterruption",
+						"ID": 5,
+						"BehaviorDesigner.Runtime.Tasks.Interrupt[],interruptTasks": [3],
+						"Boolean,interruptSuccess": true
+					}]
+				},{
+					"Type": "BehaviorDesigner.Runtime.Tasks.Idle",
+					"ID": 6
+				}]
+			}
+		});
+		let tree_bytes = tree_json.to_string().as_bytes().to_vec();
+
+		let parser = JsonParser::new();
+		let clock:Rc<RefCell<Box<dyn IClock>>> = Rc::new(RefCell::new(Box::new(DummyClock)));
+		let records = Rc::new(RefCell::new(Vec::new()));
+		let behavior_tree = new_behavior_tree(&tree_bytes, &parser, &clock, &records);
+		let mut behavior_tree = behavior_tree.borrow_mut();
+
+		behavior_tree.enable().unwrap();
+		assert_eq!(behavior_tree.task_id_by_config_id(3), Some(3));
+		assert_eq!(behavior_tree.task_id_by_config_id(99), None);
+		behavior_tree.update();
+		//	打断请求在本帧结束的时候处理，Idle被打断，Interrupt本身还在栈上
+		assert!(records.borrow().ends_with(&["end 4".to_string()]));
+		records.borrow_mut().clear();
+
+		behavior_tree.update();
+		assert_eq!(*records.borrow(), vec!["end 3", "end 2", "start 6"]);
+		assert!(behavior_tree.is_runnning());
+	}
+
+	#[test]
+	fn test_behavior_tree_snapshot_restore() {
+		let file_bytes = std::fs::read("src/behavior_tree/test_behaviortree.json").unwrap();
+		let parser = JsonParser::new();
+		let clock:Rc<RefCell<Box<dyn IClock>>> = Rc::new(RefCell::new(Box::new(DummyClock)));
+
+		let records = Rc::new(RefCell::new(Vec::new()));
+		let behavior_tree = new_behavior_tree(&file_bytes, &parser, &clock, &records);
+		let mut behavior_tree = behavior_tree.borrow_mut();
+		behavior_tree.enable().unwrap();
+		behavior_tree.update();
+
+		let snapshot = behavior_tree.snapshot().unwrap();
+		let snapshot_bytes = serde_json::to_vec(&snapshot).unwrap();
+		let snapshot:BehaviorTreeSnapshot = serde_json::from_slice(&snapshot_bytes).unwrap();
+
+		let restored_records = Rc::new(RefCell::new(Vec::new()));
+		let restored_behavior_tree = new_behavior_tree(&file_bytes, &parser, &clock, &restored_records);
+		let mut restored_behavior_tree = restored_behavior_tree.borrow_mut();
+		restored_behavior_tree.restore(&snapshot).unwrap();
+		assert!(restored_behavior_tree.is_runnning());
+		assert_eq!(serde_json::to_vec(&restored_behavior_tree.snapshot().unwrap()).unwrap(), snapshot_bytes);
+
+		//	恢复以后两棵树的后续执行要完全一致
+		records.borrow_mut().clear();
+		behavior_tree.interrupt(4, TaskStatus::Failure, true).unwrap();
+		behavior_tree.update();
+		restored_behavior_tree.interrupt(4, TaskStatus::Failure, true).unwrap();
+		restored_behavior_tree.update();
+		assert_eq!(*records.borrow(), *restored_records.borrow());
+		assert_eq!(serde_json::to_vec(&behavior_tree.snapshot().unwrap()).unwrap(), serde_json::to_vec(&restored_behavior_tree.snapshot().unwrap()).unwrap());
+
+		assert!(restored_behavior_tree.restore(&snapshot).is_err());
+	}
+
+	#[test]
+	fn test_behavior_tree_restore_corrupt_snapshot() {
+		let file_bytes = std::fs::read("src/behavior_tree/test_behaviortree.json").unwrap();
+		let parser = JsonParser::new();
+		let clock:Rc<RefCell<Box<dyn IClock>>> = Rc::new(RefCell::new(Box::new(DummyClock)));
+
+		let records = Rc::new(RefCell::new(Vec::new()));
+		let behavior_tree = new_behavior_tree(&file_bytes, &parser, &clock, &records);
+		let mut behavior_tree = behavior_tree.borrow_mut();
+		behavior_tree.enable().unwrap();
+		behavior_tree.update();
+		let snapshot = behavior_tree.snapshot().unwrap();
+		behavior_tree.disable().unwrap();
+		let stopped_snapshot = serde_json::to_vec(&behavior_tree.snapshot().unwrap()).unwrap();
+
+		let corrupt_snapshots:[fn(&mut BehaviorTreeSnapshot); 10] = [
+			|snapshot| snapshot.active_stack[0].stack.push(100),
+			|snapshot| snapshot.active_stack[1].stack_id = 100,
+			|snapshot| snapshot.stack_datas[0].stack_id = 100,
+			|snapshot| snapshot.task_datas[0].active_stack_id = 100,
+			|snapshot| snapshot.conditional_reevaluate[0].composite_index = 100,
+			|snapshot| snapshot.conditional_reevaluate[0].index = -2,
+			|snapshot| snapshot.stack_id_to_parallel_task_id[0].0 = 100,
+			|snapshot| snapshot.parallel_task_id_to_stack_ids[0].1.push(100),
+			|snapshot| snapshot.task_states[0].task_id = 100,
+			//	索引都合法，但是最后一个任务的状态反序列化失败
+			|snapshot| snapshot.task_states.last_mut().unwrap().state = json!("corrupt"),
+		];
+		for corrupt_snapshot in corrupt_snapshots.iter(){
+			let mut snapshot = snapshot.clone();
+			corrupt_snapshot(&mut snapshot);
+			records.borrow_mut().clear();
+			assert!(behavior_tree.restore(&snapshot).is_err());
+
+			//	失败的restore不能改动树的状态
+			assert!(!behavior_tree.is_runnning());
+			assert!(records.borrow().is_empty());
+			assert_eq!(serde_json::to_vec(&behavior_tree.snapshot().unwrap()).unwrap(), stopped_snapshot);
+		}
+
+		behavior_tree.restore(&snapshot).unwrap();
+		assert!(behavior_tree.is_runnning());
+	}
+
+	#[test]
+	fn test_sync_update_policy() {
+		let datas = |value:u8| vec![vec![value]];
+
+		let mut state = SyncUpdateState::new(0);
+		assert_eq!(state.filter(SyncUpdatePolicy::EveryUpdate, 0, &TaskStatus::Running, &[]).map(<[Vec<u8>]>::to_vec), Some(Vec::new()));
+		assert_eq!(state.filter(SyncUpdatePolicy::StartEndOnly, 0, &TaskStatus::Success, &datas(1)).map(<[Vec<u8>]>::to_vec), None);
+
+		let mut state = SyncUpdateState::new(0);
+		assert_eq!(state.filter(SyncUpdatePolicy::OnChange, 10, &TaskStatus::Running, &[]).map(<[Vec<u8>]>::to_vec), None);
+		assert_eq!(state.filter(SyncUpdatePolicy::OnChange, 20, &TaskStatus::Running, &datas(1)).map(<[Vec<u8>]>::to_vec), Some(datas(1)));
+		assert_eq!(state.filter(SyncUpdatePolicy::OnChange, 30, &TaskStatus::Running, &datas(1)).map(<[Vec<u8>]>::to_vec), None);
+		assert_eq!(state.filter(SyncUpdatePolicy::OnChange, 40, &TaskStatus::Success, &datas(1)).map(<[Vec<u8>]>::to_vec), Some(datas(1)));
+
+		//	间隔内的数据留到下一次同步
+		let mut state = SyncUpdateState::new(100);
+		assert_eq!(state.filter(SyncUpdatePolicy::Interval(50), 120, &TaskStatus::Running, &datas(1)).map(<[Vec<u8>]>::to_vec), None);
+		assert_eq!(state.filter(SyncUpdatePolicy::Interval(50), 140, &TaskStatus::Running, &[]).map(<[Vec<u8>]>::to_vec), None);
+		assert_eq!(state.filter(SyncUpdatePolicy::Interval(50), 150, &TaskStatus::Running, &[]).map(<[Vec<u8>]>::to_vec), Some(datas(1)));
+		assert_eq!(state.filter(SyncUpdatePolicy::Interval(50), 160, &TaskStatus::Running, &datas(2)).map(<[Vec<u8>]>::to_vec), None);
+		assert_eq!(state.filter(SyncUpdatePolicy::Interval(50), 170, &TaskStatus::Failure, &[]).map(<[Vec<u8>]>::to_vec), Some(datas(2)));
+
+		//	间隔很大的时候不能溢出
+		let mut state = SyncUpdateState::new(100);
+		assert_eq!(state.filter(SyncUpdatePolicy::Interval(u64::MAX), 120, &TaskStatus::Running, &datas(1)).map(<[Vec<u8>]>::to_vec), None);
+		assert_eq!(state.filter(SyncUpdatePolicy::Interval(u64::MAX), 130, &TaskStatus::Success, &[]).map(<[Vec<u8>]>::to_vec), Some(datas(1)));
+	}
+
+	#[test]
+	fn test_start_end_only_action_skips_update_sync() {
+		use super::super::trace::{Trace, TraceEventKind, TraceRuntimeEventHandle};
+
+		let file_bytes = std::fs::read("src/behavior_tree/test_behaviortree.json").unwrap();
+		let parser = JsonParser::new();
+		let clock:Rc<RefCell<Box<dyn IClock>>> = Rc::new(RefCell::new(Box::new(DummyClock)));
+		let trace = Rc::new(RefCell::new(Trace::new()));
+		let behavior_tree = BehaviorTree::new(0, &file_bytes, 0, &Rc::downgrade(&clock), Box::new(TraceRuntimeEventHandle::new(trace.clone())), Rc::downgrade(&parser));
+		let mut behavior_tree = behavior_tree.borrow_mut();
+		behavior_tree.enable().unwrap();
+		for _ in 0..3{
+			behavior_tree.update();
+		}
+
+		//	PlayAniForSync每帧都在update，但只同步了开始
+		let trace = trace.borrow();
+		let sync_task_ids:Vec<Option<i32>> = trace.of_kind(TraceEventKind::ActionPostOnStart).map(|event| event.task_id).collect();
+		assert!(!sync_task_ids.is_empty());
+		assert!(trace.of_kind(TraceEventKind::PostOnUpdate).filter(|event| sync_task_ids.contains(&event.task_id)).count() >= 3);
+		assert_eq!(trace.of_kind(TraceEventKind::ActionPostOnUpdate).count(), 0);
+	}
+
+	//	回调里通过&dyn IBehaviorTree重新进入正在update的树
+	struct ReentrantRuntimeEventHandle{
+		records:Rc<RefCell<Vec<String>>>,
+	}
+
+	struct CountStackCollector{
+		stacks:usize,
+	}
+
+	#[allow(unused_variables)]
+	impl IRebuildSyncDataCollector for CountStackCollector{
+		fn stack(&mut self, behavior_tree: &dyn IBehaviorTree, data: &StackRuntimeData){
+			self.stacks += 1;
+		}
+		fn action(&mut self, behavior_tree:&dyn IBehaviorTree, task_runtime_data:&TaskRuntimeData, stack_runtime_data:&StackRuntimeData, task:&dyn ITaskProxy, datas:&[Vec<u8>]){}
+		fn parallel(&mut self, behavior_tree:&dyn IBehaviorTree, task_runtime_data:&TaskRuntimeData, stack_runtime_data:&StackRuntimeData, task:&dyn ITaskProxy, child_stack_runtime_datas:&Vec<StackRuntimeData>){}
+	}
+
+	#[allow(unused_variables)]
+	impl IRuntimeEventHandle for ReentrantRuntimeEventHandle {
+		fn pre_on_start(&self, behavior_tree: &dyn IBehaviorTree, task_runtime_data: &TaskRuntimeData, stack_runtime_data: &StackRuntimeData, task: &dyn ITaskProxy) {
+			self.records.borrow_mut().push(format!("start {}", task.id()));
+			//	Parallel 1已经从task_list取出来了
+			let mut collector = CountStackCollector{stacks:0};
+			behavior_tree.rebuild_sync(&mut collector);
+			let snapshot = if behavior_tree.snapshot().is_ok() { "ok" } else { "err" };
+			self.records.borrow_mut().push(format!("snapshot {}", snapshot));
+			if task.id() == 4{
+				behavior_tree.request_interrupt(1, TaskStatus::Failure, false);
+			}
+		}
+		fn post_on_end(&self, behavior_tree: &dyn IBehaviorTree, task_runtime_data: &TaskRuntimeData, stack_runtime_data: &StackRuntimeData, task: &dyn ITaskProxy, timestamp_in_mill: u64) {
+			self.records.borrow_mut().push(format!("end {}", task.id()));
+			let mut collector = CountStackCollector{stacks:0};
+			behavior_tree.rebuild_sync(&mut collector);
+			let _ = behavior_tree.snapshot();
+		}
+	}
+
+	#[test]
+	fn test_callback_reenters_behavior_tree_during_update() {
+		let config = tree_json(task_json("Parallel", 1, vec![
+			task_json("Sequence", 2, vec![task_json("Idle", 3, vec![])]),
+			task_json("Idle", 4, vec![]),
+		]));
+		let parser = JsonParser::new();
+		let clock = new_clock();
+		let records = Rc::new(RefCell::new(Vec::new()));
+		let runtime_event_handle = Box::new(ReentrantRuntimeEventHandle{records:records.clone()});
+		let behavior_tree = BehaviorTree::new(0, &config, 0, &Rc::downgrade(&clock), runtime_event_handle, Rc::downgrade(&parser));
+
+		behavior_tree.borrow_mut().enable().unwrap();
+		behavior_tree.borrow_mut().update();
+
+		//	执行中的快照返回错误，请求的打断在update结束时处理
+		let records = records.borrow();
+		assert!(records.contains(&"start 4".to_string()));
+		assert!(records.iter().filter(|record| record.starts_with("snapshot")).all(|record| record == "snapshot err"));
+		let end_3 = records.iter().position(|record| record == "end 3").unwrap();
+		let end_4 = records.iter().position(|record| record == "end 4").unwrap();
+		let start_4 = records.iter().position(|record| record == "start 4").unwrap();
+		assert!(end_3 > start_4 && end_4 > start_4);
+		assert!(behavior_tree.borrow().snapshot().is_ok());
+	}
+
+	//	只统计打开了计数的线程，其它测试并行跑的时候不受影响
+	struct CountingAllocator;
+
+	thread_local!{
+		static COUNT_ALLOCATIONS:std::cell::Cell<bool> = const { std::cell::Cell::new(false) };
+		static ALLOCATIONS:std::cell::Cell<u64> = const { std::cell::Cell::new(0) };
+	}
+
+	fn record_allocation(){
+		if COUNT_ALLOCATIONS.try_with(|count| count.get()).unwrap_or(false){
+			let _ = ALLOCATIONS.try_with(|allocations| allocations.set(allocations.get() + 1));
+		}
+	}
+
+	unsafe impl std::alloc::GlobalAlloc for CountingAllocator{
+		unsafe fn alloc(&self, layout:std::alloc::Layout)->*mut u8{
+			record_allocation();
+			unsafe { std::alloc::System.alloc(layout) }
+		}
+
+		unsafe fn alloc_zeroed(&self, layout:std::alloc::Layout)->*mut u8{
+			record_allocation();
+			unsafe { std::alloc::System.alloc_zeroed(layout) }
+		}
+
+		unsafe fn realloc(&self, ptr:*mut u8, layout:std::alloc::Layout, new_size:usize)->*mut u8{
+			record_allocation();
+			unsafe { std::alloc::System.realloc(ptr, layout, new_size) }
+		}
+
+		unsafe fn dealloc(&self, ptr:*mut u8, layout:std::alloc::Layout){
+			unsafe { std::alloc::System.dealloc(ptr, layout) }
+		}
+	}
+
+	#[global_allocator]
+	static COUNTING_ALLOCATOR:CountingAllocator = CountingAllocator;
+
+	fn count_allocations(f:impl FnOnce())->u64{
+		ALLOCATIONS.with(|allocations| allocations.set(0));
+		COUNT_ALLOCATIONS.with(|count| count.set(true));
+		f();
+		COUNT_ALLOCATIONS.with(|count| count.set(false));
+		ALLOCATIONS.with(|allocations| allocations.get())
+	}
+
+	struct NoopRuntimeEventHandle;
+	impl IRuntimeEventHandle for NoopRuntimeEventHandle {}
+
+	#[test]
+	fn test_steady_state_update_does_not_allocate() {
+		use super::super::bench::{generate_tree, BenchTreeShape};
+
+		assert_eq!(count_allocations(|| { std::hint::black_box(Vec::<u8>::with_capacity(1)); }), 1);
+
+		let parser = JsonParser::new();
+		let clock:Rc<RefCell<Box<dyn IClock>>> = Rc::new(RefCell::new(Box::new(DummyClock)));
+		let configs = [
+			std::fs::read("src/behavior_tree/test_behaviortree.json").unwrap(),
+			generate_tree(BenchTreeShape::DeepSequence(16)),
+			generate_tree(BenchTreeShape::WideParallel(16)),
+			generate_tree(BenchTreeShape::ConditionalAborts(16)),
+		];
+		for config in configs.iter(){
+			let behavior_tree = BehaviorTree::new(0, config, 0, &Rc::downgrade(&clock), Box::new(NoopRuntimeEventHandle), Rc::downgrade(&parser));
+			let mut behavior_tree = behavior_tree.borrow_mut();
+			behavior_tree.enable().unwrap();
+			//	前几帧会创建执行栈和条件重新评估的记录
+			for _ in 0..3{
+				behavior_tree.update();
+			}
+
+			let allocations = count_allocations(||{
+				for _ in 0..100{
+					behavior_tree.update();
+				}
+			});
+			assert!(behavior_tree.is_runnning());
+			assert_eq!(allocations, 0);
+		}
+	}
+
+	//	开始的那一帧运行，下一帧成功结束
+	struct FinishAction{
+		updates:u32,
+	}
+	impl IAction for FinishAction{
+		fn on_start(&mut self, _task_proxy:&mut dyn ITaskProxy, _behavior_tree:&dyn IBehaviorTree){
+			self.updates = 0;
+		}
+
+		fn on_update(&mut self, _task_proxy:&mut dyn ITaskProxy, _behavior_tree:&dyn IBehaviorTree)->TaskStatus{
+			self.updates += 1;
+			if self.updates > 1 {TaskStatus::Success} else {TaskStatus::Running}
+		}
+	}
+
+	//	每次评估结果都反过来，第一次失败
+	struct ToggleConditional{
+		flag:bool,
+	}
+	impl IConditional for ToggleConditional{
+		fn on_update(&mut self, _task_proxy:&dyn ITaskProxy, _behavior_tree:&dyn IBehaviorTree)->TaskStatus{
+			self.flag = !self.flag;
+			if self.flag {TaskStatus::Failure} else {TaskStatus::Success}
+		}
+	}
+
+	//	统计任务的开始、结束和执行栈的创建，计数本身不分配内存
+	#[derive(Default)]
+	struct CountRuntimeEventHandle{
+		starts:Rc<std::cell::Cell<u64>>,
+		ends:Rc<std::cell::Cell<u64>>,
+		new_stacks:Rc<std::cell::Cell<u64>>,
+		aborted_starts:Rc<std::cell::Cell<u64>>,
+	}
+
+	#[allow(unused_variables)]
+	impl IRuntimeEventHandle for CountRuntimeEventHandle {
+		fn new_stack(&self, behavior_tree: &dyn IBehaviorTree, data: &StackRuntimeData) {
+			self.new_stacks.set(self.new_stacks.get() + 1);
+		}
+		fn pre_on_start(&self, behavior_tree: &dyn IBehaviorTree, task_runtime_data: &TaskRuntimeData, stack_runtime_data: &StackRuntimeData, task: &dyn ITaskProxy) {
+			self.starts.set(self.starts.get() + 1);
+			//	PlayAniForSync 11只有在条件打断以后才会重新开始
+			if task.id() == 11{
+				self.aborted_starts.set(self.aborted_starts.get() + 1);
+			}
+		}
+		fn post_on_end(&self, behavior_tree: &dyn IBehaviorTree, task_runtime_data: &TaskRuntimeData, stack_runtime_data: &StackRuntimeData, task: &dyn ITaskProxy, timestamp_in_mill: u64) {
+			self.ends.set(self.ends.get() + 1);
+		}
+	}
+
+	#[test]
+	fn test_cycling_update_does_not_allocate() {
+		use super::super::interface::{IAction, IConditional};
+		use super::super::registry::TaskRegistry;
+		use super::super::event_handle::MultiplexRuntimeEventHandle;
+		use super::super::wire::{SyncPacket, SyncMessage, SyncSequences, WireEncodeRuntimeEventHandle};
+
+		let registry = TaskRegistry::new();
+		registry.write().unwrap().register_action_fn("FinishAction", |_variables, _task_ids| -> Box<dyn IAction> {Box::new(FinishAction{updates:0})});
+		registry.write().unwrap().register_conditional_fn("ToggleConditional", |_variables, _task_ids| -> Box<dyn IConditional> {Box::new(ToggleConditional{flag:false})});
+		let parser = JsonParser::with_registry(registry);
+		let clock = new_clock();
+
+		let custom_json = |corresponding_type:&str, id:i32| json!({"Type": corresponding_type, "ID": id});
+		let mut abort_sequence = task_json("Sequence", 8, vec![custom_json("ToggleConditional", 9), task_json("Idle", 10, vec![])]);
+		abort_sequence["BehaviorDesigner.Runtime.Tasks.AbortType,abortType"] = json!("LowerPriority");
+		let mut play_ani = task_json("PlayAniForSync", 11, vec![]);
+		play_ani["String,AnimationName"] = json!("run");
+		let config = tree_json(task_json("Parallel", 1, vec![
+			//	一直循环的Sequence，子任务每帧都结束
+			task_json("UntilForever", 2, vec![task_json("Sequence", 3, vec![custom_json("FinishAction", 4), custom_json("FinishAction", 5)])]),
+			//	条件每帧都变化，LowerPriority打断反复触发，同步的动画跟着反复开始和结束
+			task_json("UntilForever", 6, vec![task_json("Selector", 7, vec![abort_sequence, play_ani])]),
+			//	每帧都重新开始的Parallel，子栈反复创建和删除
+			task_json("UntilForever", 12, vec![task_json("Parallel", 13, vec![custom_json("FinishAction", 14), custom_json("FinishAction", 15)])]),
+		]));
+
+		let runtime_event_handle = CountRuntimeEventHandle::default();
+		let (starts, ends, new_stacks, aborted_starts) = (runtime_event_handle.starts.clone(), runtime_event_handle.ends.clone(), runtime_event_handle.new_stacks.clone(), runtime_event_handle.aborted_starts.clone());
+		let messages = Rc::new(RefCell::new(Vec::new()));
+		let wire_event_handle = WireEncodeRuntimeEventHandle::new(messages.clone(), SyncSequences::new());
+		let runtime_event_handle = MultiplexRuntimeEventHandle::new(vec![Box::new(runtime_event_handle), Box::new(wire_event_handle)]);
+		let behavior_tree = BehaviorTree::new(0, &config, 0, &Rc::downgrade(&clock), Box::new(runtime_event_handle), Rc::downgrade(&parser));
+		let mut behavior_tree = behavior_tree.borrow_mut();
+		behavior_tree.enable().unwrap();
+		for _ in 0..10{
+			behavior_tree.update();
+			messages.borrow_mut().clear();
+		}
+
+		let (starts_before, ends_before, new_stacks_before, aborted_starts_before) = (starts.get(), ends.get(), new_stacks.get(), aborted_starts.get());
+		//	发出去的消息先挪到这里，统计完再解码
+		let mut sent = Vec::with_capacity(10000);
+		let allocations = count_allocations(||{
+			for _ in 0..100{
+				behavior_tree.update();
+				sent.extend(messages.borrow_mut().drain(..));
+			}
+		});
+		let animation_starts = sent.iter().filter(|bytes| matches!(&SyncPacket::decode(bytes).unwrap().message, SyncMessage::ActionStart{datas, ..} if datas.len() == 1)).count();
+		assert!(behavior_tree.is_runnning());
+		//	确实一直有任务在开始和结束
+		assert!(starts.get() - starts_before >= 100);
+		assert!(ends.get() - ends_before >= 100);
+		assert!(new_stacks.get() - new_stacks_before >= 100);
+		assert!(aborted_starts.get() - aborted_starts_before >= 10);
+		assert!(animation_starts >= 10);
+		//	只有发出去的消息本身要分配
+		assert_eq!(allocations, sent.len() as u64);
 	}
 }
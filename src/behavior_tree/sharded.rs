@@ -0,0 +1,401 @@
+use std::{collections::{BTreeMap, HashMap}, sync::{Arc, Mutex, mpsc, atomic::{AtomicU64, Ordering}}, thread, time::Instant, panic::{self, AssertUnwindSafe}};
+
+use super::interface::{IBehaviorTree, IClock, IParser, IRuntimeEventHandle, TaskAddData};
+use super::json_parser::JsonParser;
+use super::manager::FrameStats;
+use super::registry::TaskRegistry;
+use super::runtime::SendBehaviorTree;
+use super::wire::{SyncSequences, SendWireEncodeRuntimeEventHandle};
+
+//	(tree_id, unit_id, 分片的发件箱, 分片的同步序号)，写进发件箱的消息update以后按顺序带回主线程
+pub type ShardEventHandleFactory = Arc<dyn Fn(u64, u64, Arc<Mutex<Vec<Vec<u8>>>>, Arc<Mutex<SyncSequences>>)->Box<dyn IRuntimeEventHandle + Send> + Send + Sync>;
+
+//	所有工作线程共用一个解析器，注册了自定义节点的项目传自己的
+pub fn default_shard_parser()->Arc<dyn IParser + Send + Sync>{
+	Arc::new(JsonParser::from_registry(TaskRegistry::new()))
+}
+
+//	跟单线程一样直接编码成SyncPacket
+pub fn wire_shard_event_handle_factory()->ShardEventHandleFactory{
+	Arc::new(|_, _, outbox, sequences| Box::new(SendWireEncodeRuntimeEventHandle::new(outbox, sequences)))
+}
+
+//	一个分片一帧的结果，messages里同一棵树的消息保持产生的顺序
+#[derive(Clone, Default, Debug)]
+pub struct ShardFrame{
+	pub shard:usize,
+	pub messages:Vec<Vec<u8>>,
+	pub stats:FrameStats,
+}
+
+struct ShardClock{
+	now:Arc<AtomicU64>,
+}
+
+impl IClock for ShardClock{
+	fn timestamp_in_mill(&self)->u64{
+		//	主线程设置完时间才把分片发给工作线程，通道保证了先后顺序
+		self.now.load(Ordering::Relaxed)
+	}
+}
+
+//	分片拥有自己的树，update的时候整个分片移动到空闲的工作线程
+struct Shard{
+	index:usize,
+	trees:BTreeMap<u64, SendBehaviorTree>,
+	outbox:Arc<Mutex<Vec<Vec<u8>>>>,
+	sequences:Arc<Mutex<SyncSequences>>,
+}
+
+impl Shard{
+	fn new(index:usize)->Self{
+		Self{
+			index,
+			trees:BTreeMap::new(),
+			outbox:Arc::new(Mutex::new(Vec::new())),
+			sequences:Arc::new(Mutex::new(SyncSequences::default())),
+		}
+	}
+
+	fn tree_mut(&mut self, tree_id:u64)->Result<&mut SendBehaviorTree, Box<dyn std::error::Error>>{
+		self.trees.get_mut(&tree_id).ok_or_else(|| format!("unknown behavior tree {}", tree_id).into())
+	}
+
+	fn update(&mut self)->ShardFrame{
+		let start = Instant::now();
+		let mut ticked = 0;
+		for behavior_tree in self.trees.values_mut(){
+			if behavior_tree.is_runnning(){
+				behavior_tree.update();
+				ticked += 1;
+			}
+		}
+		ShardFrame{
+			shard:self.index,
+			messages:std::mem::take(&mut *self.outbox.lock().unwrap()),
+			stats:FrameStats{
+				due:self.trees.len(),
+				ticked,
+				deferred:0,
+				elapsed:start.elapsed(),
+			},
+		}
+	}
+}
+
+//	分片的树执行中panic了，树的状态已经不完整，整个分片丢掉
+type ShardResult = Result<(Shard, ShardFrame), usize>;
+
+fn run_worker(jobs:Arc<Mutex<mpsc::Receiver<Shard>>>, results:mpsc::Sender<ShardResult>){
+	loop{
+		//	只在取分片的时候持有锁，update的时候别的线程可以取别的分片
+		let shard = match jobs.lock(){
+			Ok(jobs) => jobs.recv(),
+			Err(_) => break,
+		};
+		//	主线程关掉了任务通道
+		let Ok(mut shard) = shard else {
+			break;
+		};
+		let index = shard.index;
+		let result = match panic::catch_unwind(AssertUnwindSafe(|| shard.update())){
+			Ok(frame) => Ok((shard, frame)),
+			Err(_) => Err(index),
+		};
+		if results.send(result).is_err(){
+			break;
+		}
+	}
+}
+
+//	SendBehaviorTree可以跨线程移动，树归分片所有，分片不固定在某个线程上
+//	每帧把所有分片交给工作线程池，哪个线程空闲就update哪个分片，update完连同消息一起还回来
+//	同一个单位的树在同一个分片，每棵树自己还是单线程执行，事件顺序不变
+//	enable/disable/remove/spawn在主线程直接调用树，产生的消息在下一次update时一起带回
+pub struct ShardedBehaviorTreeManager{
+	shards:Vec<Shard>,
+	jobs:Option<mpsc::Sender<Shard>>,
+	results:mpsc::Receiver<ShardResult>,
+	workers:Vec<thread::JoinHandle<()>>,
+	now:Arc<AtomicU64>,
+	clock:Arc<dyn IClock + Send + Sync>,
+	parser:Arc<dyn IParser + Send + Sync>,
+	event_handle_factory:ShardEventHandleFactory,
+	templates:HashMap<String, Vec<u8>>,
+	trees:HashMap<u64, (u64, usize)>,
+}
+
+impl ShardedBehaviorTreeManager{
+	pub fn new(shard_count:usize, worker_count:usize, parser:Arc<dyn IParser + Send + Sync>, event_handle_factory:ShardEventHandleFactory)->Result<Self, Box<dyn std::error::Error>>{
+		if shard_count == 0{
+			return Err("shard count must be positive".into());
+		}
+		if worker_count == 0{
+			return Err("worker count must be positive".into());
+		}
+
+		let (job_sender, job_receiver) = mpsc::channel();
+		let (result_sender, result_receiver) = mpsc::channel();
+		let job_receiver = Arc::new(Mutex::new(job_receiver));
+		let mut workers = Vec::new();
+		for worker in 0..worker_count{
+			let jobs = job_receiver.clone();
+			let results = result_sender.clone();
+			workers.push(thread::Builder::new()
+				.name(format!("behavior-tree-worker-{}", worker))
+				.spawn(move || run_worker(jobs, results))?);
+		}
+
+		let now = Arc::new(AtomicU64::new(0));
+		Ok(Self{
+			shards:(0..shard_count).map(Shard::new).collect(),
+			jobs:Some(job_sender),
+			results:result_receiver,
+			workers,
+			clock:Arc::new(ShardClock{now:now.clone()}),
+			now,
+			parser,
+			event_handle_factory,
+			templates:HashMap::new(),
+			trees:HashMap::new(),
+		})
+	}
+
+	pub fn shard_count(&self)->usize{
+		self.shards.len()
+	}
+
+	pub fn worker_count(&self)->usize{
+		self.workers.len()
+	}
+
+	pub fn shard_of_unit(&self, unit_id:u64)->usize{
+		(unit_id % self.shards.len() as u64) as usize
+	}
+
+	pub fn shard_of_tree(&self, tree_id:u64)->Option<usize>{
+		self.trees.get(&tree_id).map(|(_, shard)| *shard)
+	}
+
+	pub fn tree_ids(&self)->Vec<u64>{
+		let mut tree_ids:Vec<u64> = self.trees.keys().cloned().collect();
+		tree_ids.sort();
+		tree_ids
+	}
+
+	pub fn len(&self)->usize{
+		self.trees.len()
+	}
+
+	pub fn is_empty(&self)->bool{
+		self.trees.is_empty()
+	}
+
+	pub fn register_template(&mut self, name:&str, config:Vec<u8>)->Result<(), Box<dyn std::error::Error>>{
+		if self.templates.contains_key(name){
+			return Err(format!("behavior tree template {} already registered", name).into());
+		}
+		self.parser.deserialize(&config, &mut TaskAddData::new())?;
+		self.templates.insert(name.to_string(), config);
+		Ok(())
+	}
+
+	pub fn spawn(&mut self, tree_id:u64, template:&str, unit_id:u64)->Result<(), Box<dyn std::error::Error>>{
+		if self.trees.contains_key(&tree_id){
+			return Err(format!("behavior tree {} already exists", tree_id).into());
+		}
+		let config = self.templates.get(template).ok_or_else(|| format!("unknown behavior tree template {}", template))?;
+		let shard = self.shard_of_unit(unit_id);
+		let runtime_event_handle = (self.event_handle_factory)(tree_id, unit_id, self.shards[shard].outbox.clone(), self.shards[shard].sequences.clone());
+		let behavior_tree = SendBehaviorTree::from_config(tree_id, config, unit_id, self.clock.clone(), runtime_event_handle, self.parser.clone());
+		self.shards[shard].trees.insert(tree_id, behavior_tree);
+		self.trees.insert(tree_id, (unit_id, shard));
+		Ok(())
+	}
+
+	fn tree_mut(&mut self, tree_id:u64)->Result<&mut SendBehaviorTree, Box<dyn std::error::Error>>{
+		let shard = self.shard_of_tree(tree_id).ok_or_else(|| format!("unknown behavior tree {}", tree_id))?;
+		self.shards[shard].tree_mut(tree_id)
+	}
+
+	pub fn enable(&mut self, tree_id:u64)->Result<(), Box<dyn std::error::Error>>{
+		self.tree_mut(tree_id)?.enable()
+	}
+
+	pub fn disable(&mut self, tree_id:u64)->Result<(), Box<dyn std::error::Error>>{
+		self.tree_mut(tree_id)?.disable()
+	}
+
+	//	还在运行的树会先disable，让客户端收到执行栈的移除
+	pub fn remove(&mut self, tree_id:u64)->Result<(), Box<dyn std::error::Error>>{
+		let (_, shard) = self.trees.remove(&tree_id).ok_or_else(|| format!("unknown behavior tree {}", tree_id))?;
+		let mut behavior_tree = self.shards[shard].trees.remove(&tree_id).ok_or_else(|| format!("unknown behavior tree {}", tree_id))?;
+		if behavior_tree.is_runnning(){
+			behavior_tree.disable()?;
+		}
+		Ok(())
+	}
+
+	pub fn trees_of_unit(&self, unit_id:u64)->Vec<u64>{
+		let mut tree_ids:Vec<u64> = self.trees.iter().filter(|(_, (tree_unit_id, _))| *tree_unit_id == unit_id).map(|(tree_id, _)| *tree_id).collect();
+		tree_ids.sort();
+		tree_ids
+	}
+
+	pub fn disable_unit(&mut self, unit_id:u64)->Result<(), Box<dyn std::error::Error>>{
+		let mut result = Ok(());
+		for tree_id in self.trees_of_unit(unit_id){
+			let tree_result = self.disable(tree_id);
+			if result.is_ok(){
+				result = tree_result;
+			}
+		}
+		result
+	}
+
+	pub fn remove_unit(&mut self, unit_id:u64)->Result<(), Box<dyn std::error::Error>>{
+		let mut result = Ok(());
+		for tree_id in self.trees_of_unit(unit_id){
+			let tree_result = self.remove(tree_id);
+			if result.is_ok(){
+				result = tree_result;
+			}
+		}
+		result
+	}
+
+	//	所有分片交给线程池并行update，结果按分片顺序返回，now是这一帧所有树看到的时间
+	//	有分片panic的时候其它分片照常还回来，panic的分片连同它的树一起丢掉，返回错误
+	pub fn update(&mut self, now:u64)->Result<Vec<ShardFrame>, Box<dyn std::error::Error>>{
+		self.now.store(now, Ordering::Relaxed);
+		let shard_count = self.shards.len();
+		let jobs = self.jobs.as_ref().ok_or("behavior tree workers stopped")?;
+		for shard in self.shards.drain(..){
+			//	工作线程都退出了，分片跟着通道一起丢了，只能重建
+			if jobs.send(shard).is_err(){
+				return Err("behavior tree workers stopped".into());
+			}
+		}
+
+		let mut shards = Vec::new();
+		let mut frames = Vec::new();
+		let mut panicked = Vec::new();
+		for _ in 0..shard_count{
+			match self.results.recv().map_err(|_| "behavior tree workers stopped")?{
+				Ok((shard, frame)) => {
+					shards.push(shard);
+					frames.push(frame);
+				},
+				Err(index) => {
+					panicked.push(index);
+					shards.push(Shard::new(index));
+				},
+			}
+		}
+		shards.sort_by_key(|shard| shard.index);
+		frames.sort_by_key(|frame| frame.shard);
+		self.shards = shards;
+
+		if !panicked.is_empty(){
+			self.trees.retain(|_, (_, shard)| !panicked.contains(shard));
+			return Err(format!("behavior tree shards {:?} panicked", panicked).into());
+		}
+		Ok(frames)
+	}
+}
+
+impl Drop for ShardedBehaviorTreeManager{
+	fn drop(&mut self){
+		//	关掉任务通道，工作线程退出循环
+		self.jobs = None;
+		for worker in self.workers.drain(..){
+			let _ = worker.join();
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::{rc::Rc, cell::RefCell};
+	use super::super::manager::BehaviorTreeManager;
+	use super::super::wire::WireEncodeRuntimeEventHandle;
+	use super::super::mirror::SyncMirror;
+	use super::super::wire::SyncPacket;
+
+	fn assert_send<T:Send>(){}
+
+	fn group_by_tree(messages:Vec<Vec<u8>>, groups:&mut BTreeMap<u64, Vec<Vec<u8>>>){
+		for bytes in messages{
+			let tree_id = SyncPacket::decode(&bytes).unwrap().message.tree_id();
+			groups.entry(tree_id).or_default().push(bytes);
+		}
+	}
+
+	#[test]
+	fn test_sharded_behavior_tree_manager() {
+		assert_send::<ShardedBehaviorTreeManager>();
+		assert_send::<SendBehaviorTree>();
+		assert!(ShardedBehaviorTreeManager::new(0, 2, default_shard_parser(), wire_shard_event_handle_factory()).is_err());
+		assert!(ShardedBehaviorTreeManager::new(3, 0, default_shard_parser(), wire_shard_event_handle_factory()).is_err());
+
+		let file_bytes = std::fs::read("src/behavior_tree/test_behaviortree.json").unwrap();
+		let mut sharded = ShardedBehaviorTreeManager::new(3, 2, default_shard_parser(), wire_shard_event_handle_factory()).unwrap();
+		assert_eq!(sharded.worker_count(), 2);
+		sharded.register_template("role", file_bytes.clone()).unwrap();
+		assert!(sharded.register_template("role", file_bytes.clone()).is_err());
+		assert!(sharded.spawn(100, "monster", 1).is_err());
+
+		//	单线程的manager作为对照
+		let parser = JsonParser::new();
+		let now = Arc::new(AtomicU64::new(0));
+		let clock:Rc<RefCell<Box<dyn IClock>>> = Rc::new(RefCell::new(Box::new(ShardClock{now:now.clone()})));
+		let manager = BehaviorTreeManager::new(&Rc::downgrade(&clock), Rc::downgrade(&parser));
+		let messages = Rc::new(RefCell::new(Vec::new()));
+		let sequences = SyncSequences::new();
+		manager.borrow_mut().register_template("role", file_bytes).unwrap();
+
+		for tree_id in 0..12{
+			let unit_id = tree_id / 2;
+			sharded.spawn(tree_id, "role", unit_id).unwrap();
+			sharded.enable(tree_id).unwrap();
+			manager.borrow_mut().spawn(tree_id, "role", unit_id, Box::new(WireEncodeRuntimeEventHandle::new(messages.clone(), sequences.clone()))).unwrap();
+			manager.borrow_mut().enable(tree_id).unwrap();
+		}
+		assert!(sharded.spawn(0, "role", 0).is_err());
+		assert_eq!(sharded.shard_of_tree(2), sharded.shard_of_tree(3));
+
+		let mut sharded_messages = BTreeMap::new();
+		let mut expected_messages = BTreeMap::new();
+		for frame in 0..3{
+			now.store(frame * 33, Ordering::Relaxed);
+			let frames = sharded.update(frame * 33).unwrap();
+			assert_eq!(frames.len(), 3);
+			assert_eq!(frames.iter().map(|frame| frame.stats.ticked).sum::<usize>(), 12);
+			for frame in frames{
+				group_by_tree(frame.messages, &mut sharded_messages);
+			}
+			BehaviorTreeManager::update(&manager);
+			group_by_tree(messages.borrow_mut().drain(..).collect(), &mut expected_messages);
+		}
+
+		sharded.disable_unit(1).unwrap();
+		sharded.remove_unit(2).unwrap();
+		manager.borrow_mut().disable_unit(1).unwrap();
+		manager.borrow_mut().remove_unit(2).unwrap();
+		assert_eq!(sharded.len(), 10);
+		for frame in sharded.update(100).unwrap(){
+			group_by_tree(frame.messages, &mut sharded_messages);
+		}
+		group_by_tree(messages.borrow_mut().drain(..).collect(), &mut expected_messages);
+
+		//	每棵树的消息跟单线程的完全一样
+		assert_eq!(sharded_messages, expected_messages);
+		let mut mirror = SyncMirror::new();
+		for bytes in sharded_messages.values().flatten(){
+			mirror.apply_bytes(bytes).unwrap();
+		}
+		assert!(mirror.take_resync_requests().is_empty());
+		assert_eq!(mirror.tree_ids(), vec![0, 1, 6, 7, 8, 9, 10, 11]);
+	}
+}
@@ -0,0 +1,67 @@
+use serde::{Serialize, Deserialize};
+
+use super::consts::TaskStatus;
+use super::interface::{StackRuntimeData, TaskRuntimeData};
+
+pub const SNAPSHOT_VERSION:u32 = 1;
+
+//	任务的状态就是任务结构体本身的时候，在IParentTask或者IConditional的impl里展开，整个结构体用serde保存和恢复
+//	反序列化失败的时候任务保持原来的状态
+macro_rules! serde_task_state {
+	() => {
+		fn save_state(&self, _task_proxy:&dyn $crate::behavior_tree::interface::ITaskProxy)->Option<serde_json::Value> {
+			serde_json::to_value(self).ok()
+		}
+
+		fn load_state(&mut self, state:&serde_json::Value, _task_proxy:&dyn $crate::behavior_tree::interface::ITaskProxy)->Result<(), Box<dyn std::error::Error>> {
+			*self = serde_json::from_value(state.clone())?;
+			Ok(())
+		}
+	};
+}
+pub(crate) use serde_task_state;
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct RunningStackSnapshot{
+	pub stack_id:usize,
+	pub stack:Vec<u32>,
+	pub non_instant_task_status:TaskStatus,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ConditionalReevaluateSnapshot{
+	pub index:i32,
+	pub task_status:TaskStatus,
+	pub composite_index:i32,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct TaskStateSnapshot{
+	pub task_id:i32,
+	pub state:serde_json::Value,
+}
+
+//	行为树运行时状态的快照，map都转成按key排好序的列表，保证同样的状态序列化结果一致
+#[derive(Clone, Serialize, Deserialize)]
+pub struct BehaviorTreeSnapshot{
+	pub version:u32,
+	pub id:u64,
+	pub unit_id:u64,
+	pub task_count:usize,
+
+	pub is_running:bool,
+	pub initialize_first_stack_and_first_task:bool,
+	pub execution_status:TaskStatus,
+
+	pub active_stack:Vec<RunningStackSnapshot>,
+	pub stack_datas:Vec<StackRuntimeData>,
+	pub task_datas:Vec<TaskRuntimeData>,
+	pub conditional_reevaluate:Vec<ConditionalReevaluateSnapshot>,
+	pub stack_id_to_parallel_task_id:Vec<(u32, u32)>,
+	pub parallel_task_id_to_stack_ids:Vec<(i32, Vec<u32>)>,
+
+	pub stack_id:usize,
+	pub task_execute_id:u32,
+
+	pub task_states:Vec<TaskStateSnapshot>,
+}
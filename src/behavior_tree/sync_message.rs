@@ -0,0 +1,265 @@
+use std::{any::Any, collections::HashMap};
+
+use super::interface::{ITaskProxy, SyncDataCollector};
+use super::wire::{WireReader, WireWriter};
+
+//	带类型的同步数据，格式：消息类型(varint) + 内容
+//	内容跟同步消息一样用WireWriter写，每帧都要发，不用json
+//	MESSAGE_TYPE由项目自己分配，服务器和客户端要一致
+pub trait SyncMessageType: Sized + 'static{
+	const MESSAGE_TYPE:u32;
+
+	fn write(&self, writer:&mut WireWriter)->Result<(), Box<dyn std::error::Error>>;
+	fn read(reader:&mut WireReader)->Result<Self, Box<dyn std::error::Error>>;
+}
+
+pub fn encode_sync_message<T:SyncMessageType>(message:&T)->Result<Vec<u8>, Box<dyn std::error::Error>>{
+	write_sync_message(WireWriter::new(), message)
+}
+
+fn write_sync_message<T:SyncMessageType>(mut writer:WireWriter, message:&T)->Result<Vec<u8>, Box<dyn std::error::Error>>{
+	writer.write_varint(T::MESSAGE_TYPE as u64);
+	message.write(&mut writer)?;
+	Ok(writer.into_bytes())
+}
+
+fn read_message_type(reader:&mut WireReader)->Result<u32, Box<dyn std::error::Error>>{
+	u32::try_from(reader.read_varint()?).map_err(|_| "sync message type out of range".into())
+}
+
+pub fn sync_message_type(data:&[u8])->Result<u32, Box<dyn std::error::Error>>{
+	read_message_type(&mut WireReader::new(data))
+}
+
+impl SyncDataCollector{
+	pub fn add_message<T:SyncMessageType>(&mut self, message:&T)->Result<(), Box<dyn std::error::Error>>{
+		//	用上一次清掉的缓冲区，每次开始都发消息的action不用重新分配
+		let data = write_sync_message(WireWriter::with_buffer(self.buffer()), message)?;
+		self.add_data(data);
+		Ok(())
+	}
+}
+
+//	action在on_start/on_update/on_end/rebuild_sync_datas里调用，跟send_sync_data一样，不需要同步的action调用了也没有效果
+pub fn send_sync_message<T:SyncMessageType>(task_proxy:&dyn ITaskProxy, message:&T)->Result<(), Box<dyn std::error::Error>>{
+	if let Some(collector) = task_proxy.sync_data_collector(){
+		collector.borrow_mut().add_message(message)?;
+	}
+	Ok(())
+}
+
+pub struct TypedSyncMessage{
+	pub message_type:u32,
+	pub value:Box<dyn Any>,
+}
+
+impl TypedSyncMessage{
+	pub fn downcast_ref<T:SyncMessageType>(&self)->Option<&T>{
+		self.value.downcast_ref::<T>()
+	}
+}
+
+type SyncMessageDecoder = fn(&[u8])->Result<Box<dyn Any>, Box<dyn std::error::Error>>;
+
+fn decode_value<T:SyncMessageType>(bytes:&[u8])->Result<Box<dyn Any>, Box<dyn std::error::Error>>{
+	let mut reader = WireReader::new(bytes);
+	let value = T::read(&mut reader)?;
+	if reader.remaining() != 0{
+		return Err(format!("trailing bytes after sync message type {}", T::MESSAGE_TYPE).into());
+	}
+	Ok(Box::new(value))
+}
+
+//	客户端按消息类型把同步数据解回具体的结构
+#[derive(Default)]
+pub struct SyncMessageRegistry{
+	decoders:HashMap<u32, SyncMessageDecoder>,
+}
+
+impl SyncMessageRegistry{
+	pub fn new()->Self{
+		Self::default()
+	}
+
+	pub fn register<T:SyncMessageType>(&mut self)->Result<(), Box<dyn std::error::Error>>{
+		if self.decoders.contains_key(&T::MESSAGE_TYPE){
+			return Err(format!("sync message type {} already registered", T::MESSAGE_TYPE).into());
+		}
+		self.decoders.insert(T::MESSAGE_TYPE, decode_value::<T>);
+		Ok(())
+	}
+
+	pub fn decode(&self, data:&[u8])->Result<TypedSyncMessage, Box<dyn std::error::Error>>{
+		let mut reader = WireReader::new(data);
+		let message_type = read_message_type(&mut reader)?;
+		let decoder = self.decoders.get(&message_type).ok_or_else(|| format!("unknown sync message type {}", message_type))?;
+		let value = decoder(&data[data.len() - reader.remaining()..])?;
+		Ok(TypedSyncMessage{message_type, value})
+	}
+
+	pub fn decode_as<T:SyncMessageType>(&self, data:&[u8])->Result<T, Box<dyn std::error::Error>>{
+		let message = self.decode(data)?;
+		match message.value.downcast::<T>(){
+			Ok(value) => Ok(*value),
+			Err(_) => Err(format!("sync message type {} is not {}", message.message_type, T::MESSAGE_TYPE).into()),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::{rc::Rc, cell::RefCell};
+	use super::super::action::play_ani_for_sync::PlayAniSyncData;
+	use super::super::consts::TaskStatus;
+	use super::super::interface::{IAction, IBehaviorTree, IClock, IRuntimeEventHandle};
+	use super::super::json_parser::JsonParser;
+	use super::super::registry::TaskRegistry;
+	use super::super::mirror::SyncMirror;
+	use super::super::runtime::BehaviorTree;
+	use super::super::wire::{FullStateCollector, SyncSequences, WireEncodeRuntimeEventHandle};
+
+	#[derive(PartialEq, Debug)]
+	struct Damage{
+		value:i32,
+	}
+
+	impl SyncMessageType for Damage{
+		const MESSAGE_TYPE:u32 = 1000;
+
+		fn write(&self, writer:&mut WireWriter)->Result<(), Box<dyn std::error::Error>>{
+			writer.write_zigzag(self.value as i64);
+			Ok(())
+		}
+
+		fn read(reader:&mut WireReader)->Result<Self, Box<dyn std::error::Error>>{
+			let value = i32::try_from(reader.read_zigzag()?).map_err(|_| "damage out of range")?;
+			Ok(Self{value})
+		}
+	}
+
+	struct DummyClock;
+
+	impl IClock for DummyClock{
+		fn timestamp_in_mill(&self)->u64{
+			0
+		}
+	}
+
+	#[test]
+	fn test_sync_message_registry() {
+		let mut registry = SyncMessageRegistry::new();
+		registry.register::<Damage>().unwrap();
+		assert!(registry.register::<Damage>().is_err());
+
+		let data = encode_sync_message(&Damage{value:-3}).unwrap();
+		//	类型2个字节，内容1个字节
+		assert_eq!(data.len(), 3);
+		assert_eq!(sync_message_type(&data).unwrap(), 1000);
+		let message = registry.decode(&data).unwrap();
+		assert_eq!(message.message_type, 1000);
+		assert_eq!(message.downcast_ref::<Damage>(), Some(&Damage{value:-3}));
+		assert_eq!(registry.decode_as::<Damage>(&data).unwrap(), Damage{value:-3});
+
+		let mut trailing = data.clone();
+		trailing.push(0);
+		assert!(registry.decode(&trailing).is_err());
+		assert!(registry.decode(&data[..data.len() - 1]).is_err());
+
+		let data = encode_sync_message(&PlayAniSyncData{animation:"run".to_string(), is_loop:true}).unwrap();
+		assert_eq!(data.len(), 6);
+		assert!(registry.decode(&data).is_err());
+	}
+
+	#[test]
+	fn test_play_ani_for_sync_typed_message() {
+		let file_bytes = std::fs::read("src/behavior_tree/test_behaviortree.json").unwrap();
+		let parser = JsonParser::new();
+		let clock:Rc<RefCell<Box<dyn IClock>>> = Rc::new(RefCell::new(Box::new(DummyClock)));
+		let messages = Rc::new(RefCell::new(Vec::new()));
+		let sequences = SyncSequences::new();
+		let behavior_tree = BehaviorTree::new(1, &file_bytes, 1, &Rc::downgrade(&clock), Box::new(WireEncodeRuntimeEventHandle::new(messages.clone(), sequences.clone())), Rc::downgrade(&parser));
+		let mut registry = SyncMessageRegistry::new();
+		registry.register::<PlayAniSyncData>().unwrap();
+		let mut mirror = SyncMirror::new();
+
+		behavior_tree.borrow_mut().enable().unwrap();
+		behavior_tree.borrow_mut().update();
+		for bytes in messages.borrow_mut().drain(..){
+			mirror.apply_bytes(&bytes).unwrap();
+		}
+
+		let animations = |mirror:&SyncMirror|->Vec<PlayAniSyncData>{
+			mirror.tree(1).unwrap().stacks()
+				.flat_map(|stack| stack.actions.values())
+				.flat_map(|action| action.start_datas.iter())
+				.map(|data| registry.decode_as::<PlayAniSyncData>(data).unwrap())
+				.collect()
+		};
+		assert_eq!(animations(&mirror), vec![PlayAniSyncData{animation:"run".to_string(), is_loop:true}]);
+
+		//	中途加入的客户端通过rebuild_sync_datas拿到同样的数据
+		let mut late_mirror = SyncMirror::new();
+		late_mirror.apply_bytes(&FullStateCollector::collect(behavior_tree.borrow().as_ref(), &sequences.borrow()).encode()).unwrap();
+		assert_eq!(animations(&late_mirror), animations(&mirror));
+	}
+
+	//	写入一定失败的消息
+	struct BrokenMessage;
+
+	impl SyncMessageType for BrokenMessage{
+		const MESSAGE_TYPE:u32 = 1001;
+
+		fn write(&self, _writer:&mut WireWriter)->Result<(), Box<dyn std::error::Error>>{
+			Err("broken message".into())
+		}
+
+		fn read(_reader:&mut WireReader)->Result<Self, Box<dyn std::error::Error>>{
+			Ok(Self)
+		}
+	}
+
+	struct BrokenSyncAction;
+
+	impl IAction for BrokenSyncAction{
+		fn on_start(&mut self, task_proxy:&mut dyn ITaskProxy, behavior_tree:&dyn IBehaviorTree){
+			if let Err(error) = send_sync_message(task_proxy, &BrokenMessage){
+				behavior_tree.report_task_error(task_proxy, error.as_ref());
+			}
+		}
+
+		fn on_update(&mut self, _task_proxy:&mut dyn ITaskProxy, _behavior_tree:&dyn IBehaviorTree)->TaskStatus{
+			TaskStatus::Running
+		}
+
+		fn is_sync_to_client(&self)->bool{
+			true
+		}
+	}
+
+	struct ErrorRuntimeEventHandle{
+		errors:Rc<RefCell<Vec<String>>>,
+	}
+
+	impl IRuntimeEventHandle for ErrorRuntimeEventHandle{
+		fn on_task_error(&self, _behavior_tree:&dyn IBehaviorTree, task:&dyn ITaskProxy, error:&dyn std::error::Error){
+			self.errors.borrow_mut().push(format!("{} {}", task.id(), error));
+		}
+	}
+
+	#[test]
+	fn test_send_sync_message_error_reported() {
+		let registry = TaskRegistry::new();
+		registry.write().unwrap().register_action_fn("BrokenSyncAction", |_variables, _task_ids| -> Box<dyn IAction> {Box::new(BrokenSyncAction)});
+		let parser = JsonParser::with_registry(registry);
+		let config = serde_json::json!({"RootTask": {"Type": "BrokenSyncAction", "ID": 1}}).to_string().into_bytes();
+		let clock:Rc<RefCell<Box<dyn IClock>>> = Rc::new(RefCell::new(Box::new(DummyClock)));
+		let errors = Rc::new(RefCell::new(Vec::new()));
+		let behavior_tree = BehaviorTree::new(1, &config, 1, &Rc::downgrade(&clock), Box::new(ErrorRuntimeEventHandle{errors:errors.clone()}), Rc::downgrade(&parser));
+
+		behavior_tree.borrow_mut().enable().unwrap();
+		behavior_tree.borrow_mut().update();
+		assert_eq!(*errors.borrow(), vec!["1 broken message".to_string()]);
+		assert!(behavior_tree.borrow().is_runnning());
+	}
+}
@@ -0,0 +1,320 @@
+use std::{rc::Rc, cell::RefCell};
+use serde::{Serialize, Deserialize};
+
+use super::consts::TaskStatus;
+use super::interface::{IBehaviorTree, IRuntimeEventHandle, ITaskProxy, StackRuntimeData, TaskRuntimeData};
+
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum TraceEventKind{
+	PostInitialize,
+	PostOnComplete,
+	NewStack,
+	RemoveStack,
+	PreOnStart,
+	PreOnUpdate,
+	PostOnUpdate,
+	PostOnEnd,
+	ActionPostOnStart,
+	ActionPostOnUpdate,
+	ActionPostOnEnd,
+	ParallelPreOnStart,
+	ParallelPostOnEnd,
+	ParallelAddChildStack,
+	ParallelRemoveChildStack,
+	OnTaskError,
+}
+
+//	一次IRuntimeEventHandle回调，没有的字段为None
+//	timestamp是回调带的当前时间，没有的话取任务或者栈的开始时间
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+pub struct TraceEvent{
+	pub kind:TraceEventKind,
+	pub tree_id:u64,
+	pub unit_id:u64,
+	pub task_id:Option<i32>,
+	pub task_name:Option<String>,
+	pub execute_id:Option<u32>,
+	pub stack_id:Option<usize>,
+	pub child_stack_id:Option<usize>,
+	pub timestamp:u64,
+	pub status:Option<TaskStatus>,
+	pub datas:Vec<Vec<u8>>,
+	#[serde(default)]
+	pub error:Option<String>,
+}
+
+impl TraceEvent{
+	pub fn tree(kind:TraceEventKind, behavior_tree:&dyn IBehaviorTree, timestamp:u64)->Self{
+		Self{
+			kind,
+			tree_id:behavior_tree.id(),
+			unit_id:behavior_tree.unit_id(),
+			task_id:None,
+			task_name:None,
+			execute_id:None,
+			stack_id:None,
+			child_stack_id:None,
+			timestamp,
+			status:None,
+			datas:Vec::new(),
+			error:None,
+		}
+	}
+
+	pub fn stack(kind:TraceEventKind, behavior_tree:&dyn IBehaviorTree, data:&StackRuntimeData, timestamp:u64)->Self{
+		let mut event = Self::tree(kind, behavior_tree, timestamp);
+		event.stack_id = Some(data.stack_id);
+		event
+	}
+
+	pub fn task(kind:TraceEventKind, behavior_tree:&dyn IBehaviorTree, task_runtime_data:&TaskRuntimeData, stack_runtime_data:&StackRuntimeData, task:&dyn ITaskProxy, timestamp:u64)->Self{
+		let mut event = Self::stack(kind, behavior_tree, stack_runtime_data, timestamp);
+		event.task_id = Some(task_runtime_data.task_id);
+		event.task_name = Some(task.name().to_string());
+		event.execute_id = Some(task_runtime_data.execute_id);
+		event
+	}
+
+	pub fn with_status(mut self, status:TaskStatus)->Self{
+		self.status = Some(status);
+		self
+	}
+
+	pub fn with_datas(mut self, datas:Vec<Vec<u8>>)->Self{
+		self.datas = datas;
+		self
+	}
+
+	pub fn with_error(mut self, error:&dyn std::error::Error)->Self{
+		self.error = Some(error.to_string());
+		self
+	}
+
+	pub fn with_child_stack(mut self, child_stack_runtime_data:&StackRuntimeData)->Self{
+		self.child_stack_id = Some(child_stack_runtime_data.stack_id);
+		self
+	}
+
+	pub fn to_json_line(&self)->String{
+		serde_json::to_string(self).unwrap_or_default()
+	}
+}
+
+#[derive(Clone, Default)]
+pub struct Trace{
+	events:Vec<TraceEvent>,
+}
+
+impl Trace{
+	pub fn new()->Self{
+		Self::default()
+	}
+
+	pub fn push(&mut self, event:TraceEvent){
+		self.events.push(event);
+	}
+
+	pub fn events(&self)->&Vec<TraceEvent>{
+		&self.events
+	}
+
+	pub fn clear(&mut self){
+		self.events.clear();
+	}
+
+	pub fn of_kind(&self, kind:TraceEventKind)->impl Iterator<Item = &TraceEvent>{
+		self.events.iter().filter(move |event| event.kind == kind)
+	}
+
+	pub fn of_task(&self, task_id:i32)->impl Iterator<Item = &TraceEvent>{
+		self.events.iter().filter(move |event| event.task_id == Some(task_id))
+	}
+
+	pub fn of_stack(&self, stack_id:usize)->impl Iterator<Item = &TraceEvent>{
+		self.events.iter().filter(move |event| event.stack_id == Some(stack_id))
+	}
+
+	//	每行一个事件
+	pub fn to_json_lines(&self)->String{
+		let mut lines = String::new();
+		for event in self.events.iter(){
+			lines.push_str(&event.to_json_line());
+			lines.push('\n');
+		}
+		lines
+	}
+
+	pub fn from_json_lines(lines:&str)->Result<Self, Box<dyn std::error::Error>>{
+		let mut events = Vec::new();
+		for line in lines.lines().filter(|line| !line.trim().is_empty()){
+			events.push(serde_json::from_str(line)?);
+		}
+		Ok(Self{events})
+	}
+}
+
+//	把每个回调记成TraceEvent，trace可以在外面共享查询
+pub struct TraceRuntimeEventHandle{
+	trace:Rc<RefCell<Trace>>,
+}
+
+impl TraceRuntimeEventHandle{
+	pub fn new(trace:Rc<RefCell<Trace>>)->Self{
+		Self{trace}
+	}
+}
+
+impl IRuntimeEventHandle for TraceRuntimeEventHandle{
+	fn post_initialize(&self, behavior_tree:&dyn IBehaviorTree, now_timestamp_in_milli:u64){
+		self.trace.borrow_mut().push(TraceEvent::tree(TraceEventKind::PostInitialize, behavior_tree, now_timestamp_in_milli));
+	}
+
+	fn post_on_complete(&self, behavior_tree:&dyn IBehaviorTree, now_timestamp_in_milli:u64){
+		self.trace.borrow_mut().push(TraceEvent::tree(TraceEventKind::PostOnComplete, behavior_tree, now_timestamp_in_milli));
+	}
+
+	fn new_stack(&self, behavior_tree:&dyn IBehaviorTree, data:&StackRuntimeData){
+		self.trace.borrow_mut().push(TraceEvent::stack(TraceEventKind::NewStack, behavior_tree, data, data.start_time));
+	}
+
+	fn remove_stack(&self, behavior_tree:&dyn IBehaviorTree, data:&StackRuntimeData, now_timestamp_in_milli:u64){
+		self.trace.borrow_mut().push(TraceEvent::stack(TraceEventKind::RemoveStack, behavior_tree, data, now_timestamp_in_milli));
+	}
+
+	fn pre_on_start(&self, behavior_tree:&dyn IBehaviorTree, task_runtime_data:&TaskRuntimeData, stack_runtime_data:&StackRuntimeData, task:&dyn ITaskProxy){
+		self.trace.borrow_mut().push(TraceEvent::task(TraceEventKind::PreOnStart, behavior_tree, task_runtime_data, stack_runtime_data, task, task_runtime_data.start_time));
+	}
+
+	fn pre_on_update(&self, behavior_tree:&dyn IBehaviorTree, task_runtime_data:&TaskRuntimeData, stack_runtime_data:&StackRuntimeData, task:&dyn ITaskProxy){
+		self.trace.borrow_mut().push(TraceEvent::task(TraceEventKind::PreOnUpdate, behavior_tree, task_runtime_data, stack_runtime_data, task, behavior_tree.timestamp_in_mill()));
+	}
+
+	fn post_on_update(&self, behavior_tree:&dyn IBehaviorTree, task_runtime_data:&TaskRuntimeData, stack_runtime_data:&StackRuntimeData, task:&dyn ITaskProxy, now_timestamp_in_milli:u64, status:TaskStatus){
+		self.trace.borrow_mut().push(TraceEvent::task(TraceEventKind::PostOnUpdate, behavior_tree, task_runtime_data, stack_runtime_data, task, now_timestamp_in_milli).with_status(status));
+	}
+
+	fn post_on_end(&self, behavior_tree:&dyn IBehaviorTree, task_runtime_data:&TaskRuntimeData, stack_runtime_data:&StackRuntimeData, task:&dyn ITaskProxy, now_timestamp_in_milli:u64){
+		self.trace.borrow_mut().push(TraceEvent::task(TraceEventKind::PostOnEnd, behavior_tree, task_runtime_data, stack_runtime_data, task, now_timestamp_in_milli));
+	}
+
+	fn action_post_on_start(&self, behavior_tree:&dyn IBehaviorTree, task_runtime_data:&TaskRuntimeData, stack_runtime_data:&StackRuntimeData, task:&dyn ITaskProxy, datas:&[Vec<u8>]){
+		self.trace.borrow_mut().push(TraceEvent::task(TraceEventKind::ActionPostOnStart, behavior_tree, task_runtime_data, stack_runtime_data, task, task_runtime_data.start_time).with_datas(datas.to_vec()));
+	}
+
+	fn action_post_on_update(&self, behavior_tree:&dyn IBehaviorTree, task_runtime_data:&TaskRuntimeData, stack_runtime_data:&StackRuntimeData, task:&dyn ITaskProxy, now_timestamp_in_milli:u64, status:TaskStatus, datas:&[Vec<u8>]){
+		self.trace.borrow_mut().push(TraceEvent::task(TraceEventKind::ActionPostOnUpdate, behavior_tree, task_runtime_data, stack_runtime_data, task, now_timestamp_in_milli).with_status(status).with_datas(datas.to_vec()));
+	}
+
+	fn action_post_on_end(&self, behavior_tree:&dyn IBehaviorTree, task_runtime_data:&TaskRuntimeData, stack_runtime_data:&StackRuntimeData, task:&dyn ITaskProxy, now_timestamp_in_milli:u64, datas:&[Vec<u8>]){
+		self.trace.borrow_mut().push(TraceEvent::task(TraceEventKind::ActionPostOnEnd, behavior_tree, task_runtime_data, stack_runtime_data, task, now_timestamp_in_milli).with_datas(datas.to_vec()));
+	}
+
+	fn parallel_pre_on_start(&self, behavior_tree:&dyn IBehaviorTree, task_runtime_data:&TaskRuntimeData, stack_runtime_data:&StackRuntimeData, task:&dyn ITaskProxy){
+		self.trace.borrow_mut().push(TraceEvent::task(TraceEventKind::ParallelPreOnStart, behavior_tree, task_runtime_data, stack_runtime_data, task, task_runtime_data.start_time));
+	}
+
+	fn parallel_post_on_end(&self, behavior_tree:&dyn IBehaviorTree, task_runtime_data:&TaskRuntimeData, stack_runtime_data:&StackRuntimeData, task:&dyn ITaskProxy, now_timestamp_in_milli:u64){
+		self.trace.borrow_mut().push(TraceEvent::task(TraceEventKind::ParallelPostOnEnd, behavior_tree, task_runtime_data, stack_runtime_data, task, now_timestamp_in_milli));
+	}
+
+	fn parallel_add_child_stack(&self, behavior_tree:&dyn IBehaviorTree, task_runtime_data:&TaskRuntimeData, stack_runtime_data:&StackRuntimeData, task:&dyn ITaskProxy, child_stack_runtime_data:&StackRuntimeData){
+		self.trace.borrow_mut().push(TraceEvent::task(TraceEventKind::ParallelAddChildStack, behavior_tree, task_runtime_data, stack_runtime_data, task, child_stack_runtime_data.start_time).with_child_stack(child_stack_runtime_data));
+	}
+
+	fn parallel_remove_child_stack(&self, behavior_tree:&dyn IBehaviorTree, task_runtime_data:&TaskRuntimeData, stack_runtime_data:&StackRuntimeData, task:&dyn ITaskProxy, child_stack_runtime_data:&StackRuntimeData, now_timestamp_in_milli:u64){
+		self.trace.borrow_mut().push(TraceEvent::task(TraceEventKind::ParallelRemoveChildStack, behavior_tree, task_runtime_data, stack_runtime_data, task, now_timestamp_in_milli).with_child_stack(child_stack_runtime_data));
+	}
+
+	//	报错的时候拿不到任务的运行时数据，只记任务id和名字
+	fn on_task_error(&self, behavior_tree:&dyn IBehaviorTree, task:&dyn ITaskProxy, error:&dyn std::error::Error){
+		let mut event = TraceEvent::tree(TraceEventKind::OnTaskError, behavior_tree, behavior_tree.timestamp_in_mill()).with_error(error);
+		event.task_id = Some(task.id());
+		event.task_name = Some(task.name().to_string());
+		self.trace.borrow_mut().push(event);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use super::super::interface::{IAction, IClock};
+	use super::super::json_parser::JsonParser;
+	use super::super::registry::TaskRegistry;
+	use super::super::runtime::BehaviorTree;
+
+	struct DummyClock;
+
+	impl IClock for DummyClock{
+		fn timestamp_in_mill(&self)->u64{
+			100
+		}
+	}
+
+	struct ErrorAction;
+
+	impl IAction for ErrorAction{
+		fn on_start(&mut self, task_proxy:&mut dyn ITaskProxy, behavior_tree:&dyn IBehaviorTree){
+			behavior_tree.report_task_error(task_proxy, &std::io::Error::other("bad config"));
+		}
+
+		fn on_update(&mut self, _task_proxy:&mut dyn ITaskProxy, _behavior_tree:&dyn IBehaviorTree)->TaskStatus{
+			TaskStatus::Running
+		}
+	}
+
+	#[test]
+	fn test_trace_runtime_event_handle() {
+		let file_bytes = std::fs::read("src/behavior_tree/test_behaviortree.json").unwrap();
+		let parser = JsonParser::new();
+		let clock:Rc<RefCell<Box<dyn IClock>>> = Rc::new(RefCell::new(Box::new(DummyClock)));
+		let trace = Rc::new(RefCell::new(Trace::new()));
+		let behavior_tree = BehaviorTree::new(3, &file_bytes, 5, &Rc::downgrade(&clock), Box::new(TraceRuntimeEventHandle::new(trace.clone())), Rc::downgrade(&parser));
+		behavior_tree.borrow_mut().enable().unwrap();
+		behavior_tree.borrow_mut().update();
+
+		let first = trace.borrow().events()[0].clone();
+		assert!(first.kind == TraceEventKind::PostInitialize);
+		assert_eq!(first.tree_id, 3);
+		assert_eq!(first.unit_id, 5);
+		assert_eq!(first.timestamp, 100);
+
+		let trace_ref = trace.borrow();
+		let starts:Vec<&TraceEvent> = trace_ref.of_kind(TraceEventKind::PreOnStart).collect();
+		assert!(starts.len() > 0);
+		assert_eq!(starts[0].task_id, Some(0));
+		assert!(starts.iter().all(|event| event.task_name.is_some() && event.execute_id.is_some() && event.stack_id.is_some()));
+		assert!(trace_ref.of_kind(TraceEventKind::ParallelAddChildStack).all(|event| event.child_stack_id.is_some()));
+		assert!(trace_ref.of_task(0).count() > 0);
+		//	每个PostOnUpdate前面都有同一个任务的PreOnUpdate
+		assert!(trace_ref.of_kind(TraceEventKind::PreOnUpdate).count() > 0);
+		for (i, event) in trace_ref.events().iter().enumerate(){
+			if event.kind == TraceEventKind::PreOnUpdate{
+				assert_eq!(event.timestamp, 100);
+				assert!(trace_ref.events()[i + 1..].iter().any(|next| next.kind == TraceEventKind::PostOnUpdate && next.task_id == event.task_id && next.execute_id == event.execute_id));
+			}
+		}
+
+		let lines = trace_ref.to_json_lines();
+		assert_eq!(lines.lines().count(), trace_ref.events().len());
+		let parsed = Trace::from_json_lines(&lines).unwrap();
+		assert!(parsed.events() == trace_ref.events());
+
+		let registry = TaskRegistry::new();
+		registry.write().unwrap().register_action_fn("ErrorAction", |_variables, _task_ids| -> Box<dyn IAction> {Box::new(ErrorAction)});
+		let parser = JsonParser::with_registry(registry);
+		let config = serde_json::json!({"RootTask": {"Type": "ErrorAction", "Name": "LoadAni", "ID": 1}}).to_string().into_bytes();
+		let error_trace = Rc::new(RefCell::new(Trace::new()));
+		let behavior_tree = BehaviorTree::new(4, &config, 5, &Rc::downgrade(&clock), Box::new(TraceRuntimeEventHandle::new(error_trace.clone())), Rc::downgrade(&parser));
+		behavior_tree.borrow_mut().enable().unwrap();
+		behavior_tree.borrow_mut().update();
+
+		let error_trace = error_trace.borrow();
+		let errors:Vec<&TraceEvent> = error_trace.of_kind(TraceEventKind::OnTaskError).collect();
+		assert_eq!(errors.len(), 1);
+		assert_eq!(errors[0].task_id, Some(1));
+		assert_eq!(errors[0].task_name.as_deref(), Some("LoadAni"));
+		assert_eq!(errors[0].error.as_deref(), Some("bad config"));
+		assert_eq!(errors[0].timestamp, 100);
+		assert!(Trace::from_json_lines(&error_trace.to_json_lines()).unwrap().events() == error_trace.events());
+	}
+}
@@ -0,0 +1,789 @@
+use std::{rc::Rc, cell::RefCell, collections::HashMap, sync::{Arc, Mutex}};
+
+use super::bandwidth::BandwidthMeter;
+use super::consts::TaskStatus;
+use super::interface::{IBehaviorTree, IRebuildSyncDataCollector, IRuntimeEventHandle, ITaskProxy, StackRuntimeData, TaskRuntimeData};
+
+//	消息格式：版本(1字节) 序号(varint) 类型(1字节) 字段...
+//	整数都用varint，task_id是有符号的用zigzag，datas是 个数 + (长度 + 内容)...
+//	序号按树递增，从1开始，全量状态的序号是它包含的最后一个增量消息的序号
+pub const WIRE_VERSION:u8 = 2;
+
+const KIND_NEW_STACK:u8 = 1;
+const KIND_REMOVE_STACK:u8 = 2;
+const KIND_ACTION_START:u8 = 3;
+const KIND_ACTION_UPDATE:u8 = 4;
+const KIND_ACTION_END:u8 = 5;
+const KIND_PARALLEL_ADD_CHILD_STACK:u8 = 6;
+const KIND_PARALLEL_REMOVE_CHILD_STACK:u8 = 7;
+const KIND_FULL_STATE:u8 = 8;
+
+//	全量同步里正在执行的同步action，datas是rebuild_sync_datas产生的数据
+#[derive(Clone, PartialEq, Debug)]
+pub struct FullStateAction{
+	pub task:TaskRuntimeData,
+	pub stack:StackRuntimeData,
+	pub corresponding_type:String,
+	pub datas:Vec<Vec<u8>>,
+}
+
+//	全量同步里并发任务和它的子栈
+#[derive(Clone, PartialEq, Debug)]
+pub struct FullStateParallel{
+	pub task:TaskRuntimeData,
+	pub stack:StackRuntimeData,
+	pub child_stacks:Vec<StackRuntimeData>,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum SyncMessage{
+	NewStack{tree_id:u64, stack:StackRuntimeData},
+	RemoveStack{tree_id:u64, stack:StackRuntimeData, now:u64},
+	ActionStart{tree_id:u64, task:TaskRuntimeData, stack:StackRuntimeData, corresponding_type:String, datas:Vec<Vec<u8>>},
+	ActionUpdate{tree_id:u64, task:TaskRuntimeData, stack:StackRuntimeData, now:u64, status:TaskStatus, datas:Vec<Vec<u8>>},
+	ActionEnd{tree_id:u64, task:TaskRuntimeData, stack:StackRuntimeData, now:u64, datas:Vec<Vec<u8>>},
+	ParallelAddChildStack{tree_id:u64, task:TaskRuntimeData, stack:StackRuntimeData, child_stack:StackRuntimeData},
+	ParallelRemoveChildStack{tree_id:u64, task:TaskRuntimeData, stack:StackRuntimeData, child_stack:StackRuntimeData, now:u64},
+	//	中途加入的客户端用这个替换整棵树的状态，之后再接着收增量消息
+	FullState{tree_id:u64, stacks:Vec<StackRuntimeData>, parallels:Vec<FullStateParallel>, actions:Vec<FullStateAction>},
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Debug)]
+pub enum SyncMessageKind{
+	NewStack,
+	RemoveStack,
+	ActionStart,
+	ActionUpdate,
+	ActionEnd,
+	ParallelAddChildStack,
+	ParallelRemoveChildStack,
+	FullState,
+}
+
+impl SyncMessage{
+	pub fn kind(&self)->SyncMessageKind{
+		match self{
+			SyncMessage::NewStack{..} => SyncMessageKind::NewStack,
+			SyncMessage::RemoveStack{..} => SyncMessageKind::RemoveStack,
+			SyncMessage::ActionStart{..} => SyncMessageKind::ActionStart,
+			SyncMessage::ActionUpdate{..} => SyncMessageKind::ActionUpdate,
+			SyncMessage::ActionEnd{..} => SyncMessageKind::ActionEnd,
+			SyncMessage::ParallelAddChildStack{..} => SyncMessageKind::ParallelAddChildStack,
+			SyncMessage::ParallelRemoveChildStack{..} => SyncMessageKind::ParallelRemoveChildStack,
+			SyncMessage::FullState{..} => SyncMessageKind::FullState,
+		}
+	}
+
+	pub fn tree_id(&self)->u64{
+		match self{
+			SyncMessage::NewStack{tree_id, ..} => *tree_id,
+			SyncMessage::RemoveStack{tree_id, ..} => *tree_id,
+			SyncMessage::ActionStart{tree_id, ..} => *tree_id,
+			SyncMessage::ActionUpdate{tree_id, ..} => *tree_id,
+			SyncMessage::ActionEnd{tree_id, ..} => *tree_id,
+			SyncMessage::ParallelAddChildStack{tree_id, ..} => *tree_id,
+			SyncMessage::ParallelRemoveChildStack{tree_id, ..} => *tree_id,
+			SyncMessage::FullState{tree_id, ..} => *tree_id,
+		}
+	}
+
+	fn write(&self, writer:&mut WireWriter){
+		match self{
+			SyncMessage::NewStack{tree_id, stack} => {
+				writer.write_u8(KIND_NEW_STACK);
+				writer.write_varint(*tree_id);
+				writer.write_stack(stack);
+			},
+			SyncMessage::RemoveStack{tree_id, stack, now} => {
+				writer.write_u8(KIND_REMOVE_STACK);
+				writer.write_varint(*tree_id);
+				writer.write_stack(stack);
+				writer.write_varint(*now);
+			},
+			SyncMessage::ActionStart{tree_id, task, stack, corresponding_type, datas} => write_action_start(writer, *tree_id, task, stack, corresponding_type, datas),
+			SyncMessage::ActionUpdate{tree_id, task, stack, now, status, datas} => write_action_update(writer, *tree_id, task, stack, *now, status, datas),
+			SyncMessage::ActionEnd{tree_id, task, stack, now, datas} => write_action_end(writer, *tree_id, task, stack, *now, datas),
+			SyncMessage::ParallelAddChildStack{tree_id, task, stack, child_stack} => {
+				writer.write_u8(KIND_PARALLEL_ADD_CHILD_STACK);
+				writer.write_varint(*tree_id);
+				writer.write_task(task);
+				writer.write_stack(stack);
+				writer.write_stack(child_stack);
+			},
+			SyncMessage::ParallelRemoveChildStack{tree_id, task, stack, child_stack, now} => {
+				writer.write_u8(KIND_PARALLEL_REMOVE_CHILD_STACK);
+				writer.write_varint(*tree_id);
+				writer.write_task(task);
+				writer.write_stack(stack);
+				writer.write_stack(child_stack);
+				writer.write_varint(*now);
+			},
+			SyncMessage::FullState{tree_id, stacks, parallels, actions} => {
+				writer.write_u8(KIND_FULL_STATE);
+				writer.write_varint(*tree_id);
+				writer.write_varint(stacks.len() as u64);
+				for stack in stacks.iter(){
+					writer.write_stack(stack);
+				}
+				writer.write_varint(parallels.len() as u64);
+				for parallel in parallels.iter(){
+					writer.write_task(&parallel.task);
+					writer.write_stack(&parallel.stack);
+					writer.write_varint(parallel.child_stacks.len() as u64);
+					for child_stack in parallel.child_stacks.iter(){
+						writer.write_stack(child_stack);
+					}
+				}
+				writer.write_varint(actions.len() as u64);
+				for action in actions.iter(){
+					writer.write_task(&action.task);
+					writer.write_stack(&action.stack);
+					writer.write_bytes(action.corresponding_type.as_bytes());
+					writer.write_datas(&action.datas);
+				}
+			},
+		}
+	}
+
+	fn read(reader:&mut WireReader)->Result<Self, Box<dyn std::error::Error>>{
+		let kind = reader.read_u8()?;
+		let message = match kind{
+			KIND_NEW_STACK => SyncMessage::NewStack{
+				tree_id:reader.read_varint()?,
+				stack:reader.read_stack()?,
+			},
+			KIND_REMOVE_STACK => SyncMessage::RemoveStack{
+				tree_id:reader.read_varint()?,
+				stack:reader.read_stack()?,
+				now:reader.read_varint()?,
+			},
+			KIND_ACTION_START => SyncMessage::ActionStart{
+				tree_id:reader.read_varint()?,
+				task:reader.read_task()?,
+				stack:reader.read_stack()?,
+				corresponding_type:String::from_utf8(reader.read_bytes()?.to_vec())?,
+				datas:reader.read_datas()?,
+			},
+			KIND_ACTION_UPDATE => SyncMessage::ActionUpdate{
+				tree_id:reader.read_varint()?,
+				task:reader.read_task()?,
+				stack:reader.read_stack()?,
+				now:reader.read_varint()?,
+				status:reader.read_status()?,
+				datas:reader.read_datas()?,
+			},
+			KIND_ACTION_END => SyncMessage::ActionEnd{
+				tree_id:reader.read_varint()?,
+				task:reader.read_task()?,
+				stack:reader.read_stack()?,
+				now:reader.read_varint()?,
+				datas:reader.read_datas()?,
+			},
+			KIND_PARALLEL_ADD_CHILD_STACK => SyncMessage::ParallelAddChildStack{
+				tree_id:reader.read_varint()?,
+				task:reader.read_task()?,
+				stack:reader.read_stack()?,
+				child_stack:reader.read_stack()?,
+			},
+			KIND_PARALLEL_REMOVE_CHILD_STACK => SyncMessage::ParallelRemoveChildStack{
+				tree_id:reader.read_varint()?,
+				task:reader.read_task()?,
+				stack:reader.read_stack()?,
+				child_stack:reader.read_stack()?,
+				now:reader.read_varint()?,
+			},
+			KIND_FULL_STATE => {
+				let tree_id = reader.read_varint()?;
+				let mut stacks = Vec::new();
+				for _ in 0..reader.read_varint()?{
+					stacks.push(reader.read_stack()?);
+				}
+				let mut parallels = Vec::new();
+				for _ in 0..reader.read_varint()?{
+					let task = reader.read_task()?;
+					let stack = reader.read_stack()?;
+					let mut child_stacks = Vec::new();
+					for _ in 0..reader.read_varint()?{
+						child_stacks.push(reader.read_stack()?);
+					}
+					parallels.push(FullStateParallel{task, stack, child_stacks});
+				}
+				let mut actions = Vec::new();
+				for _ in 0..reader.read_varint()?{
+					actions.push(FullStateAction{
+						task:reader.read_task()?,
+						stack:reader.read_stack()?,
+						corresponding_type:String::from_utf8(reader.read_bytes()?.to_vec())?,
+						datas:reader.read_datas()?,
+					});
+				}
+				SyncMessage::FullState{tree_id, stacks, parallels, actions}
+			},
+			_ => return Err(format!("unknown sync message kind: {}", kind).into()),
+		};
+		Ok(message)
+	}
+}
+
+//	action的消息直接从借用的数据编码，WireEncodeEventHandle不用先拷贝出一个SyncMessage
+fn write_action_start(writer:&mut WireWriter, tree_id:u64, task:&TaskRuntimeData, stack:&StackRuntimeData, corresponding_type:&str, datas:&[Vec<u8>]){
+	writer.write_u8(KIND_ACTION_START);
+	writer.write_varint(tree_id);
+	writer.write_task(task);
+	writer.write_stack(stack);
+	writer.write_str(corresponding_type);
+	writer.write_datas(datas);
+}
+
+fn write_action_update(writer:&mut WireWriter, tree_id:u64, task:&TaskRuntimeData, stack:&StackRuntimeData, now:u64, status:&TaskStatus, datas:&[Vec<u8>]){
+	writer.write_u8(KIND_ACTION_UPDATE);
+	writer.write_varint(tree_id);
+	writer.write_task(task);
+	writer.write_stack(stack);
+	writer.write_varint(now);
+	writer.write_status(status);
+	writer.write_datas(datas);
+}
+
+fn write_action_end(writer:&mut WireWriter, tree_id:u64, task:&TaskRuntimeData, stack:&StackRuntimeData, now:u64, datas:&[Vec<u8>]){
+	writer.write_u8(KIND_ACTION_END);
+	writer.write_varint(tree_id);
+	writer.write_task(task);
+	writer.write_stack(stack);
+	writer.write_varint(now);
+	writer.write_datas(datas);
+}
+
+//	实际发送的单位：带序号的同步消息
+#[derive(Clone, PartialEq, Debug)]
+pub struct SyncPacket{
+	pub sequence:u64,
+	pub message:SyncMessage,
+}
+
+impl SyncPacket{
+	pub fn new(sequence:u64, message:SyncMessage)->Self{
+		Self{sequence, message}
+	}
+
+	pub fn encode(&self)->Vec<u8>{
+		let mut writer = WireWriter::new();
+		writer.write_u8(WIRE_VERSION);
+		writer.write_varint(self.sequence);
+		self.message.write(&mut writer);
+		writer.into_bytes()
+	}
+
+	pub fn decode(bytes:&[u8])->Result<Self, Box<dyn std::error::Error>>{
+		let mut reader = WireReader::new(bytes);
+		let version = reader.read_u8()?;
+		if version != WIRE_VERSION{
+			return Err(format!("unsupported wire version: {}", version).into());
+		}
+
+		let sequence = reader.read_varint()?;
+		let message = SyncMessage::read(&mut reader)?;
+		if !reader.is_empty(){
+			return Err("trailing bytes after sync message".into());
+		}
+		Ok(Self{sequence, message})
+	}
+}
+
+//	每棵树最后发出的消息序号，编码事件和收集全量状态共用
+#[derive(Clone, Default)]
+pub struct SyncSequences{
+	last_sequences:HashMap<u64, u64>,
+}
+
+impl SyncSequences{
+	pub fn new()->Rc<RefCell<Self>>{
+		Rc::new(RefCell::new(Self::default()))
+	}
+
+	pub fn next(&mut self, tree_id:u64)->u64{
+		let sequence = self.last_sequences.entry(tree_id).or_insert(0);
+		*sequence += 1;
+		*sequence
+	}
+
+	pub fn last(&self, tree_id:u64)->u64{
+		self.last_sequences.get(&tree_id).cloned().unwrap_or(0)
+	}
+}
+
+pub struct WireWriter{
+	bytes:Vec<u8>,
+}
+
+impl WireWriter{
+	pub fn new()->Self{
+		Self{bytes:Vec::new()}
+	}
+
+	//	接着用已有的缓冲区，原来的内容清掉
+	pub fn with_buffer(mut bytes:Vec<u8>)->Self{
+		bytes.clear();
+		Self{bytes}
+	}
+
+	pub fn into_bytes(self)->Vec<u8>{
+		self.bytes
+	}
+
+	pub fn clear(&mut self){
+		self.bytes.clear();
+	}
+
+	pub fn bytes(&self)->&[u8]{
+		&self.bytes
+	}
+
+	pub fn write_u8(&mut self, value:u8){
+		self.bytes.push(value);
+	}
+
+	pub fn write_varint(&mut self, mut value:u64){
+		while value >= 0x80{
+			self.bytes.push((value as u8) | 0x80);
+			value >>= 7;
+		}
+		self.bytes.push(value as u8);
+	}
+
+	pub fn write_zigzag(&mut self, value:i64){
+		self.write_varint(((value << 1) ^ (value >> 63)) as u64);
+	}
+
+	pub fn write_bytes(&mut self, bytes:&[u8]){
+		self.write_varint(bytes.len() as u64);
+		self.bytes.extend_from_slice(bytes);
+	}
+
+	pub fn write_bool(&mut self, value:bool){
+		self.write_u8(value as u8);
+	}
+
+	pub fn write_str(&mut self, value:&str){
+		self.write_bytes(value.as_bytes());
+	}
+
+	pub fn write_datas(&mut self, datas:&[Vec<u8>]){
+		self.write_varint(datas.len() as u64);
+		for data in datas.iter(){
+			self.write_bytes(data);
+		}
+	}
+
+	pub fn write_status(&mut self, status:&TaskStatus){
+		self.write_u8(match status{
+			TaskStatus::Inactive => 0,
+			TaskStatus::Running => 1,
+			TaskStatus::Success => 2,
+			TaskStatus::Failure => 3,
+		});
+	}
+
+	pub fn write_stack(&mut self, stack:&StackRuntimeData){
+		self.write_varint(stack.stack_id as u64);
+		self.write_varint(stack.start_time);
+	}
+
+	pub fn write_task(&mut self, task:&TaskRuntimeData){
+		self.write_zigzag(task.task_id as i64);
+		self.write_varint(task.start_time);
+		self.write_varint(task.execute_id as u64);
+		self.write_varint(task.active_stack_id as u64);
+	}
+}
+
+impl Default for WireWriter{
+	fn default()->Self{
+		Self::new()
+	}
+}
+
+pub struct WireReader<'a>{
+	bytes:&'a [u8],
+	position:usize,
+}
+
+impl<'a> WireReader<'a>{
+	pub fn new(bytes:&'a [u8])->Self{
+		Self{bytes, position:0}
+	}
+
+	pub fn is_empty(&self)->bool{
+		self.position >= self.bytes.len()
+	}
+
+	pub fn remaining(&self)->usize{
+		self.bytes.len() - self.position
+	}
+
+	pub fn read_u8(&mut self)->Result<u8, Box<dyn std::error::Error>>{
+		match self.bytes.get(self.position){
+			Some(value) => {
+				self.position += 1;
+				Ok(*value)
+			},
+			None => Err("unexpected end of sync message".into()),
+		}
+	}
+
+	pub fn read_varint(&mut self)->Result<u64, Box<dyn std::error::Error>>{
+		let mut value:u64 = 0;
+		let mut shift = 0;
+		loop{
+			let byte = self.read_u8()?;
+			//	第10个字节只剩最高的1位可用，多出来的位会溢出u64
+			if shift >= 64 || (shift == 63 && byte & 0x7e != 0){
+				return Err("varint too long".into());
+			}
+			value |= ((byte & 0x7f) as u64) << shift;
+			if byte & 0x80 == 0{
+				return Ok(value);
+			}
+			shift += 7;
+		}
+	}
+
+	pub fn read_zigzag(&mut self)->Result<i64, Box<dyn std::error::Error>>{
+		let value = self.read_varint()?;
+		Ok(((value >> 1) as i64) ^ -((value & 1) as i64))
+	}
+
+	pub fn read_bytes(&mut self)->Result<&'a [u8], Box<dyn std::error::Error>>{
+		let len = self.read_varint()? as usize;
+		if len > self.bytes.len() - self.position{
+			return Err("unexpected end of sync message".into());
+		}
+		let bytes = &self.bytes[self.position..self.position + len];
+		self.position += len;
+		Ok(bytes)
+	}
+
+	pub fn read_bool(&mut self)->Result<bool, Box<dyn std::error::Error>>{
+		match self.read_u8()?{
+			0 => Ok(false),
+			1 => Ok(true),
+			value => Err(format!("invalid bool {}", value).into()),
+		}
+	}
+
+	pub fn read_string(&mut self)->Result<String, Box<dyn std::error::Error>>{
+		Ok(String::from_utf8(self.read_bytes()?.to_vec())?)
+	}
+
+	pub fn read_datas(&mut self)->Result<Vec<Vec<u8>>, Box<dyn std::error::Error>>{
+		let count = self.read_varint()? as usize;
+		let mut datas = Vec::new();
+		for _ in 0..count{
+			datas.push(self.read_bytes()?.to_vec());
+		}
+		Ok(datas)
+	}
+
+	pub fn read_status(&mut self)->Result<TaskStatus, Box<dyn std::error::Error>>{
+		match self.read_u8()?{
+			0 => Ok(TaskStatus::Inactive),
+			1 => Ok(TaskStatus::Running),
+			2 => Ok(TaskStatus::Success),
+			3 => Ok(TaskStatus::Failure),
+			status => Err(format!("unknown task status: {}", status).into()),
+		}
+	}
+
+	pub fn read_stack(&mut self)->Result<StackRuntimeData, Box<dyn std::error::Error>>{
+		let stack_id = self.read_varint()? as usize;
+		let start_time = self.read_varint()?;
+		Ok(StackRuntimeData::new(stack_id, start_time))
+	}
+
+	pub fn read_task(&mut self)->Result<TaskRuntimeData, Box<dyn std::error::Error>>{
+		let task_id = i32::try_from(self.read_zigzag()?).map_err(|_| "task id out of range")?;
+		let start_time = self.read_varint()?;
+		let execute_id = u32::try_from(self.read_varint()?).map_err(|_| "execute id out of range")?;
+		let active_stack_id = self.read_varint()? as usize;
+		Ok(TaskRuntimeData::new(task_id, start_time, execute_id, active_stack_id))
+	}
+}
+
+//	编码好的消息放到哪里，序号从哪里取
+pub trait ISyncOutbox{
+	fn next_sequence(&self, tree_id:u64)->u64;
+	fn push(&self, behavior_tree:&dyn IBehaviorTree, kind:SyncMessageKind, task:Option<&dyn ITaskProxy>, bytes:Vec<u8>);
+}
+
+//	单线程用，可以统计发出去的字节数
+pub struct LocalSyncOutbox{
+	messages:Rc<RefCell<Vec<Vec<u8>>>>,
+	sequences:Rc<RefCell<SyncSequences>>,
+	meter:Option<Rc<RefCell<BandwidthMeter>>>,
+}
+
+impl ISyncOutbox for LocalSyncOutbox{
+	fn next_sequence(&self, tree_id:u64)->u64{
+		self.sequences.borrow_mut().next(tree_id)
+	}
+
+	fn push(&self, behavior_tree:&dyn IBehaviorTree, kind:SyncMessageKind, task:Option<&dyn ITaskProxy>, bytes:Vec<u8>){
+		if let Some(meter) = &self.meter{
+			meter.borrow_mut().record_message(behavior_tree, kind, task, bytes.len());
+		}
+		self.messages.borrow_mut().push(bytes);
+	}
+}
+
+//	SendBehaviorTree用，树在哪个线程update都可以写进来
+pub struct SharedSyncOutbox{
+	messages:Arc<Mutex<Vec<Vec<u8>>>>,
+	sequences:Arc<Mutex<SyncSequences>>,
+}
+
+#[allow(unused_variables)]
+impl ISyncOutbox for SharedSyncOutbox{
+	fn next_sequence(&self, tree_id:u64)->u64{
+		self.sequences.lock().unwrap().next(tree_id)
+	}
+
+	fn push(&self, behavior_tree:&dyn IBehaviorTree, kind:SyncMessageKind, task:Option<&dyn ITaskProxy>, bytes:Vec<u8>){
+		self.messages.lock().unwrap().push(bytes);
+	}
+}
+
+//	把同步需要的回调编码成消息，按顺序放到outbox里，由外部取走发送
+//	消息先写到复用的writer里，每条消息只分配放进outbox的那一份
+pub struct WireEncodeEventHandle<O:ISyncOutbox>{
+	outbox:O,
+	writer:RefCell<WireWriter>,
+}
+
+pub type WireEncodeRuntimeEventHandle = WireEncodeEventHandle<LocalSyncOutbox>;
+pub type SendWireEncodeRuntimeEventHandle = WireEncodeEventHandle<SharedSyncOutbox>;
+
+impl WireEncodeRuntimeEventHandle{
+	pub fn new(messages:Rc<RefCell<Vec<Vec<u8>>>>, sequences:Rc<RefCell<SyncSequences>>)->Self{
+		Self{outbox:LocalSyncOutbox{messages, sequences, meter:None}, writer:RefCell::new(WireWriter::new())}
+	}
+
+	//	统计发出去的字节数
+	pub fn with_meter(mut self, meter:Rc<RefCell<BandwidthMeter>>)->Self{
+		self.outbox.meter = Some(meter);
+		self
+	}
+}
+
+impl SendWireEncodeRuntimeEventHandle{
+	pub fn new(messages:Arc<Mutex<Vec<Vec<u8>>>>, sequences:Arc<Mutex<SyncSequences>>)->Self{
+		Self{outbox:SharedSyncOutbox{messages, sequences}, writer:RefCell::new(WireWriter::new())}
+	}
+}
+
+impl<O:ISyncOutbox> WireEncodeEventHandle<O>{
+	fn send(&self, behavior_tree:&dyn IBehaviorTree, message:SyncMessage, task:Option<&dyn ITaskProxy>){
+		self.send_with(behavior_tree, message.kind(), task, |writer| message.write(writer));
+	}
+
+	//	跟SyncPacket::encode一样的格式
+	fn send_with(&self, behavior_tree:&dyn IBehaviorTree, kind:SyncMessageKind, task:Option<&dyn ITaskProxy>, write:impl FnOnce(&mut WireWriter)){
+		let sequence = self.outbox.next_sequence(behavior_tree.id());
+		let mut writer = self.writer.borrow_mut();
+		writer.clear();
+		writer.write_u8(WIRE_VERSION);
+		writer.write_varint(sequence);
+		write(&mut writer);
+		self.outbox.push(behavior_tree, kind, task, writer.bytes().to_vec());
+	}
+}
+
+#[allow(unused_variables)]
+impl<O:ISyncOutbox> IRuntimeEventHandle for WireEncodeEventHandle<O>{
+	fn new_stack(&self, behavior_tree:&dyn IBehaviorTree, data:&StackRuntimeData){
+		self.send(behavior_tree, SyncMessage::NewStack{tree_id:behavior_tree.id(), stack:*data}, None);
+	}
+
+	fn remove_stack(&self, behavior_tree:&dyn IBehaviorTree, data:&StackRuntimeData, now_timestamp_in_milli:u64){
+		self.send(behavior_tree, SyncMessage::RemoveStack{tree_id:behavior_tree.id(), stack:*data, now:now_timestamp_in_milli}, None);
+	}
+
+	fn action_post_on_start(&self, behavior_tree:&dyn IBehaviorTree, task_runtime_data:&TaskRuntimeData, stack_runtime_data:&StackRuntimeData, task:&dyn ITaskProxy, datas:&[Vec<u8>]){
+		self.send_with(behavior_tree, SyncMessageKind::ActionStart, Some(task), |writer| write_action_start(writer, behavior_tree.id(), task_runtime_data, stack_runtime_data, task.corresponding_type(), datas));
+	}
+
+	fn action_post_on_update(&self, behavior_tree:&dyn IBehaviorTree, task_runtime_data:&TaskRuntimeData, stack_runtime_data:&StackRuntimeData, task:&dyn ITaskProxy, now_timestamp_in_milli:u64, status:TaskStatus, datas:&[Vec<u8>]){
+		self.send_with(behavior_tree, SyncMessageKind::ActionUpdate, Some(task), |writer| write_action_update(writer, behavior_tree.id(), task_runtime_data, stack_runtime_data, now_timestamp_in_milli, &status, datas));
+	}
+
+	fn action_post_on_end(&self, behavior_tree:&dyn IBehaviorTree, task_runtime_data:&TaskRuntimeData, stack_runtime_data:&StackRuntimeData, task:&dyn ITaskProxy, now_timestamp_in_milli:u64, datas:&[Vec<u8>]){
+		self.send_with(behavior_tree, SyncMessageKind::ActionEnd, Some(task), |writer| write_action_end(writer, behavior_tree.id(), task_runtime_data, stack_runtime_data, now_timestamp_in_milli, datas));
+	}
+
+	fn parallel_add_child_stack(&self, behavior_tree:&dyn IBehaviorTree, task_runtime_data:&TaskRuntimeData, stack_runtime_data:&StackRuntimeData, task:&dyn ITaskProxy, child_stack_runtime_data:&StackRuntimeData){
+		self.send(behavior_tree, SyncMessage::ParallelAddChildStack{tree_id:behavior_tree.id(), task:*task_runtime_data, stack:*stack_runtime_data, child_stack:*child_stack_runtime_data}, Some(task));
+	}
+
+	fn parallel_remove_child_stack(&self, behavior_tree:&dyn IBehaviorTree, task_runtime_data:&TaskRuntimeData, stack_runtime_data:&StackRuntimeData, task:&dyn ITaskProxy, child_stack_runtime_data:&StackRuntimeData, now_timestamp_in_milli:u64){
+		self.send(behavior_tree, SyncMessage::ParallelRemoveChildStack{tree_id:behavior_tree.id(), task:*task_runtime_data, stack:*stack_runtime_data, child_stack:*child_stack_runtime_data, now:now_timestamp_in_milli}, Some(task));
+	}
+}
+
+//	通过rebuild_sync收集树当前的全量同步状态
+pub struct FullStateCollector{
+	tree_id:u64,
+	stacks:Vec<StackRuntimeData>,
+	parallels:Vec<FullStateParallel>,
+	actions:Vec<FullStateAction>,
+}
+
+impl FullStateCollector{
+	pub fn new(tree_id:u64)->Self{
+		Self{
+			tree_id,
+			stacks:Vec::new(),
+			parallels:Vec::new(),
+			actions:Vec::new(),
+		}
+	}
+
+	//	在两次update之间调用，之后产生的增量消息正好接在全量状态后面
+	pub fn collect(behavior_tree:&dyn IBehaviorTree, sequences:&SyncSequences)->SyncPacket{
+		let mut collector = Self::new(behavior_tree.id());
+		behavior_tree.rebuild_sync(&mut collector);
+		SyncPacket::new(sequences.last(behavior_tree.id()), collector.into_message())
+	}
+
+	//	收集并编码全量状态，字节数记到meter里，和增量消息一起统计
+	pub fn encode_with_meter(behavior_tree:&dyn IBehaviorTree, sequences:&SyncSequences, meter:&RefCell<BandwidthMeter>)->Vec<u8>{
+		let packet = Self::collect(behavior_tree, sequences);
+		let bytes = packet.encode();
+		meter.borrow_mut().record_packet(behavior_tree, &packet, None, bytes.len());
+		bytes
+	}
+
+	pub fn into_message(self)->SyncMessage{
+		SyncMessage::FullState{
+			tree_id:self.tree_id,
+			stacks:self.stacks,
+			parallels:self.parallels,
+			actions:self.actions,
+		}
+	}
+}
+
+#[allow(unused_variables)]
+impl IRebuildSyncDataCollector for FullStateCollector{
+	fn stack(&mut self, behavior_tree: &dyn IBehaviorTree, data: &StackRuntimeData){
+		self.stacks.push(*data);
+	}
+
+	fn action(&mut self, behavior_tree:&dyn IBehaviorTree, task_runtime_data:&TaskRuntimeData, stack_runtime_data:&StackRuntimeData, task:&dyn ITaskProxy, datas:&[Vec<u8>]){
+		self.actions.push(FullStateAction{
+			task:*task_runtime_data,
+			stack:*stack_runtime_data,
+			corresponding_type:task.corresponding_type().to_string(),
+			datas:datas.to_vec(),
+		});
+	}
+
+	fn parallel(&mut self, behavior_tree:&dyn IBehaviorTree, task_runtime_data:&TaskRuntimeData, stack_runtime_data:&StackRuntimeData, task:&dyn ITaskProxy, child_stack_runtime_datas:&Vec<StackRuntimeData>){
+		self.parallels.push(FullStateParallel{
+			task:*task_runtime_data,
+			stack:*stack_runtime_data,
+			child_stacks:child_stack_runtime_datas.clone(),
+		});
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use super::super::interface::IClock;
+	use super::super::json_parser::JsonParser;
+	use super::super::runtime::BehaviorTree;
+
+	struct DummyClock;
+
+	impl IClock for DummyClock{
+		fn timestamp_in_mill(&self)->u64{
+			1_700_000_000_000
+		}
+	}
+
+	#[test]
+	fn test_sync_packet_encode_decode() {
+		let task = TaskRuntimeData::new(-1, 1_700_000_000_000, 300, 2);
+		let stack = StackRuntimeData::new(2, 1_700_000_000_000);
+		let child_stack = StackRuntimeData::new(3, 1_700_000_000_033);
+		let messages = vec![
+			SyncMessage::NewStack{tree_id:1, stack},
+			SyncMessage::RemoveStack{tree_id:1, stack, now:5},
+			SyncMessage::ActionStart{tree_id:u64::MAX, task, stack, corresponding_type:"Role.Ani".to_string(), datas:vec![vec![1, 2, 3], vec![], vec![0xff; 200]]},
+			SyncMessage::ActionUpdate{tree_id:1, task, stack, now:6, status:TaskStatus::Failure, datas:Vec::new()},
+			SyncMessage::ActionEnd{tree_id:1, task, stack, now:7, datas:vec![vec![9]]},
+			SyncMessage::ParallelAddChildStack{tree_id:1, task, stack, child_stack},
+			SyncMessage::ParallelRemoveChildStack{tree_id:1, task, stack, child_stack, now:8},
+			SyncMessage::FullState{tree_id:1, stacks:vec![stack, child_stack],
+				parallels:vec![FullStateParallel{task, stack, child_stacks:vec![child_stack]}],
+				actions:vec![FullStateAction{task, stack:child_stack, corresponding_type:"Role.Ani".to_string(), datas:vec![vec![4, 5]]}]},
+		];
+
+		for (sequence, message) in messages.into_iter().enumerate(){
+			let packet = SyncPacket::new(sequence as u64 * 1000, message);
+			let bytes = packet.encode();
+			assert_eq!(bytes[0], WIRE_VERSION);
+			assert_eq!(SyncPacket::decode(&bytes).unwrap(), packet);
+			assert!(SyncPacket::decode(&bytes[..bytes.len() - 1]).is_err());
+
+			let mut bytes = bytes;
+			bytes[0] = WIRE_VERSION + 1;
+			assert!(SyncPacket::decode(&bytes).is_err());
+		}
+	}
+
+	#[test]
+	fn test_wire_reader_rejects_overflow() {
+		let mut writer = WireWriter::new();
+		writer.write_varint(u64::MAX);
+		assert_eq!(WireReader::new(&writer.bytes).read_varint().unwrap(), u64::MAX);
+
+		//	第10个字节带了超过u64的位
+		let mut bytes = writer.bytes.clone();
+		*bytes.last_mut().unwrap() = 0x03;
+		assert!(WireReader::new(&bytes).read_varint().is_err());
+
+		let mut writer = WireWriter::new();
+		writer.write_zigzag(i32::MAX as i64 + 1);
+		writer.write_varint(0);
+		writer.write_varint(0);
+		writer.write_varint(0);
+		assert!(WireReader::new(&writer.bytes).read_task().is_err());
+
+		let mut writer = WireWriter::new();
+		writer.write_zigzag(-1);
+		writer.write_varint(0);
+		writer.write_varint(u32::MAX as u64 + 1);
+		writer.write_varint(0);
+		assert!(WireReader::new(&writer.bytes).read_task().is_err());
+	}
+
+	#[test]
+	fn test_wire_encode_runtime_event_handle() {
+		let file_bytes = std::fs::read("src/behavior_tree/test_behaviortree.json").unwrap();
+		let parser = JsonParser::new();
+		let clock:Rc<RefCell<Box<dyn IClock>>> = Rc::new(RefCell::new(Box::new(DummyClock)));
+		let messages = Rc::new(RefCell::new(Vec::new()));
+		let behavior_tree = BehaviorTree::new(11, &file_bytes, 1, &Rc::downgrade(&clock), Box::new(WireEncodeRuntimeEventHandle::new(messages.clone(), SyncSequences::new())), Rc::downgrade(&parser));
+		behavior_tree.borrow_mut().enable().unwrap();
+		behavior_tree.borrow_mut().update();
+		behavior_tree.borrow_mut().disable().unwrap();
+
+		let packets:Vec<SyncPacket> = messages.borrow().iter().map(|bytes| SyncPacket::decode(bytes).unwrap()).collect();
+		assert!(packets.iter().enumerate().all(|(index, packet)| packet.sequence == index as u64 + 1));
+		let messages:Vec<SyncMessage> = packets.into_iter().map(|packet| packet.message).collect();
+		assert!(messages.iter().all(|message| message.tree_id() == 11));
+		assert!(matches!(messages.first(), Some(SyncMessage::NewStack{stack, ..}) if stack.stack_id == 1));
+		assert!(matches!(messages.last(), Some(SyncMessage::RemoveStack{stack, ..}) if stack.stack_id == 1));
+		assert!(messages.iter().any(|message| matches!(message, SyncMessage::ParallelAddChildStack{..})));
+	}
+}
@@ -0,0 +1,25 @@
+use real_time_sync::behavior_tree::compiled::compile_json;
+use real_time_sync::behavior_tree::registry::TaskRegistry;
+
+//	把Behavior Designer导出的json编译成二进制格式，只认识默认注册的任务类型
+//	compile_behavior_tree <input.json> <output>
+fn main(){
+	let args:Vec<String> = std::env::args().skip(1).collect();
+	if args.len() != 2{
+		eprintln!("usage: compile_behavior_tree <input.json> <output>");
+		std::process::exit(2);
+	}
+
+	let registry = TaskRegistry::new();
+	let registry = registry.read().unwrap_or_else(|err| err.into_inner());
+	let result = std::fs::read(&args[0]).map_err(|err| err.into())
+		.and_then(|config| compile_json(&config, &registry).map(|compiled| (config.len(), compiled)))
+		.and_then(|(json_len, compiled)| std::fs::write(&args[1], &compiled).map(|_| (json_len, compiled.len())).map_err(|err| err.into()));
+	match result{
+		Ok((json_len, compiled_len)) => println!("{} ({} bytes) -> {} ({} bytes)", args[0], json_len, args[1], compiled_len),
+		Err(err) => {
+			eprintln!("compile {} failed: {}", args[0], err);
+			std::process::exit(1);
+		},
+	}
+}